@@ -0,0 +1,68 @@
+//! Derive macros for [simpledb](https://crates.io/crates/simpledb), enable them with the `derive`
+//! feature of `simpledb`.
+
+use proc_macro::TokenStream;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DataStruct, DeriveInput, Fields};
+
+/// Implement `simpledb::Record` for a struct with named fields, every struct field is stored as a
+/// field of a `map` key. Also generates `update_{field}(db, key, value)` methods to update a
+/// single field.
+#[proc_macro_derive(SimpledbRecord)]
+pub fn derive_simpledb_record(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    let name = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(DataStruct {
+            fields: Fields::Named(fields),
+            ..
+        }) => &fields.named,
+        _ => {
+            return syn::Error::new_spanned(
+                name,
+                "SimpledbRecord only supports structs with named fields",
+            )
+            .to_compile_error()
+            .into()
+        }
+    };
+    let idents: Vec<_> = fields.iter().filter_map(|f| f.ident.clone()).collect();
+    let field_names: Vec<_> = idents.iter().map(|i| i.to_string()).collect();
+    let types: Vec<_> = fields.iter().map(|f| f.ty.clone()).collect();
+    let update_fns: Vec<_> = idents
+        .iter()
+        .map(|i| format_ident!("update_{}", i))
+        .collect();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let expanded = quote! {
+        impl #impl_generics ::simpledb::Record for #name #ty_generics #where_clause {
+            fn save(&self, db: &::simpledb::Database, key: &str) -> ::simpledb::Result<()> {
+                #( ::simpledb::record::save_field(db, key, #field_names, &self.#idents)?; )*
+                Ok(())
+            }
+
+            fn load(db: &::simpledb::Database, key: &str) -> ::simpledb::Result<Option<Self>> {
+                if db.get_meta(key)?.is_none() {
+                    return Ok(None);
+                }
+                Ok(Some(Self {
+                    #( #idents: ::simpledb::record::load_field(db, key, #field_names)?, )*
+                }))
+            }
+        }
+
+        impl #impl_generics #name #ty_generics #where_clause {
+            #(
+                pub fn #update_fns(
+                    db: &::simpledb::Database,
+                    key: &str,
+                    value: &#types,
+                ) -> ::simpledb::Result<()> {
+                    ::simpledb::record::save_field(db, key, #field_names, value)
+                }
+            )*
+        }
+    };
+    expanded.into()
+}
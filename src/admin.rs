@@ -0,0 +1,104 @@
+use std::sync::atomic::Ordering;
+
+use bytes::{Buf, BufMut, BytesMut};
+use rocksdb::{Direction, IteratorMode};
+
+use crate::codec::*;
+use crate::database::{now_millis, Database, Result};
+
+/// Key prefix for the log of administrative operations, followed by the sequence of the entry.
+pub static PREFIX_ADMIN_LOG: &[u8] = b"l";
+
+/// An administrative operation recorded in the log, see `Database::admin_log()`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct AdminLogEntry {
+    /// Increasing sequence of the entry.
+    pub seq: u64,
+    /// Milliseconds since the UNIX epoch.
+    pub time: u64,
+    /// The operation, e.g. `delete_all`.
+    pub op: String,
+    /// The key name the operation works on, empty for the operations on many keys.
+    pub key: String,
+    /// The count returned by the operation, e.g. the deleted rows.
+    pub count: u64,
+}
+
+/// Encode key of an entry of the admin log.
+pub fn encode_admin_log_key(seq: u64) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(PREFIX_ADMIN_LOG.len() + 8);
+    buf.put_slice(PREFIX_ADMIN_LOG);
+    buf.put_u64(seq);
+    buf
+}
+
+impl Database {
+    /// Get at most `limit` newest entries of the admin log, newest first. The log records
+    /// `delete_all()`, `delete_keys()`, `restore_key()`, `import_range()`, `import_meta()`,
+    /// `copy_key_to()` (into the destination), `rename_key_rewriting()`, `rebuild_type_index()`,
+    /// `seal_key()`, `unseal_key()`, `freeze_key()`, `unfreeze_key()`, `gc()` and the
+    /// quarantines of `verify_metas()` and `Options::bad_row_policy`, so operators can
+    /// reconstruct what happened to a dataset.
+    pub fn admin_log(&self, limit: usize) -> Result<Vec<AdminLogEntry>> {
+        let end = encode_admin_log_key(u64::MAX);
+        let iter = self
+            .rocksdb
+            .iterator(IteratorMode::From(&end, Direction::Reverse))
+            .take_while(|(k, _)| has_prefix(PREFIX_ADMIN_LOG, k))
+            .take(limit);
+        let mut entries = Vec::new();
+        for (k, v) in iter {
+            if let Some(entry) = decode_admin_log_entry(&k, &v) {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Append an entry to the admin log.
+    pub(crate) fn log_admin(&self, op: &str, key: impl AsRef<[u8]>, count: u64) -> Result<()> {
+        let key = key.as_ref();
+        let seq = self.admin_log_seq.fetch_add(1, Ordering::SeqCst);
+        let mut buf = BytesMut::with_capacity(20 + op.len() + key.len());
+        buf.put_u64(now_millis());
+        buf.put_u64(count);
+        buf.put_u16(op.len() as u16);
+        buf.put_slice(op.as_bytes());
+        buf.put_slice(key);
+        self.rocksdb.put(encode_admin_log_key(seq), buf)?;
+        Ok(())
+    }
+
+    /// Load the next sequence of the admin log from the last entry.
+    pub(crate) fn load_admin_log_seq(&self) {
+        let end = encode_admin_log_key(u64::MAX);
+        let seq = self
+            .rocksdb
+            .iterator(IteratorMode::From(&end, Direction::Reverse))
+            .next()
+            .filter(|(k, _)| has_prefix(PREFIX_ADMIN_LOG, k) && k.len() == 9)
+            .map(|(k, _)| (&k[1..]).get_u64() + 1)
+            .unwrap_or(0);
+        self.admin_log_seq.store(seq, Ordering::SeqCst);
+    }
+}
+
+fn decode_admin_log_entry(key: &[u8], value: &[u8]) -> Option<AdminLogEntry> {
+    if key.len() != 9 || value.len() < 18 {
+        return None;
+    }
+    let mut buf = value;
+    let time = buf.get_u64();
+    let count = buf.get_u64();
+    let op_len = buf.get_u16() as usize;
+    if buf.len() < op_len {
+        return None;
+    }
+    Some(AdminLogEntry {
+        seq: (&key[1..]).get_u64(),
+        time,
+        op: String::from_utf8_lossy(&buf[..op_len]).into_owned(),
+        key: String::from_utf8_lossy(&buf[op_len..]).into_owned(),
+        count,
+    })
+}
@@ -0,0 +1,114 @@
+use std::borrow::Cow;
+
+use bytes::{BufMut, BytesMut};
+use rocksdb::WriteBatch;
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+
+/// Key prefix for key aliases, the value is the target key name.
+pub static PREFIX_ALIAS: &[u8] = b"a";
+
+/// Encode key of an alias.
+pub fn encode_alias_key(alias: impl AsRef<[u8]>) -> BytesMut {
+    let alias = alias.as_ref();
+    let mut buf = BytesMut::with_capacity(PREFIX_ALIAS.len() + alias.len());
+    buf.put_slice(PREFIX_ALIAS);
+    buf.put_slice(alias);
+    buf
+}
+
+impl Database {
+    /// Make `alias` resolve to the same key as `target`, the data of `target` can be read and
+    /// written through both names. An alias of an alias points to the final target.
+    /// Fails if `alias` is an existing key or the target of other aliases.
+    pub fn alias_key(&self, alias: &str, target: &str) -> Result<()> {
+        // the check and the put are done under the lock, so two threads can't alias each other
+        let mut aliases = self.aliases.write().unwrap();
+        let target = aliases
+            .get(target.as_bytes())
+            .cloned()
+            .unwrap_or_else(|| target.as_bytes().to_vec());
+        let alias = alias.as_bytes();
+        if alias == target.as_slice() {
+            return Err(Error::Message(format!(
+                "can not alias key {} to itself",
+                String::from_utf8_lossy(alias)
+            )));
+        }
+        if self.rocksdb.get_pinned(encode_meta_key(alias))?.is_some()
+            || aliases.values().any(|t| t.as_slice() == alias)
+        {
+            return Err(Error::Message(format!(
+                "can not alias existing key {}",
+                String::from_utf8_lossy(alias)
+            )));
+        }
+        self.rocksdb.put(encode_alias_key(alias), &target)?;
+        aliases.insert(alias.to_vec(), target);
+        Ok(())
+    }
+
+    /// Remove an alias, the target key is not changed. Returns `false` if it's not an alias.
+    pub fn remove_alias(&self, alias: &str) -> Result<bool> {
+        let mut aliases = self.aliases.write().unwrap();
+        if !aliases.contains_key(alias.as_bytes()) {
+            return Ok(false);
+        }
+        self.rocksdb.delete(encode_alias_key(alias))?;
+        aliases.remove(alias.as_bytes());
+        Ok(true)
+    }
+
+    /// Get the aliases of a key, sorted by name.
+    pub fn aliases_of(&self, key: &str) -> Vec<String> {
+        let target = self.resolve_key(key.as_bytes());
+        let mut vec: Vec<String> = self
+            .aliases
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, t)| t.as_slice() == target.as_ref())
+            .map(|(a, _)| String::from_utf8_lossy(a).to_string())
+            .collect();
+        vec.sort();
+        vec
+    }
+
+    /// Get the key name an alias resolves to, or the key itself if it's not an alias.
+    pub(crate) fn resolve_key<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.aliases.read().unwrap().get(key) {
+            Some(target) => Cow::Owned(target.clone()),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    /// Put the deletes of all the aliases of `target` into a write batch, call
+    /// `forget_aliases()` after the batch is written.
+    pub(crate) fn delete_aliases_to_batch(&self, batch: &mut WriteBatch, target: &[u8]) {
+        for (alias, t) in self.aliases.read().unwrap().iter() {
+            if t.as_slice() == target {
+                batch.delete(encode_alias_key(alias));
+            }
+        }
+    }
+
+    pub(crate) fn forget_aliases(&self, target: &[u8]) {
+        self.aliases
+            .write()
+            .unwrap()
+            .retain(|_, t| t.as_slice() != target);
+    }
+
+    pub(crate) fn load_aliases(&self) {
+        let mut aliases = self.aliases.write().unwrap();
+        aliases.clear();
+        let iter = self.rocksdb.prefix_iterator(PREFIX_ALIAS);
+        for (k, v) in iter {
+            if !has_prefix(PREFIX_ALIAS, &k) {
+                break;
+            }
+            aliases.insert(k[PREFIX_ALIAS.len()..].to_vec(), v.to_vec());
+        }
+    }
+}
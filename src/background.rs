@@ -0,0 +1,128 @@
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// Timeout of `BackgroundTasks::shutdown()` when the database is dropped.
+pub const BACKGROUND_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Stop signal of a background task.
+#[derive(Clone, Default)]
+pub struct StopSignal {
+    state: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl StopSignal {
+    pub fn is_stopped(&self) -> bool {
+        *self.state.0.lock().unwrap()
+    }
+
+    /// Sleep until the timeout or the task is stopped, returns `true` if it's stopped.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let (lock, cvar) = &*self.state;
+        let stopped = lock.lock().unwrap();
+        let (stopped, _) = cvar
+            .wait_timeout_while(stopped, timeout, |stopped| !*stopped)
+            .unwrap();
+        *stopped
+    }
+
+    fn stop(&self) {
+        let (lock, cvar) = &*self.state;
+        *lock.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+}
+
+struct Task {
+    name: String,
+    stop: StopSignal,
+    handle: JoinHandle<()>,
+}
+
+/// Background threads owned by a database, they are shut down when the database is dropped.
+#[derive(Default)]
+pub struct BackgroundTasks {
+    tasks: Mutex<Vec<Task>>,
+    panics: Arc<AtomicU64>,
+}
+
+impl BackgroundTasks {
+    /// Start a task running `f` every `interval` in a new thread until it's stopped.
+    /// A panic of `f` is caught and counted, the task keeps running at the next interval.
+    pub fn spawn<F>(&self, name: &str, interval: Duration, mut f: F)
+    where
+        F: FnMut(&StopSignal) + Send + 'static,
+    {
+        let stop = StopSignal::default();
+        let stop2 = stop.clone();
+        let panics = self.panics.clone();
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                while !stop2.is_stopped() {
+                    if panic::catch_unwind(AssertUnwindSafe(|| f(&stop2))).is_err() {
+                        panics.fetch_add(1, Ordering::Relaxed);
+                    }
+                    if stop2.wait(interval) {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn background task thread");
+        self.tasks.lock().unwrap().push(Task {
+            name: name.to_string(),
+            stop,
+            handle,
+        });
+    }
+
+    /// Stop the tasks named `name` without waiting, returns `false` if there is no such task.
+    pub fn stop(&self, name: &str) -> bool {
+        let mut found = false;
+        for task in self.tasks.lock().unwrap().iter().filter(|t| t.name == name) {
+            task.stop.stop();
+            found = true;
+        }
+        found
+    }
+
+    /// Names of the running tasks.
+    pub fn names(&self) -> Vec<String> {
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|t| !t.handle.is_finished());
+        tasks.iter().map(|t| t.name.clone()).collect()
+    }
+
+    /// Total panics caught from the tasks.
+    pub fn panic_count(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Stop all the tasks and wait for them at most `timeout`, the threads not finished in time
+    /// are detached. Returns `true` if all the tasks are finished.
+    pub fn shutdown(&self, timeout: Duration) -> bool {
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        for task in tasks.iter() {
+            task.stop.stop();
+        }
+        let deadline = Instant::now() + timeout;
+        let mut all_finished = true;
+        for task in tasks {
+            while !task.handle.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1));
+            }
+            if task.handle.is_finished() {
+                let _ = task.handle.join();
+            } else {
+                all_finished = false;
+            }
+        }
+        all_finished
+    }
+}
@@ -0,0 +1,130 @@
+use bytes::{Buf, BufMut, BytesMut};
+use rocksdb::WriteBatch;
+use sha2::{Digest, Sha256};
+
+use crate::codec::{encode_hex, has_prefix};
+use crate::database::{Database, Error, Result};
+
+/// SHA-256 hash of a blob.
+pub type BlobHash = [u8; 32];
+
+/// Key prefix for blobs.
+pub static PREFIX_BLOB: &[u8] = b"b";
+static TAG_DATA: u8 = b'd';
+static TAG_COUNT: u8 = b'c';
+static TAG_REF: u8 = b'r';
+
+fn encode_blob_key(tag: u8, hash: &BlobHash) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(34);
+    buf.put_slice(PREFIX_BLOB);
+    buf.put_u8(tag);
+    buf.put_slice(hash);
+    buf
+}
+
+fn encode_blob_ref_key(hash: &BlobHash, key: &str) -> BytesMut {
+    let mut buf = encode_blob_key(TAG_REF, hash);
+    buf.put_slice(key.as_bytes());
+    buf
+}
+
+impl Database {
+    /// Store a blob by its content, the same content is stored only once. Returns the hash.
+    /// A blob without references is deleted by `blob_gc()`, use `blob_ref()` to keep it.
+    pub fn blob_put(&self, data: &[u8]) -> Result<BlobHash> {
+        let hash: BlobHash = Sha256::digest(data).into();
+        let k = encode_blob_key(TAG_DATA, &hash);
+        let _lock = self.blob_lock.lock().unwrap();
+        if self.rocksdb.get_pinned(&k)?.is_none() {
+            self.rocksdb.put(&k, data)?;
+        }
+        Ok(hash)
+    }
+
+    pub fn blob_get(&self, hash: &BlobHash) -> Result<Option<Vec<u8>>> {
+        Ok(self.rocksdb.get(encode_blob_key(TAG_DATA, hash))?)
+    }
+
+    /// Get the references count of a blob.
+    pub fn blob_ref_count(&self, hash: &BlobHash) -> Result<u64> {
+        Ok(self
+            .rocksdb
+            .get_pinned(encode_blob_key(TAG_COUNT, hash))?
+            .map(|v| v.as_ref().get_u64())
+            .unwrap_or(0))
+    }
+
+    /// Record that `key` references the blob, a key references the same blob at most once.
+    /// Returns the references count.
+    pub fn blob_ref(&self, key: &str, hash: &BlobHash) -> Result<u64> {
+        let _lock = self.blob_lock.lock().unwrap();
+        if self
+            .rocksdb
+            .get_pinned(encode_blob_key(TAG_DATA, hash))?
+            .is_none()
+        {
+            return Err(Error::Message(format!(
+                "blob {} not found",
+                encode_hex(hash)
+            )));
+        }
+        let count = self.blob_ref_count(hash)?;
+        let ref_key = encode_blob_ref_key(hash, key);
+        if self.rocksdb.get_pinned(&ref_key)?.is_some() {
+            return Ok(count);
+        }
+        let mut batch = WriteBatch::default();
+        batch.put(ref_key, []);
+        batch.put(encode_blob_key(TAG_COUNT, hash), (count + 1).to_be_bytes());
+        self.rocksdb.write(batch)?;
+        Ok(count + 1)
+    }
+
+    /// Remove the reference of `key` to the blob, returns the references count.
+    pub fn blob_unref(&self, key: &str, hash: &BlobHash) -> Result<u64> {
+        let _lock = self.blob_lock.lock().unwrap();
+        let count = self.blob_ref_count(hash)?;
+        let ref_key = encode_blob_ref_key(hash, key);
+        if self.rocksdb.get_pinned(&ref_key)?.is_none() {
+            return Ok(count);
+        }
+        let mut batch = WriteBatch::default();
+        batch.delete(ref_key);
+        let count_key = encode_blob_key(TAG_COUNT, hash);
+        if count > 1 {
+            batch.put(count_key, (count - 1).to_be_bytes());
+        } else {
+            batch.delete(count_key);
+        }
+        self.rocksdb.write(batch)?;
+        Ok(count.saturating_sub(1))
+    }
+
+    /// Delete all blobs without references, returns the deleted count.
+    pub fn blob_gc(&self) -> Result<u64> {
+        let mut prefix = BytesMut::with_capacity(2);
+        prefix.put_slice(PREFIX_BLOB);
+        prefix.put_u8(TAG_DATA);
+        let mut hashes = Vec::new();
+        let iter = self.rocksdb.prefix_iterator(&prefix);
+        for (k, _) in iter {
+            if !has_prefix(&prefix, &k) {
+                break;
+            }
+            if let Ok(hash) = BlobHash::try_from(&k[2..]) {
+                hashes.push(hash);
+            }
+        }
+        // the counts are checked under the lock, so a blob referenced meanwhile is kept
+        let _lock = self.blob_lock.lock().unwrap();
+        let mut batch = WriteBatch::default();
+        for hash in hashes.iter() {
+            if self.blob_ref_count(hash)? < 1 {
+                batch.delete(encode_blob_key(TAG_DATA, hash));
+            }
+        }
+        let deleted = batch.len() as u64;
+        self.rocksdb.write(batch)?;
+        Ok(deleted)
+    }
+}
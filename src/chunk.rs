@@ -0,0 +1,215 @@
+use std::sync::atomic::Ordering;
+
+use bytes::{Buf, BufMut, BytesMut};
+use rocksdb::WriteBatch;
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+
+/// Key prefix for the large `map` values split into chunks, see
+/// `Options::large_value_threshold`. It's followed by the key ID, the length and the name of
+/// the field, the row is the manifest of the value, and the rows of the chunks are followed by
+/// the chunk index.
+pub static PREFIX_VALUE_CHUNK: &[u8] = b"v";
+
+/// Encode the prefix of the chunk rows of a key ID.
+pub fn encode_value_chunk_prefix(key_id: u64) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(PREFIX_VALUE_CHUNK.len() + 8);
+    buf.put_slice(PREFIX_VALUE_CHUNK);
+    buf.put_u64(key_id);
+    buf
+}
+
+/// Encode key of the manifest of a chunked value.
+pub fn encode_value_manifest_key(key_id: u64, field: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(PREFIX_VALUE_CHUNK.len() + 12 + field.len() + 4);
+    buf.put_slice(PREFIX_VALUE_CHUNK);
+    buf.put_u64(key_id);
+    buf.put_u32(field.len() as u32);
+    buf.put_slice(field);
+    buf
+}
+
+/// Encode key of a chunk of a chunked value.
+pub fn encode_value_chunk_key(key_id: u64, field: &[u8], index: u32) -> BytesMut {
+    let mut buf = encode_value_manifest_key(key_id, field);
+    buf.put_u32(index);
+    buf
+}
+
+/// Decode key of a chunk row, returns (key_id, field, index), the index is `None` for the
+/// manifest.
+pub fn decode_value_chunk_key(key: &[u8]) -> Option<(u64, &[u8], Option<u32>)> {
+    let mut rest = key.strip_prefix(PREFIX_VALUE_CHUNK)?;
+    if rest.len() < 12 {
+        return None;
+    }
+    let key_id = rest.get_u64();
+    let field_len = rest.get_u32() as usize;
+    if rest.len() < field_len {
+        return None;
+    }
+    let (field, mut rest) = rest.split_at(field_len);
+    match rest.len() {
+        0 => Some((key_id, field, None)),
+        4 => Some((key_id, field, Some(rest.get_u32()))),
+        _ => None,
+    }
+}
+
+/// Encode the manifest of a chunked value, the length of the value and the chunks count.
+fn encode_value_manifest(len: u64, chunks: u32) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(12);
+    buf.put_u64(len);
+    buf.put_u32(chunks);
+    buf
+}
+
+/// Decode the manifest of a chunked value, returns (len, chunks).
+fn decode_value_manifest(mut value: &[u8]) -> Option<(u64, u32)> {
+    if value.len() != 12 {
+        return None;
+    }
+    Some((value.get_u64(), value.get_u32()))
+}
+
+/// Reassemble the chunked value of a field, the rows are read by `get`. Returns `None` if the
+/// field has no manifest, i.e. its value is empty.
+pub(crate) fn read_chunked_value<F>(
+    mut get: F,
+    key_id: u64,
+    field: &[u8],
+) -> Result<Option<Vec<u8>>>
+where
+    F: FnMut(&[u8]) -> Result<Option<Vec<u8>>>,
+{
+    let manifest = match get(&encode_value_manifest_key(key_id, field))? {
+        None => return Ok(None),
+        Some(v) => v,
+    };
+    let broken = |reason: String| {
+        Error::Message(format!(
+            "chunked value of field {} is broken, {}",
+            String::from_utf8_lossy(field),
+            reason
+        ))
+    };
+    let (len, chunks) = decode_value_manifest(&manifest)
+        .ok_or_else(|| broken(format!("bad manifest of {} bytes", manifest.len())))?;
+    let mut value = Vec::with_capacity(len as usize);
+    for index in 0..chunks {
+        match get(&encode_value_chunk_key(key_id, field, index))? {
+            Some(chunk) => value.extend_from_slice(&chunk),
+            None => return Err(broken(format!("chunk {} is missing", index))),
+        }
+    }
+    if value.len() as u64 != len {
+        return Err(broken(format!(
+            "expects {} bytes but got {}",
+            len,
+            value.len()
+        )));
+    }
+    Ok(Some(value))
+}
+
+impl Database {
+    /// Get the value of a `map` item from its row, a chunked value is reassembled.
+    pub(crate) fn map_item_value<V>(&self, key_id: u64, field: &[u8], row: V) -> Result<V>
+    where
+        V: AsRef<[u8]> + From<Vec<u8>>,
+    {
+        if !row.as_ref().is_empty() || !self.chunked_values.load(Ordering::SeqCst) {
+            return Ok(row);
+        }
+        let value = read_chunked_value(|k| Ok(self.rocksdb.get(k)?), key_id, field)?;
+        Ok(value.map(V::from).unwrap_or(row))
+    }
+
+    /// Get the length of the value of a `map` item from the length of its row, the chunks of a
+    /// chunked value are not read.
+    pub(crate) fn map_item_value_len(
+        &self,
+        key_id: u64,
+        field: &[u8],
+        row_len: usize,
+    ) -> Result<usize> {
+        if row_len > 0 || !self.chunked_values.load(Ordering::SeqCst) {
+            return Ok(row_len);
+        }
+        Ok(self
+            .rocksdb
+            .get_pinned(encode_value_manifest_key(key_id, field))?
+            .and_then(|v| decode_value_manifest(&v))
+            .map(|(len, _)| len as usize)
+            .unwrap_or(row_len))
+    }
+
+    /// Put the value of a `map` item into the batch. A value larger than
+    /// `Options::large_value_threshold` is split into chunks of `Options::value_chunk_size`
+    /// bytes, and its item row is left empty. `old` is the old row of the item, the chunks of
+    /// the old value are deleted.
+    pub(crate) fn map_item_put_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key_id: u64,
+        field: &[u8],
+        value: &[u8],
+        old: Option<&[u8]>,
+    ) -> Result<()> {
+        if let Some(old) = old {
+            self.map_item_delete_chunks_to_batch(batch, key_id, field, old)?;
+        }
+        let item_key = encode_data_key_map_item(key_id, field);
+        match self.options.large_value_threshold {
+            Some(threshold) if value.len() > threshold as usize => {
+                let chunk_size = self.options.value_chunk_size.max(1) as usize;
+                let mut chunks = 0;
+                for chunk in value.chunks(chunk_size) {
+                    batch.put(encode_value_chunk_key(key_id, field, chunks), chunk);
+                    chunks += 1;
+                }
+                batch.put(
+                    encode_value_manifest_key(key_id, field),
+                    encode_value_manifest(value.len() as u64, chunks),
+                );
+                batch.put(item_key, FILL_EMPTY_DATA);
+            }
+            _ => batch.put(item_key, value),
+        }
+        Ok(())
+    }
+
+    /// Delete the chunks of the value of a `map` item in the batch, `old` is the row of the
+    /// item.
+    pub(crate) fn map_item_delete_chunks_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key_id: u64,
+        field: &[u8],
+        old: &[u8],
+    ) -> Result<()> {
+        if !old.is_empty() || !self.chunked_values.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let from = encode_value_manifest_key(key_id, field);
+        if self.rocksdb.get_pinned(&from)?.is_some() {
+            // the chunk keys are the manifest key followed by a 4 bytes index
+            let mut to = from.clone();
+            to.put_slice(&[0xff; 5]);
+            self.delete_range_to_batch(batch, from, to);
+        }
+        Ok(())
+    }
+
+    /// Delete the chunks of all the values of a `map` key.
+    pub(crate) fn delete_value_chunks_to_batch(&self, batch: &mut WriteBatch, meta: &KeyMeta) {
+        if meta.key_type == KeyType::Map && self.chunked_values.load(Ordering::SeqCst) {
+            self.delete_range_to_batch(
+                batch,
+                encode_value_chunk_prefix(meta.id),
+                encode_value_chunk_prefix(meta.id + 1),
+            );
+        }
+    }
+}
@@ -4,11 +4,26 @@ use bytes::{Buf, BufMut, BytesMut};
 
 pub type ScoreVal = (Box<[u8]>, Box<[u8]>);
 pub type VecScoreVal = Vec<ScoreVal>;
+pub type TokenScoreVal = (Box<[u8]>, Box<[u8]>, Box<[u8]>);
+/// `sorted list` item with its sequence, the insertion order among the items of equal scores.
+pub type ScoreSeqVal = (Box<[u8]>, u64, Box<[u8]>);
+pub type OffsetVal = (u64, Box<[u8]>);
+pub type FieldVal = (Box<[u8]>, Box<[u8]>);
+/// `map` field decoded as UTF-8 with its value.
+pub type Utf8FieldVal = (String, Box<[u8]>);
 
 /// Key prefix for meta data.
 pub static PREFIX_META: &[u8] = b"m";
 /// Key prefix for row data.
 pub static PREFIX_DATA: &[u8] = b"d";
+/// Key prefix for reserved `sorted list` items.
+pub static PREFIX_RESERVED: &[u8] = b"r";
+/// Key prefix for the index of key names by key type.
+pub static PREFIX_TYPE_INDEX: &[u8] = b"t";
+/// Key of the persisted next key ID, it's outside of all the other prefixes.
+pub static KEY_NEXT_KEY_ID: &[u8] = b"snext_key_id";
+/// Key marks the index of key types has been built.
+pub static KEY_TYPE_INDEX_READY: &[u8] = b"stype_index_ready";
 /// Fill data for empty row.
 pub static FILL_EMPTY_DATA: &[u8] = b"";
 
@@ -17,6 +32,22 @@ pub fn has_prefix(prefix: &[u8], key: &[u8]) -> bool {
     prefix.iter().zip(key).take_while(|(x, y)| x == y).count() == prefix.len()
 }
 
+/// Encode bytes as lowercase hex.
+pub fn encode_hex(b: &[u8]) -> String {
+    b.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Decode bytes from hex, returns `None` if it's invalid.
+pub fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
 /// Encode a meta key.
 pub fn encode_meta_key(key: impl AsRef<[u8]>) -> BytesMut {
     let mut buf = BytesMut::with_capacity(9);
@@ -30,6 +61,26 @@ pub fn decode_meta_key(key: &[u8]) -> Result<String, FromUtf8Error> {
     String::from_utf8(key[1..].to_vec())
 }
 
+/// Encode key of the index of key types.
+pub fn encode_type_index_key(key_type: KeyType, key: impl AsRef<[u8]>) -> BytesMut {
+    let key = key.as_ref();
+    let mut buf = BytesMut::with_capacity(2 + key.len());
+    buf.put_slice(PREFIX_TYPE_INDEX);
+    buf.put_u8(key_type.to_u8());
+    buf.put_slice(key);
+    buf
+}
+
+/// Encode key prefix of the index of a key type.
+pub fn encode_type_index_prefix(key_type: KeyType) -> BytesMut {
+    encode_type_index_key(key_type, b"")
+}
+
+/// Decode key name from key of the index of key types.
+pub fn decode_type_index_key(key: &[u8]) -> Result<String, FromUtf8Error> {
+    String::from_utf8(key[2..].to_vec())
+}
+
 /// Encode data key
 pub fn encode_data_key(key_id: u64) -> BytesMut {
     let mut buf = BytesMut::with_capacity(9);
@@ -53,6 +104,11 @@ pub fn decode_data_key_map_item(key: &[u8]) -> Result<String, FromUtf8Error> {
     String::from_utf8(key[9..].to_vec())
 }
 
+/// Decode data key of `map` item, returns the raw field name.
+pub fn decode_data_key_map_field(key: &[u8]) -> &[u8] {
+    key[9..].as_ref()
+}
+
 /// Encode data key of `set` item.
 pub fn encode_data_key_set_item(key_id: u64, value: &[u8]) -> BytesMut {
     let mut buf = BytesMut::with_capacity(9 + value.len());
@@ -96,6 +152,101 @@ pub fn decode_data_key_sorted_list_item(key: &[u8]) -> &[u8] {
     key[9..key.len() - 8].as_ref()
 }
 
+/// Decode the sequence in data key of `sorted list` item.
+pub fn decode_data_key_sorted_list_sequence(key: &[u8]) -> u64 {
+    (&key[key.len() - 8..]).get_u64()
+}
+
+/// Encode data key of an item of a custom key type.
+pub fn encode_data_key_custom_item(key_id: u64, suffix: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(9 + suffix.len());
+    buf.put_slice(PREFIX_DATA);
+    buf.put_u64(key_id);
+    buf.put_slice(suffix);
+    buf
+}
+
+/// Encode reserved key of `sorted list` item, the rest part is the same as the data key.
+pub fn encode_reserved_key(data_key: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(data_key.len());
+    buf.put_slice(PREFIX_RESERVED);
+    buf.put_slice(&data_key[1..]);
+    buf
+}
+
+/// Encode reserved key prefix of `sorted list` items.
+pub fn encode_reserved_key_prefix(key_id: u64) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(9);
+    buf.put_slice(PREFIX_RESERVED);
+    buf.put_u64(key_id);
+    buf
+}
+
+/// Decode reserved key to the data key of `sorted list` item.
+pub fn decode_reserved_key(key: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(key.len());
+    buf.put_slice(PREFIX_DATA);
+    buf.put_slice(&key[1..]);
+    buf
+}
+
+/// Encode reserved value of `sorted list` item.
+pub fn encode_reserved_value(expires_at: u64, key: &[u8], value: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(12 + key.len() + value.len());
+    buf.put_u64(expires_at);
+    buf.put_u32(key.len() as u32);
+    buf.put_slice(key);
+    buf.put_slice(value);
+    buf
+}
+
+/// Decode reserved value of `sorted list` item.
+/// returns (expires_at[u64], key, value)
+pub fn decode_reserved_value(mut buf: &[u8]) -> (u64, &[u8], &[u8]) {
+    let expires_at = buf.get_u64();
+    let key_len = buf.get_u32() as usize;
+    (expires_at, &buf[..key_len], &buf[key_len..])
+}
+
+/// Encode a value with its expire time in milliseconds since the Unix epoch.
+pub fn encode_expiring_value(expires_at: u64, value: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(8 + value.len());
+    buf.put_u64(expires_at);
+    buf.put_slice(value);
+    buf
+}
+
+/// Decode a value with its expire time.
+/// returns (expires_at[u64], value)
+pub fn decode_expiring_value(mut buf: &[u8]) -> (u64, &[u8]) {
+    let expires_at = buf.get_u64();
+    (expires_at, buf)
+}
+
+/// Encode data key of `value` chunk.
+pub fn encode_data_key_value_chunk(key_id: u64, index: u32) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(13);
+    buf.put_slice(PREFIX_DATA);
+    buf.put_u64(key_id);
+    buf.put_u32(index);
+    buf
+}
+
+/// Encode data key of `log` entry.
+pub fn encode_data_key_log_entry(key_id: u64, offset: u64) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(17);
+    buf.put_slice(PREFIX_DATA);
+    buf.put_u64(key_id);
+    buf.put_u64(offset);
+    buf
+}
+
+/// Decode offset from data key of `log` entry.
+pub fn decode_data_key_log_offset(key: &[u8]) -> u64 {
+    let mut buf = &key[9..];
+    buf.get_u64()
+}
+
 /// Encode data key prefix of `sorted set` item
 pub fn encode_data_key_sorted_set_prefix(key_id: u64) -> BytesMut {
     let mut buf = BytesMut::with_capacity(10);
@@ -312,6 +463,10 @@ pub fn get_next_upper_bound(bound: &[u8]) -> Vec<u8> {
     next.iter().map(|v| *v as u8).collect()
 }
 
+/// Smallest tag of the user-defined key types, the smaller tags are reserved for the built-in
+/// types.
+pub const CUSTOM_KEY_TYPE_MIN: u8 = 128;
+
 /// Supported data type of this database.
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum KeyType {
@@ -320,6 +475,10 @@ pub enum KeyType {
     SortedList,
     Set,
     SortedSet,
+    Value,
+    Log,
+    /// User-defined key type with its tag, see `Database::register_key_type()`.
+    Custom(u8),
 }
 
 impl KeyType {
@@ -330,6 +489,9 @@ impl KeyType {
             3 => Some(KeyType::SortedList),
             4 => Some(KeyType::Set),
             5 => Some(KeyType::SortedSet),
+            6 => Some(KeyType::Value),
+            7 => Some(KeyType::Log),
+            c if c >= CUSTOM_KEY_TYPE_MIN => Some(KeyType::Custom(c)),
             _ => None,
         }
     }
@@ -341,6 +503,9 @@ impl KeyType {
             KeyType::SortedList => 3,
             KeyType::Set => 4,
             KeyType::SortedSet => 5,
+            KeyType::Value => 6,
+            KeyType::Log => 7,
+            KeyType::Custom(c) => *c,
         }
     }
 }
@@ -438,10 +603,33 @@ impl KeyMeta {
         left_deleted_count: u32,
         right_deleted_count: u32,
     ) {
-        let mut buf = BytesMut::with_capacity(12);
+        let compact_deletes_count = self.decode_sorted_list_compact_deletes_count();
+        let mut buf = BytesMut::with_capacity(20);
+        buf.put_u64(sequence);
+        buf.put_u32(left_deleted_count);
+        buf.put_u32(right_deleted_count);
+        buf.put_u32(compact_deletes_count);
+        self.extra = Some(buf.to_vec())
+    }
+
+    /// Decode the per-key override of `Options::sorted_list_compact_deletes_count` for
+    /// `sorted list` data type, 0 if it's not overridden.
+    pub fn decode_sorted_list_compact_deletes_count(&self) -> u32 {
+        match &self.extra {
+            Some(b) if b.len() >= 20 => (&b[16..]).get_u32(),
+            _ => 0,
+        }
+    }
+
+    /// Encode the per-key override of `Options::sorted_list_compact_deletes_count` for
+    /// `sorted list` data type, 0 to use the option.
+    pub fn encode_sorted_list_compact_deletes_count(&mut self, count: u32) {
+        let (sequence, left_deleted_count, right_deleted_count) = self.decode_sorted_list_extra();
+        let mut buf = BytesMut::with_capacity(20);
         buf.put_u64(sequence);
         buf.put_u32(left_deleted_count);
         buf.put_u32(right_deleted_count);
+        buf.put_u32(count);
         self.extra = Some(buf.to_vec())
     }
 
@@ -463,4 +651,42 @@ impl KeyMeta {
         buf.put_u8(score_len);
         self.extra = Some(buf.to_vec())
     }
+
+    /// Decode extra data for `value` data type.
+    /// returns (total_len[u64], chunk_size[u32])
+    pub fn decode_value_extra(&self) -> (u64, u32) {
+        if let Some(b) = &self.extra {
+            let mut buf = b.as_slice();
+            (buf.get_u64(), buf.get_u32())
+        } else {
+            (0, 0)
+        }
+    }
+
+    /// Encode extra data for `value` data type.
+    pub fn encode_value_extra(&mut self, total_len: u64, chunk_size: u32) {
+        let mut buf = BytesMut::with_capacity(12);
+        buf.put_u64(total_len);
+        buf.put_u32(chunk_size);
+        self.extra = Some(buf.to_vec())
+    }
+
+    /// Decode extra data for `log` data type.
+    /// returns (first_offset[u64], next_offset[u64])
+    pub fn decode_log_extra(&self) -> (u64, u64) {
+        if let Some(b) = &self.extra {
+            let mut buf = b.as_slice();
+            (buf.get_u64(), buf.get_u64())
+        } else {
+            (0, 0)
+        }
+    }
+
+    /// Encode extra data for `log` data type.
+    pub fn encode_log_extra(&mut self, first_offset: u64, next_offset: u64) {
+        let mut buf = BytesMut::with_capacity(16);
+        buf.put_u64(first_offset);
+        buf.put_u64(next_offset);
+        self.extra = Some(buf.to_vec())
+    }
 }
@@ -0,0 +1,123 @@
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+use rocksdb::LiveFile;
+
+use crate::database::{Database, Result};
+
+/// Receiver of the flushes and compactions run by the database, set by
+/// `Options::compaction_listener`, e.g. to correlate latency spikes with compaction activity.
+///
+/// The RocksDB C API has no event listeners, so the automatic background jobs of RocksDB are
+/// not reported, only the ones run by the database: `Database::flush()` and the manual
+/// compactions of `compact_all()`, `compact_on_open()` and the deletes.
+pub trait CompactionListener: Send + Sync {
+    /// Called before a flush or compaction, the output fields of `event` are not set yet.
+    fn on_compaction_start(&self, event: &CompactionEvent);
+
+    /// Called after a flush or compaction finished.
+    fn on_compaction_finish(&self, event: &CompactionEvent, duration: Duration);
+}
+
+/// Kind of a `CompactionEvent`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CompactionKind {
+    Flush,
+    Compaction,
+}
+
+/// Flush or compaction reported to a `CompactionListener`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompactionEvent {
+    pub kind: CompactionKind,
+    /// Deepest level of the input files, 0 for a flush.
+    pub input_level: i32,
+    /// Deepest level of the output files, 0 for a flush.
+    pub output_level: i32,
+    /// Size of the input table files in bytes, the memtable size for a flush.
+    pub input_bytes: u64,
+    /// Size of the output table files in bytes.
+    pub output_bytes: u64,
+}
+
+impl Database {
+    /// Flush the memtables into table files.
+    pub fn flush(&self) -> Result<()> {
+        let listener = match &self.options.compaction_listener {
+            None => return Ok(self.rocksdb.flush()?),
+            Some(listener) => listener,
+        };
+        let before = self.rocksdb.live_files()?;
+        let mut event = CompactionEvent {
+            kind: CompactionKind::Flush,
+            input_level: 0,
+            output_level: 0,
+            input_bytes: self
+                .rocksdb
+                .property_int_value("rocksdb.cur-size-all-mem-tables")?
+                .unwrap_or(0),
+            output_bytes: 0,
+        };
+        listener.on_compaction_start(&event);
+        let started = Instant::now();
+        self.rocksdb.flush()?;
+        let before: HashSet<_> = before.into_iter().map(|f| f.name).collect();
+        event.output_bytes = self
+            .rocksdb
+            .live_files()?
+            .iter()
+            .filter(|f| !before.contains(&f.name))
+            .map(|f| f.size as u64)
+            .sum();
+        listener.on_compaction_finish(&event, started.elapsed());
+        Ok(())
+    }
+
+    /// Compact the rows from `start` to `end`, reported to `Options::compaction_listener`.
+    pub(crate) fn compact_range(&self, start: Option<&[u8]>, end: Option<&[u8]>) {
+        let listener = match &self.options.compaction_listener {
+            None => return self.rocksdb.compact_range(start, end),
+            Some(listener) => listener,
+        };
+        // the sizes are best effort, the compaction runs even if the files can't be listed
+        let before = self.rocksdb.live_files().unwrap_or_default();
+        let inputs: Vec<&LiveFile> = before
+            .iter()
+            .filter(|f| file_overlaps(f, start, end))
+            .collect();
+        let mut event = CompactionEvent {
+            kind: CompactionKind::Compaction,
+            input_level: inputs.iter().map(|f| f.level).max().unwrap_or(0),
+            output_level: 0,
+            input_bytes: inputs.iter().map(|f| f.size as u64).sum(),
+            output_bytes: 0,
+        };
+        listener.on_compaction_start(&event);
+        let started = Instant::now();
+        self.rocksdb.compact_range(start, end);
+        // a trivially moved file keeps its name on the new level
+        let before: HashSet<_> = before.iter().map(|f| (f.name.as_str(), f.level)).collect();
+        let after = self.rocksdb.live_files().unwrap_or_default();
+        let outputs = after
+            .iter()
+            .filter(|f| !before.contains(&(f.name.as_str(), f.level)));
+        for file in outputs {
+            event.output_level = event.output_level.max(file.level);
+            event.output_bytes += file.size as u64;
+        }
+        listener.on_compaction_finish(&event, started.elapsed());
+    }
+}
+
+/// Check if a table file has rows from `start` (inclusive) to `end` (exclusive).
+fn file_overlaps(file: &LiveFile, start: Option<&[u8]>, end: Option<&[u8]>) -> bool {
+    let after_start = match (start, &file.end_key) {
+        (Some(start), Some(last)) => last.as_slice() >= start,
+        _ => true,
+    };
+    let before_end = match (end, &file.start_key) {
+        (Some(end), Some(first)) => first.as_slice() < end,
+        _ => true,
+    };
+    after_start && before_end
+}
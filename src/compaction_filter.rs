@@ -0,0 +1,116 @@
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+
+use bytes::{Buf, BufMut, BytesMut};
+use rocksdb::{CompactionDecision, Options as RocksDBOptions, WriteBatch};
+
+use crate::codec::*;
+use crate::database::{now_millis, Database};
+
+/// Key prefix for the IDs of the keys deleted by `Options::lazy_delete`, followed by the key ID.
+/// The data rows of the IDs are dropped by the compactions.
+pub static PREFIX_DROPPED_KEY_ID: &[u8] = b"x";
+
+/// Key prefix for the IDs of the `sorted set` keys with members added by
+/// `Database::sorted_set_add_ex()`, followed by the key ID. The expired members of the IDs are
+/// dropped by the compactions.
+pub static PREFIX_EXPIRING_KEY_ID: &[u8] = b"y";
+
+/// IDs of the deleted keys whose data rows are dropped by the compaction filter.
+pub(crate) type DroppedKeyIds = Arc<RwLock<BTreeSet<u64>>>;
+
+/// IDs of the `sorted set` keys whose expired members are dropped by the compaction filter.
+pub(crate) type ExpiringKeyIds = Arc<RwLock<BTreeSet<u64>>>;
+
+/// Encode key of a deleted key ID.
+pub fn encode_dropped_key_id_key(key_id: u64) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(PREFIX_DROPPED_KEY_ID.len() + 8);
+    buf.put_slice(PREFIX_DROPPED_KEY_ID);
+    buf.put_u64(key_id);
+    buf
+}
+
+/// Encode key of a `sorted set` key ID with expiring members.
+pub fn encode_expiring_key_id_key(key_id: u64) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(PREFIX_EXPIRING_KEY_ID.len() + 8);
+    buf.put_slice(PREFIX_EXPIRING_KEY_ID);
+    buf.put_u64(key_id);
+    buf
+}
+
+/// Install the compaction filter dropping the data rows of `dropped`, and the rows ordered by
+/// the score of the expired members of `expiring`. The rows without score of the expired
+/// members are left, they and the counts are fixed by `Database::sorted_set_purge_expired()`.
+pub(crate) fn set_compaction_filter(
+    opts: &mut RocksDBOptions,
+    dropped: DroppedKeyIds,
+    expiring: ExpiringKeyIds,
+) {
+    opts.set_compaction_filter(
+        "simpledb_dropped_keys",
+        move |_, key: &[u8], value: &[u8]| {
+            if !has_prefix(PREFIX_DATA, key) || key.len() < PREFIX_DATA.len() + 8 {
+                return CompactionDecision::Keep;
+            }
+            let key_id = (&key[PREFIX_DATA.len()..]).get_u64();
+            if dropped.read().unwrap().contains(&key_id) {
+                return CompactionDecision::Remove;
+            }
+            // the value of the row ordered by the score is the expire time of the member
+            let with_score = key.get(PREFIX_DATA.len() + 8) == Some(&1);
+            if with_score
+                && value.len() == 8
+                && (&value[..]).get_u64() <= now_millis()
+                && expiring.read().unwrap().contains(&key_id)
+            {
+                return CompactionDecision::Remove;
+            }
+            CompactionDecision::Keep
+        },
+    );
+}
+
+impl Database {
+    /// Load the IDs of the deleted keys whose data rows may be not compacted yet, e.g. the
+    /// process stopped before the compaction of `delete_all()`.
+    pub(crate) fn load_dropped_key_ids(&self) {
+        let mut dropped = self.dropped_key_ids.write().unwrap();
+        dropped.clear();
+        self.prefix_iterator(PREFIX_DROPPED_KEY_ID, |k, _| {
+            if k.len() == PREFIX_DROPPED_KEY_ID.len() + 8 {
+                dropped.insert((&k[PREFIX_DROPPED_KEY_ID.len()..]).get_u64());
+            }
+            true
+        });
+    }
+
+    /// Load the IDs of the `sorted set` keys with expiring members.
+    pub(crate) fn load_expiring_key_ids(&self) {
+        let mut expiring = self.expiring_key_ids.write().unwrap();
+        expiring.clear();
+        self.prefix_iterator(PREFIX_EXPIRING_KEY_ID, |k, _| {
+            if k.len() == PREFIX_EXPIRING_KEY_ID.len() + 8 {
+                expiring.insert((&k[PREFIX_EXPIRING_KEY_ID.len()..]).get_u64());
+            }
+            true
+        });
+    }
+
+    /// Record the ID of a `sorted set` key with expiring members in the batch, returns `true`
+    /// if it's not recorded yet, then it's added to `expiring_key_ids` after the batch is
+    /// written. It's only recorded with `Options::lazy_delete`, which installs the filter.
+    pub(crate) fn record_expiring_key_id_to_batch(&self, batch: &mut WriteBatch, id: u64) -> bool {
+        if !self.options.lazy_delete || self.expiring_key_ids.read().unwrap().contains(&id) {
+            return false;
+        }
+        batch.put(encode_expiring_key_id_key(id), FILL_EMPTY_DATA);
+        true
+    }
+
+    /// Delete the record of a `sorted set` key with expiring members.
+    pub(crate) fn delete_expiring_key_id_to_batch(&self, batch: &mut WriteBatch, meta: &KeyMeta) {
+        if meta.key_type == KeyType::SortedSet {
+            batch.delete(encode_expiring_key_id_key(meta.id));
+        }
+    }
+}
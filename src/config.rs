@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result, SaveMetaPolicy};
+use crate::retention::SortedListRetention;
+
+/// Key prefix for the configurations of keys, followed by the key name.
+pub static PREFIX_KEY_CONFIG: &[u8] = b"c";
+
+/// Format version of an encoded `KeyConfig`.
+const KEY_CONFIG_VERSION: u8 = 1;
+
+/// Configuration of a single key, persisted under `PREFIX_KEY_CONFIG` and kept when the key is
+/// deleted, see `Database::set_key_config()`. `None` falls back to the patterns registered on
+/// the database and the `Options`.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct KeyConfig {
+    /// Retention policy of a `sorted list`, overrides `Database::sorted_list_set_retention()`.
+    pub retention: Option<SortedListRetention>,
+    /// Max items count, the writes adding an item to a full key fail.
+    pub max_count: Option<u64>,
+    /// Run RocksDB `compact` operation every specific deletes count of a `sorted list` or a
+    /// `sorted set`, overrides `Options::sorted_list_compact_deletes_count`.
+    pub compact_deletes_count: Option<u32>,
+    /// Whether the empty meta is deleted, overrides `Database::set_save_meta_policy()`.
+    pub save_meta_policy: Option<SaveMetaPolicy>,
+}
+
+/// Encode key of the configuration of a key.
+pub fn encode_key_config_key(key: impl AsRef<[u8]>) -> BytesMut {
+    let key = key.as_ref();
+    let mut buf = BytesMut::with_capacity(PREFIX_KEY_CONFIG.len() + key.len());
+    buf.put_slice(PREFIX_KEY_CONFIG);
+    buf.put_slice(key);
+    buf
+}
+
+impl KeyConfig {
+    /// Get bytes.
+    pub fn get_bytes(&self) -> BytesMut {
+        let mut buf = BytesMut::with_capacity(24);
+        buf.put_u8(KEY_CONFIG_VERSION);
+        match self.retention {
+            None => buf.put_slice(&[0; 9]),
+            Some(SortedListRetention::MaxAge(window)) => {
+                buf.put_u8(1);
+                buf.put_u64(window.as_millis() as u64);
+            }
+            Some(SortedListRetention::MaxCount(n)) => {
+                buf.put_u8(2);
+                buf.put_u64(n);
+            }
+        }
+        match self.max_count {
+            None => buf.put_slice(&[0; 9]),
+            Some(n) => {
+                buf.put_u8(1);
+                buf.put_u64(n);
+            }
+        }
+        // 0 is not a valid count, it means unset
+        buf.put_u32(self.compact_deletes_count.unwrap_or(0));
+        buf.put_u8(match self.save_meta_policy {
+            None => 0,
+            Some(SaveMetaPolicy::DeleteWhenEmpty) => 1,
+            Some(SaveMetaPolicy::KeepWhenEmpty) => 2,
+        });
+        buf
+    }
+
+    /// Decode `KeyConfig` from bytes, `None` if it's invalid.
+    pub fn from_bytes(input: &[u8]) -> Option<KeyConfig> {
+        if input.len() != 24 || input[0] != KEY_CONFIG_VERSION {
+            return None;
+        }
+        let mut buf = &input[1..];
+        let retention = match (buf.get_u8(), buf.get_u64()) {
+            (0, _) => None,
+            (1, millis) => Some(SortedListRetention::MaxAge(Duration::from_millis(millis))),
+            (2, n) => Some(SortedListRetention::MaxCount(n)),
+            _ => return None,
+        };
+        let max_count = match (buf.get_u8(), buf.get_u64()) {
+            (0, _) => None,
+            (1, n) => Some(n),
+            _ => return None,
+        };
+        let compact_deletes_count = match buf.get_u32() {
+            0 => None,
+            n => Some(n),
+        };
+        let save_meta_policy = match buf.get_u8() {
+            0 => None,
+            1 => Some(SaveMetaPolicy::DeleteWhenEmpty),
+            2 => Some(SaveMetaPolicy::KeepWhenEmpty),
+            _ => return None,
+        };
+        Some(KeyConfig {
+            retention,
+            max_count,
+            compact_deletes_count,
+            save_meta_policy,
+        })
+    }
+}
+
+impl Database {
+    /// Set the configuration of a key, it's consulted by the operations on the key and kept
+    /// across restarts, even if the key is deleted. Fails if `compact_deletes_count` is 0.
+    pub fn set_key_config(&self, key: &str, config: KeyConfig) -> Result<()> {
+        if config.compact_deletes_count == Some(0) {
+            return Err(Error::Message(
+                "compact_deletes_count must not be 0".to_string(),
+            ));
+        }
+        let key = self.resolve_key(key.as_bytes()).into_owned();
+        self.check_writable(&key)?;
+        let mut configs = self.key_configs.write().unwrap();
+        self.rocksdb
+            .put(encode_key_config_key(&key), config.get_bytes())?;
+        configs.insert(key, config);
+        Ok(())
+    }
+
+    /// Remove the configuration of a key, returns `false` if it's not set.
+    pub fn remove_key_config(&self, key: &str) -> Result<bool> {
+        let key = self.resolve_key(key.as_bytes()).into_owned();
+        self.check_writable(&key)?;
+        let mut configs = self.key_configs.write().unwrap();
+        if !configs.contains_key(&key) {
+            return Ok(false);
+        }
+        self.rocksdb.delete(encode_key_config_key(&key))?;
+        configs.remove(&key);
+        Ok(true)
+    }
+
+    /// Get the configuration of a key, `None` if it's not set.
+    pub fn key_config(&self, key: impl AsRef<[u8]>) -> Option<KeyConfig> {
+        let key = self.resolve_key(key.as_ref());
+        self.key_configs.read().unwrap().get(key.as_ref()).copied()
+    }
+
+    /// Fail if a key is full by the `max_count` of its configuration, call it before adding an
+    /// item.
+    pub(crate) fn check_max_count(&self, key: impl AsRef<[u8]>, meta: &KeyMeta) -> Result<()> {
+        let key = key.as_ref();
+        match self.key_config(key).and_then(|c| c.max_count) {
+            Some(max_count) if meta.count >= max_count => Err(Error::Message(format!(
+                "key {} is full, max count is {}",
+                String::from_utf8_lossy(key),
+                max_count
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn load_key_configs(&self) {
+        let mut configs = self.key_configs.write().unwrap();
+        configs.clear();
+        let iter = self.rocksdb.prefix_iterator(PREFIX_KEY_CONFIG);
+        for (k, v) in iter {
+            if !has_prefix(PREFIX_KEY_CONFIG, &k) {
+                break;
+            }
+            if let Some(config) = KeyConfig::from_bytes(&v) {
+                configs.insert(k[PREFIX_KEY_CONFIG.len()..].to_vec(), config);
+            }
+        }
+    }
+}
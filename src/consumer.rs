@@ -0,0 +1,95 @@
+use bytes::{Buf, BufMut, BytesMut};
+use rocksdb::{Direction, IteratorMode, WriteBatch};
+
+use crate::codec::*;
+use crate::database::{Database, Result};
+
+/// Key prefix for the read offsets of the consumer groups of `list` keys, followed by the key
+/// ID and the group name, the value is the position of the next item to read.
+pub static PREFIX_CONSUMER: &[u8] = b"g";
+
+/// Encode key of the read offset of a consumer group.
+pub fn encode_consumer_key(key_id: u64, group: &str) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(PREFIX_CONSUMER.len() + 8 + group.len());
+    buf.put_slice(PREFIX_CONSUMER);
+    buf.put_u64(key_id);
+    buf.put_slice(group.as_bytes());
+    buf
+}
+
+/// Encode key prefix of the read offsets of all the consumer groups of a key ID.
+pub fn encode_consumer_key_prefix(key_id: u64) -> BytesMut {
+    encode_consumer_key(key_id, "")
+}
+
+impl Database {
+    /// Read at most `count` items of a list for a consumer group without removing them, the
+    /// group continues from the item after the last one it read, so independent groups can
+    /// tail the same list like a log, e.g. fed by `list_right_push()`.
+    ///
+    /// A new group starts from the first item, and a group skips the items already popped.
+    /// The offsets are positions, so the items moved by `list_rotate()` and `list_dedupe()` may
+    /// be skipped or read again, `list_reindex()` moves the offsets with the items.
+    pub fn list_consumer_read(
+        &self,
+        key: impl AsRef<[u8]>,
+        group: &str,
+        count: usize,
+    ) -> Result<Vec<Box<[u8]>>> {
+        // the offset is not written while `list_reindex()` moves it
+        let _guard = self.list_lock.read().unwrap();
+        let meta = match self.get_meta(key)? {
+            None => return Ok(Vec::new()),
+            Some(meta) => meta,
+        };
+        let (left, right) = meta.decode_list_extra();
+        let consumer_key = encode_consumer_key(meta.id, group);
+        let next = match self.rocksdb.get_pinned(&consumer_key)? {
+            Some(v) if v.len() == 8 => v.as_ref().get_i64(),
+            _ => left + 1,
+        }
+        .max(left + 1);
+        if count == 0 || next >= right {
+            return Ok(Vec::new());
+        }
+        let first = encode_data_key_list_item(meta.id, next);
+        let last = encode_data_key_list_item(meta.id, right);
+        let iter = self
+            .rocksdb
+            .iterator(IteratorMode::From(&first, Direction::Forward));
+        let mut vec = Vec::with_capacity(count.min((right - next) as usize));
+        let mut next = next;
+        for (k, v) in iter.take(count) {
+            if k.as_ref() >= last.as_ref() {
+                break;
+            }
+            next = (&k[k.len() - 8..]).get_i64() + 1;
+            vec.push(v);
+        }
+        self.rocksdb.put(consumer_key, next.to_be_bytes())?;
+        Ok(vec)
+    }
+
+    /// Delete the read offset of a consumer group, it reads from the first item next time.
+    /// Returns `false` if the key does not exist.
+    pub fn list_consumer_reset(&self, key: impl AsRef<[u8]>, group: &str) -> Result<bool> {
+        match self.get_meta(key)? {
+            None => Ok(false),
+            Some(meta) => {
+                self.rocksdb.delete(encode_consumer_key(meta.id, group))?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Delete the read offsets of all the consumer groups of a `list` key.
+    pub(crate) fn delete_consumers_to_batch(&self, batch: &mut WriteBatch, meta: &KeyMeta) {
+        if meta.key_type == KeyType::List {
+            self.delete_range_to_batch(
+                batch,
+                encode_consumer_key_prefix(meta.id),
+                encode_consumer_key_prefix(meta.id + 1),
+            );
+        }
+    }
+}
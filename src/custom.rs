@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use rocksdb::WriteBatch;
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+
+/// Hooks of a user-defined key type, register it with `Database::register_key_type()`.
+///
+/// The items of a custom key are stored as data rows of its key ID like the built-in types, so
+/// they are listed by `keys()` and `keys_of_type()`, and deleted by `delete_all()`.
+pub trait CustomKeyType: Send + Sync {
+    /// Name of the key type, see `Database::key_type_name()`.
+    fn name(&self) -> &str;
+
+    /// Encode an item into the suffix of its data key and the row value, items with the same
+    /// suffix replace each other.
+    fn encode_item(&self, item: &[u8]) -> (Vec<u8>, Vec<u8>);
+
+    /// Decode an item from the suffix of its data key and the row value.
+    fn decode_item(&self, suffix: &[u8], value: &[u8]) -> Vec<u8>;
+
+    /// Called by `delete_all()` before the rows of a key are deleted.
+    fn on_delete(&self, _key: &str, _meta: &KeyMeta) {}
+}
+
+impl Database {
+    /// Register a user-defined key type with a tag from `CUSTOM_KEY_TYPE_MIN` to 255.
+    pub fn register_key_type(&self, tag: u8, key_type: Arc<dyn CustomKeyType>) -> Result<()> {
+        if tag < CUSTOM_KEY_TYPE_MIN {
+            return Err(Error::Message(format!(
+                "tag {} of custom key type is reserved, expects {} to 255",
+                tag, CUSTOM_KEY_TYPE_MIN
+            )));
+        }
+        let mut types = self.custom_key_types.write().unwrap();
+        if types.iter().any(|(t, _)| *t == tag) {
+            return Err(Error::Message(format!(
+                "tag {} of custom key type is already registered",
+                tag
+            )));
+        }
+        types.push((tag, key_type));
+        Ok(())
+    }
+
+    /// Get the name of a key type, the registered name for the custom key types.
+    pub fn key_type_name(&self, key_type: KeyType) -> String {
+        match key_type {
+            KeyType::Custom(tag) => match self.custom_key_type(tag) {
+                Ok(t) => t.name().to_string(),
+                Err(_) => format!("Custom({})", tag),
+            },
+            t => format!("{:?}", t),
+        }
+    }
+
+    /// Put an item into a key of a custom key type. Returns `true` if it's a new item.
+    pub fn custom_put(&self, key: &str, tag: u8, item: &[u8]) -> Result<bool> {
+        self.check_writable(key)?;
+        let key_type = self.custom_key_type(tag)?;
+        let mut meta = self.get_or_create_meta(key, KeyType::Custom(tag))?;
+        self.check_custom_meta(key, &meta, tag)?;
+        let (suffix, value) = key_type.encode_item(item);
+        let full_key = encode_data_key_custom_item(meta.id, &suffix);
+        let is_new = self.rocksdb.get_pinned(&full_key)?.is_none();
+        let mut batch = WriteBatch::default();
+        batch.put(&full_key, value);
+        if is_new {
+            meta.count += 1;
+            self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+        }
+        self.rocksdb.write(batch)?;
+        Ok(is_new)
+    }
+
+    /// Delete an item from a key of a custom key type. Returns `false` if it does not exist.
+    pub fn custom_delete(&self, key: &str, tag: u8, item: &[u8]) -> Result<bool> {
+        self.check_writable(key)?;
+        let key_type = self.custom_key_type(tag)?;
+        let mut meta = match self.get_meta(key)? {
+            None => return Ok(false),
+            Some(meta) => meta,
+        };
+        self.check_custom_meta(key, &meta, tag)?;
+        let (suffix, _) = key_type.encode_item(item);
+        let full_key = encode_data_key_custom_item(meta.id, &suffix);
+        if self.rocksdb.get_pinned(&full_key)?.is_none() {
+            return Ok(false);
+        }
+        let mut batch = WriteBatch::default();
+        batch.delete(&full_key);
+        meta.count -= 1;
+        self.save_meta_to_batch(&mut batch, key, &meta, true)?;
+        self.rocksdb.write(batch)?;
+        Ok(true)
+    }
+
+    /// Get all the decoded items of a key of a custom key type.
+    pub fn custom_items(&self, key: &str) -> Result<Vec<Vec<u8>>> {
+        let meta = match self.get_meta(key)? {
+            None => return Ok(Vec::new()),
+            Some(meta) => meta,
+        };
+        let tag = match meta.key_type {
+            KeyType::Custom(tag) => tag,
+            t => {
+                return Err(Error::Message(format!(
+                    "key {} is {:?}, expects a custom key type",
+                    key, t
+                )))
+            }
+        };
+        let key_type = self.custom_key_type(tag)?;
+        let mut vec = Vec::with_capacity(meta.count as usize);
+        self.for_each_data(key, None, |k, v| {
+            vec.push(key_type.decode_item(&k[9..], &v));
+            true
+        })?;
+        Ok(vec)
+    }
+
+    pub(crate) fn custom_key_type(&self, tag: u8) -> Result<Arc<dyn CustomKeyType>> {
+        self.custom_key_types
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, key_type)| key_type.clone())
+            .ok_or_else(|| Error::Message(format!("custom key type {} is not registered", tag)))
+    }
+
+    fn check_custom_meta(&self, key: &str, meta: &KeyMeta, tag: u8) -> Result<()> {
+        if meta.key_type != KeyType::Custom(tag) {
+            return Err(Error::Message(format!(
+                "key {} is {:?}, expects {}",
+                key,
+                meta.key_type,
+                self.key_type_name(KeyType::Custom(tag))
+            )));
+        }
+        Ok(())
+    }
+}
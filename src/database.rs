@@ -1,20 +1,114 @@
-use std::{cell::Cell, fmt::Formatter, path::Path, string::FromUtf8Error};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Formatter,
+    io,
+    path::{Path, PathBuf},
+    string::FromUtf8Error,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, RwLock, RwLockReadGuard,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 use rocksdb::{
-    Direction, Error as RocksDBError, IteratorMode, Options as RocksDBOptions, ReadOptions, DB,
+    perf::get_memory_usage_stats, properties, BlockBasedOptions, Cache, DBPinnableSlice, Direction,
+    Error as RocksDBError, IteratorMode, Options as RocksDBOptions, ReadOptions, WriteBatch, DB,
 };
 
+use crate::background::{BackgroundTasks, BACKGROUND_SHUTDOWN_TIMEOUT};
 use crate::codec::*;
+use crate::compaction::CompactionListener;
+use crate::compaction_filter::{
+    encode_dropped_key_id_key, set_compaction_filter, DroppedKeyIds, ExpiringKeyIds,
+};
+use crate::config::KeyConfig;
+use crate::consumer::{encode_consumer_key, encode_consumer_key_prefix};
+use crate::custom::CustomKeyType;
+use crate::expire::ExpiredCallback;
+use crate::freeze::Freeze;
+use crate::handle::{KVBytes, HANDLE_ITER_PAGE_SIZE};
+use crate::journal::encode_journal_key;
+use crate::metrics::MetricsRecorder;
+use crate::namespace::namespace_merge;
+use crate::retention::{SortedListRetention, SORTED_LIST_TRIM_STEP};
+use crate::schema::{key_matches_pattern, Schema};
+use crate::tier::Tier;
+use crate::verify::{BadMeta, BadRowCallback, BadRowPolicy};
 
 /// Database instance.
 pub struct Database {
     pub path: String,
     pub rocksdb: DB,
     pub options: Options,
-    next_key_id: Cell<u64>,
+    next_key_id: AtomicU64,
+    /// Turn of the round-robin of `sorted_list_pop_any()`.
+    pop_any_turn: AtomicU64,
+    /// Held while allocating a key ID, so the saves of `KEY_NEXT_KEY_ID` are in order.
+    key_id_lock: Mutex<()>,
+    /// Held while creating a meta, so the threads creating the same key get the same key ID.
+    pub(crate) create_meta_lock: Mutex<()>,
+    /// IDs of the open `BlobWriter`s, their chunks are written before the meta.
+    pub(crate) writing_key_ids: Mutex<HashSet<u64>>,
+    /// Held by `map_swap()`, so the swaps of a field do not interleave.
+    map_swap_lock: Mutex<()>,
+    /// Held while the references counts of the blobs are read and written, see `blob_ref()`.
+    pub(crate) blob_lock: Mutex<()>,
+    /// Held while the sketches of the `set` keys are read and written, see `set_register_sketch()`.
+    pub(crate) sketch_lock: Mutex<()>,
+    /// The counters of the namespaces are kept, see `namespace_stats()`.
+    pub(crate) namespace_counters: AtomicBool,
+    /// The feature `chunked_values` is recorded in the manifest, so the empty `map` values are
+    /// checked for chunks, see `Options::large_value_threshold`.
+    pub(crate) chunked_values: AtomicBool,
+    /// Held by `idempotency_check_and_set()`, so a request ID is set only once.
+    idempotency_lock: Mutex<()>,
+    /// Held by `dedupe_window()`, so an id is seen as new only once.
+    dedupe_lock: Mutex<()>,
+    /// Held for reading by the list writes from reading the meta to writing it, and for writing
+    /// by `list_reindex()`, so no item is written while the items are moved.
+    pub(crate) list_lock: RwLock<()>,
+    /// Held by the pops of the sorted lists from peeking the item to deleting it.
+    sorted_list_pop_lock: Mutex<()>,
+    /// Held by the writes of the logs, so two appends never get the same offset.
+    pub(crate) log_lock: Mutex<()>,
+    /// Loads of `cache_get_or_insert_with()` in flight.
+    cache_loads: Mutex<CacheLoads>,
+    /// The sequence of the next entry of the admin log.
+    pub(crate) admin_log_seq: AtomicU64,
+    pub(crate) schemas: RwLock<Vec<(String, Schema)>>,
+    pub(crate) retentions: RwLock<Vec<(String, SortedListRetention)>>,
+    save_meta_policies: RwLock<Vec<(String, SaveMetaPolicy)>>,
+    /// Configurations loaded from the `PREFIX_KEY_CONFIG` rows, key name to configuration.
+    pub(crate) key_configs: RwLock<HashMap<Vec<u8>, KeyConfig>>,
+    pub(crate) expired_callbacks: RwLock<Vec<ExpiredCallback>>,
+    pub(crate) bad_row_callbacks: RwLock<Vec<BadRowCallback>>,
+    pub(crate) custom_key_types: RwLock<Vec<(u8, Arc<dyn CustomKeyType>)>>,
+    /// Aliases loaded from the `PREFIX_ALIAS` rows, alias name to target key name.
+    pub(crate) aliases: RwLock<HashMap<Vec<u8>, Vec<u8>>>,
+    /// Names of the sealed keys, see `seal_key()`.
+    pub(crate) sealed_keys: RwLock<HashSet<Vec<u8>>>,
+    /// Keys frozen by `freeze_key()`, they are checked by the other threads.
+    pub(crate) frozen_keys: RwLock<HashMap<Vec<u8>, Freeze>>,
+    /// IDs of the keys deleted by `Options::lazy_delete` with data rows not compacted yet.
+    pub(crate) dropped_key_ids: DroppedKeyIds,
+    /// IDs of the `sorted set` keys whose expired members are dropped by the compactions.
+    pub(crate) expiring_key_ids: ExpiringKeyIds,
+    /// Names of the keys known to be absent, see `Options::meta_negative_cache_size`.
+    absent_keys: RwLock<HashSet<Vec<u8>>>,
+    /// Increased when a meta is saved, a name read as absent is cached only if no meta is
+    /// saved meanwhile.
+    meta_generation: AtomicU64,
+    background: BackgroundTasks,
+    verify_report: Vec<BadMeta>,
 }
 
+/// Key and field of the loads of `cache_get_or_insert_with()` to the locks held by the threads
+/// running the loaders.
+type CacheLoads = HashMap<(Vec<u8>, Vec<u8>), Arc<Mutex<()>>>;
+
 unsafe impl Send for Database {}
 
 unsafe impl Sync for Database {}
@@ -27,7 +121,82 @@ pub struct Options {
     /// This is a performance optimization strategy.
     pub sorted_list_compact_deletes_count: u32,
     /// Auto delete the key meta when items count is 0, the key ID will be different for the next time when reuse the same key.
+    /// It can be overridden for some keys by `Database::set_save_meta_policy()`.
     pub delete_meta_when_empty: bool,
+    /// Maximum number of concurrent background flush and compaction jobs.
+    pub max_background_jobs: Option<i32>,
+    /// Size of a single memtable in bytes, writes stall when all memtables are full.
+    pub write_buffer_size: Option<usize>,
+    /// Maximum number of memtables, both active and immutable.
+    pub max_write_buffer_number: Option<i32>,
+    /// Total memtable size limit across the whole database in bytes.
+    pub db_write_buffer_size: Option<usize>,
+    /// Limit the write rate of flushes and compactions in bytes per second.
+    pub rate_limit_bytes_per_sec: Option<i64>,
+    /// Capacity of the LRU block cache in bytes, it replaces the table factory of
+    /// `rocksdb_options`.
+    pub block_cache_size: Option<usize>,
+    /// For `value` data type, values larger than this size are split into multiple rows. It's
+    /// also the size of the chunks of the large `map` values.
+    pub value_chunk_size: u32,
+    /// For `map` data type, values larger than this size are split into chunks of
+    /// `value_chunk_size` bytes transparently, so the huge values don't bloat the rows of
+    /// RocksDB and the block cache. `None` to store every value in a single row. Enabling it
+    /// records the feature `chunked_values` in the manifest.
+    pub large_value_threshold: Option<u32>,
+    /// For `list` data type, run `list_reindex()` automatically when a push reaches the bounds of
+    /// the position space, otherwise the push returns `Error::ListOverflow`.
+    pub list_auto_reindex: bool,
+    /// Check every meta row when opening the database, see `Database::verify_metas()`.
+    /// The bad rows are reported by `Database::verify_report()`.
+    pub verify_on_open: bool,
+    /// Move the bad meta rows found by `verify_on_open` under `PREFIX_QUARANTINE`.
+    pub quarantine_on_verify: bool,
+    /// What the scans of keys, e.g. `for_each_key()` and `export_range()`, do with a bad meta
+    /// row, and `export_range()` and `dump_key()` with a bad data row, so a single corrupted
+    /// row doesn't have to abort the whole scan.
+    pub bad_row_policy: BadRowPolicy,
+    /// Max retries of an operation failed with a transient error, see `Error::is_transient()`.
+    /// The operations committing several writes, e.g. `delete_all()`, are not retried.
+    pub retry_max_retries: u32,
+    /// Sleep before the first retry, doubled for every next retry.
+    pub retry_backoff: Duration,
+    /// Receive the duration and result of the write and pop operations.
+    pub metrics_recorder: Option<Arc<dyn MetricsRecorder>>,
+    /// Compact the database right after it's opened, see `Database::compact_on_open()`.
+    pub compact_on_open: CompactOnOpen,
+    /// Remote tier consulted on `map_get()` misses and by `Database::evict()`.
+    pub tier: Option<Arc<dyn Tier>>,
+    /// Reject key names which are not valid UTF-8 when creating keys, the binary names break
+    /// the string-based key scans, e.g. `for_each_key()`. Use `for_each_key_bytes()` to scan
+    /// the binary names.
+    pub strict_utf8_keys: bool,
+    /// Receive the flushes and compactions run by the database, see `CompactionListener`.
+    pub compaction_listener: Option<Arc<dyn CompactionListener>>,
+    /// Remember up to this many absent key names, so probing missing keys, e.g. `set_is_member()`
+    /// on absent keys, skips the meta lookup in RocksDB. The cache is cleared when it's full,
+    /// and a name is removed when its meta is saved. 0 to disable. The meta rows written to
+    /// `Database::rocksdb` directly are not seen by the cache.
+    pub meta_negative_cache_size: usize,
+    /// Fail the write operations with `Error::Backpressure` while RocksDB stops the writes,
+    /// instead of blocking inside them, see `Database::wait_for_write_capacity()`.
+    pub backpressure_on_write_stall: bool,
+    /// Keep the RocksDB WAL files for this long, so `Database::replicate_to()` can read the
+    /// write batches after they are flushed. The rows of the deleted keys are deleted one by
+    /// one instead of by range deletions when it's set.
+    pub replication_wal_ttl: Option<Duration>,
+    /// Read ahead this many bytes in the long sequential scans, e.g. `for_each_data()`,
+    /// `export_range()`, `diff_against()` and the iterators of `DatabaseHandle`, so the scans
+    /// on spinning disks and network filesystems are not dominated by small reads. `None` uses
+    /// the RocksDB default.
+    pub scan_readahead_size: Option<usize>,
+    /// Items read by a single seek of the iterators of `DatabaseHandle`.
+    pub iter_page_size: usize,
+    /// Delete the data rows of a key in `delete_all()` by a compaction filter instead of
+    /// writing a tombstone for every row, the rows are dropped by the compaction run right
+    /// after the meta is deleted, or by the later compactions if the process stops. The
+    /// compactions drop the expired members of `Database::sorted_set_add_ex()` too.
+    pub lazy_delete: bool,
 }
 
 impl Default for Options {
@@ -38,17 +207,217 @@ impl Default for Options {
             rocksdb_options,
             sorted_list_compact_deletes_count: 300,
             delete_meta_when_empty: true,
+            max_background_jobs: None,
+            write_buffer_size: None,
+            max_write_buffer_number: None,
+            db_write_buffer_size: None,
+            rate_limit_bytes_per_sec: None,
+            block_cache_size: None,
+            value_chunk_size: 1 << 20,
+            large_value_threshold: None,
+            list_auto_reindex: false,
+            verify_on_open: false,
+            quarantine_on_verify: false,
+            bad_row_policy: BadRowPolicy::Error,
+            retry_max_retries: 0,
+            retry_backoff: Duration::from_millis(10),
+            metrics_recorder: None,
+            compact_on_open: CompactOnOpen::Disabled,
+            tier: None,
+            strict_utf8_keys: false,
+            compaction_listener: None,
+            meta_negative_cache_size: 0,
+            backpressure_on_write_stall: false,
+            replication_wal_ttl: None,
+            scan_readahead_size: None,
+            iter_page_size: HANDLE_ITER_PAGE_SIZE,
+            lazy_delete: false,
+        }
+    }
+}
+
+/// When to compact the database after it's opened, see `Options::compact_on_open`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CompactOnOpen {
+    Disabled,
+    /// Always run a full compaction.
+    Full,
+    /// Run a full compaction only when the tombstone ratio of the table files reaches the
+    /// value, see `Database::tombstone_ratio()`.
+    TombstoneRatio(f64),
+}
+
+/// Which value is kept when a field exists in both maps, see `Database::map_merge()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OnConflict {
+    /// Keep the value of the destination map.
+    KeepDst,
+    /// Overwrite with the value of the source map.
+    Overwrite,
+}
+
+/// Whether the meta of a key is deleted when its items count drops to 0, overrides
+/// `Options::delete_meta_when_empty` for the keys, see `Database::set_save_meta_policy()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SaveMetaPolicy {
+    /// Delete the meta, the key gets a new key ID when it's reused.
+    DeleteWhenEmpty,
+    /// Keep the meta, so the key ID is stable, e.g. for queues.
+    KeepWhenEmpty,
+}
+
+/// Tuning presets for common workloads, see `Options::with_preset()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Preset {
+    /// Large block cache with bloom filters, for lookup-dominated workloads.
+    ReadHeavy,
+    /// Large memtables and more background jobs, for sustained write throughput.
+    WriteHeavy,
+    /// Disable auto compactions and write stalls, for loading a large dataset once.
+    /// Run a full compaction after the import is finished.
+    BulkLoad,
+    /// Mmap reads without the block cache, for cold read-mostly archives and large sequential
+    /// exports, the OS page cache caches the files instead.
+    Archive,
+}
+
+impl Options {
+    /// Create options tuned for a specific workload, fails if the block cache of
+    /// `Preset::ReadHeavy` can't be created.
+    pub fn with_preset(preset: Preset) -> Result<Options> {
+        let mut options = Options::default();
+        match preset {
+            Preset::ReadHeavy => {
+                // set on `rocksdb_options` instead of `block_cache_size`, so a table factory set
+                // by the user afterwards is kept
+                options
+                    .rocksdb_options
+                    .set_block_based_table_factory(&block_cache_table(256 << 20)?);
+            }
+            Preset::WriteHeavy => {
+                options.max_background_jobs = Some(8);
+                options.write_buffer_size = Some(128 << 20);
+                options.max_write_buffer_number = Some(4);
+                options.db_write_buffer_size = Some(512 << 20);
+            }
+            Preset::BulkLoad => {
+                options.rocksdb_options.prepare_for_bulk_load();
+                options.max_background_jobs = Some(8);
+                options.write_buffer_size = Some(256 << 20);
+                options.max_write_buffer_number = Some(6);
+            }
+            Preset::Archive => return Ok(Options::archive()),
+        }
+        Ok(options)
+    }
+
+    /// Same as `Options::with_preset(Preset::Archive)`.
+    pub fn archive() -> Options {
+        let mut options = Options::default();
+        let opts = &mut options.rocksdb_options;
+        opts.set_allow_mmap_reads(true);
+        opts.set_advise_random_on_open(false);
+        let mut table = BlockBasedOptions::default();
+        table.disable_cache();
+        opts.set_block_based_table_factory(&table);
+        options.max_background_jobs = Some(1);
+        options
+    }
+
+    /// Apply the typed tuning fields onto `rocksdb_options`.
+    fn apply_tuning(&mut self) -> Result<()> {
+        let opts = &mut self.rocksdb_options;
+        if let Some(n) = self.max_background_jobs {
+            opts.set_max_background_jobs(n);
+        }
+        if let Some(n) = self.write_buffer_size {
+            opts.set_write_buffer_size(n);
         }
+        if let Some(n) = self.max_write_buffer_number {
+            opts.set_max_write_buffer_number(n);
+        }
+        if let Some(n) = self.db_write_buffer_size {
+            opts.set_db_write_buffer_size(n);
+        }
+        if let Some(n) = self.rate_limit_bytes_per_sec {
+            opts.set_ratelimiter(n, 100_000, 10);
+        }
+        if let Some(n) = self.block_cache_size {
+            opts.set_block_based_table_factory(&block_cache_table(n)?);
+        }
+        if let Some(ttl) = self.replication_wal_ttl {
+            opts.set_wal_ttl_seconds(ttl.as_secs().max(1));
+        }
+        Ok(())
     }
 }
 
+/// Block based table options with an LRU block cache of `size` bytes and bloom filters.
+fn block_cache_table(size: usize) -> Result<BlockBasedOptions> {
+    let cache = Cache::new_lru_cache(size)?;
+    let mut table = BlockBasedOptions::default();
+    table.set_block_cache(&cache);
+    table.set_bloom_filter(10.0, false);
+    table.set_cache_index_and_filter_blocks(true);
+    Ok(table)
+}
+
 pub type Result<T> = core::result::Result<T, Error>;
 
 #[derive(Debug, Clone)]
 pub enum Error {
     FromUtf8(FromUtf8Error),
     RocksDB(RocksDBError),
+    Io(Arc<io::Error>),
+    /// The list positions of the key reach the bounds of `i64`, call `list_reindex()` to fix it.
+    ListOverflow(String),
+    /// The key type or the value does not match the registered schema.
+    Schema(String),
     Message(String),
+    /// The writes are stopped by RocksDB, returned with `Options::backpressure_on_write_stall`.
+    Backpressure(WriteStall),
+    /// The key is sealed by `Database::seal_key()`, it can not be written.
+    Sealed(String),
+    /// The key is frozen by `Database::freeze_key()` for maintenance.
+    Frozen(String),
+    /// The database is written by a newer version of this crate, see `Manifest`.
+    IncompatibleFormat(String),
+    /// An error with the operation and the key, returned with the `error-context` feature.
+    Context {
+        op: &'static str,
+        key: String,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Get the innermost error, without the contexts.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Context { source, .. } => source.root(),
+            err => err,
+        }
+    }
+
+    /// Returns `true` if the operation may succeed by retrying, e.g. RocksDB `Busy`, `TryAgain`,
+    /// `TimedOut` and `Incomplete` (write stalls with `no_slowdown`).
+    pub fn is_transient(&self) -> bool {
+        match self.root() {
+            Error::RocksDB(err) => matches!(
+                rocksdb_error_kind(err),
+                RocksDBErrorKind::Busy
+                    | RocksDBErrorKind::TryAgain
+                    | RocksDBErrorKind::TimedOut
+                    | RocksDBErrorKind::Incomplete
+            ),
+            Error::Io(err) => matches!(
+                err.kind(),
+                io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            Error::Backpressure(_) => true,
+            _ => false,
+        }
+    }
 }
 
 impl std::fmt::Display for Error {
@@ -56,12 +425,128 @@ impl std::fmt::Display for Error {
         match self {
             Error::FromUtf8(err) => write!(f, "FromUtf8Error: {}", err),
             Error::RocksDB(err) => write!(f, "RocksDBError: {}", err),
+            Error::Io(err) => write!(f, "IoError: {}", err),
+            Error::ListOverflow(key) => write!(
+                f,
+                "ListOverflow: list positions of key {} reach the bounds, call list_reindex() to fix it",
+                key
+            ),
+            Error::Schema(err) => write!(f, "SchemaError: {}", err),
             Error::Message(err) => write!(f, "Error: {}", err),
+            Error::Backpressure(stall) => write!(f, "Backpressure: writes are {:?}", stall),
+            Error::Sealed(key) => write!(f, "Sealed: key {} is sealed", key),
+            Error::Frozen(key) => write!(f, "Frozen: key {} is frozen", key),
+            Error::IncompatibleFormat(msg) => write!(f, "IncompatibleFormat: {}", msg),
+            Error::Context { op, key, source } => write!(f, "{} {}: {}", op, key, source),
         }
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Context { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Status codes of RocksDB errors checked by `Error::is_transient()`, like `rocksdb::ErrorKind`
+/// of the later versions of the rocksdb crate.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum RocksDBErrorKind {
+    Busy,
+    TryAgain,
+    TimedOut,
+    Incomplete,
+    Other,
+}
+
+/// Get the status code of a RocksDB error. rocksdb 0.18 keeps only the message, which starts
+/// with the name of the code printed by `Status::ToString()`, followed by `:`.
+fn rocksdb_error_kind(err: &RocksDBError) -> RocksDBErrorKind {
+    match err.as_ref().split(':').next().unwrap_or("") {
+        "Resource busy" => RocksDBErrorKind::Busy,
+        "Operation failed. Try again." => RocksDBErrorKind::TryAgain,
+        "Operation timed out" => RocksDBErrorKind::TimedOut,
+        "Result incomplete" => RocksDBErrorKind::Incomplete,
+        _ => RocksDBErrorKind::Other,
+    }
+}
+
+/// Run `$body` with the retry policy of `$db` and report it to the metrics recorder, then wrap
+/// its error with the operation name and the key when the `error-context` feature is enabled.
+///
+/// `$access` is one of:
+/// - `read`, the body writes nothing.
+/// - `write`, the body commits its changes in a single `WriteBatch`, besides creating the meta
+///   of a new key, which is idempotent, so it's retried like a read.
+/// - `multi_write`, the body commits several writes, it's not retried since a retry after a
+///   committed write would apply the changes twice.
+///
+/// The writes are checked by `check_writable()` and throttled by
+/// `Options::backpressure_on_write_stall` first. `$key` is a key, or `[keys]` for the
+/// operations on several keys.
+macro_rules! run_op {
+    (@check read, $db:expr, $key:tt) => {};
+    (@check $access:ident, $db:expr, [$keys:expr]) => {
+        for key in $keys.iter() {
+            $db.check_writable(key.as_ref() as &[u8])?;
+        }
+        $db.check_write_capacity()?;
+    };
+    (@check $access:ident, $db:expr, $key:tt) => {
+        $db.check_writable($key.as_ref() as &[u8])?;
+        $db.check_write_capacity()?;
+    };
+    (@name [$keys:expr]) => {
+        $keys
+            .iter()
+            .map(|key| String::from_utf8_lossy(AsRef::<[u8]>::as_ref(key)).into_owned())
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    (@name $key:tt) => {
+        String::from_utf8_lossy($key.as_ref()).to_string()
+    };
+    (@run multi_write, $db:expr, $f:expr) => {
+        $f()
+    };
+    (@run $access:ident, $db:expr, $f:expr) => {
+        $db.retry($f)
+    };
+    ($db:expr, $op:expr, $access:ident, $key_type:expr, $key:tt, $body:block) => {{
+        let started = $db
+            .options
+            .metrics_recorder
+            .as_ref()
+            .map(|_| Instant::now());
+        let result = run_op!(@run $access, $db, || -> Result<_> {
+            run_op!(@check $access, $db, $key);
+            $body
+        });
+        if let (Some(recorder), Some(started)) = (&$db.options.metrics_recorder, started) {
+            recorder.record_op(
+                $op,
+                $key_type,
+                started.elapsed(),
+                result.as_ref().map(|_| ()),
+            );
+        }
+        #[cfg(feature = "error-context")]
+        {
+            result.map_err(|err| Error::Context {
+                op: $op,
+                key: run_op!(@name $key),
+                source: Box::new(err),
+            })
+        }
+        #[cfg(not(feature = "error-context"))]
+        {
+            result
+        }
+    }};
+}
 
 impl From<FromUtf8Error> for Error {
     fn from(e: FromUtf8Error) -> Self {
@@ -75,6 +560,180 @@ impl From<RocksDBError> for Error {
     }
 }
 
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(Arc::new(e))
+    }
+}
+
+/// Health status of a database, see `Database::health_check()`.
+#[derive(Debug, Copy, Clone)]
+pub struct HealthStatus {
+    /// Modification time of the newest table file, written by the last flush or compaction.
+    /// `None` if nothing has been flushed yet.
+    pub last_flush_time: Option<SystemTime>,
+    /// Estimated bytes to be rewritten by compactions.
+    pub pending_compaction_bytes: u64,
+    /// Accumulated number of background errors.
+    pub background_errors: u64,
+    /// Writes are stopped, e.g. too many memtables or level 0 files.
+    pub write_stopped: bool,
+    /// Writes are slowed down.
+    pub write_delayed: bool,
+    /// Free disk space available at the database path in bytes, `None` if unknown.
+    pub free_disk_space: Option<u64>,
+}
+
+impl HealthStatus {
+    /// Returns `true` if there is no background error and writes are not stopped.
+    pub fn is_healthy(&self) -> bool {
+        self.background_errors == 0 && !self.write_stopped
+    }
+}
+
+/// Write stall state of RocksDB, see `Database::write_stall()`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum WriteStall {
+    Normal,
+    /// Writes are slowed down to the rate in bytes per second.
+    Delayed(u64),
+    /// Writes are stopped, e.g. too many memtables or level 0 files.
+    Stopped,
+}
+
+/// Approximate memory usage of a database in bytes.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct MemoryUsage {
+    /// All the memtables, includes the ones that have been flushed but still pinned.
+    pub mem_table_total: u64,
+    /// The memtables that have not been flushed yet.
+    pub mem_table_unflushed: u64,
+    /// Table readers, includes index and filter blocks not stored in the block cache.
+    pub table_readers_total: u64,
+    /// Block cache.
+    pub block_cache_total: u64,
+    /// Entries of the block cache pinned by iterators or table readers.
+    pub block_cache_pinned: u64,
+}
+
+/// Aggregate function for `map_aggregate()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Aggregate {
+    Sum,
+    Min,
+    Max,
+    Avg,
+}
+
+/// Encoding of numeric values for `map_aggregate()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum NumberFormat {
+    /// 8 bytes big-endian `i64`.
+    I64,
+    /// 8 bytes big-endian `f64`.
+    F64,
+    /// `i64` encoded by `BytesComparableScore`.
+    ScoreI64,
+    /// `f64` encoded by `BytesComparableScore`.
+    ScoreF64,
+    /// UTF-8 decimal text, e.g. `-12.5`.
+    Text,
+}
+
+impl NumberFormat {
+    /// Parse a value as `f64`.
+    pub fn parse(&self, value: &[u8]) -> Option<f64> {
+        match self {
+            NumberFormat::I64 => value.try_into().ok().map(|b| i64::from_be_bytes(b) as f64),
+            NumberFormat::F64 => value.try_into().ok().map(f64::from_be_bytes),
+            NumberFormat::ScoreI64 if value.len() == 9 => {
+                Some(<i64 as BytesComparableScore>::from_bytes(value) as f64)
+            }
+            NumberFormat::ScoreF64 if value.len() == 9 => {
+                Some(<f64 as BytesComparableScore>::from_bytes(value))
+            }
+            NumberFormat::Text => std::str::from_utf8(value).ok()?.trim().parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// Buckets count of the histogram in `ScoreStats`.
+pub const SCORE_STATS_BUCKETS: u64 = 10;
+
+/// Max writes of a single batch in `list_reindex()`.
+const LIST_REINDEX_BATCH_SIZE: usize = 10000;
+
+/// Max writes of a single batch in `sorted_list_reseed()`.
+const SORTED_LIST_RESEED_BATCH_SIZE: usize = 10000;
+
+/// Max keys deleted by a single write batch in `delete_keys()`.
+const DELETE_KEYS_BATCH_SIZE: usize = 1000;
+
+/// Key IDs of the data rows compacted by a single chunk of `compact_all()`.
+pub const COMPACT_CHUNK_KEY_IDS: u64 = 1000;
+/// Interval of checking the write stall state in `Database::wait_for_write_capacity()`.
+pub const WRITE_CAPACITY_POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Name of the file written into the directory of a database at open, see
+/// `Database::destroy_checked()`.
+pub const MARKER_FILE: &str = "SIMPLEDB";
+
+/// Progress of `compact_all()`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CompactProgress {
+    /// Compacted chunks count.
+    pub done: u64,
+    /// Total chunks count.
+    pub total: u64,
+}
+
+/// Score distribution statistics of a `sorted list` or `sorted set`.
+#[derive(Debug, Clone, Default)]
+pub struct ScoreStats {
+    /// Total items count.
+    pub count: u64,
+    /// The lowest score.
+    pub min: Option<Box<[u8]>>,
+    /// The highest score.
+    pub max: Option<Box<[u8]>>,
+    /// Approximate equi-depth histogram, each bucket is (upper_bound_score, items_count).
+    pub histogram: Vec<(Box<[u8]>, u64)>,
+}
+
+/// Build `ScoreStats` from scores in ascending order in one pass.
+struct ScoreStatsBuilder {
+    stats: ScoreStats,
+    bucket_size: u64,
+}
+
+impl ScoreStatsBuilder {
+    fn new(expected_count: u64) -> ScoreStatsBuilder {
+        ScoreStatsBuilder {
+            stats: ScoreStats::default(),
+            bucket_size: expected_count.div_ceil(SCORE_STATS_BUCKETS).max(1),
+        }
+    }
+
+    fn push(&mut self, score: &[u8]) {
+        let stats = &mut self.stats;
+        if stats.count == 0 {
+            stats.min = Some(Box::from(score));
+        }
+        if stats.count.is_multiple_of(self.bucket_size) {
+            stats.histogram.push((Box::from(score), 0));
+        }
+        if let Some(bucket) = stats.histogram.last_mut() {
+            *bucket = (Box::from(score), bucket.1 + 1);
+        }
+        stats.count += 1;
+    }
+
+    fn finish(mut self) -> ScoreStats {
+        self.stats.max = self.stats.histogram.last().map(|(s, _)| s.clone());
+        self.stats
+    }
+}
+
 impl Database {
     /// Open database with default options.
     pub fn open(path: impl AsRef<Path>) -> Result<Database> {
@@ -82,16 +741,66 @@ impl Database {
     }
 
     /// Open database with specific options.
-    pub fn open_with_options(path: impl AsRef<Path>, options: Options) -> Result<Database> {
+    pub fn open_with_options(path: impl AsRef<Path>, mut options: Options) -> Result<Database> {
         let path = path.as_ref();
+        options.apply_tuning()?;
+        let dropped_key_ids = DroppedKeyIds::default();
+        let expiring_key_ids = ExpiringKeyIds::default();
+        options
+            .rocksdb_options
+            .set_merge_operator_associative("simpledb_namespace_stats", namespace_merge);
+        if options.lazy_delete {
+            set_compaction_filter(
+                &mut options.rocksdb_options,
+                dropped_key_ids.clone(),
+                expiring_key_ids.clone(),
+            );
+        }
         let db = DB::open(&options.rocksdb_options, path)?;
+        let marker = path.join(MARKER_FILE);
+        if !marker.exists() {
+            std::fs::write(marker, MARKER_FILE)?;
+        }
         let mut db = Database {
             path: path.display().to_string(),
             rocksdb: db,
             options,
-            next_key_id: Cell::new(1),
+            next_key_id: AtomicU64::new(1),
+            pop_any_turn: AtomicU64::new(0),
+            key_id_lock: Mutex::new(()),
+            create_meta_lock: Mutex::new(()),
+            writing_key_ids: Mutex::new(HashSet::new()),
+            map_swap_lock: Mutex::new(()),
+            blob_lock: Mutex::new(()),
+            sketch_lock: Mutex::new(()),
+            namespace_counters: AtomicBool::new(false),
+            chunked_values: AtomicBool::new(false),
+            idempotency_lock: Mutex::new(()),
+            dedupe_lock: Mutex::new(()),
+            list_lock: RwLock::new(()),
+            sorted_list_pop_lock: Mutex::new(()),
+            log_lock: Mutex::new(()),
+            cache_loads: Mutex::new(HashMap::new()),
+            admin_log_seq: AtomicU64::new(0),
+            schemas: RwLock::new(Vec::new()),
+            retentions: RwLock::new(Vec::new()),
+            save_meta_policies: RwLock::new(Vec::new()),
+            key_configs: RwLock::new(HashMap::new()),
+            expired_callbacks: RwLock::new(Vec::new()),
+            bad_row_callbacks: RwLock::new(Vec::new()),
+            custom_key_types: RwLock::new(Vec::new()),
+            aliases: RwLock::new(HashMap::new()),
+            sealed_keys: RwLock::new(HashSet::new()),
+            frozen_keys: RwLock::new(HashMap::new()),
+            dropped_key_ids,
+            expiring_key_ids,
+            absent_keys: RwLock::new(HashSet::new()),
+            meta_generation: AtomicU64::new(0),
+            background: BackgroundTasks::default(),
+            verify_report: Vec::new(),
         };
         db.after_open()?;
+        db.compact_on_open(db.options.compact_on_open)?;
         Ok(db)
     }
 
@@ -100,23 +809,364 @@ impl Database {
         Ok(DB::destroy(&RocksDBOptions::default(), path)?)
     }
 
+    /// Destroy database only if `path` contains a database opened by simpledb, i.e. both the
+    /// `MARKER_FILE` and the RocksDB `CURRENT` file exist. Does nothing if `path` does not
+    /// exist. Returns the removed files, or the files which would be removed if `dry_run` is
+    /// set. The files unknown to RocksDB are kept, so is the directory then.
+    pub fn destroy_checked(path: impl AsRef<Path>, dry_run: bool) -> Result<Vec<PathBuf>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+        if !path.join(MARKER_FILE).is_file() || !path.join("CURRENT").is_file() {
+            return Err(Error::Message(format!(
+                "{} is not a simpledb database",
+                path.display()
+            )));
+        }
+        let mut files = Vec::new();
+        for entry in std::fs::read_dir(path)? {
+            files.push(entry?.path());
+        }
+        files.sort();
+        if dry_run {
+            return Ok(files);
+        }
+        Database::destroy(path)?;
+        std::fs::remove_file(path.join(MARKER_FILE))?;
+        if std::fs::read_dir(path)?.next().is_none() {
+            std::fs::remove_dir(path)?;
+        }
+        files.retain(|file| !file.exists());
+        Ok(files)
+    }
+
+    /// Compact the whole database chunk by chunk, the data rows are split into chunks of
+    /// `COMPACT_CHUNK_KEY_IDS` key IDs, and the rows of the other prefixes are the last two
+    /// chunks. `progress` is called after every chunk, return `false` to cancel the rest.
+    /// Returns `false` if it's cancelled.
+    pub fn compact_all<F>(&self, mut progress: F) -> Result<bool>
+    where
+        F: FnMut(CompactProgress) -> bool,
+    {
+        let next_key_id = self.next_key_id.load(Ordering::SeqCst);
+        let data_chunks = next_key_id.div_ceil(COMPACT_CHUNK_KEY_IDS);
+        let mut state = CompactProgress {
+            done: 0,
+            total: data_chunks + 2,
+        };
+        let data_end = encode_data_key(next_key_id);
+        for i in 0..data_chunks {
+            let start = i * COMPACT_CHUNK_KEY_IDS;
+            let end = (start + COMPACT_CHUNK_KEY_IDS).min(next_key_id);
+            self.compact_range(
+                Some(encode_data_key(start).as_ref()),
+                Some(encode_data_key(end).as_ref()),
+            );
+            state.done += 1;
+            if !progress(state) {
+                return Ok(false);
+            }
+        }
+        self.compact_range(None, Some(PREFIX_DATA));
+        state.done += 1;
+        if !progress(state) {
+            return Ok(false);
+        }
+        self.compact_range(Some(data_end.as_ref()), None);
+        state.done += 1;
+        progress(state);
+        Ok(true)
+    }
+
+    /// Compact the whole database if `mode` requires, e.g. after heavy deletes, scans are slow
+    /// until the tombstones are compacted. It runs automatically when opening the database with
+    /// `Options::compact_on_open`. Returns `true` if the compaction is run.
+    pub fn compact_on_open(&self, mode: CompactOnOpen) -> Result<bool> {
+        let run = match mode {
+            CompactOnOpen::Disabled => false,
+            CompactOnOpen::Full => true,
+            CompactOnOpen::TombstoneRatio(ratio) => self.tombstone_ratio()? >= ratio,
+        };
+        if run {
+            self.compact_range(None, None);
+        }
+        Ok(run)
+    }
+
+    /// Get the ratio of deletions to all the entries in the table files, from 0 to 1.
+    /// The rows still in the memtables are not counted.
+    pub fn tombstone_ratio(&self) -> Result<f64> {
+        let properties = self
+            .rocksdb
+            .property_value(properties::AGGREGATED_TABLE_PROPERTIES)?
+            .unwrap_or_default();
+        let mut entries: u64 = 0;
+        let mut deletions: u64 = 0;
+        for item in properties.split(';') {
+            if let Some((name, value)) = item.split_once('=') {
+                match name.trim() {
+                    "# entries" => entries = value.trim().parse().unwrap_or(0),
+                    "# deletions" => deletions = value.trim().parse().unwrap_or(0),
+                    _ => {}
+                }
+            }
+        }
+        if entries == 0 {
+            return Ok(0.0);
+        }
+        Ok(deletions as f64 / entries as f64)
+    }
+
+    /// Get the write stall state of RocksDB.
+    pub fn write_stall(&self) -> Result<WriteStall> {
+        let property =
+            |name| -> Result<u64> { Ok(self.rocksdb.property_int_value(name)?.unwrap_or(0)) };
+        if property(properties::IS_WRITE_STOPPED)? > 0 {
+            return Ok(WriteStall::Stopped);
+        }
+        Ok(match property(properties::ACTUAL_DELAYED_WRITE_RATE)? {
+            0 => WriteStall::Normal,
+            rate => WriteStall::Delayed(rate),
+        })
+    }
+
+    /// Wait until RocksDB accepts writes again, checked every `WRITE_CAPACITY_POLL_INTERVAL`.
+    /// Returns `false` if the writes are still stopped after `timeout`.
+    pub fn wait_for_write_capacity(&self, timeout: Duration) -> Result<bool> {
+        let started = Instant::now();
+        while self.write_stall()? == WriteStall::Stopped {
+            if started.elapsed() >= timeout {
+                return Ok(false);
+            }
+            std::thread::sleep(WRITE_CAPACITY_POLL_INTERVAL.min(timeout - started.elapsed()));
+        }
+        Ok(true)
+    }
+
+    pub(crate) fn check_write_capacity(&self) -> Result<()> {
+        if self.options.backpressure_on_write_stall && self.write_stall()? == WriteStall::Stopped {
+            return Err(Error::Backpressure(WriteStall::Stopped));
+        }
+        Ok(())
+    }
+
+    /// Background tasks of this database, they are shut down when the database is dropped.
+    pub fn background_tasks(&self) -> &BackgroundTasks {
+        &self.background
+    }
+
+    /// Get approximate memory usage of this database.
+    pub fn memory_usage(&self) -> Result<MemoryUsage> {
+        let stats = get_memory_usage_stats(Some(&[&self.rocksdb]), None)?;
+        let property =
+            |name| -> Result<u64> { Ok(self.rocksdb.property_int_value(name)?.unwrap_or(0)) };
+        Ok(MemoryUsage {
+            mem_table_total: stats.mem_table_total,
+            mem_table_unflushed: stats.mem_table_unflushed,
+            table_readers_total: stats.mem_table_readers_total,
+            block_cache_total: property(properties::BLOCK_CACHE_USAGE)?,
+            block_cache_pinned: property(properties::BLOCK_CACHE_PINNED_USAGE)?,
+        })
+    }
+
+    /// Check the health of this database, e.g. for a readiness probe.
+    pub fn health_check(&self) -> Result<HealthStatus> {
+        let property =
+            |name| -> Result<u64> { Ok(self.rocksdb.property_int_value(name)?.unwrap_or(0)) };
+        let mut last_flush_time = None;
+        for entry in std::fs::read_dir(&self.path)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().ends_with(".sst") {
+                let modified = entry.metadata()?.modified()?;
+                last_flush_time = last_flush_time.max(Some(modified));
+            }
+        }
+        Ok(HealthStatus {
+            last_flush_time,
+            pending_compaction_bytes: property(properties::ESTIMATE_PENDING_COMPACTION_BYTES)?,
+            background_errors: property(properties::BACKGROUND_ERRORS)?,
+            write_stopped: property(properties::IS_WRITE_STOPPED)? > 0,
+            write_delayed: property(properties::ACTUAL_DELAYED_WRITE_RATE)? > 0,
+            free_disk_space: free_disk_space(&self.path),
+        })
+    }
+
+    /// Run `f` and retry it when it fails with a transient error, at most
+    /// `Options::retry_max_retries` times with exponential backoff.
+    pub fn retry<T, F>(&self, mut f: F) -> Result<T>
+    where
+        F: FnMut() -> Result<T>,
+    {
+        let mut retries = 0;
+        loop {
+            match f() {
+                Err(err) if err.is_transient() && retries < self.options.retry_max_retries => {
+                    std::thread::sleep(self.options.retry_backoff * (1 << retries.min(16)));
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Allocate a new key ID. The next key ID is saved in `KEY_NEXT_KEY_ID` before the ID is
+    /// returned, so the ID is never allocated again after reopening, even if the write of the
+    /// meta using it fails.
+    pub(crate) fn allocate_key_id(&self) -> Result<u64> {
+        let _guard = self.key_id_lock.lock().unwrap();
+        let id = self.next_key_id.load(Ordering::SeqCst);
+        self.rocksdb.put(KEY_NEXT_KEY_ID, (id + 1).to_be_bytes())?;
+        self.next_key_id.store(id + 1, Ordering::SeqCst);
+        Ok(id)
+    }
+
+    /// Ensure the key ID is not allocated again, e.g. for a meta written with its own key ID.
+    pub(crate) fn reserve_key_id(&self, id: u64) -> Result<()> {
+        let _guard = self.key_id_lock.lock().unwrap();
+        if id >= self.next_key_id.load(Ordering::SeqCst) {
+            self.rocksdb.put(KEY_NEXT_KEY_ID, (id + 1).to_be_bytes())?;
+            self.next_key_id.store(id + 1, Ordering::SeqCst);
+        }
+        Ok(())
+    }
+
     fn after_open(&mut self) -> Result<()> {
+        self.check_manifest()?;
+        self.load_aliases();
+        self.load_key_configs();
+        self.load_sealed_keys();
+        self.load_dropped_key_ids();
+        self.load_expiring_key_ids();
+        self.load_admin_log_seq();
+        self.recover_journal()?;
+        if self.options.verify_on_open {
+            self.verify_report = self.verify_metas(self.options.quarantine_on_verify)?;
+        }
+        let quarantined = self.options.quarantine_on_verify && !self.verify_report.is_empty();
+        if quarantined || self.rocksdb.get_pinned(KEY_TYPE_INDEX_READY)?.is_none() {
+            self.rebuild_type_index()?;
+        }
+        self.open_namespace_stats(quarantined)?;
+        if let Some(v) = self.rocksdb.get_pinned(KEY_NEXT_KEY_ID)? {
+            if v.len() == 8 {
+                let next_key_id = v.as_ref().get_u64().max(1);
+                self.next_key_id.store(next_key_id, Ordering::SeqCst);
+                return Ok(());
+            }
+        }
+        // databases created by old versions have no persisted key ID, find the max one
         let mut last_key_id: u64 = 0;
-        self.for_each_key(|_, m| {
-            last_key_id = m.id;
+        self.prefix_iterator(PREFIX_META, |_, v| {
+            // skip the bad rows, they are reported by `verify_metas()`
+            if v.len() >= 17 {
+                last_key_id = last_key_id.max(KeyMeta::from_bytes(v.as_ref()).id);
+            }
             true
-        })?;
-        self.next_key_id.set(last_key_id + 1);
+        });
+        self.next_key_id.store(last_key_id + 1, Ordering::SeqCst);
+        self.rocksdb
+            .put(KEY_NEXT_KEY_ID, (last_key_id + 1).to_be_bytes())?;
         Ok(())
     }
 
-    fn prefix_iterator<F>(&self, prefix: &[u8], mut f: F)
+    /// Rebuild the index of key types from all the metas, it runs automatically when opening a
+    /// database created by an old version.
+    pub fn rebuild_type_index(&self) -> Result<usize> {
+        let mut batch = WriteBatch::default();
+        self.delete_range_to_batch(&mut batch, PREFIX_TYPE_INDEX, b"u");
+        let mut count = 0;
+        self.prefix_iterator(PREFIX_META, |k, v| {
+            if v.len() >= 17 {
+                let meta = KeyMeta::from_bytes(v.as_ref());
+                batch.put(
+                    encode_type_index_key(meta.key_type, &k[PREFIX_META.len()..]),
+                    FILL_EMPTY_DATA,
+                );
+                count += 1;
+            }
+            true
+        });
+        batch.put(KEY_TYPE_INDEX_READY, FILL_EMPTY_DATA);
+        self.rocksdb.write(batch)?;
+        self.log_admin("rebuild_type_index", "", count as u64)?;
+        Ok(count)
+    }
+
+    /// Get the names of all the keys of a data type, read from the index of key types.
+    pub fn keys_of_type(&self, key_type: KeyType) -> Result<Vec<String>> {
+        let prefix = encode_type_index_prefix(key_type);
+        let mut vec = Vec::new();
+        let mut has_error = None;
+        self.prefix_iterator(prefix.as_ref(), |k, _| {
+            match decode_type_index_key(k.as_ref()) {
+                Ok(key) => {
+                    vec.push(key);
+                    true
+                }
+                Err(err) => {
+                    has_error = Some(err);
+                    false
+                }
+            }
+        });
+        match has_error {
+            None => Ok(vec),
+            Some(err) => Err(err.into()),
+        }
+    }
+
+    /// Bad meta rows found when opening the database with `Options::verify_on_open`.
+    pub fn verify_report(&self) -> &[BadMeta] {
+        &self.verify_report
+    }
+
+    /// Read options of the long sequential scans, see `Options::scan_readahead_size`.
+    pub(crate) fn scan_read_options(&self) -> ReadOptions {
+        let mut opts = ReadOptions::default();
+        if let Some(size) = self.options.scan_readahead_size {
+            opts.set_readahead_size(size);
+        }
+        opts
+    }
+
+    /// Delete the rows in `[from, to)` in the batch. When the replication is enabled by
+    /// `Options::replication_wal_ttl`, the rows are deleted one by one, as `replicate_to()`
+    /// can't read the range deletions from the WAL. The range deletion is still written if
+    /// the rows can't be listed, then `replicate_to()` fails on the batch.
+    pub(crate) fn delete_range_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        from: impl AsRef<[u8]>,
+        to: impl AsRef<[u8]>,
+    ) {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        if self.options.replication_wal_ttl.is_some() {
+            let mut keys = Vec::new();
+            let mut iter = self.rocksdb.raw_iterator_opt(self.scan_read_options());
+            iter.seek(from);
+            while let Some(k) = iter.key().filter(|k| *k < to) {
+                keys.push(k.to_vec());
+                iter.next();
+            }
+            if iter.status().is_ok() {
+                for k in keys {
+                    batch.delete(k);
+                }
+                return;
+            }
+        }
+        batch.delete_range(from, to);
+    }
+
+    pub(crate) fn prefix_iterator<F>(&self, prefix: &[u8], mut f: F)
     where
         F: FnMut(Box<[u8]>, Box<[u8]>) -> bool,
     {
-        let iter = self
-            .rocksdb
-            .iterator(IteratorMode::From(prefix, Direction::Forward));
+        let iter = self.rocksdb.iterator_opt(
+            IteratorMode::From(prefix, Direction::Forward),
+            self.scan_read_options(),
+        );
         for (k, v) in iter {
             if !has_prefix(prefix, k.as_ref()) {
                 break;
@@ -133,29 +1183,201 @@ impl Database {
         meta: &KeyMeta,
         delete_if_empty: bool,
     ) -> Result<()> {
-        if self.options.delete_meta_when_empty && delete_if_empty && meta.count < 1 {
-            Ok(self.rocksdb.delete(encode_meta_key(key))?)
+        let mut batch = WriteBatch::default();
+        self.save_meta_to_batch(&mut batch, key, meta, delete_if_empty)?;
+        Ok(self.rocksdb.write(batch)?)
+    }
+
+    /// Same as `save_meta()`, but put the operation into a write batch. The stored meta is
+    /// read to update the counters of its namespace in the batch, only if the counters are
+    /// kept, see `namespace_stats()`.
+    pub fn save_meta_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key: impl AsRef<[u8]>,
+        meta: &KeyMeta,
+        delete_if_empty: bool,
+    ) -> Result<()> {
+        let key = self.resolve_key(key.as_ref());
+        let old_count = if self.namespace_counters.load(Ordering::SeqCst) {
+            self.rocksdb
+                .get_pinned(encode_meta_key(key.as_ref()))?
+                .filter(|v| v.len() >= 17)
+                .map(|v| KeyMeta::from_bytes(&v).count as i64)
         } else {
-            Ok(self.rocksdb.put(encode_meta_key(key), meta.get_bytes())?)
+            None
+        };
+        if delete_if_empty && meta.count < 1 && self.deletes_empty_meta(key.as_ref()) {
+            self.delete_key_to_batch(batch, key.as_ref(), meta, old_count);
+        } else {
+            self.forget_absent_key(key.as_ref());
+            batch.put(encode_meta_key(key.as_ref()), meta.get_bytes());
+            self.count_namespace_to_batch(
+                batch,
+                key.as_ref(),
+                old_count.is_none() as i64,
+                meta.count as i64 - old_count.unwrap_or(0),
+            );
+        }
+        Ok(())
+    }
+
+    /// Delete a key in the batch except its data rows: the type index, the meta, the reserved
+    /// items, the sketch, the expiring ID marker and the consumer groups. `stored_count` is the
+    /// items count of the stored meta, it's removed from the counters of the namespace, `None`
+    /// if the meta is not stored.
+    pub(crate) fn delete_key_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        meta: &KeyMeta,
+        stored_count: Option<i64>,
+    ) {
+        batch.delete(encode_type_index_key(meta.key_type, key));
+        batch.delete(encode_meta_key(key));
+        if meta.key_type == KeyType::SortedList {
+            self.delete_range_to_batch(
+                batch,
+                encode_reserved_key_prefix(meta.id),
+                encode_reserved_key_prefix(meta.id + 1),
+            );
+        }
+        self.delete_value_chunks_to_batch(batch, meta);
+        self.delete_sketch_to_batch(batch, meta);
+        self.delete_expiring_key_id_to_batch(batch, meta);
+        self.delete_consumers_to_batch(batch, meta);
+        if let Some(count) = stored_count {
+            self.count_namespace_to_batch(batch, key, -1, -count);
+        }
+    }
+
+    /// Set the policy of the empty metas of the keys matched `pattern`, the pattern is a key
+    /// name or a prefix ending with `*`. The first set pattern wins if a key matches many
+    /// patterns, the other keys follow `Options::delete_meta_when_empty`.
+    pub fn set_save_meta_policy(&self, pattern: &str, policy: SaveMetaPolicy) {
+        let mut policies = self.save_meta_policies.write().unwrap();
+        policies.retain(|(p, _)| p != pattern);
+        policies.push((pattern.to_string(), policy));
+    }
+
+    /// Remove the policy set with `pattern`, returns `false` if it's not set.
+    pub fn remove_save_meta_policy(&self, pattern: &str) -> bool {
+        let mut policies = self.save_meta_policies.write().unwrap();
+        let len = policies.len();
+        policies.retain(|(p, _)| p != pattern);
+        policies.len() != len
+    }
+
+    /// Get the policy of the empty meta of a key, the policy in its `KeyConfig` wins.
+    pub fn save_meta_policy(&self, key: impl AsRef<[u8]>) -> SaveMetaPolicy {
+        let key = self.resolve_key(key.as_ref());
+        let policy = self
+            .key_config(key.as_ref())
+            .and_then(|c| c.save_meta_policy)
+            .or_else(|| {
+                self.save_meta_policies
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .find(|(pattern, _)| key_matches_pattern(key.as_ref(), pattern))
+                    .map(|(_, policy)| *policy)
+            });
+        match policy {
+            Some(policy) => policy,
+            None if self.options.delete_meta_when_empty => SaveMetaPolicy::DeleteWhenEmpty,
+            None => SaveMetaPolicy::KeepWhenEmpty,
         }
     }
 
+    fn deletes_empty_meta(&self, key: &[u8]) -> bool {
+        self.save_meta_policy(key) == SaveMetaPolicy::DeleteWhenEmpty
+    }
+
     pub fn get_meta(&self, key: impl AsRef<[u8]>) -> Result<Option<KeyMeta>> {
-        Ok(self
+        let key = self.resolve_key(key.as_ref());
+        self.check_frozen(&key, false)?;
+        let cache_size = self.options.meta_negative_cache_size;
+        if cache_size > 0 && self.absent_keys.read().unwrap().contains(key.as_ref()) {
+            return Ok(None);
+        }
+        let generation = self.meta_generation.load(Ordering::SeqCst);
+        let meta = self
             .rocksdb
-            .get(encode_meta_key(key))
-            .map(|v| v.map(|v| KeyMeta::from_bytes(v.as_slice())))?)
+            .get_pinned(encode_meta_key(key.as_ref()))?
+            .map(|v| KeyMeta::from_bytes(v.as_ref()));
+        if cache_size > 0 && meta.is_none() {
+            let mut absent_keys = self.absent_keys.write().unwrap();
+            // a meta saved after the read may be the one of this key
+            if self.meta_generation.load(Ordering::SeqCst) == generation {
+                if absent_keys.len() >= cache_size {
+                    absent_keys.clear();
+                }
+                absent_keys.insert(key.into_owned());
+            }
+        }
+        Ok(meta)
+    }
+
+    /// Reload the states cached from the rows, after the rows are written into
+    /// `Database::rocksdb` directly, e.g. by `DatabaseSink`.
+    pub(crate) fn reload_cached_states(&self) {
+        self.meta_generation.fetch_add(1, Ordering::SeqCst);
+        self.absent_keys.write().unwrap().clear();
+        self.load_aliases();
+        self.load_key_configs();
+        self.load_sealed_keys();
+        self.load_dropped_key_ids();
+        self.load_expiring_key_ids();
+        self.load_admin_log_seq();
+        self.load_chunked_values();
+        let _guard = self.key_id_lock.lock().unwrap();
+        if let Ok(Some(v)) = self.rocksdb.get_pinned(KEY_NEXT_KEY_ID) {
+            if v.len() == 8 {
+                let next_key_id = v.as_ref().get_u64().max(1);
+                self.next_key_id.store(next_key_id, Ordering::SeqCst);
+            }
+        }
+    }
+
+    /// Remove a name from the cache of the absent keys, it's called before a meta is saved and
+    /// again after a new key is written, so a read between them can not cache the name.
+    pub(crate) fn forget_absent_key(&self, key: &[u8]) {
+        if self.options.meta_negative_cache_size > 0 {
+            let mut absent_keys = self.absent_keys.write().unwrap();
+            self.meta_generation.fetch_add(1, Ordering::SeqCst);
+            absent_keys.remove(key);
+        }
     }
 
     pub fn get_or_create_meta(&self, key: impl AsRef<[u8]>, key_type: KeyType) -> Result<KeyMeta> {
+        let key = self.resolve_key(key.as_ref());
         let key = key.as_ref();
-        let m = self.get_meta(key)?;
+        if self.options.strict_utf8_keys && std::str::from_utf8(key).is_err() {
+            return Err(Error::Message(format!(
+                "key {} is not valid UTF-8",
+                String::from_utf8_lossy(key)
+            )));
+        }
+        self.check_schema_key_type(key, key_type)?;
+        if let Some(m) = self.get_meta(key)? {
+            return Ok(m);
+        }
+        // check again under the lock, the key may be created by another thread meanwhile, the
+        // negative cache is skipped because it may be filled before the other thread's write
+        let _guard = self.create_meta_lock.lock().unwrap();
+        let m = self
+            .rocksdb
+            .get_pinned(encode_meta_key(key))?
+            .map(|v| KeyMeta::from_bytes(v.as_ref()));
         match m {
             Some(m) => Ok(m),
             None => {
-                let m = KeyMeta::new(self.next_key_id.get(), key_type);
-                self.next_key_id.set(self.next_key_id.get() + 1);
-                self.save_meta(key, &m, false)?;
+                let m = KeyMeta::new(self.allocate_key_id()?, key_type);
+                let mut batch = WriteBatch::default();
+                batch.put(encode_type_index_key(key_type, key), FILL_EMPTY_DATA);
+                self.save_meta_to_batch(&mut batch, key, &m, false)?;
+                self.rocksdb.write(batch)?;
+                self.forget_absent_key(key);
                 Ok(m)
             }
         }
@@ -168,21 +1390,43 @@ impl Database {
         let mut counter: usize = 0;
         let mut has_error = None;
         self.prefix_iterator(PREFIX_META, |k, v| {
+            match self.check_scanned_meta(k.as_ref(), v.as_ref(), true) {
+                Ok(true) => {}
+                Ok(false) => return true,
+                Err(err) => {
+                    has_error = Some(err);
+                    return false;
+                }
+            }
             counter += 1;
             match decode_meta_key(k.as_ref()) {
                 Ok(key) => f(key.as_str(), &KeyMeta::from_bytes(v.as_ref())),
                 Err(err) => {
-                    has_error = Some(err);
+                    has_error = Some(err.into());
                     false
                 }
             }
         });
         match has_error {
             None => Ok(counter),
-            Some(err) => Err(err.into()),
+            Some(err) => Err(err),
         }
     }
 
+    /// Same as `for_each_key()`, but the key names are raw bytes, so the names which are not
+    /// valid UTF-8 don't stop the scan.
+    pub fn for_each_key_bytes<F>(&self, mut f: F) -> usize
+    where
+        F: FnMut(&[u8], &KeyMeta) -> bool,
+    {
+        let mut counter: usize = 0;
+        self.prefix_iterator(PREFIX_META, |k, v| {
+            counter += 1;
+            f(&k[PREFIX_META.len()..], &KeyMeta::from_bytes(v.as_ref()))
+        });
+        counter
+    }
+
     pub fn for_each_key_with_limit<F>(&self, limit: usize, mut f: F) -> Result<usize>
     where
         F: FnMut(&str, &KeyMeta) -> bool,
@@ -190,22 +1434,30 @@ impl Database {
         let mut counter: usize = 0;
         let mut has_error = None;
         self.prefix_iterator(PREFIX_META, |k, v| {
+            if counter >= limit {
+                counter += 1;
+                return false;
+            }
+            match self.check_scanned_meta(k.as_ref(), v.as_ref(), true) {
+                Ok(true) => {}
+                Ok(false) => return true,
+                Err(err) => {
+                    has_error = Some(err);
+                    return false;
+                }
+            }
             counter += 1;
-            if counter > limit {
-                false
-            } else {
-                match decode_meta_key(k.as_ref()) {
-                    Ok(key) => f(key.as_str(), &KeyMeta::from_bytes(v.as_ref())),
-                    Err(err) => {
-                        has_error = Some(err);
-                        false
-                    }
+            match decode_meta_key(k.as_ref()) {
+                Ok(key) => f(key.as_str(), &KeyMeta::from_bytes(v.as_ref())),
+                Err(err) => {
+                    has_error = Some(err.into());
+                    false
                 }
             }
         });
         match has_error {
             None => Ok(counter),
-            Some(err) => Err(err.into()),
+            Some(err) => Err(err),
         }
     }
 
@@ -223,21 +1475,96 @@ impl Database {
             buf
         };
         self.prefix_iterator(k.as_ref(), |k, v| {
+            match self.check_scanned_meta(k.as_ref(), v.as_ref(), true) {
+                Ok(true) => {}
+                Ok(false) => return true,
+                Err(err) => {
+                    has_error = Some(err);
+                    return false;
+                }
+            }
             counter += 1;
             match decode_meta_key(k.as_ref()) {
                 Ok(key) => f(key.as_str(), &KeyMeta::from_bytes(v.as_ref())),
                 Err(err) => {
-                    has_error = Some(err);
+                    has_error = Some(err.into());
                     false
                 }
             }
         });
+        match has_error {
+            None => Ok(counter),
+            Some(err) => Err(err),
+        }
+    }
+
+    /// Iterate at most `limit` keys in byte order of their names, starting right after the key
+    /// `start_after`, so a scan can be resumed from the last key of the previous page.
+    /// Returns the count of the iterated keys.
+    pub fn for_each_key_after<F>(
+        &self,
+        start_after: Option<&str>,
+        limit: usize,
+        mut f: F,
+    ) -> Result<usize>
+    where
+        F: FnMut(&str, &KeyMeta) -> bool,
+    {
+        let start = match start_after {
+            Some(key) => {
+                let mut k = encode_meta_key(key);
+                k.put_u8(0);
+                k
+            }
+            None => encode_meta_key(b""),
+        };
+        let mut counter: usize = 0;
+        if limit == 0 {
+            return Ok(counter);
+        }
+        let mut has_error = None;
+        let iter = self
+            .rocksdb
+            .iterator(IteratorMode::From(&start, Direction::Forward));
+        for (k, v) in iter {
+            if !has_prefix(PREFIX_META, k.as_ref()) {
+                break;
+            }
+            if !self.check_scanned_meta(k.as_ref(), v.as_ref(), true)? {
+                continue;
+            }
+            counter += 1;
+            let next = match decode_meta_key(k.as_ref()) {
+                Ok(key) => f(key.as_str(), &KeyMeta::from_bytes(v.as_ref())),
+                Err(err) => {
+                    has_error = Some(err);
+                    false
+                }
+            };
+            if !next || counter >= limit {
+                break;
+            }
+        }
         match has_error {
             None => Ok(counter),
             Some(err) => Err(err.into()),
         }
     }
 
+    /// Get a page of at most `limit` keys after the key `start_after`, see `for_each_key_after()`.
+    pub fn keys_after(
+        &self,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, KeyMeta)>> {
+        let mut vec = Vec::new();
+        self.for_each_key_after(start_after, limit, |k, meta| {
+            vec.push((k.to_string(), meta.clone()));
+            true
+        })?;
+        Ok(vec)
+    }
+
     pub fn keys(&self) -> Result<Vec<(String, KeyMeta)>> {
         let mut vec = Vec::new();
         self.for_each_key(|k, meta| {
@@ -247,6 +1574,16 @@ impl Database {
         Ok(vec)
     }
 
+    /// Same as `keys()`, but the key names are raw bytes.
+    pub fn keys_bytes(&self) -> Vec<(Box<[u8]>, KeyMeta)> {
+        let mut vec = Vec::new();
+        self.for_each_key_bytes(|k, meta| {
+            vec.push((Box::from(k), meta.clone()));
+            true
+        });
+        vec
+    }
+
     pub fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<(String, KeyMeta)>> {
         let mut vec = Vec::new();
         self.for_each_key_with_prefix(prefix, |k, meta| {
@@ -256,10 +1593,16 @@ impl Database {
         Ok(vec)
     }
 
-    pub fn for_each_data<F>(&self, key: &str, prefix: Option<&str>, mut f: F) -> Result<u64>
+    pub fn for_each_data<F>(
+        &self,
+        key: impl AsRef<[u8]>,
+        prefix: Option<&str>,
+        mut f: F,
+    ) -> Result<u64>
     where
         F: FnMut(Box<[u8]>, Box<[u8]>) -> bool,
     {
+        let key = key.as_ref();
         let meta = self.get_meta(key)?;
         match meta {
             Some(meta) => {
@@ -279,11 +1622,25 @@ impl Database {
                             buf
                         }
                     };
+                    let mut has_error = None;
                     self.prefix_iterator(k.as_ref(), |k, v| {
                         counter += 1;
-                        f(k, v)
+                        if meta.key_type != KeyType::Map {
+                            return f(k, v);
+                        }
+                        let field = decode_data_key_map_field(&k);
+                        match self.map_item_value(meta.id, field, v) {
+                            Ok(v) => f(k, v),
+                            Err(err) => {
+                                has_error = Some(err);
+                                false
+                            }
+                        }
                     });
-                    Ok(counter)
+                    match has_error {
+                        None => Ok(counter),
+                        Some(err) => Err(err),
+                    }
                 } else {
                     Ok(0)
                 }
@@ -300,31 +1657,142 @@ impl Database {
         })
     }
 
+    /// Get the items count of many keys with a single `multi_get`, 0 for the missing keys.
+    pub fn get_counts(&self, keys: &[&str]) -> Result<Vec<u64>> {
+        self.rocksdb
+            .multi_get(
+                keys.iter()
+                    .map(|k| encode_meta_key(self.resolve_key(k.as_bytes()))),
+            )
+            .into_iter()
+            .map(|v| Ok(v?.map(|v| KeyMeta::from_bytes(&v).count).unwrap_or(0)))
+            .collect()
+    }
+
+    /// Delete many keys with range deletes, each write batch deletes at most
+    /// `DELETE_KEYS_BATCH_SIZE` keys atomically. The aliases of the keys are removed too, and
+    /// the names resolved to the same key are deleted once. With `Options::lazy_delete` the
+    /// data rows are dropped by the compaction run after each batch instead.
+    /// Returns the count of the keys actually deleted.
+    pub fn delete_keys(&self, keys: &[&str]) -> Result<u64> {
+        run_op!(self, "delete_keys", multi_write, None, [keys], {
+            let lazy = self.options.lazy_delete;
+            let mut deleted = 0;
+            let mut seen = HashSet::new();
+            for keys in keys.chunks(DELETE_KEYS_BATCH_SIZE) {
+                let keys: Vec<_> = keys
+                    .iter()
+                    .map(|k| self.resolve_key(k.as_bytes()))
+                    .filter(|k| seen.insert(k.to_vec()))
+                    .collect();
+                let metas = self.rocksdb.multi_get(keys.iter().map(encode_meta_key));
+                let mut batch = WriteBatch::default();
+                let mut ids = Vec::new();
+                for (key, meta) in keys.iter().zip(metas) {
+                    self.delete_aliases_to_batch(&mut batch, key);
+                    let meta = match meta? {
+                        Some(v) => KeyMeta::from_bytes(&v),
+                        None => continue,
+                    };
+                    if let KeyType::Custom(tag) = meta.key_type {
+                        if let Ok(key_type) = self.custom_key_type(tag) {
+                            key_type.on_delete(&String::from_utf8_lossy(key), &meta);
+                        }
+                    }
+                    if lazy {
+                        batch.put(encode_dropped_key_id_key(meta.id), FILL_EMPTY_DATA);
+                        self.dropped_key_ids.write().unwrap().insert(meta.id);
+                    } else {
+                        self.delete_range_to_batch(
+                            &mut batch,
+                            encode_data_key(meta.id),
+                            encode_data_key(meta.id + 1),
+                        );
+                    }
+                    self.delete_key_to_batch(&mut batch, key, &meta, Some(meta.count as i64));
+                    ids.push(meta.id);
+                    deleted += 1;
+                }
+                self.rocksdb.write(batch)?;
+                for key in keys.iter() {
+                    self.forget_aliases(key);
+                }
+                if lazy && !ids.is_empty() {
+                    let (min, max) = (ids.iter().min().unwrap(), ids.iter().max().unwrap());
+                    self.compact_range(
+                        Some(encode_data_key(*min).as_ref()),
+                        Some(encode_data_key(max + 1).as_ref()),
+                    );
+                    // the compaction above dropped the rows
+                    let mut batch = WriteBatch::default();
+                    for id in ids.iter() {
+                        batch.delete(encode_dropped_key_id_key(*id));
+                    }
+                    self.rocksdb.write(batch)?;
+                    let mut dropped = self.dropped_key_ids.write().unwrap();
+                    for id in ids.iter() {
+                        dropped.remove(id);
+                    }
+                }
+            }
+            self.log_admin("delete_keys", "", deleted)?;
+            Ok(deleted)
+        })
+    }
+
+    /// Delete a key and all its data, deleting through an alias deletes the target key,
+    /// and the aliases of the key are removed too.
     pub fn delete_all(&self, key: &str) -> Result<u64> {
-        let meta = self.get_meta(key)?;
-        let mut deletes_count = 0;
-        if let Some(meta) = meta {
-            let mut has_error = None;
-            self.for_each_data(key, None, |k, _| {
-                deletes_count += 1;
-                match self.rocksdb.delete(k) {
-                    Ok(_) => true,
-                    Err(err) => {
-                        has_error = Some(err);
-                        false
+        run_op!(self, "delete_all", multi_write, None, key, {
+            let meta = self.get_meta(key)?;
+            let mut deletes_count = 0;
+            if let Some(meta) = meta {
+                if let KeyType::Custom(tag) = meta.key_type {
+                    if let Ok(key_type) = self.custom_key_type(tag) {
+                        key_type.on_delete(key, &meta);
                     }
                 }
-            })?;
-            if let Some(err) = has_error {
-                return Err(err.into());
+                let lazy = self.options.lazy_delete;
+                let mut has_error = None;
+                self.for_each_data(key, None, |k, _| {
+                    deletes_count += 1;
+                    if lazy {
+                        return true;
+                    }
+                    match self.rocksdb.delete(k) {
+                        Ok(_) => true,
+                        Err(err) => {
+                            has_error = Some(err);
+                            false
+                        }
+                    }
+                })?;
+                if let Some(err) = has_error {
+                    return Err(err.into());
+                }
+                let key = self.resolve_key(key.as_bytes());
+                let mut batch = WriteBatch::default();
+                self.delete_key_to_batch(&mut batch, key.as_ref(), &meta, Some(meta.count as i64));
+                self.delete_aliases_to_batch(&mut batch, key.as_ref());
+                if lazy {
+                    batch.put(encode_dropped_key_id_key(meta.id), FILL_EMPTY_DATA);
+                    self.dropped_key_ids.write().unwrap().insert(meta.id);
+                }
+                self.rocksdb.write(batch)?;
+                self.forget_aliases(key.as_ref());
+                self.compact_range(
+                    Some(encode_data_key(meta.id).as_ref()),
+                    Some(encode_data_key(meta.id + 1).as_ref()),
+                );
+                if lazy {
+                    // the compaction above dropped the rows
+                    self.rocksdb.delete(encode_dropped_key_id_key(meta.id))?;
+                    self.dropped_key_ids.write().unwrap().remove(&meta.id);
+                }
+                self.log_admin("delete_all", key, deletes_count)?;
             }
-            self.rocksdb.delete(encode_meta_key(key))?;
-            self.rocksdb.compact_range(
-                Some(encode_data_key(meta.id).as_ref()),
-                Some(encode_data_key(meta.id + 1).as_ref()),
-            );
-        }
-        Ok(deletes_count)
+            Ok(deletes_count)
+        })
     }
 
     pub fn map_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
@@ -336,9 +1804,62 @@ impl Database {
         key: impl AsRef<[u8]>,
         field: impl AsRef<[u8]>,
     ) -> Result<Option<Vec<u8>>> {
-        let meta = self.get_or_create_meta(key, KeyType::Map)?;
-        let full_key = encode_data_key_map_item(meta.id, field);
-        Ok(self.rocksdb.get(full_key)?)
+        run_op!(self, "map_get", read, Some(KeyType::Map), key, {
+            let meta = self.get_or_create_meta(key.as_ref(), KeyType::Map)?;
+            let full_key = encode_data_key_map_item(meta.id, field.as_ref());
+            let value = match self.rocksdb.get(full_key)? {
+                Some(row) => Some(self.map_item_value(meta.id, field.as_ref(), row)?),
+                None => None,
+            };
+            match (value, self.options.tier.as_ref()) {
+                (None, Some(tier)) => {
+                    let key = String::from_utf8_lossy(key.as_ref());
+                    let value = tier.fetch_missing(&key, field.as_ref())?;
+                    if let Some(value) = value.as_ref() {
+                        self.map_put(key.as_bytes(), field.as_ref(), value)?;
+                    }
+                    Ok(value)
+                }
+                (value, _) => Ok(value),
+            }
+        })
+    }
+
+    /// Get one field of each map with a single `multi_get`, e.g. a field from each of many
+    /// maps, `None` for the missing keys and fields. The metas are resolved once per key by
+    /// `get_meta()`, and the remote tier is not consulted.
+    pub fn multi_map_get<K: AsRef<[u8]>>(
+        &self,
+        pairs: &[(K, &[u8])],
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let mut metas = HashMap::new();
+        for (key, _) in pairs.iter() {
+            let key = key.as_ref();
+            if !metas.contains_key(key) {
+                let meta = self.get_meta(key)?.filter(|m| m.key_type == KeyType::Map);
+                metas.insert(key, meta);
+            }
+        }
+        let full_keys: Vec<_> = pairs
+            .iter()
+            .filter_map(|(key, field)| {
+                metas[key.as_ref()]
+                    .as_ref()
+                    .map(|meta| encode_data_key_map_item(meta.id, field))
+            })
+            .collect();
+        let mut values = self.rocksdb.multi_get(full_keys).into_iter();
+        let mut result = Vec::with_capacity(pairs.len());
+        for (key, field) in pairs.iter() {
+            result.push(match &metas[key.as_ref()] {
+                None => None,
+                Some(meta) => match values.next().transpose()?.flatten() {
+                    Some(row) => Some(self.map_item_value(meta.id, field, row)?),
+                    None => None,
+                },
+            });
+        }
+        Ok(result)
     }
 
     pub fn map_put(
@@ -347,35 +1868,290 @@ impl Database {
         field: impl AsRef<[u8]>,
         value: impl AsRef<[u8]>,
     ) -> Result<()> {
-        let key = key.as_ref();
+        run_op!(self, "map_put", write, Some(KeyType::Map), key, {
+            let mut batch = WriteBatch::default();
+            self.map_put_to_batch(&mut batch, key.as_ref(), field.as_ref(), value.as_ref())?;
+            Ok(self.rocksdb.write(batch)?)
+        })
+    }
+
+    /// Put a field into the batch, the meta is read from the database, so the batch must not
+    /// have other writes of the key.
+    pub(crate) fn map_put_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        field: &[u8],
+        value: &[u8],
+    ) -> Result<()> {
+        self.check_schema_value(key, value)?;
         let mut meta = self.get_or_create_meta(key, KeyType::Map)?;
         let full_key = encode_data_key_map_item(meta.id, field);
-        if self.rocksdb.get(&full_key)?.is_none() {
+        let old = self.rocksdb.get_pinned(&full_key)?;
+        if old.is_none() {
+            self.check_max_count(key, &meta)?;
             meta.count += 1;
         }
-        self.rocksdb.put(&full_key, value)?;
-        self.save_meta(key, &meta, false)
+        self.map_item_put_to_batch(batch, meta.id, field, value, old.as_deref())?;
+        self.save_meta_to_batch(batch, key, &meta, false)
     }
 
-    pub fn map_delete(&self, key: impl AsRef<[u8]>, field: impl AsRef<[u8]>) -> Result<bool> {
-        let key = key.as_ref();
+    /// Same as `map_get()`, but the value is pinned in RocksDB instead of copied out. A value
+    /// split into chunks by `Options::large_value_threshold` can't be pinned, it fails, read it
+    /// by `map_get()`.
+    pub fn map_get_pinned(
+        &self,
+        key: impl AsRef<[u8]>,
+        field: impl AsRef<[u8]>,
+    ) -> Result<Option<DBPinnableSlice<'_>>> {
         match self.get_meta(key)? {
-            None => Ok(false),
-            Some(mut meta) => {
+            None => Ok(None),
+            Some(meta) => {
+                let field = field.as_ref();
                 let full_key = encode_data_key_map_item(meta.id, field);
-                if self.rocksdb.get(&full_key)?.is_some() {
-                    meta.count -= 1;
-                    self.rocksdb.delete(&full_key)?;
-                    self.save_meta(key, &meta, true)?;
-                    Ok(true)
-                } else {
-                    Ok(false)
+                let row = self.rocksdb.get_pinned(full_key)?;
+                if let Some(row) = row.as_ref() {
+                    if self.map_item_value_len(meta.id, field, row.len())? != row.len() {
+                        return Err(Error::Message(format!(
+                            "value of field {} is chunked, it can't be pinned",
+                            String::from_utf8_lossy(field)
+                        )));
+                    }
                 }
+                Ok(row)
+            }
+        }
+    }
+
+    /// Get the size of a field value in bytes without copying the value out, the remote tier
+    /// is not consulted. Returns `None` if the field does not exist.
+    pub fn map_value_len(
+        &self,
+        key: impl AsRef<[u8]>,
+        field: impl AsRef<[u8]>,
+    ) -> Result<Option<usize>> {
+        let meta = match self.get_meta(key)? {
+            None => return Ok(None),
+            Some(meta) => meta,
+        };
+        let field = field.as_ref();
+        match self
+            .rocksdb
+            .get_pinned(encode_data_key_map_item(meta.id, field))?
+        {
+            None => Ok(None),
+            Some(row) => Ok(Some(self.map_item_value_len(meta.id, field, row.len())?)),
+        }
+    }
+
+    /// Put `value` into a field and get the old value in a single step, e.g. for state machine
+    /// style fields. The swaps are serialized by a lock, so two swaps of a field never return
+    /// the same old value, `map_put()` does not take the lock.
+    /// Returns `None` if the field did not exist.
+    pub fn map_swap(
+        &self,
+        key: impl AsRef<[u8]>,
+        field: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<Option<Vec<u8>>> {
+        run_op!(self, "map_swap", write, Some(KeyType::Map), key, {
+            let key = key.as_ref();
+            self.check_schema_value(key, value.as_ref())?;
+            let _guard = self.map_swap_lock.lock().unwrap();
+            let mut meta = self.get_or_create_meta(key, KeyType::Map)?;
+            let field = field.as_ref();
+            let full_key = encode_data_key_map_item(meta.id, field);
+            let old = self.rocksdb.get(&full_key)?;
+            let mut batch = WriteBatch::default();
+            self.map_item_put_to_batch(&mut batch, meta.id, field, value.as_ref(), old.as_deref())?;
+            let old = match old {
+                Some(row) => Some(self.map_item_value(meta.id, field, row)?),
+                None => None,
+            };
+            if old.is_none() {
+                self.check_max_count(key, &meta)?;
+                meta.count += 1;
+                self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+            }
+            self.rocksdb.write(batch)?;
+            Ok(old)
+        })
+    }
+
+    pub fn map_delete(&self, key: impl AsRef<[u8]>, field: impl AsRef<[u8]>) -> Result<bool> {
+        run_op!(self, "map_delete", write, Some(KeyType::Map), key, {
+            let mut batch = WriteBatch::default();
+            let deleted = self.map_delete_to_batch(&mut batch, key.as_ref(), field.as_ref())?;
+            if deleted {
+                self.rocksdb.write(batch)?;
+            }
+            Ok(deleted)
+        })
+    }
+
+    /// Delete a field in the batch, returns `false` if the field does not exist. The batch
+    /// must not have other writes of the key, see `map_put_to_batch()`.
+    pub(crate) fn map_delete_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        field: &[u8],
+    ) -> Result<bool> {
+        let mut meta = match self.get_meta(key)? {
+            None => return Ok(false),
+            Some(meta) => meta,
+        };
+        let full_key = encode_data_key_map_item(meta.id, field);
+        match self.rocksdb.get_pinned(&full_key)? {
+            None => Ok(false),
+            Some(old) => {
+                meta.count -= 1;
+                self.map_item_delete_chunks_to_batch(batch, meta.id, field, &old)?;
+                batch.delete(&full_key);
+                self.save_meta_to_batch(batch, key, &meta, true)?;
+                Ok(true)
             }
         }
     }
 
+    /// Move a field from map `src_key` to map `dst_key` atomically, the field in `dst_key` is
+    /// overwritten if it exists. Returns `false` if the field does not exist in `src_key`.
+    pub fn map_move_field(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        field: impl AsRef<[u8]>,
+    ) -> Result<bool> {
+        run_op!(
+            self,
+            "map_move_field",
+            multi_write,
+            Some(KeyType::Map),
+            [[src_key, dst_key]],
+            {
+                let field = field.as_ref();
+                let mut src = match self.get_meta(src_key)? {
+                    None => return Ok(false),
+                    Some(meta) => meta,
+                };
+                check_key_type(src_key, &src, KeyType::Map)?;
+                let src_item = encode_data_key_map_item(src.id, field);
+                let src_row = match self.rocksdb.get(&src_item)? {
+                    None => return Ok(false),
+                    Some(row) => row,
+                };
+                let value = self.map_item_value(src.id, field, src_row.clone())?;
+                self.check_schema_value(dst_key.as_bytes(), &value)?;
+                let mut dst = self.get_or_create_meta(dst_key, KeyType::Map)?;
+                check_key_type(dst_key, &dst, KeyType::Map)?;
+                if dst.id == src.id {
+                    return Ok(true);
+                }
+                let dst_item = encode_data_key_map_item(dst.id, field);
+                let mut batch = WriteBatch::default();
+                let dst_row = self.rocksdb.get_pinned(&dst_item)?;
+                if dst_row.is_none() {
+                    dst.count += 1;
+                }
+                src.count -= 1;
+                self.map_item_delete_chunks_to_batch(&mut batch, src.id, field, &src_row)?;
+                batch.delete(&src_item);
+                self.map_item_put_to_batch(&mut batch, dst.id, field, &value, dst_row.as_deref())?;
+                self.save_meta_to_batch(&mut batch, src_key, &src, true)?;
+                self.save_meta_to_batch(&mut batch, dst_key, &dst, false)?;
+                self.rocksdb.write(batch)?;
+                Ok(true)
+            }
+        )
+    }
+
+    /// Move all the fields of map `src` into map `dst` in a single write batch, `src` is empty
+    /// afterwards. `on_conflict` decides which value is kept when a field exists in both maps.
+    /// Returns the count of fields written to `dst`.
+    pub fn map_merge(&self, dst: &str, src: &str, on_conflict: OnConflict) -> Result<u64> {
+        run_op!(
+            self,
+            "map_merge",
+            multi_write,
+            Some(KeyType::Map),
+            [[dst, src]],
+            {
+                let mut src_meta = match self.get_meta(src)? {
+                    None => return Ok(0),
+                    Some(meta) => meta,
+                };
+                check_key_type(src, &src_meta, KeyType::Map)?;
+                let mut dst_meta = self.get_or_create_meta(dst, KeyType::Map)?;
+                check_key_type(dst, &dst_meta, KeyType::Map)?;
+                if dst_meta.id == src_meta.id {
+                    return Ok(0);
+                }
+                let mut batch = WriteBatch::default();
+                let mut written = 0;
+                let mut has_error = None;
+                self.map_for_each(src, |field, value| {
+                    let dst_item = encode_data_key_map_item(dst_meta.id, field);
+                    let dst_row = match self.rocksdb.get(&dst_item) {
+                        Ok(v) => v,
+                        Err(err) => {
+                            has_error = Some(err.into());
+                            return false;
+                        }
+                    };
+                    let exists = dst_row.is_some();
+                    if !exists || on_conflict == OnConflict::Overwrite {
+                        let result =
+                            self.check_schema_value(dst.as_bytes(), &value)
+                                .and_then(|_| {
+                                    self.map_item_put_to_batch(
+                                        &mut batch,
+                                        dst_meta.id,
+                                        field,
+                                        &value,
+                                        dst_row.as_deref(),
+                                    )
+                                });
+                        if let Err(err) = result {
+                            has_error = Some(err);
+                            return false;
+                        }
+                        written += 1;
+                        if !exists {
+                            dst_meta.count += 1;
+                        }
+                    }
+                    true
+                })?;
+                if let Some(err) = has_error {
+                    return Err(err);
+                }
+                self.delete_range_to_batch(
+                    &mut batch,
+                    encode_data_key(src_meta.id),
+                    encode_data_key(src_meta.id + 1),
+                );
+                self.delete_value_chunks_to_batch(&mut batch, &src_meta);
+                src_meta.count = 0;
+                self.save_meta_to_batch(&mut batch, src, &src_meta, true)?;
+                self.save_meta_to_batch(&mut batch, dst, &dst_meta, false)?;
+                self.rocksdb.write(batch)?;
+                Ok(written)
+            }
+        )
+    }
+
+    /// Iterate the fields of a map, the field names are borrowed from the RocksDB keys.
     pub fn map_for_each<F>(&self, key: &str, mut f: F) -> Result<u64>
+    where
+        F: FnMut(&[u8], Box<[u8]>) -> bool,
+    {
+        self.for_each_data(key, None, |k, v| {
+            f(decode_data_key_map_field(k.as_ref()), v)
+        })
+    }
+
+    /// Same as `map_for_each()`, but the field names are decoded as UTF-8 strings.
+    pub fn map_for_each_utf8<F>(&self, key: &str, mut f: F) -> Result<u64>
     where
         F: FnMut(&str, Box<[u8]>) -> bool,
     {
@@ -395,10 +2171,49 @@ impl Database {
         }
     }
 
+    #[allow(clippy::unnecessary_cast)]
     pub fn map_items(&self, key: &str) -> Result<Vec<(String, Box<[u8]>)>> {
         let count = self.get_count(key)?;
         let mut vec = Vec::with_capacity(count as u64 as usize);
-        self.map_for_each(key, |f, v| {
+        self.map_for_each_utf8(key, |f, v| {
+            vec.push((String::from(f), v));
+            true
+        })?;
+        Ok(vec)
+    }
+
+    pub fn map_for_each_with_limit<F>(&self, key: &str, limit: usize, mut f: F) -> Result<u64>
+    where
+        F: FnMut(&str, Box<[u8]>) -> bool,
+    {
+        let mut has_error = None;
+        let mut counter: usize = 0;
+        self.for_each_data(key, None, |k, v| {
+            if counter >= limit {
+                return false;
+            }
+            counter += 1;
+            match decode_data_key_map_item(k.as_ref()) {
+                Ok(k) => f(&k, v),
+                Err(err) => {
+                    has_error = Some(err);
+                    false
+                }
+            }
+        })?;
+        match has_error {
+            None => Ok(counter as u64),
+            Some(err) => Err(err.into()),
+        }
+    }
+
+    pub fn map_items_with_limit(
+        &self,
+        key: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, Box<[u8]>)>> {
+        let mut vec = Vec::with_capacity(limit.min(self.get_count(key)? as usize));
+        self.map_for_each_with_limit(key, limit, |f, v| {
             vec.push((String::from(f), v));
             true
         })?;
@@ -439,63 +2254,321 @@ impl Database {
         Ok(vec)
     }
 
-    pub fn set_count(&self, key: &str) -> Result<u64> {
-        self.get_count(key)
-    }
-
-    pub fn set_add(&self, key: &str, value: &[u8]) -> Result<bool> {
-        let mut meta = self.get_or_create_meta(key, KeyType::Set)?;
-        let full_key = encode_data_key_set_item(meta.id, value);
-        let mut is_new_item = false;
-        if self.rocksdb.get(&full_key)?.is_none() {
-            meta.count += 1;
-            is_new_item = true;
-        }
-        self.rocksdb.put(&full_key, FILL_EMPTY_DATA)?;
-        if is_new_item {
-            self.save_meta(key, &meta, false)?;
+    /// Iterate fields in range `[start_field, end_field)` of a map, `None` means unbounded.
+    /// Fields are visited in descending order when `reverse` is true.
+    pub fn map_for_each_range<F>(
+        &self,
+        key: &str,
+        start_field: Option<&str>,
+        end_field: Option<&str>,
+        reverse: bool,
+        mut f: F,
+    ) -> Result<u64>
+    where
+        F: FnMut(&str, Box<[u8]>) -> bool,
+    {
+        let meta = match self.get_meta(key)? {
+            Some(m) if m.count > 0 => m,
+            _ => return Ok(0),
+        };
+        let lower = encode_data_key_map_item(meta.id, start_field.unwrap_or(""));
+        let upper = match end_field {
+            Some(end_field) => encode_data_key_map_item(meta.id, end_field),
+            None => encode_data_key(meta.id + 1),
+        };
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_lower_bound(lower.to_vec());
+        opts.set_iterate_upper_bound(upper.to_vec());
+        let mode = if reverse {
+            IteratorMode::End
+        } else {
+            IteratorMode::Start
+        };
+        let mut counter = 0;
+        for (k, v) in self.rocksdb.iterator_opt(mode, opts) {
+            counter += 1;
+            let field = decode_data_key_map_item(k.as_ref())?;
+            let v = self.map_item_value(meta.id, field.as_bytes(), v)?;
+            if !f(&field, v) {
+                break;
+            }
         }
-        Ok(is_new_item)
+        Ok(counter)
     }
 
-    pub fn set_is_member(&self, key: &str, value: &[u8]) -> Result<bool> {
-        match self.get_meta(key)? {
-            None => Ok(false),
-            Some(meta) => {
-                let full_key = encode_data_key_set_item(meta.id, value);
-                Ok(self.rocksdb.get(&full_key)?.is_some())
+    /// Aggregate all values of a map as numbers, returns `None` if the map is empty.
+    pub fn map_aggregate(
+        &self,
+        key: &str,
+        aggregate: Aggregate,
+        format: NumberFormat,
+    ) -> Result<Option<f64>> {
+        let mut result: Option<f64> = None;
+        let mut invalid_field = None;
+        let count = self.map_for_each(key, |field, value| match format.parse(&value) {
+            Some(n) => {
+                result = Some(match (result, aggregate) {
+                    (None, _) => n,
+                    (Some(r), Aggregate::Sum | Aggregate::Avg) => r + n,
+                    (Some(r), Aggregate::Min) => r.min(n),
+                    (Some(r), Aggregate::Max) => r.max(n),
+                });
+                true
             }
+            None => {
+                invalid_field = Some(String::from_utf8_lossy(field).to_string());
+                false
+            }
+        })?;
+        if let Some(field) = invalid_field {
+            return Err(Error::Message(format!(
+                "invalid {:?} number of field {} in key {}",
+                format, field, key
+            )));
         }
+        Ok(match aggregate {
+            Aggregate::Avg => result.map(|r| r / count as f64),
+            _ => result,
+        })
+    }
+
+    /// Record `request_id` with its `result` for `ttl` and returns `None` if it's a new request,
+    /// otherwise returns the stored result of the previous request. Records are stored in a `map`.
+    pub fn idempotency_check_and_set(
+        &self,
+        key: &str,
+        request_id: &str,
+        result: &[u8],
+        ttl: Duration,
+    ) -> Result<Option<Box<[u8]>>> {
+        // the check and the set are not interleaved with another thread's
+        let _guard = self.idempotency_lock.lock().unwrap();
+        let now = now_millis();
+        if let Some(v) = self.map_get(key, request_id)? {
+            let (expires_at, stored) = decode_expiring_value(&v);
+            if expires_at > now {
+                return Ok(Some(Box::from(stored)));
+            }
+        }
+        let expires_at = now.saturating_add(ttl.as_millis() as u64);
+        self.map_put(key, request_id, encode_expiring_value(expires_at, result))?;
+        Ok(None)
+    }
+
+    /// Delete the expired records of `idempotency_check_and_set()`, returns the deleted count.
+    pub fn idempotency_purge_expired(&self, key: &str) -> Result<u64> {
+        self.purge_expired_values(key)
+    }
+
+    /// Delete the expired rows of a key, the values are encoded by `encode_expiring_value()`.
+    pub(crate) fn purge_expired_values(&self, key: &str) -> Result<u64> {
+        let meta = match self.get_meta(key)? {
+            Some(m) => m,
+            None => return Ok(0),
+        };
+        let now = now_millis();
+        let mut batch = WriteBatch::default();
+        let mut expired = Vec::new();
+        self.for_each_data(key, None, |k, v| {
+            if decode_expiring_value(&v).0 <= now {
+                batch.delete(&k);
+                expired.push(k);
+            }
+            true
+        })?;
+        let counter = expired.len() as u64;
+        if counter > 0 {
+            let mut meta = meta;
+            meta.count -= counter;
+            self.save_meta_to_batch(&mut batch, key, &meta, true)?;
+            self.rocksdb.write(batch)?;
+            for k in expired.iter() {
+                self.emit_expired(key.as_bytes(), meta.key_type, decode_data_key_map_field(k));
+            }
+        }
+        Ok(counter)
+    }
+
+    /// Get the cached value of a `map` field, or call `f` to compute the value and cache it for `ttl`.
+    /// The loads are single-flight, when many threads miss the same field, `f` of only one of
+    /// them runs and the others wait for it and get the cached value.
+    pub fn cache_get_or_insert_with<F>(
+        &self,
+        key: &str,
+        field: &str,
+        ttl: Duration,
+        f: F,
+    ) -> Result<Box<[u8]>>
+    where
+        F: FnOnce() -> Vec<u8>,
+    {
+        if let Some(value) = self.cache_get(key, field)? {
+            return Ok(value);
+        }
+        let flight_key = (
+            self.resolve_key(key.as_bytes()).into_owned(),
+            field.as_bytes().to_vec(),
+        );
+        let flight = self
+            .cache_loads
+            .lock()
+            .unwrap()
+            .entry(flight_key.clone())
+            .or_default()
+            .clone();
+        let result = {
+            // poisoned if a loader panicked, nothing it guards is left broken
+            let _loading = flight.lock().unwrap_or_else(|e| e.into_inner());
+            // filled by the thread loading it while this one was waiting
+            match self.cache_get(key, field) {
+                Ok(Some(value)) => Ok(value),
+                Ok(None) => {
+                    let value = f();
+                    let expires_at = now_millis().saturating_add(ttl.as_millis() as u64);
+                    self.map_put(key, field, encode_expiring_value(expires_at, &value))
+                        .map(|_| value.into_boxed_slice())
+                }
+                Err(e) => Err(e),
+            }
+        };
+        let mut loads = self.cache_loads.lock().unwrap();
+        // the map and this thread hold the last references, no thread is waiting
+        if Arc::strong_count(&flight) == 2 {
+            loads.remove(&flight_key);
+        }
+        result
+    }
+
+    /// Get a value cached by `cache_get_or_insert_with()`, `None` if it's missing or expired.
+    fn cache_get(&self, key: &str, field: &str) -> Result<Option<Box<[u8]>>> {
+        Ok(self.map_get(key, field)?.and_then(|v| {
+            let (expires_at, value) = decode_expiring_value(&v);
+            (expires_at > now_millis()).then(|| Box::from(value))
+        }))
+    }
+
+    /// Remember the message `id` for `window` and returns `true` if it has been seen within the
+    /// window. Ids are stored in a `sorted set` scored by the seen time, the expired ones are
+    /// deleted on every call.
+    pub fn dedupe_window(&self, key: &str, id: &[u8], window: Duration) -> Result<bool> {
+        // the purge, the check and the add are not interleaved with another thread's
+        let _guard = self.dedupe_lock.lock().unwrap();
+        let now = now_millis();
+        let expired = now.saturating_sub(window.as_millis() as u64);
+        if expired > 0 {
+            self.sorted_set_delete_range_by_score(
+                key,
+                &get_score_bytes(0u64),
+                &get_score_bytes(expired - 1),
+            )?;
+        }
+        if self.sorted_set_is_member(key, id)? {
+            return Ok(true);
+        }
+        self.sorted_set_add(key, &get_score_bytes(now), id)?;
+        Ok(false)
+    }
+
+    pub fn set_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        let key = key.as_ref();
+        self.get_count(key)
+    }
+
+    pub fn set_add(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool> {
+        let key = key.as_ref();
+        run_op!(self, "set_add", write, Some(KeyType::Set), key, {
+            // the meta and the sketch are read and updated in the same batch as the member
+            let _lock = self.sketch_lock.lock().unwrap();
+            let mut batch = WriteBatch::default();
+            let added = self.set_add_to_batch(&mut batch, key, value)?;
+            if added {
+                self.rocksdb.write(batch)?;
+            }
+            Ok(added)
+        })
+    }
+
+    /// Add a member in the batch, returns `false` if it's a member already. The batch must not
+    /// have other writes of the key, see `map_put_to_batch()`.
+    pub(crate) fn set_add_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<bool> {
+        self.check_schema_value(key, value)?;
+        let mut meta = self.get_or_create_meta(key, KeyType::Set)?;
+        let full_key = encode_data_key_set_item(meta.id, value);
+        if self.rocksdb.get(&full_key)?.is_some() {
+            return Ok(false);
+        }
+        self.check_max_count(key, &meta)?;
+        meta.count += 1;
+        batch.put(&full_key, FILL_EMPTY_DATA);
+        self.save_meta_to_batch(batch, key, &meta, false)?;
+        self.sketch_add_to_batch(batch, meta.id, value)?;
+        Ok(true)
     }
 
-    pub fn set_delete(&self, key: &str, value: &[u8]) -> Result<bool> {
+    pub fn set_is_member(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool> {
+        let key = key.as_ref();
         match self.get_meta(key)? {
             None => Ok(false),
-            Some(mut meta) => {
+            Some(meta) => {
                 let full_key = encode_data_key_set_item(meta.id, value);
-                if self.rocksdb.get(&full_key)?.is_some() {
-                    meta.count -= 1;
-                    self.rocksdb.delete(full_key)?;
-                    self.save_meta(key, &meta, true)?;
-                    Ok(true)
-                } else {
-                    Ok(false)
-                }
+                Ok(self.rocksdb.get_pinned(&full_key)?.is_some())
+            }
+        }
+    }
+
+    pub fn set_delete(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool> {
+        let key = key.as_ref();
+        run_op!(self, "set_delete", write, Some(KeyType::Set), key, {
+            let mut batch = WriteBatch::default();
+            let deleted = self.set_delete_to_batch(&mut batch, key, value)?;
+            if deleted {
+                self.rocksdb.write(batch)?;
             }
+            Ok(deleted)
+        })
+    }
+
+    /// Delete a member in the batch, returns `false` if it's not a member. The batch must not
+    /// have other writes of the key, see `map_put_to_batch()`.
+    pub(crate) fn set_delete_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<bool> {
+        let mut meta = match self.get_meta(key)? {
+            None => return Ok(false),
+            Some(meta) => meta,
+        };
+        let full_key = encode_data_key_set_item(meta.id, value);
+        if self.rocksdb.get(&full_key)?.is_none() {
+            return Ok(false);
         }
+        meta.count -= 1;
+        batch.delete(full_key);
+        self.save_meta_to_batch(batch, key, &meta, true)?;
+        Ok(true)
     }
 
-    pub fn set_for_each<F>(&self, key: &str, mut f: F) -> Result<u64>
+    pub fn set_for_each<F>(&self, key: impl AsRef<[u8]>, mut f: F) -> Result<u64>
     where
         F: FnMut(Box<[u8]>) -> bool,
     {
+        let key = key.as_ref();
         self.for_each_data(key, None, |k, _| {
             let value = decode_data_key_set_item(k.as_ref());
             f(Box::from(value))
         })
     }
 
-    pub fn set_items(&self, key: &str) -> Result<Vec<Box<[u8]>>> {
+    #[allow(clippy::unnecessary_cast)]
+    pub fn set_items(&self, key: impl AsRef<[u8]>) -> Result<Vec<Box<[u8]>>> {
+        let key = key.as_ref();
         let count = self.get_count(key)?;
         let mut vec = Vec::with_capacity(count as u64 as usize);
         self.set_for_each(key, |v| {
@@ -505,80 +2578,507 @@ impl Database {
         Ok(vec)
     }
 
-    pub fn list_count(&self, key: &str) -> Result<u64> {
+    pub fn set_items_with_limit(
+        &self,
+        key: impl AsRef<[u8]>,
+        limit: usize,
+    ) -> Result<Vec<Box<[u8]>>> {
+        let key = key.as_ref();
+        let mut vec = Vec::with_capacity(limit.min(self.get_count(key)? as usize));
+        if limit == 0 {
+            return Ok(vec);
+        }
+        self.set_for_each(key, |v| {
+            vec.push(v);
+            vec.len() < limit
+        })?;
+        Ok(vec)
+    }
+
+    /// Pick `n` random members of a `set` with reservoir sampling in a single pass.
+    /// The same `seed` gives the same sample as long as the set does not change.
+    pub fn set_sample(&self, key: impl AsRef<[u8]>, n: usize, seed: u64) -> Result<Vec<Box<[u8]>>> {
+        let key = key.as_ref();
+        let mut vec = Vec::with_capacity(n.min(self.get_count(key)? as usize));
+        if n == 0 {
+            return Ok(vec);
+        }
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut seen: usize = 0;
+        self.set_for_each(key, |v| {
+            seen += 1;
+            if vec.len() < n {
+                vec.push(v);
+            } else {
+                let i = rng.gen_range(0..seen);
+                if i < n {
+                    vec[i] = v;
+                }
+            }
+            true
+        })?;
+        Ok(vec)
+    }
+
+    /// Count members of set `a` which are not members of set `b`, without materializing them.
+    pub fn set_diff_count(&self, a: &str, b: &str) -> Result<u64> {
+        Ok(self.set_merge_count(a, b)?.0)
+    }
+
+    /// Count members of both set `a` and set `b`, without materializing them.
+    pub fn set_intersect_count(&self, a: &str, b: &str) -> Result<u64> {
+        Ok(self.set_merge_count(a, b)?.1)
+    }
+
+    /// Iterate two sets in order at the same time, returns (only_in_a_count, in_both_count).
+    fn set_merge_count(&self, a: &str, b: &str) -> Result<(u64, u64)> {
+        let meta_a = match self.get_meta(a)? {
+            None => return Ok((0, 0)),
+            Some(m) => m,
+        };
+        let meta_b = match self.get_meta(b)? {
+            None => return Ok((meta_a.count, 0)),
+            Some(m) => m,
+        };
+        let prefix_a = encode_data_key(meta_a.id);
+        let prefix_b = encode_data_key(meta_b.id);
+        let mut iter_a = self
+            .rocksdb
+            .iterator(IteratorMode::From(&prefix_a, Direction::Forward))
+            .take_while(|(k, _)| has_prefix(&prefix_a, k.as_ref()))
+            .map(|(k, _)| k);
+        let mut iter_b = self
+            .rocksdb
+            .iterator(IteratorMode::From(&prefix_b, Direction::Forward))
+            .take_while(|(k, _)| has_prefix(&prefix_b, k.as_ref()))
+            .map(|(k, _)| k);
+        let (mut only_a, mut both) = (0, 0);
+        let mut item_b = iter_b.next();
+        for item_a in iter_a.by_ref() {
+            let value_a = decode_data_key_set_item(item_a.as_ref());
+            loop {
+                match &item_b {
+                    Some(k) if decode_data_key_set_item(k.as_ref()) < value_a => {
+                        item_b = iter_b.next();
+                    }
+                    Some(k) if decode_data_key_set_item(k.as_ref()) == value_a => {
+                        both += 1;
+                        item_b = iter_b.next();
+                        break;
+                    }
+                    _ => {
+                        only_a += 1;
+                        break;
+                    }
+                }
+            }
+        }
+        Ok((only_a, both))
+    }
+
+    pub fn list_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        let key = key.as_ref();
         self.get_count(key)
     }
 
-    pub fn list_left_push(&self, key: &str, value: &[u8]) -> Result<u64> {
-        let mut meta = self.get_or_create_meta(key, KeyType::List)?;
-        let (left, right) = meta.decode_list_extra();
-        let full_key = encode_data_key_list_item(meta.id, left);
-        self.rocksdb.put(full_key, value)?;
-        meta.encode_list_extra(left - 1, right);
-        meta.count += 1;
-        self.save_meta(key, &meta, false)?;
-        Ok(meta.count)
+    pub fn list_left_push(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<u64> {
+        let key = key.as_ref();
+        run_op!(self, "list_left_push", write, Some(KeyType::List), key, {
+            self.check_schema_value(key, value)?;
+            let (_guard, mut meta) = self.list_meta_for_push(key, |left, _| left == i64::MIN)?;
+            self.check_max_count(key, &meta)?;
+            let (left, right) = meta.decode_list_extra();
+            let next_left = left - 1;
+            let full_key = encode_data_key_list_item(meta.id, left);
+            meta.encode_list_extra(next_left, right);
+            meta.count += 1;
+            let mut batch = WriteBatch::default();
+            batch.put(full_key, value);
+            self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+            self.rocksdb.write(batch)?;
+            Ok(meta.count)
+        })
     }
 
-    pub fn list_right_push(&self, key: &str, value: &[u8]) -> Result<u64> {
-        let mut meta = self.get_or_create_meta(key, KeyType::List)?;
+    pub fn list_right_push(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<u64> {
+        let key = key.as_ref();
+        run_op!(self, "list_right_push", write, Some(KeyType::List), key, {
+            let mut batch = WriteBatch::default();
+            let (_guard, count) = self.list_right_push_to_batch(&mut batch, key, value)?;
+            self.rocksdb.write(batch)?;
+            Ok(count)
+        })
+    }
+
+    /// Push a value to the right of the list in the batch, returns the count of the list and
+    /// the `list_lock` guard to hold until the batch is written. The batch must not have other
+    /// writes of the key, see `map_put_to_batch()`.
+    pub(crate) fn list_right_push_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<(RwLockReadGuard<'_, ()>, u64)> {
+        self.check_schema_value(key, value)?;
+        let (guard, mut meta) = self.list_meta_for_push(key, |_, right| right == i64::MAX)?;
+        self.check_max_count(key, &meta)?;
         let (left, right) = meta.decode_list_extra();
+        let next_right = right + 1;
         let full_key = encode_data_key_list_item(meta.id, right);
-        self.rocksdb.put(full_key, value)?;
-        meta.encode_list_extra(left, right + 1);
+        meta.encode_list_extra(left, next_right);
         meta.count += 1;
-        self.save_meta(key, &meta, false)?;
-        Ok(meta.count)
+        batch.put(full_key, value);
+        self.save_meta_to_batch(batch, key, &meta, false)?;
+        Ok((guard, meta.count))
     }
 
-    pub fn list_left_pop(&self, key: &str) -> Result<Option<Box<[u8]>>> {
-        match self.get_meta(key)? {
-            None => Ok(None),
-            Some(mut meta) => {
-                let (left, right) = meta.decode_list_extra();
-                let full_key = encode_data_key_list_item(meta.id, left + 1);
-                match self.rocksdb.get(full_key.as_ref())? {
-                    Some(value) => {
-                        meta.encode_list_extra(left + 1, right);
-                        meta.count -= 1;
-                        self.save_meta(key, &meta, true)?;
-                        self.rocksdb.delete(full_key.as_ref())?;
-                        Ok(Some(Box::from(value)))
+    /// Get the list meta for a push together with the read guard of `list_lock`, `is_full`
+    /// checks whether the positions reach the bounds.
+    fn list_meta_for_push<F>(
+        &self,
+        key: &[u8],
+        is_full: F,
+    ) -> Result<(RwLockReadGuard<'_, ()>, KeyMeta)>
+    where
+        F: Fn(i64, i64) -> bool,
+    {
+        let guard = self.list_lock.read().unwrap();
+        let meta = self.get_or_create_meta(key, KeyType::List)?;
+        let (left, right) = meta.decode_list_extra();
+        if !is_full(left, right) {
+            return Ok((guard, meta));
+        }
+        if !self.options.list_auto_reindex {
+            return Err(Error::ListOverflow(
+                String::from_utf8_lossy(key).into_owned(),
+            ));
+        }
+        // the reindex takes the lock for writing
+        drop(guard);
+        self.list_reindex(key)?;
+        let guard = self.list_lock.read().unwrap();
+        let meta = self.get_or_create_meta(key, KeyType::List)?;
+        let (left, right) = meta.decode_list_extra();
+        if is_full(left, right) {
+            return Err(Error::ListOverflow(
+                String::from_utf8_lossy(key).into_owned(),
+            ));
+        }
+        Ok((guard, meta))
+    }
+
+    pub fn list_left_pop(&self, key: impl AsRef<[u8]>) -> Result<Option<Box<[u8]>>> {
+        let key = key.as_ref();
+        run_op!(self, "list_left_pop", write, Some(KeyType::List), key, {
+            let _guard = self.list_lock.read().unwrap();
+            match self.get_meta(key)? {
+                None => Ok(None),
+                Some(mut meta) => {
+                    let (left, right) = meta.decode_list_extra();
+                    let full_key = encode_data_key_list_item(meta.id, left + 1);
+                    match self.rocksdb.get(full_key.as_ref())? {
+                        Some(value) => {
+                            meta.encode_list_extra(left + 1, right);
+                            meta.count -= 1;
+                            let mut batch = WriteBatch::default();
+                            batch.delete(full_key.as_ref());
+                            self.save_meta_to_batch(&mut batch, key, &meta, true)?;
+                            self.rocksdb.write(batch)?;
+                            Ok(Some(Box::from(value)))
+                        }
+                        None => Ok(None),
                     }
-                    None => Ok(None),
                 }
             }
+        })
+    }
+
+    pub fn list_right_pop(&self, key: impl AsRef<[u8]>) -> Result<Option<Box<[u8]>>> {
+        let key = key.as_ref();
+        run_op!(self, "list_right_pop", write, Some(KeyType::List), key, {
+            let _guard = self.list_lock.read().unwrap();
+            match self.get_meta(key)? {
+                None => Ok(None),
+                Some(mut meta) => {
+                    let (left, right) = meta.decode_list_extra();
+                    let full_key = encode_data_key_list_item(meta.id, right - 1);
+                    match self.rocksdb.get(full_key.as_ref())? {
+                        Some(value) => {
+                            meta.encode_list_extra(left, right - 1);
+                            meta.count -= 1;
+                            let mut batch = WriteBatch::default();
+                            batch.delete(full_key.as_ref());
+                            self.save_meta_to_batch(&mut batch, key, &meta, true)?;
+                            self.rocksdb.write(batch)?;
+                            Ok(Some(Box::from(value)))
+                        }
+                        None => Ok(None),
+                    }
+                }
+            }
+        })
+    }
+
+    /// Pop at most `n` values from the left end of a list, the values and the meta are written
+    /// in a single batch. Returns the values in the order they are popped.
+    pub fn list_left_pop_n(&self, key: impl AsRef<[u8]>, n: usize) -> Result<Vec<Box<[u8]>>> {
+        let key = key.as_ref();
+        run_op!(self, "list_left_pop_n", write, Some(KeyType::List), key, {
+            self.list_pop_n(key, n, true)
+        })
+    }
+
+    /// Same as `list_left_pop_n()`, but pop from the right end.
+    pub fn list_right_pop_n(&self, key: impl AsRef<[u8]>, n: usize) -> Result<Vec<Box<[u8]>>> {
+        let key = key.as_ref();
+        run_op!(self, "list_right_pop_n", write, Some(KeyType::List), key, {
+            self.list_pop_n(key, n, false)
+        })
+    }
+
+    fn list_pop_n(&self, key: &[u8], n: usize, from_left: bool) -> Result<Vec<Box<[u8]>>> {
+        let _guard = self.list_lock.read().unwrap();
+        let mut meta = match self.get_meta(key)? {
+            None => return Ok(Vec::new()),
+            Some(meta) => meta,
+        };
+        check_key_type(key, &meta, KeyType::List)?;
+        let (left, right) = meta.decode_list_extra();
+        let n = n.min(meta.count as usize) as i64;
+        let positions: Vec<i64> = if from_left {
+            (left + 1..left + 1 + n).collect()
+        } else {
+            (right - n..right).rev().collect()
+        };
+        let full_keys: Vec<_> = positions
+            .iter()
+            .map(|p| encode_data_key_list_item(meta.id, *p))
+            .collect();
+        let mut batch = WriteBatch::default();
+        let mut values = Vec::with_capacity(full_keys.len());
+        for (full_key, value) in full_keys.iter().zip(self.rocksdb.multi_get(&full_keys)) {
+            match value? {
+                Some(value) => {
+                    batch.delete(full_key);
+                    values.push(Box::from(value));
+                }
+                None => break,
+            }
         }
+        if values.is_empty() {
+            return Ok(values);
+        }
+        let popped = values.len() as i64;
+        if from_left {
+            meta.encode_list_extra(left + popped, right);
+        } else {
+            meta.encode_list_extra(left, right - popped);
+        }
+        meta.count -= popped as u64;
+        self.save_meta_to_batch(&mut batch, key, &meta, true)?;
+        self.rocksdb.write(batch)?;
+        Ok(values)
     }
 
-    pub fn list_right_pop(&self, key: &str) -> Result<Option<Box<[u8]>>> {
-        match self.get_meta(key)? {
-            None => Ok(None),
-            Some(mut meta) => {
-                let (left, right) = meta.decode_list_extra();
-                let full_key = encode_data_key_list_item(meta.id, right - 1);
-                match self.rocksdb.get(full_key.as_ref())? {
-                    Some(value) => {
-                        meta.encode_list_extra(left, right - 1);
-                        meta.count -= 1;
-                        self.save_meta(key, &meta, true)?;
-                        self.rocksdb.delete(full_key.as_ref())?;
-                        Ok(Some(Box::from(value)))
+    /// Rotate the list atomically, a positive `n` moves `n` items from the left end to the right end,
+    /// a negative `n` moves items from the right end to the left end. Returns the moved items count.
+    pub fn list_rotate(&self, key: impl AsRef<[u8]>, n: i64) -> Result<u64> {
+        let key = key.as_ref();
+        run_op!(self, "list_rotate", write, Some(KeyType::List), key, {
+            let _guard = self.list_lock.read().unwrap();
+            match self.get_meta(key)? {
+                None => Ok(0),
+                Some(mut meta) => {
+                    check_key_type(key, &meta, KeyType::List)?;
+                    if meta.count < 1 {
+                        return Ok(0);
+                    }
+                    let moves = n.unsigned_abs() % meta.count;
+                    if moves == 0 {
+                        return Ok(0);
                     }
-                    None => Ok(None),
+                    let (left, right) = meta.decode_list_extra();
+                    let moves_i64 = moves as i64;
+                    let shifted = if n > 0 {
+                        right.checked_add(moves_i64)
+                    } else {
+                        left.checked_sub(moves_i64)
+                    };
+                    if shifted.is_none() {
+                        return Err(Error::ListOverflow(
+                            String::from_utf8_lossy(key).into_owned(),
+                        ));
+                    }
+                    let mut batch = WriteBatch::default();
+                    for i in 0..moves_i64 {
+                        let (from, to) = if n > 0 {
+                            (left + 1 + i, right + i)
+                        } else {
+                            (right - 1 - i, left - i)
+                        };
+                        let from = encode_data_key_list_item(meta.id, from);
+                        let value = self.rocksdb.get(&from)?.ok_or_else(|| {
+                            Error::Message(format!(
+                                "missing list item of key {}",
+                                String::from_utf8_lossy(key)
+                            ))
+                        })?;
+                        batch.delete(from);
+                        batch.put(encode_data_key_list_item(meta.id, to), value);
+                    }
+                    if n > 0 {
+                        meta.encode_list_extra(left + moves_i64, right + moves_i64);
+                    } else {
+                        meta.encode_list_extra(left - moves_i64, right - moves_i64);
+                    }
+                    self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+                    self.rocksdb.write(batch)?;
+                    Ok(moves)
                 }
             }
+        })
+    }
+
+    /// Remove repeated values of the list, keep the first occurrence and compact positions,
+    /// returns the removed items count.
+    pub fn list_dedupe(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        let key = key.as_ref();
+        run_op!(self, "list_dedupe", write, Some(KeyType::List), key, {
+            let _guard = self.list_lock.read().unwrap();
+            match self.get_meta(key)? {
+                None => Ok(0),
+                Some(mut meta) => {
+                    check_key_type(key, &meta, KeyType::List)?;
+                    let (left, right) = meta.decode_list_extra();
+                    let mut seen = HashSet::new();
+                    let mut batch = WriteBatch::default();
+                    let mut position = left + 1;
+                    let mut removed = 0;
+                    self.list_for_each(key, |v| {
+                        if seen.contains(&v) {
+                            removed += 1;
+                        } else {
+                            if removed > 0 {
+                                batch.put(encode_data_key_list_item(meta.id, position), &v);
+                            }
+                            position += 1;
+                            seen.insert(v);
+                        }
+                        true
+                    })?;
+                    if removed > 0 {
+                        for p in position..right {
+                            batch.delete(encode_data_key_list_item(meta.id, p));
+                        }
+                        meta.encode_list_extra(left, position);
+                        meta.count -= removed;
+                        self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+                        self.rocksdb.write(batch)?;
+                    }
+                    Ok(removed)
+                }
+            }
+        })
+    }
+
+    /// Rewrite the list items to a fresh range of positions centered around zero, the order of
+    /// items is kept. Use it when pushes fail because positions reach the bounds of `i64`.
+    /// Returns the rewritten items count.
+    ///
+    /// The items are read from a snapshot and written in batches of `LIST_REINDEX_BATCH_SIZE`
+    /// under a new key ID, then the meta is switched together with the deletes of the old items
+    /// in a single batch. It's journaled like `rename_key_rewriting()`, so an interrupted
+    /// reindex is rolled back. The list writes of the other threads wait for it, it fails if
+    /// the list is written by a `WritePipeline` meanwhile.
+    pub fn list_reindex(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        let key = key.as_ref();
+        self.check_writable(key)?;
+        let _guard = self.list_lock.write().unwrap();
+        let key = self.resolve_key(key).into_owned();
+        let snapshot = self.rocksdb.snapshot();
+        let stored = match snapshot.get(encode_meta_key(&key))? {
+            None => return Ok(0),
+            Some(v) => v,
+        };
+        let meta = KeyMeta::from_bytes(&stored);
+        check_key_type(&key, &meta, KeyType::List)?;
+        let (left, _) = meta.decode_list_extra();
+        let count = meta.count as i64;
+        let new_left = -(count / 2) - 1;
+        let new_right = new_left + count + 1;
+        if new_left == left {
+            return Ok(0);
+        }
+        let mut new_meta = meta.clone();
+        new_meta.id = self.allocate_key_id()?;
+        new_meta.encode_list_extra(new_left, new_right);
+        let journal_key = encode_journal_key([&key[..], &new_meta.id.to_be_bytes()].concat());
+        self.rocksdb.put(&journal_key, new_meta.id.to_be_bytes())?;
+
+        let mut batch = WriteBatch::default();
+        let prefix = encode_data_key(meta.id);
+        let iter = snapshot
+            .iterator_opt(
+                IteratorMode::From(&prefix, Direction::Forward),
+                self.scan_read_options(),
+            )
+            .take_while(|(k, _)| has_prefix(&prefix, k));
+        for (position, (_, v)) in (new_left + 1..).zip(iter) {
+            batch.put(encode_data_key_list_item(new_meta.id, position), v);
+            if batch.len() >= LIST_REINDEX_BATCH_SIZE {
+                self.rocksdb.write(std::mem::take(&mut batch))?;
+            }
+        }
+        // the read offsets of the consumer groups move with the items
+        let prefix = encode_consumer_key_prefix(meta.id);
+        let iter = snapshot
+            .iterator(IteratorMode::From(&prefix, Direction::Forward))
+            .take_while(|(k, _)| has_prefix(&prefix, k));
+        for (k, v) in iter {
+            if v.len() == 8 {
+                let next = v.as_ref().get_i64() - left + new_left;
+                let group = String::from_utf8_lossy(&k[prefix.len()..]);
+                batch.put(encode_consumer_key(new_meta.id, &group), next.to_be_bytes());
+            }
         }
+
+        if self.rocksdb.get_pinned(encode_meta_key(&key))?.as_deref() != Some(&stored[..]) {
+            let mut batch = WriteBatch::default();
+            self.delete_range_to_batch(
+                &mut batch,
+                encode_data_key(new_meta.id),
+                encode_data_key(new_meta.id + 1),
+            );
+            batch.delete(&journal_key);
+            self.rocksdb.write(batch)?;
+            return Err(Error::Message(format!(
+                "list {} is written during the reindex",
+                String::from_utf8_lossy(&key)
+            )));
+        }
+        self.delete_range_to_batch(
+            &mut batch,
+            encode_data_key(meta.id),
+            encode_data_key(meta.id + 1),
+        );
+        self.delete_consumers_to_batch(&mut batch, &meta);
+        self.save_meta_to_batch(&mut batch, &key, &new_meta, false)?;
+        batch.delete(&journal_key);
+        self.rocksdb.write(batch)?;
+        Ok(new_meta.count)
     }
 
-    pub fn list_for_each<F>(&self, key: &str, mut f: F) -> Result<u64>
+    pub fn list_for_each<F>(&self, key: impl AsRef<[u8]>, mut f: F) -> Result<u64>
     where
         F: FnMut(Box<[u8]>) -> bool,
     {
+        let key = key.as_ref();
         self.for_each_data(key, None, |_, v| f(v))
     }
 
-    pub fn list_items(&self, key: &str) -> Result<Vec<Box<[u8]>>> {
+    #[allow(clippy::unnecessary_cast)]
+    pub fn list_items(&self, key: impl AsRef<[u8]>) -> Result<Vec<Box<[u8]>>> {
+        let key = key.as_ref();
         let count = self.get_count(key)?;
         let mut vec = Vec::with_capacity(count as u64 as usize);
         self.list_for_each(key, |v| {
@@ -588,53 +3088,406 @@ impl Database {
         Ok(vec)
     }
 
-    pub fn sorted_list_count(&self, key: &str) -> Result<u64> {
+    /// Get the list items from index `start` to `stop` (both inclusive) by seeking to the first one.
+    /// A negative index counts from the end of the list, `-1` is the last item.
+    pub fn list_items_range(
+        &self,
+        key: impl AsRef<[u8]>,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Box<[u8]>>> {
+        let key = key.as_ref();
+        let meta = match self.get_meta(key)? {
+            None => return Ok(Vec::new()),
+            Some(meta) => meta,
+        };
+        check_key_type(key, &meta, KeyType::List)?;
+        let count = meta.count as i64;
+        let start = if start < 0 { count + start } else { start }.max(0);
+        let stop = if stop < 0 { count + stop } else { stop }.min(count - 1);
+        if start > stop {
+            return Ok(Vec::new());
+        }
+        let (left, _) = meta.decode_list_extra();
+        let prefix = encode_data_key(meta.id);
+        let first = encode_data_key_list_item(meta.id, left + 1 + start);
+        let iter = self
+            .rocksdb
+            .iterator(IteratorMode::From(&first, Direction::Forward));
+        let mut vec = Vec::with_capacity((stop - start + 1) as usize);
+        for (k, v) in iter.take((stop - start + 1) as usize) {
+            if !has_prefix(&prefix, &k) {
+                break;
+            }
+            vec.push(v);
+        }
+        Ok(vec)
+    }
+
+    pub fn sorted_list_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        let key = key.as_ref();
         self.get_count(key)
     }
 
-    pub fn sorted_list_add(&self, key: &str, score: &[u8], value: &[u8]) -> Result<u64> {
-        let mut meta = self.get_or_create_meta(key, KeyType::SortedList)?;
-        let (sequence, left_deleted_count, right_deleted_count) = meta.decode_sorted_list_extra();
-        let full_key = encode_data_key_sorted_list_item(meta.id, score, sequence);
-        meta.encode_sorted_list_extra(sequence + 1, left_deleted_count, right_deleted_count);
-        meta.count += 1;
-        self.rocksdb.put(full_key, value)?;
-        self.save_meta(key, &meta, false)?;
-        Ok(meta.count)
+    pub fn sorted_list_add(
+        &self,
+        key: impl AsRef<[u8]>,
+        score: &[u8],
+        value: &[u8],
+    ) -> Result<u64> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_list_add",
+            multi_write,
+            Some(KeyType::SortedList),
+            key,
+            {
+                self.check_schema_value(key, value)?;
+                let mut meta = self.get_or_create_meta(key, KeyType::SortedList)?;
+                if meta.decode_sorted_list_extra().0 == u64::MAX {
+                    self.sorted_list_reseed(key)?;
+                    meta = self.get_or_create_meta(key, KeyType::SortedList)?;
+                }
+                self.check_max_count(key, &meta)?;
+                let (sequence, left_deleted_count, right_deleted_count) =
+                    meta.decode_sorted_list_extra();
+                let full_key = encode_data_key_sorted_list_item(meta.id, score, sequence);
+                meta.encode_sorted_list_extra(
+                    sequence + 1,
+                    left_deleted_count,
+                    right_deleted_count,
+                );
+                meta.count += 1;
+                self.rocksdb.put(full_key, value)?;
+                self.save_meta(key, &meta, false)?;
+                self.sorted_list_apply_retention(
+                    key,
+                    &mut meta,
+                    Some(SORTED_LIST_TRIM_STEP),
+                    true,
+                )?;
+                Ok(meta.count)
+            }
+        )
+    }
+
+    /// Rewrite the sequences of all items from zero, the order of items is kept. It runs
+    /// automatically by `sorted_list_add()` when the sequence reaches `u64::MAX`.
+    ///
+    /// The items are read from a snapshot and moved in batches of
+    /// `SORTED_LIST_RESEED_BATCH_SIZE` writes, every batch deletes the old rows of the items it
+    /// puts, and the meta is saved with the last batch. Returns the rewritten items count.
+    ///
+    /// Fails if the list has reserved items, their tokens keep the old sequences, see
+    /// `sorted_list_reserve()`.
+    pub fn sorted_list_reseed(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        let _guard = self.sorted_list_pop_lock.lock().unwrap();
+        self.sorted_list_reseed_locked(key.as_ref())
+    }
+
+    /// Same as `sorted_list_reseed()`, the caller holds `sorted_list_pop_lock`, so no item
+    /// is popped or reserved meanwhile.
+    fn sorted_list_reseed_locked(&self, key: &[u8]) -> Result<u64> {
+        self.check_writable(key)?;
+        let meta = match self.get_meta(key)? {
+            Some(m) => m,
+            None => return Ok(0),
+        };
+        let mut has_reserved = false;
+        self.prefix_iterator(&encode_reserved_key_prefix(meta.id), |_, _| {
+            has_reserved = true;
+            false
+        });
+        if has_reserved {
+            return Err(Error::Message(format!(
+                "can not reseed sorted list {} with reserved items",
+                String::from_utf8_lossy(key)
+            )));
+        }
+        let snapshot = self.rocksdb.snapshot();
+        let prefix = encode_data_key(meta.id);
+        let iter = snapshot
+            .iterator_opt(
+                IteratorMode::From(&prefix, Direction::Forward),
+                self.scan_read_options(),
+            )
+            .take_while(|(k, _)| has_prefix(&prefix, k));
+        let mut batch = WriteBatch::default();
+        let mut count = 0;
+        // the score of the current run of equal scores and the new sequence of its first item
+        let mut run: Option<(Box<[u8]>, u64)> = None;
+        for (k, v) in iter {
+            let score = decode_data_key_sorted_list_item(&k);
+            let first = match &run {
+                Some((s, first)) if s.as_ref() == score => *first,
+                _ => {
+                    run = Some((Box::from(score), count));
+                    count
+                }
+            };
+            // the old row may be the new row of a previous item of the same score, which is
+            // put already, then it's overwritten instead of deleted
+            if !(first..count).contains(&decode_data_key_sorted_list_sequence(&k)) {
+                batch.delete(&k);
+            }
+            batch.put(encode_data_key_sorted_list_item(meta.id, score, count), v);
+            count += 1;
+            if batch.len() >= SORTED_LIST_RESEED_BATCH_SIZE {
+                self.rocksdb.write(std::mem::take(&mut batch))?;
+            }
+        }
+        // the counts may be changed by the other threads meanwhile
+        let mut meta = match self.get_meta(key)? {
+            Some(m) if m.id == meta.id => m,
+            _ => meta,
+        };
+        meta.encode_sorted_list_extra(count, 0, 0);
+        self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+        self.rocksdb.write(batch)?;
+        self.compact_range(
+            Some(encode_data_key(meta.id).as_ref()),
+            Some(encode_data_key(meta.id + 1).as_ref()),
+        );
+        Ok(count)
+    }
+
+    /// Compact the deleted items of a sorted list now, e.g. after a burst of pops on a hot
+    /// queue, and reset its deletes counters. Returns `false` if the key does not exist.
+    pub fn sorted_list_compact(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_list_compact",
+            write,
+            Some(KeyType::SortedList),
+            key,
+            {
+                let mut meta = match self.get_meta(key)? {
+                    Some(m) => m,
+                    None => return Ok(false),
+                };
+                check_key_type(key, &meta, KeyType::SortedList)?;
+                let (sequence, _, _) = meta.decode_sorted_list_extra();
+                meta.encode_sorted_list_extra(sequence, 0, 0);
+                self.save_meta(key, &meta, false)?;
+                self.compact_range(
+                    Some(encode_data_key(meta.id).as_ref()),
+                    Some(encode_data_key(meta.id + 1).as_ref()),
+                );
+                Ok(true)
+            }
+        )
+    }
+
+    /// Override `Options::sorted_list_compact_deletes_count` for a sorted list, the count is
+    /// stored in the key meta. Pass 0 to use the option again.
+    /// Returns `false` if the key does not exist.
+    pub fn sorted_list_set_compact_deletes_count(
+        &self,
+        key: impl AsRef<[u8]>,
+        count: u32,
+    ) -> Result<bool> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_list_set_compact_deletes_count",
+            write,
+            Some(KeyType::SortedList),
+            key,
+            {
+                let mut meta = match self.get_meta(key)? {
+                    Some(m) => m,
+                    None => return Ok(false),
+                };
+                check_key_type(key, &meta, KeyType::SortedList)?;
+                meta.encode_sorted_list_compact_deletes_count(count);
+                self.save_meta(key, &meta, false)?;
+                Ok(true)
+            }
+        )
+    }
+
+    /// Get the deletes count to compact a sorted list or a sorted set, the override in the
+    /// meta, the `KeyConfig` of the key or the option.
+    pub(crate) fn sorted_list_compact_deletes_count(
+        &self,
+        key: impl AsRef<[u8]>,
+        meta: &KeyMeta,
+    ) -> u32 {
+        match meta.decode_sorted_list_compact_deletes_count() {
+            0 => self
+                .key_config(key)
+                .and_then(|c| c.compact_deletes_count)
+                .unwrap_or(self.options.sorted_list_compact_deletes_count),
+            count => count,
+        }
     }
 
     pub fn sorted_list_left_pop(
         &self,
-        key: &str,
+        key: impl AsRef<[u8]>,
         max_score: Option<&[u8]>,
     ) -> Result<Option<ScoreVal>> {
-        let meta = self.get_meta(key)?;
-        if let Some(mut meta) = meta {
-            let (sequence, left_deleted_count, right_deleted_count) =
-                meta.decode_sorted_list_extra();
-            let prefix = encode_data_key(meta.id);
-            let mut opts = ReadOptions::default();
-            opts.set_prefix_same_as_start(true);
-            let mut iter = self
-                .rocksdb
-                .iterator_opt(IteratorMode::From(&prefix, Direction::Forward), opts);
-            if let Some((k, v)) = iter.next() {
-                if !has_prefix(&prefix, k.as_ref()) {
-                    return Ok(None);
+        let key = key.as_ref();
+        Ok(self
+            .sorted_list_left_pop_with_sequence(key, max_score)?
+            .map(|(score, _, value)| (score, value)))
+    }
+
+    /// Same as `sorted_list_left_pop()`, but the sequence of the item is returned too.
+    pub fn sorted_list_left_pop_with_sequence(
+        &self,
+        key: impl AsRef<[u8]>,
+        max_score: Option<&[u8]>,
+    ) -> Result<Option<ScoreSeqVal>> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_list_left_pop",
+            multi_write,
+            Some(KeyType::SortedList),
+            key,
+            {
+                let _guard = self.sorted_list_pop_lock.lock().unwrap();
+                let meta = self.get_meta(key)?;
+                if let Some(mut meta) = meta {
+                    self.sorted_list_apply_retention(
+                        key,
+                        &mut meta,
+                        Some(SORTED_LIST_TRIM_STEP),
+                        true,
+                    )?;
+                    let (sequence, left_deleted_count, right_deleted_count) =
+                        meta.decode_sorted_list_extra();
+                    let prefix = encode_data_key(meta.id);
+                    let mut opts = ReadOptions::default();
+                    opts.set_prefix_same_as_start(true);
+                    let mut iter = self
+                        .rocksdb
+                        .iterator_opt(IteratorMode::From(&prefix, Direction::Forward), opts);
+                    if let Some((k, v)) = iter.next() {
+                        if !has_prefix(&prefix, k.as_ref()) {
+                            return Ok(None);
+                        }
+                        let score = decode_data_key_sorted_list_item(k.as_ref());
+                        if let Some(max_score) = max_score {
+                            if compare_score_bytes(score, max_score) > 0 {
+                                return Ok(None);
+                            }
+                        }
+                        self.rocksdb.delete(k.as_ref())?;
+                        meta.count -= 1;
+                        if left_deleted_count > 0
+                            && left_deleted_count
+                                % self.sorted_list_compact_deletes_count(key, &meta)
+                                == 0
+                        {
+                            self.compact_range(
+                                Some(encode_data_key(meta.id).as_ref()),
+                                Some(k.as_ref()),
+                            );
+                            meta.encode_sorted_list_extra(sequence, 0, right_deleted_count);
+                        } else {
+                            meta.encode_sorted_list_extra(
+                                sequence,
+                                left_deleted_count + 1,
+                                right_deleted_count,
+                            );
+                        }
+                        self.save_meta(key, &meta, true)?;
+                        return Ok(Some((
+                            Box::from(score),
+                            decode_data_key_sorted_list_sequence(k.as_ref()),
+                            v,
+                        )));
+                    }
                 }
-                let score = decode_data_key_sorted_list_item(k.as_ref());
-                if let Some(max_score) = max_score {
-                    if compare_score_bytes(score, max_score) > 0 {
-                        return Ok(None);
+                Ok(None)
+            }
+        )
+    }
+
+    /// Pop the item with the minimum score across the sorted lists of `keys`, e.g. for a
+    /// scheduler serving several queues, so the queues checked last are not starved. The ties
+    /// of the minimum score are broken round-robin across the calls. The items are peeked
+    /// first, then the chosen one is popped in a single write batch, the other pops of the
+    /// sorted lists wait in between. Fails if a key is not a sorted list. Returns the index of the
+    /// key in `keys` and the item, `None` if all the lists are empty or the minimum score is
+    /// greater than `max_score`.
+    pub fn sorted_list_pop_any<K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+        max_score: Option<&[u8]>,
+    ) -> Result<Option<(usize, ScoreVal)>> {
+        run_op!(
+            self,
+            "sorted_list_pop_any",
+            multi_write,
+            Some(KeyType::SortedList),
+            [keys],
+            {
+                let _guard = self.sorted_list_pop_lock.lock().unwrap();
+                // the keys take turns to win the ties, starting from the key at `turn`
+                let turn = self.pop_any_turn.fetch_add(1, Ordering::Relaxed) as usize;
+                let rank = |i: usize| (i + keys.len() - turn % keys.len()) % keys.len();
+                let mut best: Option<(usize, KeyMeta, KVBytes)> = None;
+                for (i, key) in keys.iter().enumerate() {
+                    let key = key.as_ref();
+                    let mut meta = match self.get_meta(key)? {
+                        Some(meta) => meta,
+                        None => continue,
+                    };
+                    check_key_type(key, &meta, KeyType::SortedList)?;
+                    self.sorted_list_apply_retention(
+                        key,
+                        &mut meta,
+                        Some(SORTED_LIST_TRIM_STEP),
+                        true,
+                    )?;
+                    let prefix = encode_data_key(meta.id);
+                    let mut opts = ReadOptions::default();
+                    opts.set_prefix_same_as_start(true);
+                    let item = self
+                        .rocksdb
+                        .iterator_opt(IteratorMode::From(&prefix, Direction::Forward), opts)
+                        .next()
+                        .filter(|(k, _)| has_prefix(&prefix, k.as_ref()));
+                    let (k, v) = match item {
+                        Some(item) => item,
+                        None => continue,
+                    };
+                    let score = decode_data_key_sorted_list_item(k.as_ref());
+                    if let Some(max_score) = max_score {
+                        if compare_score_bytes(score, max_score) > 0 {
+                            continue;
+                        }
+                    }
+                    let better = match &best {
+                        None => true,
+                        Some((j, _, (best_key, _))) => {
+                            let best_score = decode_data_key_sorted_list_item(best_key);
+                            match compare_score_bytes(score, best_score) {
+                                0 => rank(i) < rank(*j),
+                                c => c < 0,
+                            }
+                        }
+                    };
+                    if better {
+                        best = Some((i, meta, (k, v)));
                     }
                 }
-                self.rocksdb.delete(k.as_ref())?;
-                meta.count -= 1;
-                if left_deleted_count > 0
-                    && left_deleted_count % self.options.sorted_list_compact_deletes_count == 0
-                {
-                    self.rocksdb
-                        .compact_range(Some(encode_data_key(meta.id).as_ref()), Some(k.as_ref()));
+                let (i, mut meta, (k, v)) = match best {
+                    Some(best) => best,
+                    None => return Ok(None),
+                };
+                let (sequence, left_deleted_count, right_deleted_count) =
+                    meta.decode_sorted_list_extra();
+                let compact = left_deleted_count > 0
+                    && left_deleted_count
+                        % self.sorted_list_compact_deletes_count(keys[i].as_ref(), &meta)
+                        == 0;
+                if compact {
                     meta.encode_sorted_list_extra(sequence, 0, right_deleted_count);
                 } else {
                     meta.encode_sorted_list_extra(
@@ -643,71 +3496,449 @@ impl Database {
                         right_deleted_count,
                     );
                 }
-                self.save_meta(key, &meta, true)?;
-                return Ok(Some((Box::from(score), v)));
+                meta.count -= 1;
+                let mut batch = WriteBatch::default();
+                batch.delete(k.as_ref());
+                self.save_meta_to_batch(&mut batch, keys[i].as_ref(), &meta, true)?;
+                self.rocksdb.write(batch)?;
+                if compact {
+                    self.compact_range(Some(encode_data_key(meta.id).as_ref()), Some(k.as_ref()));
+                }
+                let score = Box::from(decode_data_key_sorted_list_item(k.as_ref()));
+                Ok(Some((i, (score, v))))
+            }
+        )
+    }
+
+    /// Pop the item with the minimum score from `src` and add it to `dst` with the same score,
+    /// in a single write batch. Returns `None` if `src` is empty or the minimum score is greater
+    /// than `max_score`.
+    pub fn sorted_list_move(
+        &self,
+        src: impl AsRef<[u8]>,
+        dst: impl AsRef<[u8]>,
+        max_score: Option<&[u8]>,
+    ) -> Result<Option<ScoreVal>> {
+        let (src, dst) = (src.as_ref(), dst.as_ref());
+        self.check_writable(src)?;
+        self.check_writable(dst)?;
+        if src == dst {
+            return Err(Error::Message(format!(
+                "can not move items of sorted list {} to itself",
+                String::from_utf8_lossy(src)
+            )));
+        }
+        let _guard = self.sorted_list_pop_lock.lock().unwrap();
+        let mut src_meta = match self.get_meta(src)? {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+        self.sorted_list_apply_retention(src, &mut src_meta, Some(SORTED_LIST_TRIM_STEP), true)?;
+        let prefix = encode_data_key(src_meta.id);
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_upper_bound(encode_data_key(src_meta.id + 1).to_vec());
+        let mut iter = self
+            .rocksdb
+            .iterator_opt(IteratorMode::From(&prefix, Direction::Forward), opts);
+        let (k, v) = match iter.next() {
+            Some(item) => item,
+            None => return Ok(None),
+        };
+        let score = decode_data_key_sorted_list_item(k.as_ref());
+        if let Some(max_score) = max_score {
+            if compare_score_bytes(score, max_score) > 0 {
+                return Ok(None);
+            }
+        }
+
+        let mut dst_meta = self.get_or_create_meta(dst, KeyType::SortedList)?;
+        if dst_meta.decode_sorted_list_extra().0 == u64::MAX {
+            self.sorted_list_reseed_locked(dst)?;
+            dst_meta = self.get_or_create_meta(dst, KeyType::SortedList)?;
+        }
+        self.check_max_count(dst, &dst_meta)?;
+        let (dst_sequence, left_deleted_count, right_deleted_count) =
+            dst_meta.decode_sorted_list_extra();
+        dst_meta.encode_sorted_list_extra(
+            dst_sequence + 1,
+            left_deleted_count,
+            right_deleted_count,
+        );
+        dst_meta.count += 1;
+
+        let (sequence, left_deleted_count, right_deleted_count) =
+            src_meta.decode_sorted_list_extra();
+        let compact = left_deleted_count > 0
+            && left_deleted_count % self.sorted_list_compact_deletes_count(src, &src_meta) == 0;
+        src_meta.encode_sorted_list_extra(
+            sequence,
+            if compact { 0 } else { left_deleted_count + 1 },
+            right_deleted_count,
+        );
+        src_meta.count -= 1;
+
+        let mut batch = WriteBatch::default();
+        batch.delete(k.as_ref());
+        batch.put(
+            encode_data_key_sorted_list_item(dst_meta.id, score, dst_sequence),
+            &v,
+        );
+        self.save_meta_to_batch(&mut batch, src, &src_meta, true)?;
+        self.save_meta_to_batch(&mut batch, dst, &dst_meta, false)?;
+        self.rocksdb.write(batch)?;
+        if compact {
+            self.compact_range(Some(prefix.as_ref()), Some(k.as_ref()));
+        }
+        Ok(Some((Box::from(score), v)))
+    }
+
+    pub fn sorted_list_right_pop(
+        &self,
+        key: impl AsRef<[u8]>,
+        min_score: Option<&[u8]>,
+    ) -> Result<Option<ScoreVal>> {
+        let key = key.as_ref();
+        Ok(self
+            .sorted_list_right_pop_with_sequence(key, min_score)?
+            .map(|(score, _, value)| (score, value)))
+    }
+
+    /// Same as `sorted_list_right_pop()`, but the sequence of the item is returned too.
+    pub fn sorted_list_right_pop_with_sequence(
+        &self,
+        key: impl AsRef<[u8]>,
+        min_score: Option<&[u8]>,
+    ) -> Result<Option<ScoreSeqVal>> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_list_right_pop",
+            multi_write,
+            Some(KeyType::SortedList),
+            key,
+            {
+                let _guard = self.sorted_list_pop_lock.lock().unwrap();
+                let meta = self.get_meta(key)?;
+                if let Some(mut meta) = meta {
+                    self.sorted_list_apply_retention(
+                        key,
+                        &mut meta,
+                        Some(SORTED_LIST_TRIM_STEP),
+                        true,
+                    )?;
+                    let (sequence, left_deleted_count, right_deleted_count) =
+                        meta.decode_sorted_list_extra();
+                    let prefix = encode_data_key(meta.id);
+                    let next_prefix = encode_data_key(meta.id + 1);
+                    let opts = ReadOptions::default();
+                    let mut iter = self
+                        .rocksdb
+                        .iterator_opt(IteratorMode::From(&next_prefix, Direction::Reverse), opts);
+                    if let Some((k, v)) = iter.next() {
+                        if !has_prefix(&prefix, k.as_ref()) {
+                            return Ok(None);
+                        }
+                        let score = decode_data_key_sorted_list_item(k.as_ref());
+                        if let Some(min_score) = min_score {
+                            if compare_score_bytes(score, min_score) < 0 {
+                                return Ok(None);
+                            }
+                        }
+                        self.rocksdb.delete(k.as_ref())?;
+                        meta.count -= 1;
+                        if right_deleted_count > 0
+                            && right_deleted_count
+                                % self.sorted_list_compact_deletes_count(key, &meta)
+                                == 0
+                        {
+                            self.compact_range(Some(k.as_ref()), Some(next_prefix.as_ref()));
+                            meta.encode_sorted_list_extra(sequence, left_deleted_count, 0);
+                        } else {
+                            meta.encode_sorted_list_extra(
+                                sequence,
+                                left_deleted_count,
+                                right_deleted_count + 1,
+                            );
+                        }
+                        self.save_meta(key, &meta, true)?;
+                        return Ok(Some((
+                            Box::from(score),
+                            decode_data_key_sorted_list_sequence(k.as_ref()),
+                            v,
+                        )));
+                    }
+                }
+                Ok(None)
+            }
+        )
+    }
+
+    /// Reserve the item with the minimum score, the item is hidden from the list until
+    /// `sorted_list_commit()` deletes it or `sorted_list_release()` puts it back.
+    /// Reservations not committed within `lease_ttl` are put back by the next reserve.
+    /// Returns `(token, score, value)`.
+    pub fn sorted_list_reserve(
+        &self,
+        key: impl AsRef<[u8]>,
+        max_score: Option<&[u8]>,
+        lease_ttl: Duration,
+    ) -> Result<Option<TokenScoreVal>> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_list_reserve",
+            multi_write,
+            Some(KeyType::SortedList),
+            key,
+            {
+                let _guard = self.sorted_list_pop_lock.lock().unwrap();
+                let mut meta = match self.get_meta(key)? {
+                    Some(m) => m,
+                    None => return Ok(None),
+                };
+                check_key_type(key, &meta, KeyType::SortedList)?;
+                let now = now_millis();
+                self.sorted_list_restore_expired(key, &mut meta, now)?;
+                // keep the meta, the reserved items refer to the key ID
+                self.sorted_list_apply_retention(
+                    key,
+                    &mut meta,
+                    Some(SORTED_LIST_TRIM_STEP),
+                    false,
+                )?;
+
+                let prefix = encode_data_key(meta.id);
+                let mut opts = ReadOptions::default();
+                opts.set_iterate_upper_bound(encode_data_key(meta.id + 1).to_vec());
+                let mut iter = self
+                    .rocksdb
+                    .iterator_opt(IteratorMode::From(&prefix, Direction::Forward), opts);
+                let (k, v) = match iter.next() {
+                    Some(item) => item,
+                    None => return Ok(None),
+                };
+                let score = decode_data_key_sorted_list_item(k.as_ref());
+                if let Some(max_score) = max_score {
+                    if compare_score_bytes(score, max_score) > 0 {
+                        return Ok(None);
+                    }
+                }
+                let token = encode_reserved_key(k.as_ref());
+                let expires_at = now.saturating_add(lease_ttl.as_millis() as u64);
+                let mut batch = WriteBatch::default();
+                batch.delete(k.as_ref());
+                batch.put(&token, encode_reserved_value(expires_at, key, &v));
+                meta.count -= 1;
+                // keep the meta, the reserved items refer to the key ID
+                self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+                self.rocksdb.write(batch)?;
+                Ok(Some((Box::from(token.as_ref()), Box::from(score), v)))
+            }
+        )
+    }
+
+    /// Delete a reserved item, returns `false` if the reservation does not exist.
+    pub fn sorted_list_commit(&self, token: &[u8]) -> Result<bool> {
+        if !has_prefix(PREFIX_RESERVED, token) {
+            return Ok(false);
+        }
+        // the reservation is read under the lock, so it's committed or released only once
+        let _guard = self.sorted_list_pop_lock.lock().unwrap();
+        let reserved = match self.rocksdb.get(token)? {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        let (_, key, _) = decode_reserved_value(&reserved);
+        run_op!(
+            self,
+            "sorted_list_commit",
+            multi_write,
+            Some(KeyType::SortedList),
+            key,
+            {
+                let mut batch = WriteBatch::default();
+                batch.delete(token);
+                if let Some(meta) = self.get_meta(key)? {
+                    if meta.count < 1 && meta.id.to_be_bytes() == token[1..9] {
+                        let prefix = encode_reserved_key_prefix(meta.id);
+                        let mut reserved_count = 0;
+                        self.prefix_iterator(&prefix, |_, _| {
+                            reserved_count += 1;
+                            reserved_count < 2
+                        });
+                        // the token itself is the only reservation left
+                        if reserved_count < 2 {
+                            self.save_meta_to_batch(&mut batch, key, &meta, true)?;
+                        }
+                    }
+                }
+                self.rocksdb.write(batch)?;
+                Ok(true)
+            }
+        )
+    }
+
+    /// Put a reserved item back to the list, returns `false` if the reservation does not exist.
+    pub fn sorted_list_release(&self, token: &[u8]) -> Result<bool> {
+        if !has_prefix(PREFIX_RESERVED, token) {
+            return Ok(false);
+        }
+        // the reservation is read under the lock, so it's committed or released only once
+        let _guard = self.sorted_list_pop_lock.lock().unwrap();
+        let reserved = match self.rocksdb.get(token)? {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        let (_, key, value) = decode_reserved_value(&reserved);
+        run_op!(
+            self,
+            "sorted_list_release",
+            multi_write,
+            Some(KeyType::SortedList),
+            key,
+            {
+                let mut batch = WriteBatch::default();
+                batch.delete(token);
+                if let Some(mut meta) = self.get_meta(key)? {
+                    if meta.id.to_be_bytes() == token[1..9] {
+                        batch.put(decode_reserved_key(token), value);
+                        meta.count += 1;
+                        self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+                    }
+                }
+                self.rocksdb.write(batch)?;
+                Ok(true)
+            }
+        )
+    }
+
+    /// Put the expired reserved items of a `sorted list` back together with the updated meta,
+    /// returns the restored items count.
+    fn sorted_list_restore_expired(&self, key: &[u8], meta: &mut KeyMeta, now: u64) -> Result<u64> {
+        let mut batch = WriteBatch::default();
+        let mut counter = 0;
+        self.prefix_iterator(&encode_reserved_key_prefix(meta.id), |k, v| {
+            let (expires_at, _, value) = decode_reserved_value(&v);
+            if expires_at <= now {
+                batch.delete(&k);
+                batch.put(decode_reserved_key(&k), value);
+                counter += 1;
             }
+            true
+        });
+        if counter > 0 {
+            meta.count += counter;
+            self.save_meta_to_batch(&mut batch, key, meta, false)?;
+            self.rocksdb.write(batch)?;
         }
-        Ok(None)
+        Ok(counter)
     }
 
-    pub fn sorted_list_right_pop(
+    /// Count items with score between `min_score` and `max_score` (inclusive), only keys are scanned.
+    pub fn sorted_list_count_in_range(
         &self,
-        key: &str,
-        min_score: Option<&[u8]>,
-    ) -> Result<Option<ScoreVal>> {
-        let meta = self.get_meta(key)?;
-        if let Some(mut meta) = meta {
-            let (sequence, left_deleted_count, right_deleted_count) =
-                meta.decode_sorted_list_extra();
-            let prefix = encode_data_key(meta.id);
-            let next_prefix = encode_data_key(meta.id + 1);
-            let opts = ReadOptions::default();
-            let mut iter = self
-                .rocksdb
-                .iterator_opt(IteratorMode::From(&next_prefix, Direction::Reverse), opts);
-            if let Some((k, v)) = iter.next() {
-                if !has_prefix(&prefix, k.as_ref()) {
-                    return Ok(None);
-                }
-                let score = decode_data_key_sorted_list_item(k.as_ref());
-                if let Some(min_score) = min_score {
-                    if compare_score_bytes(score, min_score) < 0 {
-                        return Ok(None);
-                    }
-                }
-                self.rocksdb.delete(k.as_ref())?;
-                meta.count -= 1;
-                if right_deleted_count > 0
-                    && right_deleted_count % self.options.sorted_list_compact_deletes_count == 0
-                {
-                    self.rocksdb
-                        .compact_range(Some(k.as_ref()), Some(next_prefix.as_ref()));
-                    meta.encode_sorted_list_extra(sequence, left_deleted_count, 0);
-                } else {
-                    meta.encode_sorted_list_extra(
-                        sequence,
-                        left_deleted_count,
-                        right_deleted_count + 1,
-                    );
-                }
-                self.save_meta(key, &meta, true)?;
-                return Ok(Some((Box::from(score), v)));
+        key: impl AsRef<[u8]>,
+        min_score: &[u8],
+        max_score: &[u8],
+    ) -> Result<u64> {
+        let key = key.as_ref();
+        let meta = match self.get_meta(key)? {
+            Some(m) if m.count > 0 => m,
+            _ => return Ok(0),
+        };
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_upper_bound(encode_data_key(meta.id + 1).to_vec());
+        let mut iter = self.rocksdb.raw_iterator_opt(opts);
+        iter.seek(encode_data_key_sorted_list_item(meta.id, min_score, 0));
+        let mut counter = 0;
+        while let Some(k) = iter.key() {
+            let score = decode_data_key_sorted_list_item(k);
+            if compare_score_bytes(score, max_score) > 0 {
+                break;
             }
+            counter += 1;
+            iter.next();
         }
-        Ok(None)
+        iter.status()?;
+        Ok(counter)
     }
 
-    pub fn sorted_list_for_each<F>(&self, key: &str, mut f: F) -> Result<u64>
+    pub fn sorted_list_for_each<F>(&self, key: impl AsRef<[u8]>, mut f: F) -> Result<u64>
     where
         F: FnMut((Box<[u8]>, Box<[u8]>)) -> bool,
     {
+        let key = key.as_ref();
         self.for_each_data(key, None, |k, v| {
             let score = decode_data_key_sorted_list_item(k.as_ref());
             f((Box::from(score), v))
         })
     }
 
-    pub fn sorted_list_items(&self, key: &str) -> Result<VecScoreVal> {
+    /// Same as `sorted_list_for_each()`, but the sequence of every item is passed too, the
+    /// score and the sequence identify an item, see `sorted_list_items_after()`.
+    pub fn sorted_list_for_each_with_sequence<F>(
+        &self,
+        key: impl AsRef<[u8]>,
+        mut f: F,
+    ) -> Result<u64>
+    where
+        F: FnMut(ScoreSeqVal) -> bool,
+    {
+        let key = key.as_ref();
+        self.for_each_data(key, None, |k, v| {
+            let score = decode_data_key_sorted_list_item(k.as_ref());
+            f((
+                Box::from(score),
+                decode_data_key_sorted_list_sequence(k.as_ref()),
+                v,
+            ))
+        })
+    }
+
+    /// Get at most `limit` items after the item of `score` and `sequence`, or from the first
+    /// item if `after` is `None`. The last returned item is the cursor of the next page, it's
+    /// stable across the adds and deletes, even among the items of equal scores.
+    pub fn sorted_list_items_after(
+        &self,
+        key: impl AsRef<[u8]>,
+        after: Option<(&[u8], u64)>,
+        limit: usize,
+    ) -> Result<Vec<ScoreSeqVal>> {
+        let key = key.as_ref();
+        let meta = match self.get_meta(key)? {
+            None => return Ok(Vec::new()),
+            Some(meta) => meta,
+        };
+        let prefix = encode_data_key(meta.id);
+        let start = match after {
+            None => prefix.clone(),
+            Some((score, sequence)) => encode_data_key_sorted_list_item(meta.id, score, sequence),
+        };
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_upper_bound(encode_data_key(meta.id + 1).to_vec());
+        let iter = self
+            .rocksdb
+            .iterator_opt(IteratorMode::From(&start, Direction::Forward), opts);
+        let mut vec = Vec::new();
+        for (k, v) in iter {
+            if vec.len() >= limit {
+                break;
+            }
+            if after.is_some() && k.as_ref() == start.as_ref() {
+                continue;
+            }
+            vec.push((
+                Box::from(decode_data_key_sorted_list_item(k.as_ref())),
+                decode_data_key_sorted_list_sequence(k.as_ref()),
+                v,
+            ));
+        }
+        Ok(vec)
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    pub fn sorted_list_items(&self, key: impl AsRef<[u8]>) -> Result<VecScoreVal> {
+        let key = key.as_ref();
         let count = self.get_count(key)?;
         let mut vec = Vec::with_capacity(count as u64 as usize);
         self.sorted_list_for_each(key, |item| {
@@ -717,19 +3948,36 @@ impl Database {
         Ok(vec)
     }
 
-    pub fn sorted_set_count(&self, key: &str) -> Result<u64> {
+    /// Get score distribution statistics of a `sorted list` in one pass.
+    pub fn sorted_list_score_stats(&self, key: impl AsRef<[u8]>) -> Result<ScoreStats> {
+        let key = key.as_ref();
+        let mut builder = ScoreStatsBuilder::new(self.get_count(key)?);
+        self.for_each_data(key, None, |k, _| {
+            builder.push(decode_data_key_sorted_list_item(k.as_ref()));
+            true
+        })?;
+        Ok(builder.finish())
+    }
+
+    pub fn sorted_set_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        let key = key.as_ref();
         self.get_count(key)
     }
 
-    pub fn sorted_set_for_each<F>(&self, key: &str, mut f: F) -> Result<u64>
+    pub fn sorted_set_for_each<F>(&self, key: impl AsRef<[u8]>, mut f: F) -> Result<u64>
     where
         F: FnMut((Box<[u8]>, Box<[u8]>)) -> bool,
     {
+        let key = key.as_ref();
         let score_len = self
             .get_meta(key)?
             .map(|m| m.decode_sorted_set_extra().1)
             .unwrap_or(0);
-        self.for_each_data(key, None, |k, _| {
+        let now = now_millis();
+        self.for_each_data(key, None, |k, v| {
+            if sorted_set_row_expired(&v, now) {
+                return true;
+            }
             f(decode_data_key_sorted_set_item_with_score(
                 k.as_ref(),
                 score_len,
@@ -737,7 +3985,46 @@ impl Database {
         })
     }
 
-    pub fn sorted_set_items(&self, key: &str) -> Result<VecScoreVal> {
+    /// Same as `sorted_set_for_each()`, but from the highest score to the lowest, e.g. to read
+    /// the top members until `f` returns `false`.
+    pub fn sorted_set_for_each_rev<F>(&self, key: impl AsRef<[u8]>, mut f: F) -> Result<u64>
+    where
+        F: FnMut((Box<[u8]>, Box<[u8]>)) -> bool,
+    {
+        let key = key.as_ref();
+        let meta = match self.get_meta(key)? {
+            None => return Ok(0),
+            Some(meta) => meta,
+        };
+        let (_, score_len) = meta.decode_sorted_set_extra();
+        let prefix = encode_data_key_sorted_set_prefix(meta.id);
+        let next_prefix = encode_data_key(meta.id + 1);
+        let iter = self
+            .rocksdb
+            .iterator(IteratorMode::From(&next_prefix, Direction::Reverse));
+        let now = now_millis();
+        let mut counter = 0;
+        for (k, v) in iter {
+            if !has_prefix(&prefix, k.as_ref()) {
+                break;
+            }
+            if sorted_set_row_expired(&v, now) {
+                continue;
+            }
+            counter += 1;
+            if !f(decode_data_key_sorted_set_item_with_score(
+                k.as_ref(),
+                score_len,
+            )) {
+                break;
+            }
+        }
+        Ok(counter)
+    }
+
+    #[allow(clippy::unnecessary_cast)]
+    pub fn sorted_set_items(&self, key: impl AsRef<[u8]>) -> Result<VecScoreVal> {
+        let key = key.as_ref();
         let count = self.get_count(key)?;
         let mut vec = Vec::with_capacity(count as u64 as usize);
         self.sorted_set_for_each(key, |v| {
@@ -747,7 +4034,245 @@ impl Database {
         Ok(vec)
     }
 
-    pub fn sorted_set_add(&self, key: &str, score: &[u8], value: &[u8]) -> Result<u64> {
+    pub fn sorted_set_items_with_limit(
+        &self,
+        key: impl AsRef<[u8]>,
+        limit: usize,
+    ) -> Result<VecScoreVal> {
+        let key = key.as_ref();
+        let mut vec = Vec::with_capacity(limit.min(self.get_count(key)? as usize));
+        if limit == 0 {
+            return Ok(vec);
+        }
+        self.sorted_set_for_each(key, |v| {
+            vec.push(v);
+            vec.len() < limit
+        })?;
+        Ok(vec)
+    }
+
+    /// Write all score/member pairs of a `sorted set` into `writer` without loading them into memory.
+    ///
+    /// Every pair is written as a big-endian u32 length and the score bytes, followed by a
+    /// big-endian u32 length and the member bytes. Returns the count of written pairs.
+    pub fn sorted_set_write_to(
+        &self,
+        key: impl AsRef<[u8]>,
+        mut writer: impl io::Write,
+    ) -> Result<u64> {
+        let key = key.as_ref();
+        let mut has_error = None;
+        let mut count: u64 = 0;
+        self.sorted_set_for_each(key, |(score, member)| {
+            let result = writer
+                .write_all(&(score.len() as u32).to_be_bytes())
+                .and_then(|_| writer.write_all(&score))
+                .and_then(|_| writer.write_all(&(member.len() as u32).to_be_bytes()))
+                .and_then(|_| writer.write_all(&member));
+            match result {
+                Ok(_) => {
+                    count += 1;
+                    true
+                }
+                Err(err) => {
+                    has_error = Some(err);
+                    false
+                }
+            }
+        })?;
+        if let Some(err) = has_error {
+            return Err(err.into());
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Get score distribution statistics of a `sorted set` in one pass.
+    pub fn sorted_set_score_stats(&self, key: impl AsRef<[u8]>) -> Result<ScoreStats> {
+        let key = key.as_ref();
+        let mut builder = ScoreStatsBuilder::new(self.get_count(key)?);
+        self.sorted_set_for_each(key, |(score, _)| {
+            builder.push(score.as_ref());
+            true
+        })?;
+        Ok(builder.finish())
+    }
+
+    /// Get the score at percentile `p` (from 0 to 100) of a `sorted set` using the nearest-rank method.
+    pub fn sorted_set_percentile(
+        &self,
+        key: impl AsRef<[u8]>,
+        p: f64,
+    ) -> Result<Option<Box<[u8]>>> {
+        let key = key.as_ref();
+        if !(0.0..=100.0).contains(&p) {
+            return Err(Error::Message(format!(
+                "invalid percentile {}, expected 0 to 100",
+                p
+            )));
+        }
+        if !matches!(self.get_meta(key)?, Some(m) if m.count > 0) {
+            return Ok(None);
+        }
+        // the expired members are not ranked, so the members are counted by a scan instead of
+        // the meta
+        let mut count = 0;
+        self.sorted_set_for_each(key, |_| {
+            count += 1;
+            true
+        })?;
+        if count == 0 {
+            return Ok(None);
+        }
+        let rank = ((p / 100.0 * count as f64).ceil() as u64).max(1) - 1;
+        let mut index = 0;
+        let mut score = None;
+        self.sorted_set_for_each(key, |(s, _)| {
+            if index == rank {
+                score = Some(s);
+                return false;
+            }
+            index += 1;
+            true
+        })?;
+        Ok(score)
+    }
+
+    /// Count the members of a `sorted set` in the buckets split by the ascending
+    /// `bucket_boundaries`, in a single scan. Returns `bucket_boundaries.len() + 1` counts, the
+    /// bucket `i` has the scores from `bucket_boundaries[i - 1]` (inclusive) to
+    /// `bucket_boundaries[i]` (exclusive), the first and the last buckets are unbounded.
+    pub fn sorted_set_histogram(
+        &self,
+        key: impl AsRef<[u8]>,
+        bucket_boundaries: &[&[u8]],
+    ) -> Result<Vec<u64>> {
+        let key = key.as_ref();
+        if bucket_boundaries
+            .windows(2)
+            .any(|w| compare_score_bytes(w[0], w[1]) >= 0)
+        {
+            return Err(Error::Message(
+                "bucket boundaries must be strictly ascending".to_string(),
+            ));
+        }
+        let mut counts = vec![0; bucket_boundaries.len() + 1];
+        let mut bucket = 0;
+        self.sorted_set_for_each(key, |(score, _)| {
+            // the scores are ascending, so the bucket only moves forward
+            while bucket < bucket_boundaries.len()
+                && compare_score_bytes(&score, bucket_boundaries[bucket]) >= 0
+            {
+                bucket += 1;
+            }
+            counts[bucket] += 1;
+            true
+        })?;
+        Ok(counts)
+    }
+
+    pub fn sorted_set_add(&self, key: impl AsRef<[u8]>, score: &[u8], value: &[u8]) -> Result<u64> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_set_add",
+            write,
+            Some(KeyType::SortedSet),
+            key,
+            { self.sorted_set_add_row(key, score, value, FILL_EMPTY_DATA) }
+        )
+    }
+
+    /// Same as `sorted_set_add()`, but the member expires after `ttl`. The expired members are
+    /// skipped by the reads and deleted by `sorted_set_purge_expired()`, or by the sweeper
+    /// started by `DatabaseHandle::start_sorted_set_sweeper()`, they are counted until they
+    /// are purged.
+    pub fn sorted_set_add_ex(
+        &self,
+        key: impl AsRef<[u8]>,
+        score: &[u8],
+        value: &[u8],
+        ttl: Duration,
+    ) -> Result<u64> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_set_add_ex",
+            write,
+            Some(KeyType::SortedSet),
+            key,
+            {
+                let expires_at = now_millis().saturating_add(ttl.as_millis() as u64);
+                self.sorted_set_add_row(key, score, value, &expires_at.to_be_bytes())
+            }
+        )
+    }
+
+    /// Delete the expired members added by `sorted_set_add_ex()`, returns the deleted count.
+    /// Call it periodically, or start `DatabaseHandle::start_sorted_set_sweeper()`. With `Options::lazy_delete`,
+    /// the rows without score left by the compactions dropping the expired members are deleted
+    /// too.
+    pub fn sorted_set_purge_expired(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        let key = key.as_ref();
+        let mut meta = match self.get_meta(key)? {
+            Some(meta) if meta.key_type == KeyType::SortedSet => meta,
+            _ => return Ok(0),
+        };
+        let (_, score_len) = meta.decode_sorted_set_extra();
+        let now = now_millis();
+        let mut batch = WriteBatch::default();
+        let mut expired = Vec::new();
+        self.for_each_data(key, None, |k, v| {
+            if sorted_set_row_expired(&v, now) {
+                let (_, member) = decode_data_key_sorted_set_item_with_score(&k, score_len);
+                batch.delete(&k);
+                batch.delete(encode_data_key_sorted_set_item_without_score(
+                    meta.id, &member,
+                ));
+                expired.push(member);
+            }
+            true
+        })?;
+        if self.expiring_key_ids.read().unwrap().contains(&meta.id) {
+            let purged: HashSet<_> = expired.iter().cloned().collect();
+            let prefix = encode_data_key_sorted_set_item_without_score(meta.id, b"");
+            let mut opts = self.scan_read_options();
+            opts.set_iterate_upper_bound(encode_data_key_sorted_set_prefix(meta.id).to_vec());
+            let mut iter = self.rocksdb.raw_iterator_opt(opts);
+            iter.seek(&prefix);
+            while let (Some(k), Some(score)) = (iter.key(), iter.value()) {
+                let member = &k[prefix.len()..];
+                let full_key = encode_data_key_sorted_set_item_with_score(meta.id, score, member);
+                if !purged.contains(member) && self.rocksdb.get_pinned(full_key)?.is_none() {
+                    batch.delete(k);
+                    expired.push(Box::from(member));
+                }
+                iter.next();
+            }
+            iter.status()?;
+        }
+        let counter = expired.len() as u64;
+        if counter > 0 {
+            meta.count -= counter;
+            self.save_meta_to_batch(&mut batch, key, &meta, true)?;
+            self.rocksdb.write(batch)?;
+            for member in expired.iter() {
+                self.emit_expired(key, KeyType::SortedSet, member);
+            }
+        }
+        Ok(counter)
+    }
+
+    /// Put a `sorted set` member, `row` is the value of the row ordered by the score, it's
+    /// empty or the expire time.
+    fn sorted_set_add_row(
+        &self,
+        key: &[u8],
+        score: &[u8],
+        value: &[u8],
+        row: &[u8],
+    ) -> Result<u64> {
+        self.check_schema_value(key, value)?;
         let mut meta = self.get_or_create_meta(key, KeyType::SortedSet)?;
         let (deleted_count, score_len) = meta.decode_sorted_set_extra();
         let full_key1 = encode_data_key_sorted_set_item_with_score(meta.id, score, value);
@@ -763,66 +4288,209 @@ impl Database {
                 )));
             }
         }
+        self.check_max_count(key, &meta)?;
         meta.count += 1;
-        self.rocksdb.put(full_key1, FILL_EMPTY_DATA)?;
-        self.rocksdb.put(full_key2, score)?;
-        self.save_meta(key, &meta, false)?;
+        let mut batch = WriteBatch::default();
+        batch.put(full_key1, row);
+        batch.put(full_key2, score);
+        let expiring = !row.is_empty() && self.record_expiring_key_id_to_batch(&mut batch, meta.id);
+        self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+        self.rocksdb.write(batch)?;
+        if expiring {
+            self.expiring_key_ids.write().unwrap().insert(meta.id);
+        }
         Ok(meta.count)
     }
 
-    pub fn sorted_set_is_member(&self, key: &str, value: &[u8]) -> Result<bool> {
+    pub fn sorted_set_is_member(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool> {
+        let key = key.as_ref();
         match self.get_meta(key)? {
             None => Ok(false),
             Some(meta) => {
                 let full_key = encode_data_key_sorted_set_item_without_score(meta.id, value);
-                match self.rocksdb.get(full_key)? {
+                match self.rocksdb.get_pinned(full_key)? {
                     None => Ok(false),
-                    Some(_) => Ok(true),
+                    Some(score) => {
+                        let full_key =
+                            encode_data_key_sorted_set_item_with_score(meta.id, &score, value);
+                        Ok(match self.rocksdb.get_pinned(full_key)? {
+                            None => false,
+                            Some(row) => !sorted_set_row_expired(&row, now_millis()),
+                        })
+                    }
                 }
             }
         }
     }
 
-    pub fn sorted_set_delete(&self, key: &str, value: &[u8]) -> Result<bool> {
-        match self.get_meta(key)? {
-            None => Ok(false),
-            Some(mut meta) => {
-                let (deleted_count, score_len) = meta.decode_sorted_set_extra();
-                let full_key1 = encode_data_key_sorted_set_item_without_score(meta.id, value);
-                match self.rocksdb.get(full_key1.as_ref())? {
+    /// Get the score of a member pinned in RocksDB instead of copied out, `None` if the member
+    /// is missing or expired.
+    pub fn sorted_set_score_pinned(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: &[u8],
+    ) -> Result<Option<DBPinnableSlice<'_>>> {
+        let meta = match self.get_meta(key)? {
+            None => return Ok(None),
+            Some(meta) => meta,
+        };
+        let full_key = encode_data_key_sorted_set_item_without_score(meta.id, value);
+        let score = match self.rocksdb.get_pinned(full_key)? {
+            None => return Ok(None),
+            Some(score) => score,
+        };
+        let full_key = encode_data_key_sorted_set_item_with_score(meta.id, &score, value);
+        Ok(match self.rocksdb.get_pinned(full_key)? {
+            Some(row) if !sorted_set_row_expired(&row, now_millis()) => Some(score),
+            _ => None,
+        })
+    }
+
+    /// Get the scores of many members with a single `multi_get` of the rows without score,
+    /// `None` for the members which are missing or expired.
+    pub fn sorted_set_scores(
+        &self,
+        key: impl AsRef<[u8]>,
+        values: &[&[u8]],
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        let key = key.as_ref();
+        let meta = match self.get_meta(key)? {
+            None => return Ok(vec![None; values.len()]),
+            Some(meta) => meta,
+        };
+        let mut scores = Vec::with_capacity(values.len());
+        for score in self.rocksdb.multi_get(
+            values
+                .iter()
+                .map(|v| encode_data_key_sorted_set_item_without_score(meta.id, v)),
+        ) {
+            scores.push(score?);
+        }
+        // the expire times are in the rows ordered by the score
+        let rows = self
+            .rocksdb
+            .multi_get(scores.iter().zip(values).filter_map(|(score, v)| {
+                score
+                    .as_ref()
+                    .map(|s| encode_data_key_sorted_set_item_with_score(meta.id, s, v))
+            }));
+        let now = now_millis();
+        let mut rows = rows.into_iter();
+        for score in scores.iter_mut().filter(|s| s.is_some()) {
+            match rows.next().transpose()? {
+                Some(Some(row)) if !sorted_set_row_expired(&row, now) => {}
+                _ => *score = None,
+            }
+        }
+        Ok(scores)
+    }
+
+    pub fn sorted_set_delete(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_set_delete",
+            write,
+            Some(KeyType::SortedSet),
+            key,
+            {
+                match self.get_meta(key)? {
                     None => Ok(false),
-                    Some(score) => {
-                        let score = score.as_ref();
-                        let full_key2 =
-                            encode_data_key_sorted_set_item_with_score(meta.id, score, value);
-                        self.rocksdb.delete(full_key2)?;
-                        self.rocksdb.delete(full_key1)?;
-                        meta.count -= 1;
-                        if deleted_count > 0
-                            && deleted_count % self.options.sorted_list_compact_deletes_count == 0
-                        {
-                            self.rocksdb.compact_range(
-                                Some(encode_data_key(meta.id).as_ref()),
-                                Some(encode_data_key(meta.id + 1).as_ref()),
-                            );
-                            meta.encode_sorted_set_extra(0, score_len);
-                        } else {
-                            meta.encode_sorted_set_extra(deleted_count + 1, score_len);
+                    Some(mut meta) => {
+                        let (deleted_count, score_len) = meta.decode_sorted_set_extra();
+                        let full_key1 =
+                            encode_data_key_sorted_set_item_without_score(meta.id, value);
+                        match self.rocksdb.get(full_key1.as_ref())? {
+                            None => Ok(false),
+                            Some(score) => {
+                                let score = score.as_ref();
+                                let full_key2 = encode_data_key_sorted_set_item_with_score(
+                                    meta.id, score, value,
+                                );
+                                let mut batch = WriteBatch::default();
+                                batch.delete(full_key2);
+                                batch.delete(full_key1);
+                                meta.count -= 1;
+                                let compact = deleted_count > 0
+                                    && deleted_count
+                                        % self.sorted_list_compact_deletes_count(key, &meta)
+                                        == 0;
+                                if compact {
+                                    meta.encode_sorted_set_extra(0, score_len);
+                                } else {
+                                    meta.encode_sorted_set_extra(deleted_count + 1, score_len);
+                                }
+                                self.save_meta_to_batch(&mut batch, key, &meta, true)?;
+                                self.rocksdb.write(batch)?;
+                                if compact {
+                                    self.compact_range(
+                                        Some(encode_data_key(meta.id).as_ref()),
+                                        Some(encode_data_key(meta.id + 1).as_ref()),
+                                    );
+                                }
+                                Ok(true)
+                            }
                         }
-                        self.save_meta(key, &meta, true)?;
-                        Ok(true)
                     }
                 }
             }
+        )
+    }
+
+    /// Rename the member `old_value` to `new_value` keeping its score and expire time, both
+    /// rows of the member are moved in one batch. Fails if `new_value` is already a member.
+    /// Returns `false` if `old_value` is not a member.
+    pub fn sorted_set_update_member(
+        &self,
+        key: impl AsRef<[u8]>,
+        old_value: &[u8],
+        new_value: &[u8],
+    ) -> Result<bool> {
+        let key = key.as_ref();
+        self.check_writable(key)?;
+        let meta = match self.get_meta(key)? {
+            None => return Ok(false),
+            Some(meta) => meta,
+        };
+        check_key_type(key, &meta, KeyType::SortedSet)?;
+        let old_key = encode_data_key_sorted_set_item_without_score(meta.id, old_value);
+        let score = match self.rocksdb.get(&old_key)? {
+            None => return Ok(false),
+            Some(score) => score,
+        };
+        if old_value == new_value {
+            return Ok(true);
+        }
+        self.check_schema_value(key, new_value)?;
+        let new_key = encode_data_key_sorted_set_item_without_score(meta.id, new_value);
+        if self.rocksdb.get_pinned(&new_key)?.is_some() {
+            return Err(Error::Message(format!(
+                "{} is already a member of sorted set {}",
+                String::from_utf8_lossy(new_value),
+                String::from_utf8_lossy(key)
+            )));
         }
+        let old_row_key = encode_data_key_sorted_set_item_with_score(meta.id, &score, old_value);
+        let row = self.rocksdb.get(&old_row_key)?.unwrap_or_default();
+        let mut batch = WriteBatch::default();
+        batch.delete(old_row_key);
+        batch.delete(old_key);
+        batch.put(
+            encode_data_key_sorted_set_item_with_score(meta.id, &score, new_value),
+            row,
+        );
+        batch.put(new_key, &score);
+        self.rocksdb.write(batch)?;
+        Ok(true)
     }
 
     pub fn sorted_set_left(
         &self,
-        key: &str,
+        key: impl AsRef<[u8]>,
         max_score: Option<&[u8]>,
         limit: usize,
     ) -> Result<VecScoreVal> {
+        let key = key.as_ref();
         match self.get_meta(key)? {
             None => Ok(vec![]),
             Some(meta) => {
@@ -834,10 +4502,14 @@ impl Database {
                 let iter = self
                     .rocksdb
                     .iterator_opt(IteratorMode::From(&prefix, Direction::Forward), opts);
-                for (k, _) in iter {
+                let now = now_millis();
+                for (k, v) in iter {
                     if !has_prefix(&prefix, k.as_ref()) {
                         break;
                     }
+                    if sorted_set_row_expired(&v, now) {
+                        continue;
+                    }
                     let (score, value) =
                         decode_data_key_sorted_set_item_with_score(k.as_ref(), score_len);
                     if let Some(max_score) = max_score {
@@ -857,25 +4529,31 @@ impl Database {
 
     pub fn sorted_set_right(
         &self,
-        key: &str,
+        key: impl AsRef<[u8]>,
         min_score: Option<&[u8]>,
         limit: usize,
     ) -> Result<VecScoreVal> {
+        let key = key.as_ref();
         match self.get_meta(key)? {
             None => Ok(vec![]),
             Some(meta) => {
                 let (_, score_len) = meta.decode_sorted_set_extra();
                 let mut list = vec![];
                 let prefix = encode_data_key_sorted_set_prefix(meta.id);
-                let next_prefix = encode_data_key_sorted_set_prefix(meta.id + 1);
+                // the rows of the next key ID may sort before its items, e.g. the members
+                let next_prefix = encode_data_key(meta.id + 1);
                 let opts = ReadOptions::default();
                 let iter = self
                     .rocksdb
                     .iterator_opt(IteratorMode::From(&next_prefix, Direction::Reverse), opts);
-                for (k, _) in iter {
+                let now = now_millis();
+                for (k, v) in iter {
                     if !has_prefix(&prefix, k.as_ref()) {
                         break;
                     }
+                    if sorted_set_row_expired(&v, now) {
+                        continue;
+                    }
                     let (score, value) =
                         decode_data_key_sorted_set_item_with_score(k.as_ref(), score_len);
                     if let Some(min_score) = min_score {
@@ -892,4 +4570,194 @@ impl Database {
             }
         }
     }
+
+    /// Delete all members with score between `min_score` and `max_score` (inclusive),
+    /// returns the deleted members count.
+    pub fn sorted_set_delete_range_by_score(
+        &self,
+        key: impl AsRef<[u8]>,
+        min_score: &[u8],
+        max_score: &[u8],
+    ) -> Result<u64> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_set_delete_range_by_score",
+            write,
+            Some(KeyType::SortedSet),
+            key,
+            {
+                match self.get_meta(key)? {
+                    None => Ok(0),
+                    Some(mut meta) => {
+                        let (_, score_len) = meta.decode_sorted_set_extra();
+                        let prefix = encode_data_key_sorted_set_prefix(meta.id);
+                        let start =
+                            encode_data_key_sorted_set_item_with_score(meta.id, min_score, &[]);
+                        let iter = self
+                            .rocksdb
+                            .iterator(IteratorMode::From(&start, Direction::Forward));
+                        let mut batch = WriteBatch::default();
+                        let mut counter = 0;
+                        for (k, _) in iter {
+                            if !has_prefix(&prefix, k.as_ref()) {
+                                break;
+                            }
+                            let (score, value) =
+                                decode_data_key_sorted_set_item_with_score(k.as_ref(), score_len);
+                            if compare_score_bytes(score.as_ref(), max_score) > 0 {
+                                break;
+                            }
+                            batch.delete(k.as_ref());
+                            batch.delete(encode_data_key_sorted_set_item_without_score(
+                                meta.id, &value,
+                            ));
+                            counter += 1;
+                        }
+                        if counter > 0 {
+                            self.sorted_set_write_deletes(key, &mut meta, batch, counter)?;
+                        }
+                        Ok(counter)
+                    }
+                }
+            }
+        )
+    }
+
+    /// Delete members by rank between `start` and `stop` (inclusive, ordered by score from low to high),
+    /// negative ranks count from the highest score, e.g. `-1` is the last member.
+    /// Returns the deleted members count.
+    pub fn sorted_set_delete_range_by_rank(
+        &self,
+        key: impl AsRef<[u8]>,
+        start: i64,
+        stop: i64,
+    ) -> Result<u64> {
+        let key = key.as_ref();
+        run_op!(
+            self,
+            "sorted_set_delete_range_by_rank",
+            write,
+            Some(KeyType::SortedSet),
+            key,
+            {
+                // the expired members are not ranked
+                self.sorted_set_purge_expired(key)?;
+                match self.get_meta(key)? {
+                    None => Ok(0),
+                    Some(mut meta) => {
+                        let count = meta.count as i64;
+                        let start = if start < 0 { count + start } else { start }.max(0);
+                        let stop = if stop < 0 { count + stop } else { stop }.min(count - 1);
+                        if start > stop {
+                            return Ok(0);
+                        }
+                        let (_, score_len) = meta.decode_sorted_set_extra();
+                        let prefix = encode_data_key_sorted_set_prefix(meta.id);
+                        let iter = self
+                            .rocksdb
+                            .iterator(IteratorMode::From(&prefix, Direction::Forward));
+                        let mut batch = WriteBatch::default();
+                        let mut counter = 0;
+                        for (rank, (k, _)) in iter.enumerate() {
+                            let rank = rank as i64;
+                            if rank > stop || !has_prefix(&prefix, k.as_ref()) {
+                                break;
+                            }
+                            if rank < start {
+                                continue;
+                            }
+                            let (_, value) =
+                                decode_data_key_sorted_set_item_with_score(k.as_ref(), score_len);
+                            batch.delete(k.as_ref());
+                            batch.delete(encode_data_key_sorted_set_item_without_score(
+                                meta.id, &value,
+                            ));
+                            counter += 1;
+                        }
+                        if counter > 0 {
+                            self.sorted_set_write_deletes(key, &mut meta, batch, counter)?;
+                        }
+                        Ok(counter)
+                    }
+                }
+            }
+        )
+    }
+
+    /// Write the deletes batch of `sorted set` members together with the updated meta.
+    fn sorted_set_write_deletes(
+        &self,
+        key: &[u8],
+        meta: &mut KeyMeta,
+        mut batch: WriteBatch,
+        counter: u64,
+    ) -> Result<()> {
+        let (deleted_count, score_len) = meta.decode_sorted_set_extra();
+        let deleted_count = deleted_count as u64 + counter;
+        let compact = deleted_count >= self.sorted_list_compact_deletes_count(key, meta) as u64;
+        meta.count -= counter;
+        meta.encode_sorted_set_extra(if compact { 0 } else { deleted_count as u32 }, score_len);
+        self.save_meta_to_batch(&mut batch, key, meta, true)?;
+        self.rocksdb.write(batch)?;
+        if compact {
+            self.compact_range(
+                Some(encode_data_key(meta.id).as_ref()),
+                Some(encode_data_key(meta.id + 1).as_ref()),
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Database {
+    fn drop(&mut self) {
+        self.background.shutdown(BACKGROUND_SHUTDOWN_TIMEOUT);
+    }
+}
+
+#[cfg(unix)]
+#[allow(clippy::unnecessary_cast)]
+fn free_disk_space(path: &str) -> Option<u64> {
+    let path = std::ffi::CString::new(path).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+fn free_disk_space(_path: &str) -> Option<u64> {
+    None
+}
+
+/// Fail if the key is not of the expected type.
+pub(crate) fn check_key_type(
+    key: impl AsRef<[u8]>,
+    meta: &KeyMeta,
+    expected: KeyType,
+) -> Result<()> {
+    if meta.key_type != expected {
+        return Err(Error::Message(format!(
+            "key {} is {:?}, expects {:?}",
+            String::from_utf8_lossy(key.as_ref()),
+            meta.key_type,
+            expected
+        )));
+    }
+    Ok(())
+}
+
+/// Returns `true` if a `sorted set` row ordered by the score has expired, see
+/// `Database::sorted_set_add_ex()`.
+fn sorted_set_row_expired(row: &[u8], now: u64) -> bool {
+    row.len() == 8 && (&row[..]).get_u64() <= now
+}
+
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
 }
@@ -0,0 +1,122 @@
+use crate::codec::*;
+use crate::database::{Database, Result};
+
+/// Decoded extra data of a meta, see `KeyMeta::extra`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetaExtra {
+    /// The key type has no extra data, or it's not written yet.
+    None,
+    List {
+        left: i64,
+        right: i64,
+    },
+    SortedList {
+        sequence: u64,
+        left_deleted_count: u32,
+        right_deleted_count: u32,
+        /// The per-key override of `Options::sorted_list_compact_deletes_count`, 0 if unset.
+        compact_deletes_count: u32,
+    },
+    SortedSet {
+        deleted_count: u32,
+        score_len: u8,
+    },
+    Value {
+        total_len: u64,
+        chunk_size: u32,
+    },
+    Log {
+        first_offset: u64,
+        next_offset: u64,
+    },
+}
+
+/// Report of the meta and the data rows of a key, see `Database::describe_key()`.
+#[derive(Debug, Clone)]
+pub struct KeyDescription {
+    pub id: u64,
+    pub key_type: KeyType,
+    /// Items count saved in the meta.
+    pub count: u64,
+    pub extra: MetaExtra,
+    /// Count of the data rows, e.g. a `sorted set` member has 2 rows.
+    pub rows: u64,
+    /// Approximate size in bytes, the total length of the keys and values of the data rows
+    /// before compression.
+    pub size: u64,
+}
+
+impl Database {
+    /// Describe the internals of a key: the meta with the decoded extra data, and the count and
+    /// size of the data rows scanned from the database, e.g. to debug a count that drifts from
+    /// the rows. Returns `None` if the key does not exist.
+    pub fn describe_key(&self, key: &str) -> Result<Option<KeyDescription>> {
+        let meta = match self.get_meta(key)? {
+            None => return Ok(None),
+            Some(meta) => meta,
+        };
+        let prefix = encode_data_key(meta.id);
+        let mut rows = 0;
+        let mut size = 0;
+        let iter = self
+            .rocksdb
+            .prefix_iterator(&prefix)
+            .take_while(|(k, _)| has_prefix(&prefix, k.as_ref()));
+        for (k, v) in iter {
+            rows += 1;
+            size += (k.len() + v.len()) as u64;
+        }
+        Ok(Some(KeyDescription {
+            id: meta.id,
+            key_type: meta.key_type,
+            count: meta.count,
+            extra: decode_meta_extra(&meta),
+            rows,
+            size,
+        }))
+    }
+}
+
+pub(crate) fn decode_meta_extra(meta: &KeyMeta) -> MetaExtra {
+    if meta.extra.is_none() {
+        return MetaExtra::None;
+    }
+    match meta.key_type {
+        KeyType::List => {
+            let (left, right) = meta.decode_list_extra();
+            MetaExtra::List { left, right }
+        }
+        KeyType::SortedList => {
+            let (sequence, left_deleted_count, right_deleted_count) =
+                meta.decode_sorted_list_extra();
+            MetaExtra::SortedList {
+                sequence,
+                left_deleted_count,
+                right_deleted_count,
+                compact_deletes_count: meta.decode_sorted_list_compact_deletes_count(),
+            }
+        }
+        KeyType::SortedSet => {
+            let (deleted_count, score_len) = meta.decode_sorted_set_extra();
+            MetaExtra::SortedSet {
+                deleted_count,
+                score_len,
+            }
+        }
+        KeyType::Value => {
+            let (total_len, chunk_size) = meta.decode_value_extra();
+            MetaExtra::Value {
+                total_len,
+                chunk_size,
+            }
+        }
+        KeyType::Log => {
+            let (first_offset, next_offset) = meta.decode_log_extra();
+            MetaExtra::Log {
+                first_offset,
+                next_offset,
+            }
+        }
+        KeyType::Map | KeyType::Set | KeyType::Custom(_) => MetaExtra::None,
+    }
+}
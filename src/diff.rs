@@ -0,0 +1,170 @@
+use std::cmp::Ordering;
+use std::sync::atomic;
+
+use rocksdb::{Direction, IteratorMode, Snapshot};
+use sha2::{Digest, Sha256};
+
+use crate::chunk::read_chunked_value;
+use crate::codec::*;
+use crate::database::{Database, Result};
+
+/// A key which differs between two databases, see `Database::diff_against()`.
+/// The key names are raw bytes, so the names which are not valid UTF-8 are compared too.
+#[derive(Debug, Clone)]
+pub enum KeyDiff {
+    /// The key exists only in the other database.
+    Added { key: Box<[u8]>, meta: KeyMeta },
+    /// The key exists only in this database.
+    Removed { key: Box<[u8]>, meta: KeyMeta },
+    /// The key type, the count or the content of the data rows differ.
+    Changed {
+        key: Box<[u8]>,
+        meta: KeyMeta,
+        other_meta: KeyMeta,
+    },
+}
+
+impl KeyDiff {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            KeyDiff::Added { key, .. }
+            | KeyDiff::Removed { key, .. }
+            | KeyDiff::Changed { key, .. } => key,
+        }
+    }
+}
+
+impl Database {
+    /// Compare the keys of this database with `other`, e.g. to verify a migration or a
+    /// replica. Both keyspaces are streamed in order from snapshots, the keys with the same
+    /// type and count are compared by the hash of their data rows without the key IDs.
+    /// Returns the count of the different keys.
+    pub fn for_each_diff<F>(&self, other: &Database, mut f: F) -> Result<u64>
+    where
+        F: FnMut(KeyDiff) -> bool,
+    {
+        let snapshot = self.rocksdb.snapshot();
+        let other_snapshot = other.rocksdb.snapshot();
+        let mut metas = snapshot
+            .iterator_opt(
+                IteratorMode::From(PREFIX_META, Direction::Forward),
+                self.scan_read_options(),
+            )
+            .take_while(|(k, _)| has_prefix(PREFIX_META, k));
+        let mut other_metas = other_snapshot
+            .iterator_opt(
+                IteratorMode::From(PREFIX_META, Direction::Forward),
+                other.scan_read_options(),
+            )
+            .take_while(|(k, _)| has_prefix(PREFIX_META, k));
+        let mut next = next_meta(self, &mut metas)?;
+        let mut other_next = next_meta(other, &mut other_metas)?;
+        let mut count = 0;
+        loop {
+            let order = match (&next, &other_next) {
+                (None, None) => break,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((k, _)), Some((other_k, _))) => k.cmp(other_k),
+            };
+            let diff = match order {
+                Ordering::Less => {
+                    let (k, meta) = next.take().unwrap();
+                    next = next_meta(self, &mut metas)?;
+                    Some(KeyDiff::Removed {
+                        key: Box::from(&k[PREFIX_META.len()..]),
+                        meta,
+                    })
+                }
+                Ordering::Greater => {
+                    let (k, meta) = other_next.take().unwrap();
+                    other_next = next_meta(other, &mut other_metas)?;
+                    Some(KeyDiff::Added {
+                        key: Box::from(&k[PREFIX_META.len()..]),
+                        meta,
+                    })
+                }
+                Ordering::Equal => {
+                    let (k, meta) = next.take().unwrap();
+                    let (_, other_meta) = other_next.take().unwrap();
+                    next = next_meta(self, &mut metas)?;
+                    other_next = next_meta(other, &mut other_metas)?;
+                    if meta.key_type != other_meta.key_type
+                        || meta.count != other_meta.count
+                        || hash_rows(self, &snapshot, &meta)?
+                            != hash_rows(other, &other_snapshot, &other_meta)?
+                    {
+                        Some(KeyDiff::Changed {
+                            key: Box::from(&k[PREFIX_META.len()..]),
+                            meta,
+                            other_meta,
+                        })
+                    } else {
+                        None
+                    }
+                }
+            };
+            if let Some(diff) = diff {
+                count += 1;
+                if !f(diff) {
+                    break;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Get all the keys which differ from `other`, see `for_each_diff()`.
+    pub fn diff_against(&self, other: &Database) -> Result<Vec<KeyDiff>> {
+        let mut diffs = Vec::new();
+        self.for_each_diff(other, |diff| {
+            diffs.push(diff);
+            true
+        })?;
+        Ok(diffs)
+    }
+}
+
+/// Get the next meta of the iterator, the bad metas are skipped or fail by
+/// `Options::bad_row_policy` of `db`.
+fn next_meta(
+    db: &Database,
+    iter: &mut impl Iterator<Item = (Box<[u8]>, Box<[u8]>)>,
+) -> Result<Option<(Box<[u8]>, KeyMeta)>> {
+    for (k, v) in iter {
+        if db.check_scanned_meta(&k, &v, false)? {
+            return Ok(Some((k, KeyMeta::from_bytes(&v))));
+        }
+    }
+    Ok(None)
+}
+
+/// Hash the data rows of a key read from `snapshot` of `db`, the key ID is not hashed. The
+/// chunked `map` values are hashed as they are reassembled, so they are equal to the same
+/// values stored in a single row.
+fn hash_rows(db: &Database, snapshot: &Snapshot, meta: &KeyMeta) -> Result<[u8; 32]> {
+    let chunked = meta.key_type == KeyType::Map && db.chunked_values.load(atomic::Ordering::SeqCst);
+    let prefix = encode_data_key(meta.id);
+    let mut hasher = Sha256::new();
+    let iter = snapshot
+        .iterator_opt(
+            IteratorMode::From(&prefix, Direction::Forward),
+            db.scan_read_options(),
+        )
+        .take_while(|(k, _)| has_prefix(&prefix, k));
+    for (k, v) in iter {
+        let suffix = &k[prefix.len()..];
+        let v = if v.is_empty() && chunked {
+            read_chunked_value(|k| Ok(snapshot.get(k)?), meta.id, suffix)?
+                .map(Box::from)
+                .unwrap_or(v)
+        } else {
+            v
+        };
+        hasher.update((suffix.len() as u32).to_be_bytes());
+        hasher.update(suffix);
+        hasher.update((v.len() as u32).to_be_bytes());
+        hasher.update(&v);
+    }
+    Ok(hasher.finalize().into())
+}
@@ -0,0 +1,187 @@
+use bytes::{Buf, BufMut, BytesMut};
+use crc32fast::Hasher;
+use rocksdb::{Direction, IteratorMode, Snapshot, WriteBatch};
+
+use crate::chunk::encode_value_chunk_prefix;
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+use crate::verify::check_meta_value;
+
+/// Magic bytes at the beginning of a dumped key.
+pub static DUMP_MAGIC: &[u8] = b"SDBDUMP";
+/// Format version of a dumped key.
+pub const DUMP_VERSION: u8 = 1;
+
+/// Row kinds in a dumped key.
+const DUMP_ROW_DATA: u8 = 0;
+const DUMP_ROW_RESERVED: u8 = 1;
+const DUMP_ROW_VALUE_CHUNK: u8 = 2;
+
+impl Database {
+    /// Serialize a key with all its rows into a portable blob, mirrors Redis `DUMP`.
+    ///
+    /// The blob contains `DUMP_MAGIC`, `DUMP_VERSION`, the meta and the rows without the key
+    /// ID, followed by a CRC32 checksum. Use `restore_key()` to load it into any database.
+    /// Returns `None` if the key does not exist.
+    pub fn dump_key(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.dump(key.as_bytes())
+    }
+
+    /// Same as `dump_key()`, but the key name may be any bytes.
+    pub(crate) fn dump(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let snapshot = self.rocksdb.snapshot();
+        let key = self.resolve_key(key);
+        let meta = match snapshot.get(encode_meta_key(&key))? {
+            None => return Ok(None),
+            Some(v) => match check_meta_value(&v) {
+                Some(reason) => {
+                    return Err(Error::Message(format!(
+                        "bad meta row of key {}: {}",
+                        String::from_utf8_lossy(&key),
+                        reason
+                    )))
+                }
+                None => KeyMeta::from_bytes(&v),
+            },
+        };
+        Ok(Some(dump_meta(self, &snapshot, &meta)?))
+    }
+
+    /// Load a blob produced by `dump_key()` as `key`, mirrors Redis `RESTORE`.
+    /// The key gets a new key ID, fails if it already exists. Returns the count of the rows.
+    pub fn restore_key(&self, key: &str, blob: &[u8]) -> Result<u64> {
+        let rows = self.restore(key.as_bytes(), blob)?;
+        self.log_admin("restore_key", key, rows)?;
+        Ok(rows)
+    }
+
+    /// Same as `restore_key()`, but it's not recorded in the admin log and the key name may be
+    /// any bytes.
+    pub(crate) fn restore(&self, key: &[u8], blob: &[u8]) -> Result<u64> {
+        self.check_writable(key)?;
+        let invalid = |reason: &str| Error::Message(format!("invalid dumped key: {}", reason));
+        let header_len = DUMP_MAGIC.len() + 1;
+        if blob.len() < header_len + 8 || &blob[..DUMP_MAGIC.len()] != DUMP_MAGIC {
+            return Err(invalid("bad magic"));
+        }
+        if blob[DUMP_MAGIC.len()] != DUMP_VERSION {
+            return Err(invalid(&format!(
+                "unsupported version {}",
+                blob[DUMP_MAGIC.len()]
+            )));
+        }
+        let (body, checksum) = blob.split_at(blob.len() - 4);
+        let mut hasher = Hasher::new();
+        hasher.update(body);
+        if hasher.finalize() != (&checksum[..]).get_u32() {
+            return Err(invalid("checksum mismatch"));
+        }
+        if self.get_meta(key)?.is_some() {
+            return Err(Error::Message(format!(
+                "key {} already exists",
+                String::from_utf8_lossy(key)
+            )));
+        }
+
+        let mut buf = &body[header_len..];
+        let meta_len = buf.get_u32() as usize;
+        if buf.remaining() < meta_len || meta_len < 17 {
+            return Err(invalid("truncated meta"));
+        }
+        let mut meta = KeyMeta::from_bytes(&buf[..meta_len]);
+        buf.advance(meta_len);
+        meta.id = self.allocate_key_id()?;
+
+        let mut batch = WriteBatch::default();
+        let mut rows = 0;
+        while buf.has_remaining() {
+            if buf.remaining() < 5 {
+                return Err(invalid("truncated row"));
+            }
+            let mut row_key = match buf.get_u8() {
+                DUMP_ROW_DATA => encode_data_key(meta.id),
+                DUMP_ROW_RESERVED => encode_reserved_key_prefix(meta.id),
+                DUMP_ROW_VALUE_CHUNK => {
+                    self.enable_chunked_values()?;
+                    encode_value_chunk_prefix(meta.id)
+                }
+                kind => return Err(invalid(&format!("unknown row kind {}", kind))),
+            };
+            let suffix = read_bytes(&mut buf).ok_or_else(|| invalid("truncated row"))?;
+            let value = read_bytes(&mut buf).ok_or_else(|| invalid("truncated row"))?;
+            row_key.put_slice(suffix);
+            batch.put(row_key, value);
+            rows += 1;
+        }
+        batch.put(encode_type_index_key(meta.key_type, key), FILL_EMPTY_DATA);
+        self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+        self.rocksdb.write(batch)?;
+        self.forget_absent_key(&self.resolve_key(key));
+        Ok(rows)
+    }
+}
+
+/// Serialize the rows of a key read from `snapshot` of `db` in the format of
+/// `Database::dump_key()`. The bad rows are handled by `Options::bad_row_policy`.
+pub(crate) fn dump_meta(db: &Database, snapshot: &Snapshot, meta: &KeyMeta) -> Result<Vec<u8>> {
+    let mut buf = BytesMut::new();
+    buf.put_slice(DUMP_MAGIC);
+    buf.put_u8(DUMP_VERSION);
+    let meta_bytes = meta.get_bytes();
+    buf.put_u32(meta_bytes.len() as u32);
+    buf.put_slice(&meta_bytes);
+    let prefixes = [
+        (DUMP_ROW_DATA, encode_data_key(meta.id)),
+        (DUMP_ROW_RESERVED, encode_reserved_key_prefix(meta.id)),
+        (DUMP_ROW_VALUE_CHUNK, encode_value_chunk_prefix(meta.id)),
+    ];
+    for (kind, prefix) in prefixes.iter() {
+        let iter = snapshot.iterator_opt(
+            IteratorMode::From(prefix, Direction::Forward),
+            db.scan_read_options(),
+        );
+        for (k, v) in iter {
+            if !has_prefix(prefix, &k) {
+                break;
+            }
+            if !db.check_scanned_row(meta, &k, &v)? {
+                continue;
+            }
+            let suffix = &k[prefix.len()..];
+            buf.put_u8(*kind);
+            buf.put_u32(suffix.len() as u32);
+            buf.put_slice(suffix);
+            buf.put_u32(v.len() as u32);
+            buf.put_slice(&v);
+        }
+    }
+    let mut hasher = Hasher::new();
+    hasher.update(&buf);
+    buf.put_u32(hasher.finalize());
+    Ok(buf.to_vec())
+}
+
+/// Read a u32 length and the bytes, returns `None` if it's truncated.
+fn read_bytes<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if buf.remaining() < 4 {
+        return None;
+    }
+    let len = buf.get_u32() as usize;
+    if buf.remaining() < len {
+        return None;
+    }
+    let (bytes, rest) = buf.split_at(len);
+    *buf = rest;
+    Some(bytes)
+}
+
+/// Whether two blobs of `Database::dump_key()` have the same content, the key IDs in the metas
+/// and the checksums are not compared.
+pub(crate) fn same_dump_content(a: &[u8], b: &[u8]) -> bool {
+    let id_start = DUMP_MAGIC.len() + 1 + 4;
+    let id_end = id_start + 8;
+    a.len() == b.len()
+        && a.len() >= id_end + 4
+        && a[..id_start] == b[..id_start]
+        && a[id_end..a.len() - 4] == b[id_end..b.len() - 4]
+}
@@ -0,0 +1,49 @@
+use crate::codec::*;
+use crate::database::{Database, Result};
+
+/// Variants of the read methods returning `None` for a missing key, the original methods
+/// return the same as for an empty key, e.g. 0 or an empty vector.
+impl Database {
+    /// Get the items count of a key, `None` if the key does not exist.
+    pub fn get_count_if_exists(&self, key: impl AsRef<[u8]>) -> Result<Option<u64>> {
+        Ok(self.get_meta(key)?.map(|meta| meta.count))
+    }
+
+    pub fn map_items_if_exists(&self, key: &str) -> Result<Option<Vec<Utf8FieldVal>>> {
+        self.if_exists(key.as_bytes(), || self.map_items(key))
+    }
+
+    pub fn list_items_if_exists(&self, key: impl AsRef<[u8]>) -> Result<Option<Vec<Box<[u8]>>>> {
+        let key = key.as_ref();
+        self.if_exists(key, || self.list_items(key))
+    }
+
+    pub fn set_items_if_exists(&self, key: impl AsRef<[u8]>) -> Result<Option<Vec<Box<[u8]>>>> {
+        let key = key.as_ref();
+        self.if_exists(key, || self.set_items(key))
+    }
+
+    pub fn sorted_list_items_if_exists(
+        &self,
+        key: impl AsRef<[u8]>,
+    ) -> Result<Option<VecScoreVal>> {
+        let key = key.as_ref();
+        self.if_exists(key, || self.sorted_list_items(key))
+    }
+
+    pub fn sorted_set_items_if_exists(&self, key: impl AsRef<[u8]>) -> Result<Option<VecScoreVal>> {
+        let key = key.as_ref();
+        self.if_exists(key, || self.sorted_set_items(key))
+    }
+
+    /// Read with `f` if the key exists.
+    fn if_exists<T, F>(&self, key: &[u8], f: F) -> Result<Option<T>>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        match self.get_meta(key)? {
+            None => Ok(None),
+            Some(_) => f().map(Some),
+        }
+    }
+}
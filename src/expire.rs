@@ -0,0 +1,76 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::codec::KeyType;
+use crate::database::{Database, Result};
+use crate::handle::DatabaseHandle;
+
+/// Name of the background task started by `DatabaseHandle::start_sorted_set_sweeper()`.
+pub const SORTED_SET_SWEEPER_TASK_NAME: &str = "simpledb-sorted-set-sweeper";
+
+/// An expired entry removed from the database, see `Database::on_expired()`.
+#[derive(Debug, Clone, Copy)]
+pub struct ExpiredEntry<'a> {
+    pub key: &'a str,
+    pub key_type: KeyType,
+    /// Field of the entry in the key, e.g. the session token of `Sessions`.
+    pub field: &'a [u8],
+}
+
+/// Callback of the expired entries.
+pub type ExpiredCallback = Arc<dyn Fn(&ExpiredEntry) + Send + Sync>;
+
+impl Database {
+    /// Register a callback invoked after expired entries are removed, either lazily when they are
+    /// read or by the purge methods, e.g. `idempotency_purge_expired()`,
+    /// `sorted_set_purge_expired()` and `Sessions::purge_expired()`.
+    ///
+    /// Keys have no TTL of their own, so only the entries with a TTL, i.e. the idempotency
+    /// records, the sorted set members added with a TTL and the sessions, are reported.
+    pub fn on_expired<F>(&self, f: F)
+    where
+        F: Fn(&ExpiredEntry) + Send + Sync + 'static,
+    {
+        self.expired_callbacks.write().unwrap().push(Arc::new(f));
+    }
+
+    /// Delete the expired members of all the `sorted set` keys by `sorted_set_purge_expired()`,
+    /// returns the deleted count.
+    pub fn sorted_set_purge_all_expired(&self) -> Result<u64> {
+        let mut count = 0;
+        for key in self.keys_of_type(KeyType::SortedSet)? {
+            count += self.sorted_set_purge_expired(&key)?;
+        }
+        Ok(count)
+    }
+
+    pub(crate) fn emit_expired(&self, key: &[u8], key_type: KeyType, field: &[u8]) {
+        let key = String::from_utf8_lossy(key);
+        let entry = ExpiredEntry {
+            key: &key,
+            key_type,
+            field,
+        };
+        // the callbacks may register more callbacks, so they're not called under the lock
+        let callbacks = self.expired_callbacks.read().unwrap().clone();
+        for f in callbacks.iter() {
+            f(&entry);
+        }
+    }
+}
+
+impl DatabaseHandle {
+    /// Run `Database::sorted_set_purge_all_expired()` every `interval` in a background task
+    /// named `SORTED_SET_SWEEPER_TASK_NAME`, so the members added by `sorted_set_add_ex()` are
+    /// deleted and the counts are fixed without the application's sweeper. The task holds no
+    /// reference of the database, and the errors are ignored, the task retries at the next
+    /// interval.
+    pub fn start_sorted_set_sweeper(&self, interval: Duration) {
+        let db = Arc::downgrade(&self.db);
+        self.background_tasks()
+            .spawn(SORTED_SET_SWEEPER_TASK_NAME, interval, move |_| {
+                if let Some(db) = db.upgrade() {
+                    let _ = db.sorted_set_purge_all_expired();
+                }
+            });
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use bytes::{Buf, BufMut, BytesMut};
+use rocksdb::{Direction, IteratorMode};
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+use crate::dump::{dump_meta, same_dump_content};
+use crate::namespace::key_namespace;
+
+/// Magic bytes at the beginning of an export stream.
+pub static EXPORT_MAGIC: &[u8] = b"SDBEXPT1";
+/// Magic bytes at the beginning of a checkpoint file of `Database::import_range_resumable()`.
+pub static IMPORT_CHECKPOINT_MAGIC: &[u8] = b"SDBIMPC1";
+/// Keys imported between two checkpoints of `Database::import_range_resumable()`.
+pub const IMPORT_CHECKPOINT_INTERVAL: u64 = 100;
+/// Max length of a key name or a dumped key in an export stream.
+pub const EXPORT_MAX_CHUNK_SIZE: usize = 1 << 30;
+
+/// Caps of `Database::import_range_resumable()`, checked before every key is imported.
+#[derive(Debug, Clone, Default)]
+pub struct ImportQuota {
+    /// Max keys imported into a namespace, the part of the key name before `GROUP_SEPARATOR`.
+    pub max_keys_per_namespace: Option<u64>,
+    /// Max total size of the imported keys in bytes, counted by the length of the dumped keys.
+    pub max_bytes: Option<u64>,
+}
+
+/// Progress of `Database::import_range_resumable()`, it's saved in the checkpoint file.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ImportProgress {
+    /// Count of the imported keys, including the ones imported by the interrupted runs.
+    pub keys: u64,
+    /// Total length of the dumped keys imported.
+    pub bytes: u64,
+    /// Name of the last imported key, the keys up to it are skipped when resuming.
+    pub last_key: Option<Vec<u8>>,
+    /// Count of the imported keys of every namespace.
+    pub namespaces: BTreeMap<String, u64>,
+}
+
+impl Database {
+    /// Write the keys with names from `from_key` (inclusive) to `to_key` (exclusive, `None` for
+    /// the last key) into `writer`, read from a snapshot, e.g. for a partial backup.
+    ///
+    /// The stream contains `EXPORT_MAGIC` and every key name followed by its rows in the format
+    /// of `dump_key()`. The aliases are not exported. Use `import_range()` to load it.
+    /// Returns the count of the exported keys.
+    pub fn export_range(
+        &self,
+        from_key: &str,
+        to_key: Option<&str>,
+        mut writer: impl Write,
+    ) -> Result<u64> {
+        let snapshot = self.rocksdb.snapshot();
+        let start = encode_meta_key(from_key);
+        let end = to_key.map(encode_meta_key);
+        writer.write_all(EXPORT_MAGIC)?;
+        let mut count = 0;
+        let iter = snapshot.iterator_opt(
+            IteratorMode::From(&start, Direction::Forward),
+            self.scan_read_options(),
+        );
+        for (k, v) in iter {
+            if !has_prefix(PREFIX_META, &k) {
+                break;
+            }
+            if let Some(end) = &end {
+                if k.as_ref() >= end.as_ref() {
+                    break;
+                }
+            }
+            if !self.check_scanned_meta(&k, &v, false)? {
+                continue;
+            }
+            let name = &k[PREFIX_META.len()..];
+            let blob = dump_meta(self, &snapshot, &KeyMeta::from_bytes(&v))?;
+            writer.write_all(&[1])?;
+            writer.write_all(&(name.len() as u32).to_be_bytes())?;
+            writer.write_all(name)?;
+            writer.write_all(&(blob.len() as u32).to_be_bytes())?;
+            writer.write_all(&blob)?;
+            count += 1;
+        }
+        writer.write_all(&[0])?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Load the keys of a stream produced by `export_range()` with `restore_key()`, fails at
+    /// the first key which already exists, the keys before it are kept.
+    /// Returns the count of the imported keys.
+    pub fn import_range(&self, mut reader: impl Read) -> Result<u64> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != EXPORT_MAGIC {
+            return Err(Error::Message("invalid export stream".to_string()));
+        }
+        let mut count = 0;
+        loop {
+            let mut flag = [0u8; 1];
+            reader.read_exact(&mut flag)?;
+            if flag[0] == 0 {
+                break;
+            }
+            let name = read_chunk(&mut reader)?;
+            let blob = read_chunk(&mut reader)?;
+            self.restore(&name, &blob)?;
+            count += 1;
+        }
+        self.log_admin("import_range", "", count)?;
+        Ok(count)
+    }
+
+    /// Load a stream produced by `export_range()` like `import_range()`, and save the progress
+    /// into `checkpoint_path` every `IMPORT_CHECKPOINT_INTERVAL` keys, so an interrupted import
+    /// resumes after the last key of the checkpoint. `reader` must read the same stream from
+    /// the start when resuming. The existing keys with the same content as in the stream are
+    /// treated as imported by the interrupted run.
+    ///
+    /// It fails when a cap of `quota` is reached, the checkpoint is saved, so it can resume
+    /// with a larger quota. `progress` is called after every checkpoint. The checkpoint file is
+    /// removed when the import finishes. Returns the progress of the whole import.
+    pub fn import_range_resumable<F>(
+        &self,
+        mut reader: impl Read,
+        checkpoint_path: impl AsRef<Path>,
+        quota: &ImportQuota,
+        mut progress: F,
+    ) -> Result<ImportProgress>
+    where
+        F: FnMut(&ImportProgress),
+    {
+        let checkpoint_path = checkpoint_path.as_ref();
+        let mut state = if checkpoint_path.exists() {
+            decode_import_checkpoint(&fs::read(checkpoint_path)?)
+                .ok_or_else(|| Error::Message("invalid import checkpoint".to_string()))?
+        } else {
+            ImportProgress::default()
+        };
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != EXPORT_MAGIC {
+            return Err(Error::Message("invalid export stream".to_string()));
+        }
+        let imported = state.keys;
+        let result = self.import_keys(&mut reader, &mut state, quota, |state| {
+            write_import_checkpoint(checkpoint_path, state)?;
+            progress(state);
+            Ok(())
+        });
+        if let Err(err) = result {
+            write_import_checkpoint(checkpoint_path, &state)?;
+            return Err(err);
+        }
+        progress(&state);
+        self.log_admin("import_range", "", state.keys - imported)?;
+        fs::remove_file(checkpoint_path).or_else(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => Ok(()),
+            _ => Err(err),
+        })?;
+        Ok(state)
+    }
+
+    /// Import the keys after `state.last_key`, `checkpoint` is called every
+    /// `IMPORT_CHECKPOINT_INTERVAL` keys.
+    fn import_keys<F>(
+        &self,
+        reader: &mut impl Read,
+        state: &mut ImportProgress,
+        quota: &ImportQuota,
+        mut checkpoint: F,
+    ) -> Result<()>
+    where
+        F: FnMut(&ImportProgress) -> Result<()>,
+    {
+        let resume_after = state.last_key.clone();
+        let mut since_checkpoint = 0;
+        loop {
+            let mut flag = [0u8; 1];
+            reader.read_exact(&mut flag)?;
+            if flag[0] == 0 {
+                return Ok(());
+            }
+            let name = read_chunk(reader)?;
+            let blob = read_chunk(reader)?;
+            if matches!(&resume_after, Some(last) if &name <= last) {
+                continue;
+            }
+            let namespace = String::from_utf8_lossy(key_namespace(&name)).into_owned();
+            let namespace_keys = state.namespaces.get(&namespace).copied().unwrap_or(0);
+            let exists = self.get_meta(&name)?.is_some()
+                && matches!(self.dump(&name)?, Some(existing) if same_dump_content(&existing, &blob));
+            if !exists {
+                if matches!(quota.max_keys_per_namespace, Some(n) if namespace_keys >= n) {
+                    return Err(Error::Message(format!(
+                        "import quota of namespace {} is exceeded",
+                        namespace
+                    )));
+                }
+                if matches!(quota.max_bytes, Some(n) if state.bytes + blob.len() as u64 > n) {
+                    return Err(Error::Message("import size cap is exceeded".to_string()));
+                }
+                self.restore(&name, &blob)?;
+            }
+            state.keys += 1;
+            state.bytes += blob.len() as u64;
+            state.namespaces.insert(namespace, namespace_keys + 1);
+            state.last_key = Some(name);
+            since_checkpoint += 1;
+            if since_checkpoint >= IMPORT_CHECKPOINT_INTERVAL {
+                checkpoint(state)?;
+                since_checkpoint = 0;
+            }
+        }
+    }
+}
+
+/// Save the progress into the checkpoint file, it's written into a temporary file and renamed.
+fn write_import_checkpoint(path: &Path, state: &ImportProgress) -> Result<()> {
+    let mut buf = BytesMut::new();
+    buf.put_slice(IMPORT_CHECKPOINT_MAGIC);
+    buf.put_u64(state.keys);
+    buf.put_u64(state.bytes);
+    match &state.last_key {
+        None => buf.put_u8(0),
+        Some(name) => {
+            buf.put_u8(1);
+            buf.put_u32(name.len() as u32);
+            buf.put_slice(name);
+        }
+    }
+    buf.put_u32(state.namespaces.len() as u32);
+    for (namespace, keys) in state.namespaces.iter() {
+        buf.put_u32(namespace.len() as u32);
+        buf.put_slice(namespace.as_bytes());
+        buf.put_u64(*keys);
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, &buf)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Decode a checkpoint file, returns `None` if it's invalid.
+fn decode_import_checkpoint(input: &[u8]) -> Option<ImportProgress> {
+    fn get_bytes(buf: &mut &[u8]) -> Option<Vec<u8>> {
+        if buf.remaining() < 4 {
+            return None;
+        }
+        let len = buf.get_u32() as usize;
+        if buf.remaining() < len {
+            return None;
+        }
+        let bytes = buf[..len].to_vec();
+        buf.advance(len);
+        Some(bytes)
+    }
+
+    let mut buf = input.strip_prefix(IMPORT_CHECKPOINT_MAGIC)?;
+    if buf.remaining() < 17 {
+        return None;
+    }
+    let keys = buf.get_u64();
+    let bytes = buf.get_u64();
+    let last_key = match buf.get_u8() {
+        0 => None,
+        _ => Some(get_bytes(&mut buf)?),
+    };
+    if buf.remaining() < 4 {
+        return None;
+    }
+    let mut namespaces = BTreeMap::new();
+    for _ in 0..buf.get_u32() {
+        let namespace = String::from_utf8(get_bytes(&mut buf)?).ok()?;
+        if buf.remaining() < 8 {
+            return None;
+        }
+        namespaces.insert(namespace, buf.get_u64());
+    }
+    Some(ImportProgress {
+        keys,
+        bytes,
+        last_key,
+        namespaces,
+    })
+}
+
+/// Read a u32 length and the bytes, the buffer grows with the received bytes, so a broken
+/// length doesn't allocate the memory up front.
+fn read_chunk(reader: &mut impl Read) -> Result<Vec<u8>> {
+    let mut b = [0u8; 4];
+    reader.read_exact(&mut b)?;
+    let len = b.as_ref().get_u32() as usize;
+    if len > EXPORT_MAX_CHUNK_SIZE {
+        return Err(Error::Message(format!(
+            "invalid chunk size {} in export stream",
+            len
+        )));
+    }
+    let mut chunk = Vec::new();
+    reader.take(len as u64).read_to_end(&mut chunk)?;
+    if chunk.len() != len {
+        return Err(Error::Message("truncated export stream".to_string()));
+    }
+    Ok(chunk)
+}
@@ -0,0 +1,72 @@
+use std::thread::{self, ThreadId};
+
+use crate::database::{Database, Error, Result};
+
+/// A key frozen by `Database::freeze_key()`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Freeze {
+    /// The thread freezing the key, it's not blocked.
+    owner: ThreadId,
+    block_reads: bool,
+}
+
+impl Database {
+    /// Freeze a key for maintenance, e.g. `list_reindex()`, an export or a repair, while the
+    /// application keeps running. The writes of the key from the other threads fail with
+    /// `Error::Frozen` until `unfreeze_key()` is called, and so do the reads if `block_reads`
+    /// is true. The thread freezing the key is not blocked, so it runs the maintenance.
+    ///
+    /// Unlike `seal_key()`, the freeze is not saved, it's gone when the database is closed.
+    /// Returns `false` if the key is already frozen.
+    pub fn freeze_key(&self, key: &str, block_reads: bool) -> Result<bool> {
+        let key = self.resolve_key(key.as_bytes()).into_owned();
+        let mut frozen_keys = self.frozen_keys.write().unwrap();
+        if frozen_keys.contains_key(&key) {
+            return Ok(false);
+        }
+        self.log_admin("freeze_key", &key, 1)?;
+        frozen_keys.insert(
+            key,
+            Freeze {
+                owner: thread::current().id(),
+                block_reads,
+            },
+        );
+        Ok(true)
+    }
+
+    /// Unfreeze a key frozen by `freeze_key()` from any thread, returns `false` if it's not
+    /// frozen.
+    pub fn unfreeze_key(&self, key: &str) -> Result<bool> {
+        let key = self.resolve_key(key.as_bytes()).into_owned();
+        let mut frozen_keys = self.frozen_keys.write().unwrap();
+        if frozen_keys.remove(&key).is_none() {
+            return Ok(false);
+        }
+        self.log_admin("unfreeze_key", &key, 1)?;
+        Ok(true)
+    }
+
+    pub fn is_frozen(&self, key: impl AsRef<[u8]>) -> bool {
+        let key = self.resolve_key(key.as_ref());
+        self.frozen_keys.read().unwrap().contains_key(key.as_ref())
+    }
+
+    /// Fail with `Error::Frozen` if the key is frozen by another thread, and the access is a
+    /// write or the reads of the key are blocked too.
+    pub(crate) fn check_frozen(&self, key: impl AsRef<[u8]>, write: bool) -> Result<()> {
+        let frozen_keys = self.frozen_keys.read().unwrap();
+        if frozen_keys.is_empty() {
+            return Ok(());
+        }
+        let key = self.resolve_key(key.as_ref());
+        match frozen_keys.get(key.as_ref()) {
+            Some(freeze)
+                if (write || freeze.block_reads) && freeze.owner != thread::current().id() =>
+            {
+                Err(Error::Frozen(String::from_utf8_lossy(&key).into_owned()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::{sync::Arc, time::Duration};
+
+use bytes::Buf;
+use rocksdb::WriteBatch;
+
+use crate::chunk::encode_value_chunk_prefix;
+use crate::codec::*;
+use crate::compaction_filter::{encode_dropped_key_id_key, encode_expiring_key_id_key};
+use crate::consumer::encode_consumer_key_prefix;
+use crate::database::{Database, Result};
+use crate::handle::DatabaseHandle;
+use crate::journal::PREFIX_JOURNAL;
+use crate::sketch::encode_sketch_key;
+use crate::verify::PREFIX_QUARANTINE;
+
+/// Name of the background task started by `DatabaseHandle::start_gc()`.
+pub const GC_TASK_NAME: &str = "simpledb-gc";
+
+/// Result of `Database::gc()`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct GcReport {
+    /// Count of the key IDs with data rows but no meta.
+    pub orphaned_keys: u64,
+    /// Count of the deleted data rows.
+    pub reclaimed_rows: u64,
+}
+
+impl Database {
+    /// Delete the data rows whose key ID has no meta, e.g. left by a crash in `delete_all()`.
+    ///
+    /// The metas and the data rows are read from a snapshot, the key IDs of the unfinished
+    /// transfers of the journal, the quarantined metas and the open `BlobWriter`s are kept.
+    /// The rows of an orphaned key ID are deleted by a range tombstone, so the reclaimed
+    /// space is released by the later compactions.
+    pub fn gc(&self) -> Result<GcReport> {
+        let (snapshot, mut live) = {
+            // a blob writer finishes by writing its meta before it's forgotten, so the snapshot
+            // has either the meta or the ID of the writer
+            let writing = self.writing_key_ids.lock().unwrap();
+            (self.rocksdb.snapshot(), writing.clone())
+        };
+        // the quarantined data rows are skipped, their values are not metas
+        let quarantined_meta = [PREFIX_QUARANTINE, PREFIX_META].concat();
+        for prefix in [PREFIX_META, &quarantined_meta] {
+            let iter = snapshot
+                .iterator_opt(
+                    rocksdb::IteratorMode::From(prefix, rocksdb::Direction::Forward),
+                    self.scan_read_options(),
+                )
+                .take_while(|(k, _)| has_prefix(prefix, k));
+            for (_, v) in iter {
+                // the ID is the first field, even if the rest of a bad meta is broken
+                if v.len() >= 8 {
+                    live.insert(v.as_ref().get_u64());
+                }
+            }
+        }
+        let iter = snapshot
+            .iterator_opt(
+                rocksdb::IteratorMode::From(PREFIX_JOURNAL, rocksdb::Direction::Forward),
+                self.scan_read_options(),
+            )
+            .take_while(|(k, _)| has_prefix(PREFIX_JOURNAL, k));
+        for (_, v) in iter {
+            if v.len() == 8 {
+                live.insert(v.as_ref().get_u64());
+            }
+        }
+
+        let mut report = GcReport::default();
+        let mut orphaned = Vec::new();
+        let mut iter = snapshot.raw_iterator_opt(self.scan_read_options());
+        iter.seek(PREFIX_DATA);
+        while let Some(k) = iter.key() {
+            if !has_prefix(PREFIX_DATA, k) {
+                break;
+            }
+            if k.len() < PREFIX_DATA.len() + 8 {
+                iter.next();
+                continue;
+            }
+            let id = (&k[PREFIX_DATA.len()..]).get_u64();
+            if !live.contains(&id) {
+                // count the rows of the orphaned ID one by one
+                let prefix = encode_data_key(id);
+                while iter.key().is_some_and(|k| has_prefix(&prefix, k)) {
+                    report.reclaimed_rows += 1;
+                    iter.next();
+                }
+                orphaned.push(id);
+                continue;
+            }
+            // skip all the rows of the live ID
+            iter.seek(encode_data_key(id + 1));
+        }
+        iter.status()?;
+
+        if orphaned.is_empty() {
+            return Ok(report);
+        }
+        let mut batch = WriteBatch::default();
+        for id in orphaned.iter().copied() {
+            self.delete_key_id_to_batch(&mut batch, id);
+        }
+        self.rocksdb.write(batch)?;
+        let mut dropped = self.dropped_key_ids.write().unwrap();
+        for id in orphaned.iter() {
+            dropped.remove(id);
+        }
+        report.orphaned_keys = orphaned.len() as u64;
+        self.log_admin("gc", "", report.reclaimed_rows)?;
+        Ok(report)
+    }
+
+    /// Delete all the rows owned by a key ID.
+    fn delete_key_id_to_batch(&self, batch: &mut WriteBatch, id: u64) {
+        self.delete_range_to_batch(batch, encode_data_key(id), encode_data_key(id + 1));
+        self.delete_range_to_batch(
+            batch,
+            encode_reserved_key_prefix(id),
+            encode_reserved_key_prefix(id + 1),
+        );
+        batch.delete(encode_sketch_key(id));
+        self.delete_range_to_batch(
+            batch,
+            encode_consumer_key_prefix(id),
+            encode_consumer_key_prefix(id + 1),
+        );
+        self.delete_range_to_batch(
+            batch,
+            encode_value_chunk_prefix(id),
+            encode_value_chunk_prefix(id + 1),
+        );
+        batch.delete(encode_dropped_key_id_key(id));
+        batch.delete(encode_expiring_key_id_key(id));
+    }
+}
+
+impl DatabaseHandle {
+    /// Run `Database::gc()` every `interval` in a background task named `GC_TASK_NAME`, the
+    /// task holds no reference of the database, so it doesn't keep the database open.
+    /// The errors are ignored, the task retries at the next interval.
+    pub fn start_gc(&self, interval: Duration) {
+        let db = Arc::downgrade(&self.db);
+        self.background_tasks()
+            .spawn(GC_TASK_NAME, interval, move |_| {
+                if let Some(db) = db.upgrade() {
+                    let _ = db.gc();
+                }
+            });
+    }
+}
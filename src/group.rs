@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::io::{ErrorKind, Read, Write};
+
+use crate::codec::VecScoreVal;
+use crate::database::{Database, Error, Result};
+use crate::KeyMeta;
+
+/// Separator between the database name and the key name in the keys of a `DatabaseGroup`.
+pub const GROUP_SEPARATOR: char = '/';
+
+/// Databases attached under names, e.g. one per directory, read through a single handle.
+/// A key `{name}/{key}` of the group is the key `key` of the database attached as `name`.
+#[derive(Default)]
+pub struct DatabaseGroup {
+    pub databases: BTreeMap<String, Database>,
+}
+
+macro_rules! forward_to_database {
+    ($(fn $name:ident(&self, key: &str $(, $arg:ident: $ty:ty)*) -> $ret:ty;)*) => {
+        $(
+            pub fn $name(&self, key: &str $(, $arg: $ty)*) -> $ret {
+                let (db, key) = self.route(key)?;
+                db.$name(key $(, $arg)*)
+            }
+        )*
+    };
+}
+
+impl DatabaseGroup {
+    pub fn new() -> DatabaseGroup {
+        DatabaseGroup::default()
+    }
+
+    /// Attach a database as `name`, the name must not be empty or contain `GROUP_SEPARATOR`.
+    pub fn attach(&mut self, name: &str, db: Database) -> Result<()> {
+        if name.is_empty() || name.contains(GROUP_SEPARATOR) {
+            return Err(Error::Message(format!(
+                "invalid name {} of attached database",
+                name
+            )));
+        }
+        if self.databases.contains_key(name) {
+            return Err(Error::Message(format!(
+                "database {} is already attached",
+                name
+            )));
+        }
+        self.databases.insert(name.to_string(), db);
+        Ok(())
+    }
+
+    /// Detach the database attached as `name`.
+    pub fn detach(&mut self, name: &str) -> Option<Database> {
+        self.databases.remove(name)
+    }
+
+    /// Get the database attached as `name`.
+    pub fn get(&self, name: &str) -> Option<&Database> {
+        self.databases.get(name)
+    }
+
+    /// Get the database and the key name in it of a key of the group.
+    pub fn route<'a>(&self, key: &'a str) -> Result<(&Database, &'a str)> {
+        let (name, rest) = key.split_once(GROUP_SEPARATOR).ok_or_else(|| {
+            Error::Message(format!(
+                "key {} has no database name before '{}'",
+                key, GROUP_SEPARATOR
+            ))
+        })?;
+        match self.databases.get(name) {
+            Some(db) => Ok((db, rest)),
+            None => Err(Error::Message(format!("database {} is not attached", name))),
+        }
+    }
+
+    /// Get all keys of every database with the database names, ordered by the names.
+    pub fn keys(&self) -> Result<Vec<(String, KeyMeta)>> {
+        self.keys_with_prefix("")
+    }
+
+    /// Get the keys of the group starting with `prefix`, only the databases whose names match
+    /// the prefix are scanned.
+    pub fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<(String, KeyMeta)>> {
+        let (databases, key_prefix) = self.match_prefix(prefix);
+        let mut keys = Vec::new();
+        for (name, db) in databases {
+            for (key, meta) in db.keys_with_prefix(key_prefix)? {
+                keys.push((format!("{}{}{}", name, GROUP_SEPARATOR, key), meta));
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Export the keys of the group starting with `prefix` from every matched database into
+    /// `writer`, one `Database::export_range()` stream per database prefixed by its name as
+    /// a u32 length and the bytes. Returns the count of the exported keys.
+    pub fn export_prefix(&self, prefix: &str, mut writer: impl Write) -> Result<u64> {
+        let (databases, key_prefix) = self.match_prefix(prefix);
+        let to = prefix_end(key_prefix);
+        let mut count = 0;
+        for (name, db) in databases {
+            writer.write_all(&(name.len() as u32).to_be_bytes())?;
+            writer.write_all(name.as_bytes())?;
+            count += db.export_range(key_prefix, to.as_deref(), &mut writer)?;
+        }
+        Ok(count)
+    }
+
+    /// Load a stream produced by `export_prefix()` into the databases attached with the same
+    /// names, see `Database::import_range()`. Returns the count of the imported keys.
+    pub fn import(&self, mut reader: impl Read) -> Result<u64> {
+        let mut count = 0;
+        loop {
+            let mut len = [0u8; 4];
+            match reader.read_exact(&mut len) {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+            // the name must be attached, so it's not longer than the attached names
+            let len = u32::from_be_bytes(len) as usize;
+            if self.databases.keys().all(|name| name.len() < len) {
+                return Err(Error::Message(format!(
+                    "invalid database name length {} in group stream",
+                    len
+                )));
+            }
+            let mut name = vec![0u8; len];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name)?;
+            let db = self
+                .databases
+                .get(&name)
+                .ok_or_else(|| Error::Message(format!("database {} is not attached", name)))?;
+            count += db.import_range(&mut reader)?;
+        }
+        Ok(count)
+    }
+
+    /// Get the databases matching a key prefix of the group and the key prefix in them, a
+    /// prefix without `GROUP_SEPARATOR` matches the database names.
+    fn match_prefix<'a>(&self, prefix: &'a str) -> (Vec<(&String, &Database)>, &'a str) {
+        match prefix.split_once(GROUP_SEPARATOR) {
+            Some((name, rest)) => (
+                self.databases.iter().filter(|(n, _)| *n == name).collect(),
+                rest,
+            ),
+            None => (
+                self.databases
+                    .iter()
+                    .filter(|(n, _)| n.starts_with(prefix))
+                    .collect(),
+                "",
+            ),
+        }
+    }
+
+    forward_to_database! {
+        fn get_meta(&self, key: &str) -> Result<Option<KeyMeta>>;
+        fn get_count(&self, key: &str) -> Result<u64>;
+        fn dump_key(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+        fn map_get(&self, key: &str, field: impl AsRef<[u8]>) -> Result<Option<Vec<u8>>>;
+        fn map_items(&self, key: &str) -> Result<Vec<(String, Box<[u8]>)>>;
+
+        fn set_is_member(&self, key: &str, value: &[u8]) -> Result<bool>;
+        fn set_items(&self, key: &str) -> Result<Vec<Box<[u8]>>>;
+
+        fn list_items(&self, key: &str) -> Result<Vec<Box<[u8]>>>;
+
+        fn sorted_list_items(&self, key: &str) -> Result<VecScoreVal>;
+
+        fn sorted_set_is_member(&self, key: &str, value: &[u8]) -> Result<bool>;
+        fn sorted_set_items(&self, key: &str) -> Result<VecScoreVal>;
+
+        fn value_get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    }
+}
+
+/// Get the first key name after all the names starting with `prefix`, `None` if there is no
+/// such name, e.g. for an empty prefix.
+fn prefix_end(prefix: &str) -> Option<String> {
+    let mut chars: Vec<char> = prefix.chars().collect();
+    while let Some(c) = chars.pop() {
+        if let Some(next) = char::from_u32(c as u32 + 1) {
+            chars.push(next);
+            return Some(chars.into_iter().collect());
+        }
+    }
+    None
+}
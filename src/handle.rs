@@ -0,0 +1,143 @@
+use std::{collections::VecDeque, ops::Deref, sync::Arc};
+
+use bytes::Buf;
+use rocksdb::{Direction, IteratorMode};
+
+use crate::codec::*;
+use crate::database::{Database, Result};
+
+pub(crate) type KVBytes = (Box<[u8]>, Box<[u8]>);
+
+/// Default of `Options::iter_page_size`, items read by a single seek of the iterators of
+/// `DatabaseHandle`.
+pub const HANDLE_ITER_PAGE_SIZE: usize = 128;
+
+/// Cheap clonable handle of a database, it can be shared across threads and stashed in the
+/// state of web frameworks. All the methods of `Database` are available through `Deref`.
+#[derive(Clone)]
+pub struct DatabaseHandle {
+    pub(crate) db: Arc<Database>,
+}
+
+impl DatabaseHandle {
+    pub fn new(db: Database) -> DatabaseHandle {
+        DatabaseHandle { db: Arc::new(db) }
+    }
+
+    /// Iterate `(field, value)` pairs of a map, the iterator owns a clone of the handle.
+    pub fn map_iter(&self, key: &str) -> Result<MapIter> {
+        Ok(MapIter {
+            inner: self.data_iter(key.as_bytes())?,
+        })
+    }
+
+    /// Iterate values of a list, the iterator owns a clone of the handle.
+    pub fn list_iter(&self, key: impl AsRef<[u8]>) -> Result<ListIter> {
+        Ok(ListIter {
+            inner: self.data_iter(key.as_ref())?,
+        })
+    }
+
+    fn data_iter(&self, key: &[u8]) -> Result<DataIter> {
+        let (next, end) = match self.db.get_meta(key)? {
+            Some(meta) => (
+                encode_data_key(meta.id).to_vec(),
+                encode_data_key(meta.id + 1).to_vec(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+        Ok(DataIter {
+            db: self.clone(),
+            next,
+            end,
+            page: VecDeque::new(),
+        })
+    }
+}
+
+impl Deref for DatabaseHandle {
+    type Target = Database;
+
+    fn deref(&self) -> &Database {
+        &self.db
+    }
+}
+
+impl From<Database> for DatabaseHandle {
+    fn from(db: Database) -> DatabaseHandle {
+        DatabaseHandle::new(db)
+    }
+}
+
+/// Reads the data rows of a key page by page, so it does not borrow the database.
+/// Every page is read from the latest state of the database.
+struct DataIter {
+    db: DatabaseHandle,
+    next: Vec<u8>,
+    end: Vec<u8>,
+    page: VecDeque<KVBytes>,
+}
+
+impl Iterator for DataIter {
+    type Item = KVBytes;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.page.is_empty() && self.next < self.end {
+            let page_size = self.db.options.iter_page_size.max(1);
+            let mut opts = self.db.scan_read_options();
+            opts.set_iterate_upper_bound(self.end.clone());
+            let iter = self
+                .db
+                .rocksdb
+                .iterator_opt(IteratorMode::From(&self.next, Direction::Forward), opts);
+            self.page.extend(iter.take(page_size));
+            match self.page.back() {
+                Some((k, _)) if self.page.len() == page_size => {
+                    // the smallest key after the last one
+                    self.next = k.to_vec();
+                    self.next.push(0);
+                }
+                _ => self.next = self.end.clone(),
+            }
+        }
+        self.page.pop_front()
+    }
+}
+
+/// Iterator of `DatabaseHandle::map_iter()`.
+pub struct MapIter {
+    inner: DataIter,
+}
+
+impl Iterator for MapIter {
+    type Item = (Box<[u8]>, Box<[u8]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (k, v) = self.inner.next()?;
+        let field = decode_data_key_map_field(k.as_ref());
+        let v = if v.is_empty() {
+            // a chunked value is reassembled, it's left empty if the chunks can't be read
+            let key_id = (&k[PREFIX_DATA.len()..]).get_u64();
+            self.inner
+                .db
+                .map_item_value(key_id, field, v)
+                .unwrap_or_default()
+        } else {
+            v
+        };
+        Some((Box::from(field), v))
+    }
+}
+
+/// Iterator of `DatabaseHandle::list_iter()`.
+pub struct ListIter {
+    inner: DataIter,
+}
+
+impl Iterator for ListIter {
+    type Item = Box<[u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(_, v)| v)
+    }
+}
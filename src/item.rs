@@ -0,0 +1,137 @@
+use bytes::Buf;
+use sha2::{Digest, Sha256};
+
+use crate::codec::*;
+use crate::database::{Database, Result};
+
+/// An item of a key decoded per its key type, see `Database::for_each_item()`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    MapEntry {
+        field: Box<[u8]>,
+        value: Box<[u8]>,
+    },
+    SetMember(Box<[u8]>),
+    /// A `list` value, `index` counts from the left.
+    ListValue {
+        index: u64,
+        value: Box<[u8]>,
+    },
+    /// A `sorted list` item or a `sorted set` member.
+    SortedScoreValue {
+        score: Box<[u8]>,
+        value: Box<[u8]>,
+    },
+    ValueChunk {
+        index: u32,
+        data: Box<[u8]>,
+    },
+    LogEntry {
+        offset: u64,
+        value: Box<[u8]>,
+    },
+    /// An item of a custom key type decoded by its `CustomKeyType`.
+    Custom(Vec<u8>),
+}
+
+/// SHA-256 digest of the items of a key, see `Database::key_digest()`.
+pub type KeyDigest = [u8; 32];
+
+impl Database {
+    /// Iterate the items of a key of any type in the order of the type's own `for_each`, so
+    /// generic tools don't need to match the key type. Returns the count of the items.
+    pub fn for_each_item<F>(&self, key: &str, mut f: F) -> Result<u64>
+    where
+        F: FnMut(Item) -> bool,
+    {
+        let meta = match self.get_meta(key)? {
+            None => return Ok(0),
+            Some(meta) => meta,
+        };
+        match meta.key_type {
+            KeyType::Map => self.map_for_each(key, |field, value| {
+                f(Item::MapEntry {
+                    field: Box::from(field),
+                    value,
+                })
+            }),
+            KeyType::Set => self.set_for_each(key, |member| f(Item::SetMember(member))),
+            KeyType::List => {
+                let mut index = 0;
+                self.list_for_each(key, |value| {
+                    index += 1;
+                    f(Item::ListValue {
+                        index: index - 1,
+                        value,
+                    })
+                })
+            }
+            KeyType::SortedList => self.sorted_list_for_each(key, |(score, value)| {
+                f(Item::SortedScoreValue { score, value })
+            }),
+            KeyType::SortedSet => self.sorted_set_for_each(key, |(score, value)| {
+                f(Item::SortedScoreValue { score, value })
+            }),
+            KeyType::Value => self.for_each_data(key, None, |k, data| {
+                f(Item::ValueChunk {
+                    index: (&k[9..]).get_u32(),
+                    data,
+                })
+            }),
+            KeyType::Log => self.for_each_data(key, None, |k, value| {
+                f(Item::LogEntry {
+                    offset: decode_data_key_log_offset(&k),
+                    value,
+                })
+            }),
+            KeyType::Custom(tag) => {
+                let key_type = self.custom_key_type(tag)?;
+                self.for_each_data(key, None, |k, v| {
+                    f(Item::Custom(key_type.decode_item(&k[9..], &v)))
+                })
+            }
+        }
+    }
+
+    /// Compute a digest of the key type and the items of a key in the order of
+    /// `for_each_item()`, e.g. to verify a replica or a backup by comparing the digests only.
+    /// The digest does not depend on the key ID, the positions of a `list`, the sequences of a
+    /// `sorted list` or the chunk size of a `value`. Returns `None` if the key does not exist.
+    pub fn key_digest(&self, key: &str) -> Result<Option<KeyDigest>> {
+        let meta = match self.get_meta(key)? {
+            None => return Ok(None),
+            Some(meta) => meta,
+        };
+        let mut hasher = Sha256::new();
+        hasher.update([meta.key_type.to_u8()]);
+        self.for_each_item(key, |item| {
+            match item {
+                Item::MapEntry { field, value } => {
+                    update_digest(&mut hasher, &field);
+                    update_digest(&mut hasher, &value);
+                }
+                Item::SetMember(member) => update_digest(&mut hasher, &member),
+                Item::ListValue { value, .. } => update_digest(&mut hasher, &value),
+                Item::SortedScoreValue { score, value } => {
+                    update_digest(&mut hasher, &score);
+                    update_digest(&mut hasher, &value);
+                }
+                // without the length, so the digest is the same for any chunk size
+                Item::ValueChunk { data, .. } => hasher.update(&data),
+                Item::LogEntry { offset, value } => {
+                    update_digest(&mut hasher, &offset.to_be_bytes());
+                    update_digest(&mut hasher, &value);
+                }
+                Item::Custom(item) => update_digest(&mut hasher, &item),
+            }
+            true
+        })?;
+        Ok(Some(hasher.finalize().into()))
+    }
+}
+
+/// Put the length and the bytes into the digest, so the boundaries of the items count.
+fn update_digest(hasher: &mut Sha256, b: &[u8]) {
+    hasher.update((b.len() as u32).to_be_bytes());
+    hasher.update(b);
+}
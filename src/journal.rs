@@ -0,0 +1,220 @@
+use std::sync::atomic::Ordering;
+
+use bytes::{Buf, BufMut, BytesMut};
+use rocksdb::{Direction, IteratorMode, Snapshot, WriteBatch};
+
+use crate::chunk::encode_value_chunk_prefix;
+use crate::codec::*;
+use crate::consumer::encode_consumer_key_prefix;
+use crate::database::{Database, Error, Result};
+use crate::sketch::encode_sketch_key;
+
+/// Key prefix for the journal of the unfinished transfers into this database, followed by the
+/// key name, the value is the key ID allocated for the transfer.
+pub static PREFIX_JOURNAL: &[u8] = b"j";
+/// Max rows copied in a write batch by `Database::copy_key_to()`.
+pub const JOURNAL_COPY_BATCH_SIZE: usize = 1000;
+
+/// Encode key of a journal entry.
+pub fn encode_journal_key(key: impl AsRef<[u8]>) -> BytesMut {
+    let key = key.as_ref();
+    let mut buf = BytesMut::with_capacity(PREFIX_JOURNAL.len() + key.len());
+    buf.put_slice(PREFIX_JOURNAL);
+    buf.put_slice(key);
+    buf
+}
+
+impl Database {
+    /// Copy a key with all its rows into another database, the copy is read from a snapshot.
+    ///
+    /// The rows are written in batches of `JOURNAL_COPY_BATCH_SIZE` under a new key ID of `dst`,
+    /// and the meta is written together with the removal of the journal entry at last, so the
+    /// key is either fully copied or not visible at all. If it's interrupted, the half-copied
+    /// rows are rolled back when `dst` is opened next time, see `recover_journal()`.
+    /// Returns the count of the copied rows.
+    pub fn copy_key_to(&self, key: &str, dst: &Database) -> Result<u64> {
+        let snapshot = self.rocksdb.snapshot();
+        let src_key = self.resolve_key(key.as_bytes()).into_owned();
+        let meta = match snapshot.get(encode_meta_key(&src_key))? {
+            None => return Ok(0),
+            Some(v) => KeyMeta::from_bytes(&v),
+        };
+        if dst.get_meta(key)?.is_some() {
+            return Err(Error::Message(format!(
+                "key {} already exists in {}",
+                key, dst.path
+            )));
+        }
+        let mut dst_meta = meta.clone();
+        dst_meta.id = dst.allocate_key_id()?;
+        if self.chunked_values.load(Ordering::SeqCst) && meta.key_type == KeyType::Map {
+            dst.enable_chunked_values()?;
+        }
+        dst.rocksdb
+            .put(encode_journal_key(key), dst_meta.id.to_be_bytes())?;
+
+        let (copied, mut batch) = copy_rows(self, &snapshot, meta.id, dst, dst_meta.id)?;
+        batch.put(
+            encode_type_index_key(dst_meta.key_type, key),
+            FILL_EMPTY_DATA,
+        );
+        dst.save_meta_to_batch(&mut batch, key, &dst_meta, false)?;
+        batch.delete(encode_journal_key(key));
+        dst.rocksdb.write(batch)?;
+        dst.forget_absent_key(&dst.resolve_key(key.as_bytes()));
+        dst.log_admin("copy_key_to", key, copied)?;
+        Ok(copied)
+    }
+
+    /// Rename a key by rewriting all its rows under a new key ID, e.g. to retire the old key ID
+    /// after an ID collision is repaired. The old key stays readable while the rows are copied
+    /// from a snapshot, then the new meta is written together with the deletes of the old key
+    /// in a single batch. `old` is frozen by `freeze_key()` during the copy, so the writes from
+    /// the other threads fail with `Error::Frozen` instead of being lost, and the aliases of
+    /// `old` are removed. It's journaled like `copy_key_to()`, so an interrupted rename is
+    /// rolled back. Returns the count of the rewritten rows.
+    pub fn rename_key_rewriting(&self, old: &str, new: &str) -> Result<u64> {
+        self.check_writable(old)?;
+        // the key may be frozen by this thread already, then it's kept frozen
+        let frozen = self.freeze_key(old, false)?;
+        if !frozen {
+            self.check_frozen(old, true)?;
+        }
+        let result = self.rewrite_key(old, new);
+        if frozen {
+            self.unfreeze_key(old)?;
+        }
+        result
+    }
+
+    /// Rewrite the rows of the frozen key `old` as `new`, see `rename_key_rewriting()`.
+    fn rewrite_key(&self, old: &str, new: &str) -> Result<u64> {
+        let snapshot = self.rocksdb.snapshot();
+        let old_key = self.resolve_key(old.as_bytes()).into_owned();
+        let meta = match snapshot.get(encode_meta_key(&old_key))? {
+            None => return Err(Error::Message(format!("key {} does not exist", old))),
+            Some(v) => KeyMeta::from_bytes(&v),
+        };
+        if self.get_meta(new)?.is_some() {
+            return Err(Error::Message(format!("key {} already exists", new)));
+        }
+        let mut new_meta = meta.clone();
+        new_meta.id = self.allocate_key_id()?;
+        self.rocksdb
+            .put(encode_journal_key(new), new_meta.id.to_be_bytes())?;
+
+        let (rewritten, mut batch) = copy_rows(self, &snapshot, meta.id, self, new_meta.id)?;
+        batch.put(
+            encode_type_index_key(new_meta.key_type, new),
+            FILL_EMPTY_DATA,
+        );
+        self.save_meta_to_batch(&mut batch, new, &new_meta, false)?;
+        batch.delete(encode_journal_key(new));
+        self.delete_range_to_batch(
+            &mut batch,
+            encode_data_key(meta.id),
+            encode_data_key(meta.id + 1),
+        );
+        self.delete_key_to_batch(&mut batch, &old_key, &meta, Some(meta.count as i64));
+        self.delete_aliases_to_batch(&mut batch, &old_key);
+        self.rocksdb.write(batch)?;
+        self.forget_aliases(&old_key);
+        self.forget_absent_key(&self.resolve_key(new.as_bytes()));
+        self.log_admin("rename_key_rewriting", old, rewritten)?;
+        Ok(rewritten)
+    }
+
+    /// Roll back the unfinished transfers into this database by deleting their half-copied
+    /// rows, it runs automatically when opening the database. Returns the count of the
+    /// rolled back transfers.
+    pub fn recover_journal(&self) -> Result<u64> {
+        let mut batch = WriteBatch::default();
+        let mut count = 0;
+        let iter = self
+            .rocksdb
+            .prefix_iterator(PREFIX_JOURNAL)
+            .take_while(|(k, _)| has_prefix(PREFIX_JOURNAL, k.as_ref()));
+        for (k, v) in iter {
+            if v.len() == 8 {
+                let id = v.as_ref().get_u64();
+                self.delete_range_to_batch(
+                    &mut batch,
+                    encode_data_key(id),
+                    encode_data_key(id + 1),
+                );
+                self.delete_range_to_batch(
+                    &mut batch,
+                    encode_reserved_key_prefix(id),
+                    encode_reserved_key_prefix(id + 1),
+                );
+                batch.delete(encode_sketch_key(id));
+                self.delete_range_to_batch(
+                    &mut batch,
+                    encode_consumer_key_prefix(id),
+                    encode_consumer_key_prefix(id + 1),
+                );
+                self.delete_range_to_batch(
+                    &mut batch,
+                    encode_value_chunk_prefix(id),
+                    encode_value_chunk_prefix(id + 1),
+                );
+            }
+            batch.delete(k);
+            count += 1;
+        }
+        if count > 0 {
+            self.rocksdb.write(batch)?;
+        }
+        Ok(count)
+    }
+}
+
+/// Copy the data, reserved, sketch, consumer and value chunk rows of `src_id` read from
+/// `snapshot` into `dst` as `dst_id`, in batches of `JOURNAL_COPY_BATCH_SIZE`. Returns the
+/// count of the copied rows and the batch of the last rows, which is not written yet.
+fn copy_rows(
+    src: &Database,
+    snapshot: &Snapshot,
+    src_id: u64,
+    dst: &Database,
+    dst_id: u64,
+) -> Result<(u64, WriteBatch)> {
+    let mut copied = 0;
+    let mut batch = WriteBatch::default();
+    let prefixes = [
+        (encode_data_key(src_id), encode_data_key(dst_id)),
+        (
+            encode_reserved_key_prefix(src_id),
+            encode_reserved_key_prefix(dst_id),
+        ),
+        (encode_sketch_key(src_id), encode_sketch_key(dst_id)),
+        (
+            encode_consumer_key_prefix(src_id),
+            encode_consumer_key_prefix(dst_id),
+        ),
+        (
+            encode_value_chunk_prefix(src_id),
+            encode_value_chunk_prefix(dst_id),
+        ),
+    ];
+    for (src_prefix, dst_prefix) in prefixes.iter() {
+        let iter = snapshot.iterator_opt(
+            IteratorMode::From(src_prefix, Direction::Forward),
+            src.scan_read_options(),
+        );
+        for (k, v) in iter {
+            if !has_prefix(src_prefix, &k) {
+                break;
+            }
+            let mut new_key = BytesMut::with_capacity(k.len());
+            new_key.put_slice(dst_prefix);
+            new_key.put_slice(&k[src_prefix.len()..]);
+            batch.put(new_key, v);
+            copied += 1;
+            if batch.len() >= JOURNAL_COPY_BATCH_SIZE {
+                dst.rocksdb.write(std::mem::take(&mut batch))?;
+            }
+        }
+    }
+    Ok((copied, batch))
+}
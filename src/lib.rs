@@ -12,20 +12,123 @@
 //! ```
 //!
 //! ## Supported Data Type
-//! - **map**: store field/value pairs, includes the following operations with `map_` prefix: `get`, `put`, `delete`, `count`, `for_each`, `items`.
-//! - **set**: store unique values, includes the following operations with `set_` prefix: `add`, `is_member`, `delete`, `count`, `for_each`, `items`.
-//! - **list**: store ordered values, includes the following operations with `list_` prefix: `left_push`, `left_pop`, `right_push`, `right_pop`, `count`, `for_each`, `items`.
-//! - **sorted list**: store sorted score/value pairs, includes the following operations with `sorted_list_` prefix: `add`, `left_pop`, `right_pop`, `count`, `for_each`, `items`.
-//! - **sorted set**: store sorted score/value pairs, includes the following operations with `sorted_set_` prefix: `add`, `is_member`, `delete`, `left`, `right`, `for_each`, `items`.
+//! - **map**: store field/value pairs, includes the following operations with `map_` prefix: `get`, `put`, `swap`, `delete`, `count`, `for_each`, `for_each_utf8`, `for_each_range`, `items`, `aggregate`, `move_field`, `merge`, `value_len`.
+//! - **set**: store unique values, includes the following operations with `set_` prefix: `add`, `is_member`, `delete`, `diff_count`, `intersect_count`, `register_sketch`, `intersect_estimate`, `count`, `for_each`, `items`, `sample`.
+//! - **list**: store ordered values, includes the following operations with `list_` prefix: `left_push`, `left_pop`, `left_pop_n`, `right_push`, `right_pop`, `right_pop_n`, `rotate`, `dedupe`, `reindex`, `consumer_read`, `consumer_reset`, `count`, `for_each`, `items`, `items_range`.
+//! - **sorted list**: store sorted score/value pairs, includes the following operations with `sorted_list_` prefix: `add`, `left_pop`, `left_pop_with_sequence`, `right_pop`, `right_pop_with_sequence`, `pop_any`, `move`, `reserve`, `commit`, `release`, `reseed`, `trim`, `compact`, `count`, `count_in_range`, `for_each`, `for_each_with_sequence`, `items`, `items_after`, and `priority_queue` wraps it with typed priorities.
+//! - **sorted set**: store sorted score/value pairs, includes the following operations with `sorted_set_` prefix: `add`, `is_member`, `scores`, `delete`, `update_member`, `delete_range_by_score`, `delete_range_by_rank`, `left`, `right`, `percentile`, `histogram`, `for_each`, `for_each_rev`, `items`.
+//! - **value**: store a single value split into chunks, includes the following operations with `value_` prefix: `put`, `get`, `len`, and can be streamed with `blob_writer` and `blob_reader`.
+//! - **log**: store append-only values with offsets, includes the following operations with `log_` prefix: `append`, `read`, `truncate_before`.
 //! - Notes: the difference between `sorted list` and `sorted set` is `list` allow the same members, `set` does not allow the same members.
 
+mod admin;
+mod alias;
+mod background;
+mod blob;
+mod chunk;
+mod compaction;
+mod compaction_filter;
+mod config;
+mod consumer;
+mod custom;
 mod database;
+mod describe;
+mod diff;
+mod dump;
+mod existence;
+mod expire;
+mod export;
+mod freeze;
+mod gc;
+mod group;
+mod handle;
+mod item;
+mod journal;
+mod log;
+mod manifest;
+mod meta_export;
+mod metrics;
+mod namespace;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod pipeline;
+mod priority_queue;
+mod raw;
+mod read_guard;
+mod read_view;
+mod reader;
+mod replication;
+mod retention;
+mod schema;
+mod seal;
+mod sharded;
+mod sketch;
+mod snapshot;
+mod template;
+mod tier;
+mod value;
+mod verify;
 
 /// Encoding utilities.
 pub mod codec;
+/// Typed records stored in `map`.
+pub mod record;
+/// Session store on top of `map`.
+pub mod sessions;
 
-pub use codec::{BytesComparableScore, KeyMeta, KeyType};
-pub use database::{Database, Options, Result};
+pub use admin::{AdminLogEntry, PREFIX_ADMIN_LOG};
+pub use alias::PREFIX_ALIAS;
+pub use background::{BackgroundTasks, StopSignal, BACKGROUND_SHUTDOWN_TIMEOUT};
+pub use blob::BlobHash;
+pub use chunk::PREFIX_VALUE_CHUNK;
+pub use codec::{BytesComparableScore, KeyMeta, KeyType, CUSTOM_KEY_TYPE_MIN};
+pub use compaction::{CompactionEvent, CompactionKind, CompactionListener};
+pub use compaction_filter::{PREFIX_DROPPED_KEY_ID, PREFIX_EXPIRING_KEY_ID};
+pub use config::{KeyConfig, PREFIX_KEY_CONFIG};
+pub use consumer::PREFIX_CONSUMER;
+pub use custom::CustomKeyType;
+pub use database::{
+    Aggregate, CompactOnOpen, CompactProgress, Database, Error, HealthStatus, MemoryUsage,
+    NumberFormat, OnConflict, Options, Preset, Result, SaveMetaPolicy, ScoreStats, WriteStall,
+    COMPACT_CHUNK_KEY_IDS, MARKER_FILE, SCORE_STATS_BUCKETS, WRITE_CAPACITY_POLL_INTERVAL,
+};
+pub use describe::{KeyDescription, MetaExtra};
+pub use diff::KeyDiff;
+pub use dump::{DUMP_MAGIC, DUMP_VERSION};
+pub use expire::{ExpiredCallback, ExpiredEntry, SORTED_SET_SWEEPER_TASK_NAME};
+pub use export::{
+    ImportProgress, ImportQuota, EXPORT_MAGIC, EXPORT_MAX_CHUNK_SIZE, IMPORT_CHECKPOINT_INTERVAL,
+    IMPORT_CHECKPOINT_MAGIC,
+};
+pub use gc::{GcReport, GC_TASK_NAME};
+pub use group::{DatabaseGroup, GROUP_SEPARATOR};
+pub use handle::{DatabaseHandle, ListIter, MapIter, HANDLE_ITER_PAGE_SIZE};
+pub use item::{Item, KeyDigest};
+pub use journal::{JOURNAL_COPY_BATCH_SIZE, PREFIX_JOURNAL};
+pub use manifest::{Manifest, KEY_MANIFEST, MANIFEST_FEATURES, META_FORMAT_VERSION};
+pub use metrics::MetricsRecorder;
+pub use namespace::{NamespaceStats, KEY_NAMESPACE_STATS_READY, PREFIX_NAMESPACE_STATS};
+pub use pipeline::{PipelineWriter, WriteOp, WritePipeline};
+pub use priority_queue::{Priority, PriorityQueue};
+pub use raw::{decode_raw_row, RawRow};
+pub use read_guard::ReadGuard;
+pub use read_view::ReadView;
+pub use reader::Reader;
+pub use record::{Record, RecordField};
+pub use replication::{
+    DatabaseSink, ReplicationEvent, ReplicationSink, KEY_REPLICATION_CHECKPOINT,
+};
+pub use retention::{SortedListRetention, SORTED_LIST_TRIM_STEP};
+pub use schema::{Schema, ValueCodec};
+pub use seal::PREFIX_SEALED;
+pub use sharded::ShardedDatabase;
+#[cfg(feature = "derive")]
+pub use simpledb_derive::SimpledbRecord;
+pub use sketch::{PREFIX_SKETCH, SKETCH_PRECISION};
+pub use template::{Template, TemplateKey};
+pub use tier::Tier;
+pub use value::{BlobReader, BlobWriter};
+pub use verify::{BadMeta, BadRowCallback, BadRowPolicy, PREFIX_QUARANTINE};
 
 /// Re-exports the rocksdb crate.
 pub mod rocksdb {
@@ -0,0 +1,82 @@
+use rocksdb::{Direction, IteratorMode, ReadOptions, WriteBatch};
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+
+impl Database {
+    /// Append an entry to a log, returns the offset of the entry.
+    /// Offsets start from zero and increase by one, they are never reused even after truncating.
+    pub fn log_append(&self, key: &str, value: &[u8]) -> Result<u64> {
+        self.check_writable(key)?;
+        self.check_schema_value(key.as_bytes(), value)?;
+        let _guard = self.log_lock.lock().unwrap();
+        let mut meta = match self.get_log_meta(key)? {
+            Some(m) => m,
+            None => self.get_or_create_meta(key, KeyType::Log)?,
+        };
+        let (first_offset, offset) = meta.decode_log_extra();
+        meta.encode_log_extra(first_offset, offset + 1);
+        meta.count += 1;
+        let mut batch = WriteBatch::default();
+        batch.put(encode_data_key_log_entry(meta.id, offset), value);
+        self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+        self.rocksdb.write(batch)?;
+        Ok(offset)
+    }
+
+    /// Read at most `max` entries from `from_offset`, returns `(offset, value)` pairs.
+    pub fn log_read(&self, key: &str, from_offset: u64, max: usize) -> Result<Vec<OffsetVal>> {
+        let meta = match self.get_log_meta(key)? {
+            Some(m) => m,
+            None => return Ok(Vec::new()),
+        };
+        let start = encode_data_key_log_entry(meta.id, from_offset);
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_upper_bound(encode_data_key(meta.id + 1).to_vec());
+        let iter = self
+            .rocksdb
+            .iterator_opt(IteratorMode::From(&start, Direction::Forward), opts);
+        Ok(iter
+            .take(max)
+            .map(|(k, v)| (decode_data_key_log_offset(k.as_ref()), v))
+            .collect())
+    }
+
+    /// Delete all the entries before `offset`, returns the deleted entries count.
+    pub fn log_truncate_before(&self, key: &str, offset: u64) -> Result<u64> {
+        self.check_writable(key)?;
+        let _guard = self.log_lock.lock().unwrap();
+        let mut meta = match self.get_log_meta(key)? {
+            Some(m) => m,
+            None => return Ok(0),
+        };
+        let (first_offset, next_offset) = meta.decode_log_extra();
+        let offset = offset.min(next_offset);
+        if offset <= first_offset {
+            return Ok(0);
+        }
+        let deleted = offset - first_offset;
+        meta.encode_log_extra(offset, next_offset);
+        meta.count -= deleted;
+        let mut batch = WriteBatch::default();
+        self.delete_range_to_batch(
+            &mut batch,
+            encode_data_key_log_entry(meta.id, first_offset),
+            encode_data_key_log_entry(meta.id, offset),
+        );
+        // keep the meta even if it's empty, so the offsets are not reused
+        self.save_meta_to_batch(&mut batch, key, &meta, false)?;
+        self.rocksdb.write(batch)?;
+        Ok(deleted)
+    }
+
+    fn get_log_meta(&self, key: &str) -> Result<Option<KeyMeta>> {
+        match self.get_meta(key)? {
+            Some(m) if m.key_type != KeyType::Log => Err(Error::Message(format!(
+                "key {} is a {:?}, not a log",
+                key, m.key_type
+            ))),
+            m => Ok(m),
+        }
+    }
+}
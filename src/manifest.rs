@@ -0,0 +1,174 @@
+use std::sync::atomic::Ordering;
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::database::{Database, Error, Result};
+
+/// Key of the manifest row describing the format of the database, see `Manifest`.
+pub static KEY_MANIFEST: &[u8] = b"smanifest";
+
+/// Version of the format of the metas and the data rows written by this crate, it's increased
+/// when the old versions can not decode the rows written by the new one.
+pub const META_FORMAT_VERSION: u32 = 1;
+
+/// Features changing how the rows are written, an old version of this crate not knowing a
+/// feature recorded in the manifest refuses to open the database.
+pub const MANIFEST_FEATURES: &[&str] = &["lazy_delete", "chunked_values"];
+
+/// Describes the format of a database, it's saved in `KEY_MANIFEST` when the database is opened
+/// and checked when it's opened next time, see `Database::manifest()`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Manifest {
+    /// Version of the crate which opened the database last time.
+    pub crate_version: String,
+    pub meta_format_version: u32,
+    /// Features of `MANIFEST_FEATURES` ever enabled on the database, e.g. `lazy_delete`.
+    pub features: Vec<String>,
+}
+
+impl Manifest {
+    pub fn to_bytes(&self) -> BytesMut {
+        let mut buf = BytesMut::new();
+        buf.put_u32(self.meta_format_version);
+        put_string(&mut buf, &self.crate_version);
+        buf.put_u32(self.features.len() as u32);
+        for feature in self.features.iter() {
+            put_string(&mut buf, feature);
+        }
+        buf
+    }
+
+    pub fn from_bytes(mut buf: &[u8]) -> Option<Manifest> {
+        if buf.remaining() < 4 {
+            return None;
+        }
+        let meta_format_version = buf.get_u32();
+        let crate_version = get_string(&mut buf)?;
+        if buf.remaining() < 4 {
+            return None;
+        }
+        let count = buf.get_u32();
+        let mut features = Vec::new();
+        for _ in 0..count {
+            features.push(get_string(&mut buf)?);
+        }
+        Some(Manifest {
+            crate_version,
+            meta_format_version,
+            features,
+        })
+    }
+}
+
+fn put_string(buf: &mut BytesMut, s: &str) {
+    buf.put_u32(s.len() as u32);
+    buf.put_slice(s.as_bytes());
+}
+
+fn get_string(buf: &mut &[u8]) -> Option<String> {
+    if buf.remaining() < 4 {
+        return None;
+    }
+    let len = buf.get_u32() as usize;
+    if buf.remaining() < len {
+        return None;
+    }
+    let s = String::from_utf8(buf[..len].to_vec()).ok()?;
+    buf.advance(len);
+    Some(s)
+}
+
+impl Database {
+    /// Get the manifest saved when the database was opened.
+    pub fn manifest(&self) -> Result<Option<Manifest>> {
+        match self.rocksdb.get_pinned(KEY_MANIFEST)? {
+            None => Ok(None),
+            Some(v) => Manifest::from_bytes(&v)
+                .map(Some)
+                .ok_or_else(|| Error::IncompatibleFormat("invalid manifest".to_string())),
+        }
+    }
+
+    /// Check the manifest saved by the previous opens before anything is decoded, then save the
+    /// manifest of this version. The databases created before the manifest are in the first
+    /// format.
+    pub(crate) fn check_manifest(&self) -> Result<()> {
+        let old = self.manifest()?;
+        let mut features = Vec::new();
+        if let Some(old) = &old {
+            if old.meta_format_version > META_FORMAT_VERSION {
+                return Err(Error::IncompatibleFormat(format!(
+                    "the format version of database {} is {}, written by simpledb {}, this \
+                     version {} supports up to {}, upgrade simpledb to {} or later to open it",
+                    self.path,
+                    old.meta_format_version,
+                    old.crate_version,
+                    env!("CARGO_PKG_VERSION"),
+                    META_FORMAT_VERSION,
+                    old.crate_version
+                )));
+            }
+            if let Some(feature) = old
+                .features
+                .iter()
+                .find(|f| !MANIFEST_FEATURES.contains(&f.as_str()))
+            {
+                return Err(Error::IncompatibleFormat(format!(
+                    "database {} uses feature {} of simpledb {}, which is unknown to this \
+                     version {}, upgrade simpledb to {} or later to open it",
+                    self.path,
+                    feature,
+                    old.crate_version,
+                    env!("CARGO_PKG_VERSION"),
+                    old.crate_version
+                )));
+            }
+            features = old.features.clone();
+        }
+        if self.options.lazy_delete && !features.iter().any(|f| f == "lazy_delete") {
+            features.push("lazy_delete".to_string());
+        }
+        if self.options.large_value_threshold.is_some()
+            && !features.iter().any(|f| f == "chunked_values")
+        {
+            features.push("chunked_values".to_string());
+        }
+        self.chunked_values.store(
+            features.iter().any(|f| f == "chunked_values"),
+            Ordering::SeqCst,
+        );
+        let manifest = Manifest {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            meta_format_version: META_FORMAT_VERSION,
+            features,
+        };
+        if old.as_ref() != Some(&manifest) {
+            self.rocksdb.put(KEY_MANIFEST, manifest.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Record the feature `chunked_values` in the manifest, e.g. the chunked values are copied
+    /// from another database, so they are reassembled when they are read.
+    pub(crate) fn enable_chunked_values(&self) -> Result<()> {
+        if self.chunked_values.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let mut manifest = self.manifest()?.unwrap_or_default();
+        if !manifest.features.iter().any(|f| f == "chunked_values") {
+            manifest.features.push("chunked_values".to_string());
+            self.rocksdb.put(KEY_MANIFEST, manifest.to_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reload whether the feature `chunked_values` is recorded, e.g. the manifest is written
+    /// by the replication.
+    pub(crate) fn load_chunked_values(&self) {
+        if let Ok(Some(manifest)) = self.manifest() {
+            if manifest.features.iter().any(|f| f == "chunked_values") {
+                self.chunked_values.store(true, Ordering::SeqCst);
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+
+use bytes::Buf;
+use rocksdb::{Direction, IteratorMode, WriteBatch};
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+use crate::describe::decode_meta_extra;
+use crate::verify::check_meta_value;
+
+/// A meta read by `Database::import_meta()`.
+struct MetaEntry {
+    name: Vec<u8>,
+    meta: KeyMeta,
+}
+
+impl Database {
+    /// Write all the metas into `writer` as TOML, read from a snapshot, so external tooling can
+    /// audit the keyspace without reading the data rows.
+    ///
+    /// Every key is a `[[key]]` table with `name` (or `name_hex` if it's not valid UTF-8),
+    /// `type`, `id`, `count` and the raw `extra` in hex, the decoded extra is written as a
+    /// comment. Returns the count of the exported metas.
+    pub fn export_meta(&self, mut writer: impl Write) -> Result<u64> {
+        let snapshot = self.rocksdb.snapshot();
+        let iter = snapshot
+            .iterator_opt(
+                IteratorMode::From(PREFIX_META, Direction::Forward),
+                self.scan_read_options(),
+            )
+            .take_while(|(k, _)| has_prefix(PREFIX_META, k));
+        let mut count = 0;
+        for (k, v) in iter {
+            let name = &k[PREFIX_META.len()..];
+            let meta = KeyMeta::from_bytes(&v);
+            let mut table = String::from("[[key]]\n");
+            match std::str::from_utf8(name) {
+                Ok(name) => writeln!(table, "name = {}", quote_string(name)),
+                Err(_) => writeln!(table, "name_hex = \"{}\"", encode_hex(name)),
+            }
+            .unwrap();
+            writeln!(table, "type = \"{}\"", key_type_name(meta.key_type)).unwrap();
+            writeln!(table, "id = {}", meta.id).unwrap();
+            writeln!(table, "count = {}", meta.count).unwrap();
+            if let Some(extra) = &meta.extra {
+                writeln!(table, "# {:?}", decode_meta_extra(&meta)).unwrap();
+                writeln!(table, "extra = \"{}\"", encode_hex(extra)).unwrap();
+            }
+            table.push('\n');
+            writer.write_all(table.as_bytes())?;
+            count += 1;
+        }
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Write the metas of a document produced by `export_meta()`, e.g. the counts fixed by a
+    /// tool. The metas are written as they are with their key IDs, the data rows are not
+    /// touched. The whole document is parsed and checked before writing, and written in a
+    /// single batch. Fails if an extra is too short for its key type, or a key ID is used by
+    /// another key. Returns the count of the imported metas.
+    pub fn import_meta(&self, mut reader: impl Read) -> Result<u64> {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        let entries = parse_meta_document(&input)?;
+        let mut owners = HashMap::new();
+        self.prefix_iterator(PREFIX_META, |k, v| {
+            if v.len() >= 8 {
+                owners.insert(v.as_ref().get_u64(), k[PREFIX_META.len()..].to_vec());
+            }
+            true
+        });
+        for entry in entries.iter() {
+            self.check_writable(&entry.name)?;
+            if let Some(reason) = check_meta_value(&entry.meta.get_bytes()) {
+                return Err(Error::Message(format!(
+                    "invalid meta of key {}: {}",
+                    String::from_utf8_lossy(&entry.name),
+                    reason
+                )));
+            }
+        }
+        // the entries own their IDs, the IDs of the replaced metas are released first
+        for entry in entries.iter() {
+            let name = self.resolve_key(&entry.name);
+            owners.retain(|_, owner| owner.as_slice() != name.as_ref());
+        }
+        for entry in entries.iter() {
+            let name = self.resolve_key(&entry.name).into_owned();
+            if let Some(owner) = owners.insert(entry.meta.id, name.clone()) {
+                if owner != name {
+                    return Err(Error::Message(format!(
+                        "key ID {} of key {} is used by key {}",
+                        entry.meta.id,
+                        String::from_utf8_lossy(&entry.name),
+                        String::from_utf8_lossy(&owner)
+                    )));
+                }
+            }
+        }
+        let mut batch = WriteBatch::default();
+        for entry in entries.iter() {
+            if let Some(old) = self.get_meta(&entry.name)? {
+                if old.key_type != entry.meta.key_type {
+                    batch.delete(encode_type_index_key(old.key_type, &entry.name));
+                }
+            }
+            self.reserve_key_id(entry.meta.id)?;
+            batch.put(
+                encode_type_index_key(entry.meta.key_type, &entry.name),
+                FILL_EMPTY_DATA,
+            );
+            self.save_meta_to_batch(&mut batch, &entry.name, &entry.meta, false)?;
+        }
+        self.rocksdb.write(batch)?;
+        for entry in entries.iter() {
+            self.forget_absent_key(&self.resolve_key(&entry.name));
+        }
+        self.log_admin("import_meta", "", entries.len() as u64)?;
+        Ok(entries.len() as u64)
+    }
+}
+
+fn key_type_name(key_type: KeyType) -> String {
+    match key_type {
+        KeyType::Map => "map".to_string(),
+        KeyType::List => "list".to_string(),
+        KeyType::SortedList => "sorted_list".to_string(),
+        KeyType::Set => "set".to_string(),
+        KeyType::SortedSet => "sorted_set".to_string(),
+        KeyType::Value => "value".to_string(),
+        KeyType::Log => "log".to_string(),
+        KeyType::Custom(tag) => format!("custom_{}", tag),
+    }
+}
+
+fn parse_key_type(name: &str) -> Option<KeyType> {
+    match name {
+        "map" => Some(KeyType::Map),
+        "list" => Some(KeyType::List),
+        "sorted_list" => Some(KeyType::SortedList),
+        "set" => Some(KeyType::Set),
+        "sorted_set" => Some(KeyType::SortedSet),
+        "value" => Some(KeyType::Value),
+        "log" => Some(KeyType::Log),
+        _ => name
+            .strip_prefix("custom_")
+            .and_then(|tag| tag.parse().ok())
+            .filter(|tag| *tag >= CUSTOM_KEY_TYPE_MIN)
+            .map(KeyType::Custom),
+    }
+}
+
+/// Quote a TOML basic string.
+fn quote_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => write!(quoted, "\\u{:04x}", c as u32).unwrap(),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Unquote a TOML basic string, returns `None` if it's invalid.
+fn unquote_string(s: &str) -> Option<String> {
+    let s = s.strip_prefix('"')?.strip_suffix('"')?;
+    let mut unquoted = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                '"' => unquoted.push('"'),
+                '\\' => unquoted.push('\\'),
+                'n' => unquoted.push('\n'),
+                'r' => unquoted.push('\r'),
+                't' => unquoted.push('\t'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    unquoted.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                _ => return None,
+            },
+            '"' => return None,
+            c => unquoted.push(c),
+        }
+    }
+    Some(unquoted)
+}
+
+/// Parse the subset of TOML written by `Database::export_meta()`.
+fn parse_meta_document(input: &str) -> Result<Vec<MetaEntry>> {
+    let mut entries = Vec::new();
+    let mut fields: Option<Vec<(&str, &str)>> = None;
+    for (i, line) in input.lines().enumerate() {
+        let invalid = || Error::Message(format!("invalid meta document at line {}", i + 1));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line == "[[key]]" {
+            if let Some(fields) = fields.take() {
+                entries.push(parse_meta_entry(&fields).ok_or_else(invalid)?);
+            }
+            fields = Some(Vec::new());
+            continue;
+        }
+        let (name, value) = line.split_once('=').ok_or_else(invalid)?;
+        fields
+            .as_mut()
+            .ok_or_else(invalid)?
+            .push((name.trim(), value.trim()));
+    }
+    if let Some(fields) = fields {
+        let invalid = || Error::Message("invalid meta document at the last key".to_string());
+        entries.push(parse_meta_entry(&fields).ok_or_else(invalid)?);
+    }
+    Ok(entries)
+}
+
+fn parse_meta_entry(fields: &[(&str, &str)]) -> Option<MetaEntry> {
+    let mut name = None;
+    let mut key_type = None;
+    let mut id = None;
+    let mut count = None;
+    let mut extra = None;
+    for (field, value) in fields {
+        match *field {
+            "name" => name = Some(unquote_string(value)?.into_bytes()),
+            "name_hex" => name = Some(decode_hex(&unquote_string(value)?)?),
+            "type" => key_type = Some(parse_key_type(&unquote_string(value)?)?),
+            "id" => id = Some(value.parse().ok()?),
+            "count" => count = Some(value.parse().ok()?),
+            "extra" => extra = Some(decode_hex(&unquote_string(value)?)?),
+            _ => return None,
+        }
+    }
+    Some(MetaEntry {
+        name: name?,
+        meta: KeyMeta {
+            id: id?,
+            key_type: key_type?,
+            count: count?,
+            extra,
+        },
+    })
+}
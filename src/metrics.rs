@@ -0,0 +1,18 @@
+use std::time::Duration;
+
+use crate::codec::KeyType;
+use crate::database::Error;
+
+/// Receiver of the measurements of database operations, set by `Options::metrics_recorder`.
+/// Implement it to pipe the measurements into any metrics library.
+pub trait MetricsRecorder: Send + Sync {
+    /// Called after an operation finished, `key_type` is `None` if the operation is not bound
+    /// to a data type, e.g. `delete_all`.
+    fn record_op(
+        &self,
+        name: &'static str,
+        key_type: Option<KeyType>,
+        duration: Duration,
+        result: Result<(), &Error>,
+    );
+}
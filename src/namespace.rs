@@ -0,0 +1,222 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::Ordering;
+
+use bytes::{Buf, BufMut, BytesMut};
+use rocksdb::{merge_operator::MergeOperands, Direction, IteratorMode, WriteBatch};
+
+use crate::codec::*;
+use crate::database::{Database, Result};
+use crate::group::GROUP_SEPARATOR;
+
+/// Key prefix for the counters of the namespaces, followed by the namespace. The counters are
+/// changed by merges in the write batches of the metas, see `namespace_merge()`.
+pub static PREFIX_NAMESPACE_STATS: &[u8] = b"n";
+/// Marks the counters of the namespaces are built, see `Database::rebuild_namespace_stats()`.
+pub static KEY_NAMESPACE_STATS_READY: &[u8] = b"snamespace_stats_ready";
+
+/// Usage of a namespace, see `Database::namespace_stats()`.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct NamespaceStats {
+    pub keys: u64,
+    /// Total items count saved in the metas of the keys.
+    pub items: u64,
+}
+
+/// Get the namespace of a key name, the part before `GROUP_SEPARATOR`, empty if the name has
+/// no separator.
+pub fn key_namespace(name: &[u8]) -> &[u8] {
+    name.iter()
+        .position(|b| *b == GROUP_SEPARATOR as u8)
+        .map(|i| &name[..i])
+        .unwrap_or(b"")
+}
+
+/// Encode key of the counters of a namespace.
+fn encode_namespace_stats_key(namespace: &[u8]) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(PREFIX_NAMESPACE_STATS.len() + namespace.len());
+    buf.put_slice(PREFIX_NAMESPACE_STATS);
+    buf.put_slice(namespace);
+    buf
+}
+
+/// Encode the counters, or a change of them, the keys count and the items count.
+fn encode_namespace_counters(keys: i64, items: i64) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(16);
+    buf.put_i64(keys);
+    buf.put_i64(items);
+    buf
+}
+
+/// Decode the counters, returns (keys, items), zeros if the row is broken.
+fn decode_namespace_counters(mut value: &[u8]) -> (i64, i64) {
+    if value.len() != 16 {
+        return (0, 0);
+    }
+    (value.get_i64(), value.get_i64())
+}
+
+/// Merge operator adding up the changes of the counters of the namespaces.
+pub(crate) fn namespace_merge(
+    _: &[u8],
+    existing: Option<&[u8]>,
+    operands: &MergeOperands,
+) -> Option<Vec<u8>> {
+    let (mut keys, mut items) = existing.map(decode_namespace_counters).unwrap_or((0, 0));
+    for operand in operands {
+        let (k, i) = decode_namespace_counters(operand);
+        keys = keys.wrapping_add(k);
+        items = items.wrapping_add(i);
+    }
+    Some(encode_namespace_counters(keys, items).to_vec())
+}
+
+impl Database {
+    /// Get the namespaces of the keys, ordered by name. The namespace of a key is the part of
+    /// its name before `GROUP_SEPARATOR`, the keys without a separator are in the empty
+    /// namespace. The keys of a namespace are skipped by a seek, so it's cheap for namespaces
+    /// with many keys.
+    pub fn namespaces(&self) -> Result<Vec<String>> {
+        let mut namespaces = Vec::new();
+        let mut has_empty = false;
+        let mut start = PREFIX_META.to_vec();
+        loop {
+            let next = self
+                .rocksdb
+                .iterator(IteratorMode::From(&start, Direction::Forward))
+                .next()
+                .filter(|(k, _)| has_prefix(PREFIX_META, k));
+            let name = match next {
+                None => break,
+                Some((k, _)) => k[PREFIX_META.len()..].to_vec(),
+            };
+            if !name.contains(&(GROUP_SEPARATOR as u8)) {
+                // no separator, the next key may be in any namespace
+                has_empty = true;
+                start = encode_meta_key(&name).to_vec();
+                start.push(0);
+                continue;
+            }
+            let namespace = key_namespace(&name);
+            namespaces.push(String::from_utf8(namespace.to_vec())?);
+            // seek over all the keys starting with `{namespace}/`
+            start = encode_meta_key(namespace).to_vec();
+            start.push(GROUP_SEPARATOR as u8 + 1);
+        }
+        if has_empty {
+            namespaces.insert(0, String::new());
+        }
+        Ok(namespaces)
+    }
+
+    /// Get the usage of a namespace. It's read from the counters of the namespace, which are
+    /// changed in the write batches of the metas. While the replication is enabled by
+    /// `Options::replication_wal_ttl`, the counters are not kept, as merges can't be read from
+    /// the WAL, then the counts are read from the metas of the keys.
+    pub fn namespace_stats(&self, namespace: &str) -> Result<NamespaceStats> {
+        if self.namespace_counters.load(Ordering::SeqCst) {
+            let (keys, items) = self
+                .rocksdb
+                .get_pinned(encode_namespace_stats_key(namespace.as_bytes()))?
+                .map(|v| decode_namespace_counters(&v))
+                .unwrap_or((0, 0));
+            return Ok(NamespaceStats {
+                keys: keys.max(0) as u64,
+                items: items.max(0) as u64,
+            });
+        }
+        let mut stats = NamespaceStats::default();
+        let prefix = if namespace.is_empty() {
+            PREFIX_META.to_vec()
+        } else {
+            encode_meta_key(format!("{}{}", namespace, GROUP_SEPARATOR)).to_vec()
+        };
+        let iter = self
+            .rocksdb
+            .iterator_opt(
+                IteratorMode::From(&prefix, Direction::Forward),
+                self.scan_read_options(),
+            )
+            .take_while(|(k, _)| has_prefix(&prefix, k));
+        for (k, v) in iter {
+            if key_namespace(&k[PREFIX_META.len()..]) != namespace.as_bytes() || v.len() < 17 {
+                continue;
+            }
+            stats.keys += 1;
+            stats.items += KeyMeta::from_bytes(&v).count;
+        }
+        Ok(stats)
+    }
+
+    /// Rebuild the counters of the namespaces from all the metas, it runs automatically when
+    /// opening a database created by an old version, or replicated by `replicate_to()`. The
+    /// counters are wrong if keys are written while they are rebuilt.
+    pub fn rebuild_namespace_stats(&self) -> Result<usize> {
+        let mut counters: BTreeMap<Vec<u8>, (i64, i64)> = BTreeMap::new();
+        self.prefix_iterator(PREFIX_META, |k, v| {
+            if v.len() >= 17 {
+                let meta = KeyMeta::from_bytes(v.as_ref());
+                let namespace = key_namespace(&k[PREFIX_META.len()..]).to_vec();
+                let counter = counters.entry(namespace).or_default();
+                counter.0 += 1;
+                counter.1 += meta.count as i64;
+            }
+            true
+        });
+        let mut batch = WriteBatch::default();
+        self.delete_range_to_batch(&mut batch, PREFIX_NAMESPACE_STATS, b"o");
+        for (namespace, (keys, items)) in counters.iter() {
+            batch.put(
+                encode_namespace_stats_key(namespace),
+                encode_namespace_counters(*keys, *items),
+            );
+        }
+        batch.put(KEY_NAMESPACE_STATS_READY, FILL_EMPTY_DATA);
+        self.rocksdb.write(batch)?;
+        self.namespace_counters.store(true, Ordering::SeqCst);
+        Ok(counters.len())
+    }
+
+    /// Open the counters of the namespaces, they are rebuilt if they are not built yet or
+    /// the metas were changed without them. With the replication, they are dropped, so
+    /// they are rebuilt once the replication is disabled.
+    pub(crate) fn open_namespace_stats(&self, rebuild: bool) -> Result<()> {
+        if self.options.replication_wal_ttl.is_some() {
+            self.namespace_counters.store(false, Ordering::SeqCst);
+            return Ok(self.rocksdb.delete(KEY_NAMESPACE_STATS_READY)?);
+        }
+        if rebuild
+            || self
+                .rocksdb
+                .get_pinned(KEY_NAMESPACE_STATS_READY)?
+                .is_none()
+        {
+            self.rebuild_namespace_stats()?;
+        }
+        self.namespace_counters.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Stop keeping the counters of the namespaces until they are rebuilt, e.g. the metas are
+    /// written by the replication.
+    pub(crate) fn drop_namespace_stats_to_batch(&self, batch: &mut WriteBatch) {
+        self.namespace_counters.store(false, Ordering::SeqCst);
+        batch.delete(KEY_NAMESPACE_STATS_READY);
+    }
+
+    /// Record a change of the counters of the namespace of `key` in the batch.
+    pub(crate) fn count_namespace_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key: &[u8],
+        keys: i64,
+        items: i64,
+    ) {
+        if (keys == 0 && items == 0) || !self.namespace_counters.load(Ordering::SeqCst) {
+            return;
+        }
+        batch.merge(
+            encode_namespace_stats_key(key_namespace(key)),
+            encode_namespace_counters(keys, items),
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Mutex,
+};
+
+use bytes::{BufMut, BytesMut};
+use rayon::prelude::*;
+use rocksdb::{Direction, IteratorMode};
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+
+impl Database {
+    /// Same as `for_each_key()`, but split the keys into `shards` sub-ranges and iterate them on
+    /// the rayon thread pool. The callback is called in no particular order, returns `false` to
+    /// stop all the sub-ranges.
+    pub fn par_for_each_key<F>(&self, shards: usize, f: F) -> Result<usize>
+    where
+        F: Fn(&str, &KeyMeta) -> bool + Sync,
+    {
+        self.par_scan(PREFIX_META, b"n", shards, |k, v| {
+            let key = decode_meta_key(k)?;
+            Ok(f(key.as_str(), &KeyMeta::from_bytes(v)))
+        })
+    }
+
+    /// Same as `map_for_each_utf8()`, but split the fields into `shards` sub-ranges and iterate them on
+    /// the rayon thread pool. The callback is called in no particular order, returns `false` to
+    /// stop all the sub-ranges.
+    pub fn par_map_for_each<F>(&self, key: &str, shards: usize, f: F) -> Result<u64>
+    where
+        F: Fn(&str, &[u8]) -> bool + Sync,
+    {
+        let meta = match self.get_meta(key)? {
+            Some(m) if m.count > 0 => m,
+            _ => return Ok(0),
+        };
+        let lower = encode_data_key(meta.id);
+        let upper = encode_data_key(meta.id + 1);
+        let counter = self.par_scan(&lower, &upper, shards, |k, v| {
+            let field = decode_data_key_map_item(k)?;
+            if v.is_empty() {
+                let value = self.map_item_value(meta.id, field.as_bytes(), Vec::new())?;
+                return Ok(f(field.as_str(), &value));
+            }
+            Ok(f(field.as_str(), v))
+        })?;
+        Ok(counter as u64)
+    }
+
+    /// Iterate the keys between `lower` and `upper` in parallel, the range is split by the two
+    /// bytes next to `lower`.
+    fn par_scan<F>(&self, lower: &[u8], upper: &[u8], shards: usize, f: F) -> Result<usize>
+    where
+        F: Fn(&[u8], &[u8]) -> Result<bool> + Sync,
+    {
+        let shards = shards.clamp(1, 1 << 16);
+        let mut bounds = vec![lower.to_vec()];
+        for i in 1..shards {
+            let mut b = BytesMut::with_capacity(lower.len() + 2);
+            b.put_slice(lower);
+            b.put_u16((i * (1 << 16) / shards) as u16);
+            bounds.push(b.to_vec());
+        }
+        bounds.push(upper.to_vec());
+
+        let counter = AtomicUsize::new(0);
+        let stopped = AtomicBool::new(false);
+        let has_error = Mutex::new(None);
+        bounds.par_windows(2).for_each(|range| {
+            let mut opts = self.scan_read_options();
+            opts.set_iterate_upper_bound(range[1].clone());
+            let iter = self
+                .rocksdb
+                .iterator_opt(IteratorMode::From(&range[0], Direction::Forward), opts);
+            for (k, v) in iter {
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                counter.fetch_add(1, Ordering::Relaxed);
+                match f(&k, &v) {
+                    Ok(true) => {}
+                    Ok(false) => stopped.store(true, Ordering::Relaxed),
+                    Err(err) => {
+                        stopped.store(true, Ordering::Relaxed);
+                        *has_error.lock().unwrap() = Some(err);
+                    }
+                }
+            }
+        });
+        let has_error: Option<Error> = has_error.into_inner().unwrap();
+        match has_error {
+            None => Ok(counter.into_inner()),
+            Some(err) => Err(err),
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use rocksdb::WriteBatch;
+
+use crate::database::{Database, Error, Result};
+
+/// A write operation of `WritePipeline`, the same as the method of `Database` with the name.
+#[derive(Debug, Clone)]
+pub enum WriteOp {
+    /// Key, field and value.
+    MapPut(Box<[u8]>, Box<[u8]>, Box<[u8]>),
+    /// Key and field.
+    MapDelete(Box<[u8]>, Box<[u8]>),
+    /// Key and member.
+    SetAdd(Box<[u8]>, Box<[u8]>),
+    /// Key and member.
+    SetDelete(Box<[u8]>, Box<[u8]>),
+    /// Key and value.
+    ListRightPush(Box<[u8]>, Box<[u8]>),
+}
+
+impl WriteOp {
+    /// Get the key written by the operation.
+    pub fn key(&self) -> &[u8] {
+        match self {
+            WriteOp::MapPut(key, _, _)
+            | WriteOp::MapDelete(key, _)
+            | WriteOp::SetAdd(key, _)
+            | WriteOp::SetDelete(key, _)
+            | WriteOp::ListRightPush(key, _) => key,
+        }
+    }
+
+    /// Check the key is writable, and write the operation into the batch.
+    fn write_to_batch(&self, db: &Database, batch: &mut WriteBatch) -> Result<()> {
+        db.check_writable(self.key())?;
+        db.check_write_capacity()?;
+        match self {
+            WriteOp::MapPut(key, field, value) => db.map_put_to_batch(batch, key, field, value),
+            WriteOp::MapDelete(key, field) => db.map_delete_to_batch(batch, key, field).map(|_| ()),
+            WriteOp::SetAdd(key, member) => db.set_add_to_batch(batch, key, member).map(|_| ()),
+            WriteOp::SetDelete(key, member) => {
+                db.set_delete_to_batch(batch, key, member).map(|_| ())
+            }
+            WriteOp::ListRightPush(key, value) => {
+                db.list_right_push_to_batch(batch, key, value).map(|_| ())
+            }
+        }
+    }
+}
+
+type WriteRequest = (Vec<WriteOp>, SyncSender<Result<()>>);
+
+/// Group-commit write pipeline, the writes queued from many threads during an interval are merged
+/// into one `WriteBatch` and committed together. The operations are checked the same as the
+/// methods of `Database`, e.g. the writes of a sealed or a frozen key fail, even from the thread
+/// freezing the key, since they're committed by the pipeline.
+///
+/// The metas are read from the database, so a batch has at most one request writing a key, the
+/// batch is committed early when the next request writes the same key.
+pub struct WritePipeline {
+    sender: Sender<WriteRequest>,
+    stopped: Arc<AtomicBool>,
+    handle: Option<JoinHandle<Database>>,
+}
+
+/// Handle to queue writes into a `WritePipeline`, it can be cloned and sent to other threads.
+#[derive(Clone)]
+pub struct PipelineWriter {
+    sender: Sender<WriteRequest>,
+}
+
+impl WritePipeline {
+    /// Move the database into a background thread, which commits the queued writes every `interval`.
+    pub fn start(db: Database, interval: Duration) -> WritePipeline {
+        let (sender, receiver) = channel();
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopped2 = stopped.clone();
+        let handle = thread::spawn(move || {
+            run_pipeline(&db, receiver, interval, &stopped2);
+            db
+        });
+        WritePipeline {
+            sender,
+            stopped,
+            handle: Some(handle),
+        }
+    }
+
+    /// Get a writer of the pipeline.
+    pub fn writer(&self) -> PipelineWriter {
+        PipelineWriter {
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Stop the pipeline after the queued writes are committed, returns the database.
+    pub fn stop(mut self) -> Result<Database> {
+        self.stopped.store(true, Ordering::SeqCst);
+        match self.handle.take().map(|h| h.join()) {
+            Some(Ok(db)) => Ok(db),
+            _ => Err(Error::Message("write pipeline thread panicked".to_string())),
+        }
+    }
+}
+
+impl Drop for WritePipeline {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl PipelineWriter {
+    /// Queue the operations and wait until they are committed, the operations are written
+    /// atomically. The operations must write different keys.
+    pub fn write(&self, ops: Vec<WriteOp>) -> Result<()> {
+        let (done, wait) = sync_channel(1);
+        self.sender
+            .send((ops, done))
+            .map_err(|_| pipeline_stopped())?;
+        wait.recv().map_err(|_| pipeline_stopped())?
+    }
+
+    pub fn map_put(
+        &self,
+        key: impl AsRef<[u8]>,
+        field: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+    ) -> Result<()> {
+        self.write(vec![WriteOp::MapPut(
+            Box::from(key.as_ref()),
+            Box::from(field.as_ref()),
+            Box::from(value.as_ref()),
+        )])
+    }
+
+    pub fn map_delete(&self, key: impl AsRef<[u8]>, field: impl AsRef<[u8]>) -> Result<()> {
+        self.write(vec![WriteOp::MapDelete(
+            Box::from(key.as_ref()),
+            Box::from(field.as_ref()),
+        )])
+    }
+
+    pub fn set_add(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<()> {
+        self.write(vec![WriteOp::SetAdd(
+            Box::from(key.as_ref()),
+            Box::from(value),
+        )])
+    }
+
+    pub fn set_delete(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<()> {
+        self.write(vec![WriteOp::SetDelete(
+            Box::from(key.as_ref()),
+            Box::from(value),
+        )])
+    }
+
+    pub fn list_right_push(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<()> {
+        self.write(vec![WriteOp::ListRightPush(
+            Box::from(key.as_ref()),
+            Box::from(value),
+        )])
+    }
+}
+
+/// Requests merged into one batch, they write different keys.
+#[derive(Default)]
+struct PendingBatch {
+    batch: WriteBatch,
+    keys: HashSet<Vec<u8>>,
+    accepted: Vec<(Vec<WriteOp>, SyncSender<Result<()>>)>,
+}
+
+impl PendingBatch {
+    /// Write the operations of a request into the batch. A failed request fails alone, the
+    /// batch is rebuilt from the accepted requests, since its partial writes can't be undone.
+    fn push(&mut self, db: &Database, ops: Vec<WriteOp>, done: SyncSender<Result<()>>) {
+        let result = ops
+            .iter()
+            .try_for_each(|op| op.write_to_batch(db, &mut self.batch));
+        if let Err(e) = result {
+            let _ = done.send(Err(e));
+            self.rebuild(db);
+            return;
+        }
+        for op in ops.iter() {
+            self.keys.insert(db.resolve_key(op.key()).into_owned());
+        }
+        self.accepted.push((ops, done));
+    }
+
+    fn rebuild(&mut self, db: &Database) {
+        self.batch = WriteBatch::default();
+        for (ops, _) in self.accepted.iter() {
+            if let Err(e) = ops
+                .iter()
+                .try_for_each(|op| op.write_to_batch(db, &mut self.batch))
+            {
+                // e.g. an IO error, the accepted requests fail together
+                for (_, done) in std::mem::take(&mut self.accepted) {
+                    let _ = done.send(Err(e.clone()));
+                }
+                self.batch = WriteBatch::default();
+                self.keys.clear();
+                return;
+            }
+        }
+    }
+
+    /// Whether the operations write a key of the batch.
+    fn conflicts(&self, db: &Database, ops: &[WriteOp]) -> bool {
+        ops.iter()
+            .any(|op| self.keys.contains(db.resolve_key(op.key()).as_ref()))
+    }
+
+    fn commit(&mut self, db: &Database) {
+        if self.accepted.is_empty() {
+            return;
+        }
+        let batch = std::mem::take(&mut self.batch);
+        self.keys.clear();
+        let result = db.rocksdb.write(batch).map_err(Error::from);
+        for (_, done) in self.accepted.drain(..) {
+            let _ = done.send(result.clone());
+        }
+    }
+}
+
+/// Check the operations of a request write different keys.
+fn check_distinct_keys(db: &Database, ops: &[WriteOp]) -> Result<()> {
+    let mut keys = HashSet::new();
+    for op in ops {
+        if !keys.insert(db.resolve_key(op.key())) {
+            return Err(Error::Message(format!(
+                "key {} is written twice in a pipeline request",
+                String::from_utf8_lossy(op.key())
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn run_pipeline(
+    db: &Database,
+    receiver: Receiver<WriteRequest>,
+    interval: Duration,
+    stopped: &AtomicBool,
+) {
+    loop {
+        let first = match receiver.recv_timeout(interval) {
+            Ok(request) => request,
+            Err(RecvTimeoutError::Timeout) if !stopped.load(Ordering::SeqCst) => continue,
+            Err(_) => break,
+        };
+        let deadline = Instant::now() + interval;
+        let mut requests = vec![first];
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            match receiver.recv_timeout(timeout) {
+                Ok(request) => requests.push(request),
+                Err(_) => break,
+            }
+        }
+
+        let mut pending = PendingBatch::default();
+        for (ops, done) in requests {
+            if let Err(e) = check_distinct_keys(db, &ops) {
+                let _ = done.send(Err(e));
+                continue;
+            }
+            // the next operations of a key read the metas written by the previous ones
+            if pending.conflicts(db, &ops) {
+                pending.commit(db);
+            }
+            pending.push(db, ops, done);
+        }
+        pending.commit(db);
+    }
+}
+
+fn pipeline_stopped() -> Error {
+    Error::Message("write pipeline is stopped".to_string())
+}
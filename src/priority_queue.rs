@@ -0,0 +1,127 @@
+use std::marker::PhantomData;
+
+use rocksdb::{Direction, IteratorMode, ReadOptions};
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+
+/// Priority of a `PriorityQueue`, the integer types encoded by `BytesComparableScore`. `f32`
+/// and `f64` are not priorities, their negative values are not ordered by the encoding.
+pub trait Priority: BytesComparableScore {
+    /// Length of the encoded priority.
+    const ENCODED_LEN: usize;
+}
+
+impl Priority for i32 {
+    const ENCODED_LEN: usize = 5;
+}
+
+impl Priority for i64 {
+    const ENCODED_LEN: usize = 9;
+}
+
+impl Priority for u32 {
+    const ENCODED_LEN: usize = 5;
+}
+
+impl Priority for u64 {
+    const ENCODED_LEN: usize = 9;
+}
+
+/// A priority queue stored in a sorted list, see `Database::priority_queue()`.
+///
+/// The priorities are encoded by `BytesComparableScore`. Items with the same priority are
+/// popped by `pop_lowest()` in the order they are pushed, and by `pop_highest()` in the
+/// reverse order, the peeks return the same items as the pops.
+pub struct PriorityQueue<'a, P> {
+    db: &'a Database,
+    key: String,
+    _priority: PhantomData<P>,
+}
+
+impl Database {
+    /// Get a priority queue stored in the sorted list `key`.
+    pub fn priority_queue<P: Priority>(&self, key: &str) -> PriorityQueue<'_, P> {
+        PriorityQueue {
+            db: self,
+            key: key.to_string(),
+            _priority: PhantomData,
+        }
+    }
+}
+
+impl<P: Priority> PriorityQueue<'_, P> {
+    /// Push an item, returns the items count of the queue.
+    pub fn push(&self, priority: P, item: &[u8]) -> Result<u64> {
+        self.db
+            .sorted_list_add(&self.key, &priority.to_bytes(), item)
+    }
+
+    /// Remove and return the item with the highest priority, the last pushed one of the ties.
+    /// Fails if the priority of the item is not encoded by `P`, the item is removed anyway.
+    pub fn pop_highest(&self) -> Result<Option<(P, Box<[u8]>)>> {
+        self.db
+            .sorted_list_right_pop(&self.key, None)?
+            .map(|(priority, item)| Ok((self.decode_priority(&priority)?, item)))
+            .transpose()
+    }
+
+    /// Remove and return the item with the lowest priority, the first pushed one of the ties.
+    /// Fails if the priority of the item is not encoded by `P`, the item is removed anyway.
+    pub fn pop_lowest(&self) -> Result<Option<(P, Box<[u8]>)>> {
+        self.db
+            .sorted_list_left_pop(&self.key, None)?
+            .map(|(priority, item)| Ok((self.decode_priority(&priority)?, item)))
+            .transpose()
+    }
+
+    /// Get the item with the highest priority without removing it.
+    pub fn peek_highest(&self) -> Result<Option<(P, Box<[u8]>)>> {
+        let meta = match self.db.get_meta(&self.key)? {
+            None => return Ok(None),
+            Some(meta) => meta,
+        };
+        let prefix = encode_data_key(meta.id);
+        let next_prefix = encode_data_key(meta.id + 1);
+        let mut iter = self.db.rocksdb.iterator_opt(
+            IteratorMode::From(&next_prefix, Direction::Reverse),
+            ReadOptions::default(),
+        );
+        iter.next()
+            .filter(|(k, _)| has_prefix(&prefix, k))
+            .map(|(k, v)| {
+                let priority = self.decode_priority(decode_data_key_sorted_list_item(&k))?;
+                Ok((priority, v))
+            })
+            .transpose()
+    }
+
+    /// Get the item with the lowest priority without removing it.
+    pub fn peek_lowest(&self) -> Result<Option<(P, Box<[u8]>)>> {
+        self.db
+            .sorted_list_items_after(&self.key, None, 1)?
+            .pop()
+            .map(|(priority, _, item)| Ok((self.decode_priority(&priority)?, item)))
+            .transpose()
+    }
+
+    pub fn len(&self) -> Result<u64> {
+        self.db.sorted_list_count(&self.key)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Decode a priority, fails if it's not encoded by `P`, e.g. added by `sorted_list_add()`.
+    fn decode_priority(&self, b: &[u8]) -> Result<P> {
+        if b.len() != P::ENCODED_LEN {
+            return Err(Error::Message(format!(
+                "invalid priority of {} bytes in priority queue {}",
+                b.len(),
+                self.key
+            )));
+        }
+        Ok(P::from_bytes(b))
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+
+use bytes::Buf;
+use rocksdb::{Direction, IteratorMode};
+
+use crate::admin::PREFIX_ADMIN_LOG;
+use crate::alias::PREFIX_ALIAS;
+use crate::blob::PREFIX_BLOB;
+use crate::chunk::{decode_value_chunk_key, PREFIX_VALUE_CHUNK};
+use crate::codec::*;
+use crate::config::PREFIX_KEY_CONFIG;
+use crate::consumer::PREFIX_CONSUMER;
+use crate::database::{Database, Result};
+use crate::journal::PREFIX_JOURNAL;
+use crate::manifest::KEY_MANIFEST;
+use crate::namespace::{KEY_NAMESPACE_STATS_READY, PREFIX_NAMESPACE_STATS};
+use crate::seal::PREFIX_SEALED;
+use crate::sketch::PREFIX_SKETCH;
+use crate::verify::PREFIX_QUARANTINE;
+
+/// A raw row key decoded by `decode_raw_row()`.
+#[derive(Debug, Clone)]
+pub enum RawRow {
+    /// Meta of a key, `meta` is `None` if it can not be decoded.
+    Meta {
+        key: Box<[u8]>,
+        meta: Option<KeyMeta>,
+    },
+    MapItem {
+        key_id: u64,
+        field: Box<[u8]>,
+    },
+    SetItem {
+        key_id: u64,
+        member: Box<[u8]>,
+    },
+    ListItem {
+        key_id: u64,
+        position: i64,
+    },
+    SortedListItem {
+        key_id: u64,
+        score: Box<[u8]>,
+        sequence: u64,
+    },
+    /// The row of a `sorted set` member, the value is the score.
+    SortedSetMember {
+        key_id: u64,
+        member: Box<[u8]>,
+    },
+    /// The row of a `sorted set` member ordered by the score.
+    SortedSetItem {
+        key_id: u64,
+        score: Box<[u8]>,
+        member: Box<[u8]>,
+    },
+    ValueChunk {
+        key_id: u64,
+        index: u32,
+    },
+    /// Chunk of a large `map` value, `index` is `None` for the manifest of the value, see
+    /// `Options::large_value_threshold`.
+    MapValueChunk {
+        key_id: u64,
+        field: Box<[u8]>,
+        index: Option<u32>,
+    },
+    LogEntry {
+        key_id: u64,
+        offset: u64,
+    },
+    /// Data row of a key ID without a meta, or can not be decoded.
+    Data {
+        key_id: u64,
+        suffix: Box<[u8]>,
+    },
+    /// Reserved `sorted list` item, the suffix is the same as the original data row.
+    Reserved {
+        key_id: u64,
+        suffix: Box<[u8]>,
+    },
+    TypeIndex {
+        key_type: Option<KeyType>,
+        key: Box<[u8]>,
+    },
+    Blob {
+        tag: u8,
+        rest: Box<[u8]>,
+    },
+    Quarantined {
+        meta_key: Box<[u8]>,
+    },
+    Alias {
+        alias: Box<[u8]>,
+        target: Box<[u8]>,
+    },
+    /// Unfinished transfer of a key, see `Database::copy_key_to()`.
+    Journal {
+        key: Box<[u8]>,
+    },
+    /// Read offset of a consumer group of a `list` key, see `Database::list_consumer_read()`.
+    Consumer {
+        key_id: u64,
+        group: Box<[u8]>,
+    },
+    /// HyperLogLog sketch of a `set` key, see `Database::set_register_sketch()`.
+    Sketch {
+        key_id: u64,
+    },
+    /// Configuration of a key, see `Database::set_key_config()`.
+    KeyConfig {
+        key: Box<[u8]>,
+    },
+    /// Seal of a key, see `Database::seal_key()`.
+    Sealed {
+        key: Box<[u8]>,
+    },
+    /// Entry of the log of administrative operations, see `Database::admin_log()`.
+    AdminLog {
+        seq: u64,
+    },
+    /// Counters of a namespace, see `Database::namespace_stats()`.
+    NamespaceStats {
+        namespace: Box<[u8]>,
+    },
+    /// System rows, e.g. `KEY_NEXT_KEY_ID`.
+    System {
+        name: Box<[u8]>,
+    },
+    Unknown,
+}
+
+/// Decode a raw row key, `owner` is the meta of the key owns the data row, it's required to
+/// decode the data rows, otherwise they are decoded as `RawRow::Data`.
+pub fn decode_raw_row(key: &[u8], value: &[u8], owner: Option<&KeyMeta>) -> RawRow {
+    let (prefix, rest) = match key.split_first() {
+        Some((p, rest)) => (*p, rest),
+        None => return RawRow::Unknown,
+    };
+    match prefix {
+        p if p == PREFIX_META[0] => RawRow::Meta {
+            key: Box::from(rest),
+            meta: if value.len() >= 17 {
+                Some(KeyMeta::from_bytes(value))
+            } else {
+                None
+            },
+        },
+        p if p == PREFIX_DATA[0] && rest.len() >= 8 => decode_data_row(key, owner),
+        p if p == PREFIX_RESERVED[0] && rest.len() >= 8 => RawRow::Reserved {
+            key_id: (&rest[..8]).get_u64(),
+            suffix: Box::from(&rest[8..]),
+        },
+        p if p == PREFIX_TYPE_INDEX[0] && !rest.is_empty() => RawRow::TypeIndex {
+            key_type: KeyType::from_u8(rest[0]),
+            key: Box::from(&rest[1..]),
+        },
+        p if p == PREFIX_BLOB[0] && !rest.is_empty() => RawRow::Blob {
+            tag: rest[0],
+            rest: Box::from(&rest[1..]),
+        },
+        p if p == PREFIX_QUARANTINE[0] => RawRow::Quarantined {
+            meta_key: Box::from(rest),
+        },
+        p if p == PREFIX_ALIAS[0] => RawRow::Alias {
+            alias: Box::from(rest),
+            target: Box::from(value),
+        },
+        p if p == PREFIX_JOURNAL[0] => RawRow::Journal {
+            key: Box::from(rest),
+        },
+        p if p == PREFIX_CONSUMER[0] && rest.len() >= 8 => RawRow::Consumer {
+            key_id: (&rest[..8]).get_u64(),
+            group: Box::from(&rest[8..]),
+        },
+        p if p == PREFIX_VALUE_CHUNK[0] => match decode_value_chunk_key(key) {
+            Some((key_id, field, index)) => RawRow::MapValueChunk {
+                key_id,
+                field: Box::from(field),
+                index,
+            },
+            None => RawRow::Unknown,
+        },
+        p if p == PREFIX_SKETCH[0] && rest.len() == 8 => RawRow::Sketch {
+            key_id: (&rest[..]).get_u64(),
+        },
+        p if p == PREFIX_KEY_CONFIG[0] => RawRow::KeyConfig {
+            key: Box::from(rest),
+        },
+        p if p == PREFIX_SEALED[0] => RawRow::Sealed {
+            key: Box::from(rest),
+        },
+        p if p == PREFIX_ADMIN_LOG[0] && rest.len() == 8 => RawRow::AdminLog {
+            seq: (&rest[..]).get_u64(),
+        },
+        p if p == PREFIX_NAMESPACE_STATS[0] => RawRow::NamespaceStats {
+            namespace: Box::from(rest),
+        },
+        _ if key == KEY_NEXT_KEY_ID
+            || key == KEY_TYPE_INDEX_READY
+            || key == KEY_NAMESPACE_STATS_READY
+            || key == KEY_MANIFEST =>
+        {
+            RawRow::System {
+                name: Box::from(rest),
+            }
+        }
+        _ => RawRow::Unknown,
+    }
+}
+
+fn decode_data_row(key: &[u8], owner: Option<&KeyMeta>) -> RawRow {
+    let key_id = (&key[1..9]).get_u64();
+    let suffix = &key[9..];
+    let data = || RawRow::Data {
+        key_id,
+        suffix: Box::from(suffix),
+    };
+    let meta = match owner {
+        Some(m) if m.id == key_id => m,
+        _ => return data(),
+    };
+    match meta.key_type {
+        KeyType::Map => RawRow::MapItem {
+            key_id,
+            field: Box::from(suffix),
+        },
+        KeyType::Set => RawRow::SetItem {
+            key_id,
+            member: Box::from(suffix),
+        },
+        KeyType::List if suffix.len() == 9 => RawRow::ListItem {
+            key_id,
+            position: (&suffix[1..]).get_i64(),
+        },
+        KeyType::SortedList if suffix.len() >= 8 => RawRow::SortedListItem {
+            key_id,
+            score: Box::from(decode_data_key_sorted_list_item(key)),
+            sequence: (&suffix[suffix.len() - 8..]).get_u64(),
+        },
+        KeyType::SortedSet if suffix.first() == Some(&0) => RawRow::SortedSetMember {
+            key_id,
+            member: Box::from(&suffix[1..]),
+        },
+        KeyType::SortedSet if suffix.first() == Some(&1) => {
+            let score_len = meta.decode_sorted_set_extra().1;
+            if suffix.len() > score_len as usize {
+                let (score, member) = decode_data_key_sorted_set_item_with_score(key, score_len);
+                RawRow::SortedSetItem {
+                    key_id,
+                    score,
+                    member,
+                }
+            } else {
+                data()
+            }
+        }
+        KeyType::Value if suffix.len() == 4 => RawRow::ValueChunk {
+            key_id,
+            index: (&suffix[..]).get_u32(),
+        },
+        KeyType::Log if suffix.len() == 8 => RawRow::LogEntry {
+            key_id,
+            offset: decode_data_key_log_offset(key),
+        },
+        _ => data(),
+    }
+}
+
+impl Database {
+    /// Scan the raw rows starting with `prefix` for debugging, `f` receives the raw key, the
+    /// decoded row and the raw value, returns `false` to stop. Returns the scanned rows count.
+    pub fn raw_scan<F>(&self, prefix: &[u8], mut f: F) -> Result<u64>
+    where
+        F: FnMut(&[u8], &RawRow, &[u8]) -> bool,
+    {
+        // all the metas are loaded to decode the data rows
+        let mut metas = HashMap::new();
+        let iter = self
+            .rocksdb
+            .prefix_iterator(PREFIX_META)
+            .take_while(|(k, _)| has_prefix(PREFIX_META, k));
+        for (_, v) in iter {
+            if v.len() >= 17 {
+                let meta = KeyMeta::from_bytes(&v);
+                metas.insert(meta.id, meta);
+            }
+        }
+        let mut counter = 0;
+        let iter = self.rocksdb.iterator_opt(
+            IteratorMode::From(prefix, Direction::Forward),
+            self.scan_read_options(),
+        );
+        for (k, v) in iter {
+            if !has_prefix(prefix, &k) {
+                break;
+            }
+            counter += 1;
+            let owner = if k.len() >= 9 && k[0] == PREFIX_DATA[0] {
+                metas.get(&(&k[1..9]).get_u64())
+            } else {
+                None
+            };
+            if !f(&k, &decode_raw_row(&k, &v, owner), &v) {
+                break;
+            }
+        }
+        Ok(counter)
+    }
+}
@@ -0,0 +1,295 @@
+use rocksdb::DBPinnableSlice;
+
+use crate::codec::*;
+use crate::database::{check_key_type, Aggregate, Database, NumberFormat, Result, ScoreStats};
+use crate::describe::KeyDescription;
+use crate::value::BlobReader;
+
+/// Read-only access to a database, see `Database::read_guard()`.
+///
+/// Only the read methods of `Database` are exposed and the database is not reachable through
+/// the guard, so code taking a `ReadGuard` can not write. Unlike `ReadView`, the reads see the
+/// latest state of the database.
+#[derive(Clone, Copy)]
+pub struct ReadGuard<'a> {
+    db: &'a Database,
+}
+
+impl Database {
+    /// Get a guard exposing only the read methods, for the code paths that must never write.
+    pub fn read_guard(&self) -> ReadGuard<'_> {
+        ReadGuard { db: self }
+    }
+}
+
+impl<'a> ReadGuard<'a> {
+    pub fn get_meta(&self, key: impl AsRef<[u8]>) -> Result<Option<KeyMeta>> {
+        self.db.get_meta(key)
+    }
+
+    pub fn get_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        self.db.get_count(key)
+    }
+
+    pub fn get_counts(&self, keys: &[&str]) -> Result<Vec<u64>> {
+        self.db.get_counts(keys)
+    }
+
+    pub fn describe_key(&self, key: &str) -> Result<Option<KeyDescription>> {
+        self.db.describe_key(key)
+    }
+
+    pub fn for_each_key<F>(&self, f: F) -> Result<usize>
+    where
+        F: FnMut(&str, &KeyMeta) -> bool,
+    {
+        self.db.for_each_key(f)
+    }
+
+    pub fn for_each_key_with_prefix<F>(&self, prefix: &str, f: F) -> Result<usize>
+    where
+        F: FnMut(&str, &KeyMeta) -> bool,
+    {
+        self.db.for_each_key_with_prefix(prefix, f)
+    }
+
+    pub fn keys_after(
+        &self,
+        start_after: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<(String, KeyMeta)>> {
+        self.db.keys_after(start_after, limit)
+    }
+
+    pub fn keys_with_prefix(&self, prefix: &str) -> Result<Vec<(String, KeyMeta)>> {
+        self.db.keys_with_prefix(prefix)
+    }
+
+    /// Same as `Database::map_get()`, but neither the meta of a missing key is created nor
+    /// the remote tier is consulted, so nothing is written.
+    pub fn map_get(
+        &self,
+        key: impl AsRef<[u8]>,
+        field: impl AsRef<[u8]>,
+    ) -> Result<Option<Vec<u8>>> {
+        match self.db.get_meta(key.as_ref())? {
+            None => Ok(None),
+            Some(meta) => {
+                check_key_type(key.as_ref(), &meta, KeyType::Map)?;
+                let full_key = encode_data_key_map_item(meta.id, field.as_ref());
+                match self.db.rocksdb.get(full_key)? {
+                    Some(row) => Ok(Some(self.db.map_item_value(
+                        meta.id,
+                        field.as_ref(),
+                        row,
+                    )?)),
+                    None => Ok(None),
+                }
+            }
+        }
+    }
+
+    pub fn multi_map_get<K: AsRef<[u8]>>(
+        &self,
+        pairs: &[(K, &[u8])],
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        self.db.multi_map_get(pairs)
+    }
+
+    pub fn map_get_pinned(
+        &self,
+        key: impl AsRef<[u8]>,
+        field: impl AsRef<[u8]>,
+    ) -> Result<Option<DBPinnableSlice<'a>>> {
+        self.db.map_get_pinned(key, field)
+    }
+
+    pub fn map_value_len(
+        &self,
+        key: impl AsRef<[u8]>,
+        field: impl AsRef<[u8]>,
+    ) -> Result<Option<usize>> {
+        self.db.map_value_len(key, field)
+    }
+
+    pub fn map_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        self.db.map_count(key)
+    }
+
+    pub fn map_for_each<F>(&self, key: &str, f: F) -> Result<u64>
+    where
+        F: FnMut(&[u8], Box<[u8]>) -> bool,
+    {
+        self.db.map_for_each(key, f)
+    }
+
+    pub fn map_items(&self, key: &str) -> Result<Vec<(String, Box<[u8]>)>> {
+        self.db.map_items(key)
+    }
+
+    pub fn map_items_with_prefix(
+        &self,
+        key: &str,
+        prefix: &str,
+    ) -> Result<Vec<(String, Box<[u8]>)>> {
+        self.db.map_items_with_prefix(key, prefix)
+    }
+
+    pub fn map_aggregate(
+        &self,
+        key: &str,
+        aggregate: Aggregate,
+        format: NumberFormat,
+    ) -> Result<Option<f64>> {
+        self.db.map_aggregate(key, aggregate, format)
+    }
+
+    pub fn set_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        self.db.set_count(key)
+    }
+
+    pub fn set_is_member(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool> {
+        self.db.set_is_member(key, value)
+    }
+
+    pub fn set_items(&self, key: impl AsRef<[u8]>) -> Result<Vec<Box<[u8]>>> {
+        self.db.set_items(key)
+    }
+
+    pub fn set_items_with_limit(
+        &self,
+        key: impl AsRef<[u8]>,
+        limit: usize,
+    ) -> Result<Vec<Box<[u8]>>> {
+        self.db.set_items_with_limit(key, limit)
+    }
+
+    pub fn list_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        self.db.list_count(key)
+    }
+
+    pub fn list_for_each<F>(&self, key: impl AsRef<[u8]>, f: F) -> Result<u64>
+    where
+        F: FnMut(Box<[u8]>) -> bool,
+    {
+        self.db.list_for_each(key, f)
+    }
+
+    pub fn list_items(&self, key: impl AsRef<[u8]>) -> Result<Vec<Box<[u8]>>> {
+        self.db.list_items(key)
+    }
+
+    pub fn list_items_range(
+        &self,
+        key: impl AsRef<[u8]>,
+        start: i64,
+        stop: i64,
+    ) -> Result<Vec<Box<[u8]>>> {
+        self.db.list_items_range(key, start, stop)
+    }
+
+    pub fn sorted_list_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        self.db.sorted_list_count(key)
+    }
+
+    pub fn sorted_list_count_in_range(
+        &self,
+        key: impl AsRef<[u8]>,
+        min_score: &[u8],
+        max_score: &[u8],
+    ) -> Result<u64> {
+        self.db
+            .sorted_list_count_in_range(key, min_score, max_score)
+    }
+
+    pub fn sorted_list_items(&self, key: impl AsRef<[u8]>) -> Result<VecScoreVal> {
+        self.db.sorted_list_items(key)
+    }
+
+    pub fn sorted_list_items_after(
+        &self,
+        key: impl AsRef<[u8]>,
+        after: Option<(&[u8], u64)>,
+        limit: usize,
+    ) -> Result<Vec<ScoreSeqVal>> {
+        self.db.sorted_list_items_after(key, after, limit)
+    }
+
+    pub fn sorted_list_score_stats(&self, key: impl AsRef<[u8]>) -> Result<ScoreStats> {
+        self.db.sorted_list_score_stats(key)
+    }
+
+    pub fn sorted_set_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        self.db.sorted_set_count(key)
+    }
+
+    pub fn sorted_set_is_member(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool> {
+        self.db.sorted_set_is_member(key, value)
+    }
+
+    pub fn sorted_set_scores(
+        &self,
+        key: impl AsRef<[u8]>,
+        values: &[&[u8]],
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        self.db.sorted_set_scores(key, values)
+    }
+
+    pub fn sorted_set_items(&self, key: impl AsRef<[u8]>) -> Result<VecScoreVal> {
+        self.db.sorted_set_items(key)
+    }
+
+    pub fn sorted_set_items_with_limit(
+        &self,
+        key: impl AsRef<[u8]>,
+        limit: usize,
+    ) -> Result<VecScoreVal> {
+        self.db.sorted_set_items_with_limit(key, limit)
+    }
+
+    pub fn sorted_set_left(
+        &self,
+        key: impl AsRef<[u8]>,
+        max_score: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<VecScoreVal> {
+        self.db.sorted_set_left(key, max_score, limit)
+    }
+
+    pub fn sorted_set_right(
+        &self,
+        key: impl AsRef<[u8]>,
+        min_score: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<VecScoreVal> {
+        self.db.sorted_set_right(key, min_score, limit)
+    }
+
+    pub fn sorted_set_score_stats(&self, key: impl AsRef<[u8]>) -> Result<ScoreStats> {
+        self.db.sorted_set_score_stats(key)
+    }
+
+    pub fn sorted_set_percentile(
+        &self,
+        key: impl AsRef<[u8]>,
+        p: f64,
+    ) -> Result<Option<Box<[u8]>>> {
+        self.db.sorted_set_percentile(key, p)
+    }
+
+    pub fn value_get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        self.db.value_get(key)
+    }
+
+    pub fn value_len(&self, key: &str) -> Result<Option<u64>> {
+        self.db.value_len(key)
+    }
+
+    pub fn blob_reader(&self, key: &str) -> Result<Option<BlobReader<'a>>> {
+        self.db.blob_reader(key)
+    }
+
+    pub fn log_read(&self, key: &str, from_offset: u64, max: usize) -> Result<Vec<OffsetVal>> {
+        self.db.log_read(key, from_offset, max)
+    }
+}
@@ -0,0 +1,156 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use bytes::Buf;
+use rocksdb::{Direction, IteratorMode, ReadOptions, Snapshot, DB};
+
+use crate::alias::encode_alias_key;
+use crate::chunk::read_chunked_value;
+use crate::codec::*;
+use crate::database::{Database, Result};
+
+/// Consistent read-only view of a database, see `Database::read_view()`.
+///
+/// It pins a RocksDB snapshot and caches the metas of the keys read through it, the snapshot
+/// is released when the view is dropped, so keep it short-lived.
+pub struct ReadView<'a> {
+    rocksdb: &'a DB,
+    /// `None` for the secondary instances of `Reader`, they don't support snapshots and only
+    /// change when they catch up with the primary.
+    snapshot: Option<Snapshot<'a>>,
+    metas: RefCell<HashMap<Vec<u8>, Option<KeyMeta>>>,
+}
+
+impl Database {
+    /// Get a read view, all the reads through it see the database at the time it's created.
+    pub fn read_view(&self) -> ReadView<'_> {
+        ReadView::new(&self.rocksdb, true)
+    }
+}
+
+impl<'a> ReadView<'a> {
+    pub(crate) fn new(rocksdb: &'a DB, snapshot: bool) -> ReadView<'a> {
+        ReadView {
+            rocksdb,
+            snapshot: if snapshot {
+                Some(rocksdb.snapshot())
+            } else {
+                None
+            },
+            metas: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Get the meta of a key or the target of an alias, metas are read once and cached for the
+    /// lifetime of the view.
+    pub fn get_meta(&self, key: impl AsRef<[u8]>) -> Result<Option<KeyMeta>> {
+        let key = key.as_ref();
+        if let Some(meta) = self.metas.borrow().get(key) {
+            return Ok(meta.clone());
+        }
+        let target = self.get(encode_alias_key(key))?;
+        let meta = self
+            .get(encode_meta_key(target.as_deref().unwrap_or(key)))?
+            .map(|v| KeyMeta::from_bytes(v.as_ref()));
+        self.metas.borrow_mut().insert(key.to_vec(), meta.clone());
+        Ok(meta)
+    }
+
+    pub fn get_count(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        Ok(self.get_meta(key)?.map(|m| m.count).unwrap_or(0))
+    }
+
+    pub fn map_get(
+        &self,
+        key: impl AsRef<[u8]>,
+        field: impl AsRef<[u8]>,
+    ) -> Result<Option<Vec<u8>>> {
+        let field = field.as_ref();
+        match self.get_meta(key)? {
+            Some(meta) => match self.get(encode_data_key_map_item(meta.id, field))? {
+                Some(row) => Ok(Some(self.map_item_value(meta.id, field, row)?)),
+                None => Ok(None),
+            },
+            None => Ok(None),
+        }
+    }
+
+    pub fn map_items(&self, key: &str) -> Result<Vec<(String, Box<[u8]>)>> {
+        let mut vec = Vec::new();
+        self.for_each_data(key, |k, v| {
+            let v = if v.is_empty() {
+                let key_id = (&k[PREFIX_DATA.len()..]).get_u64();
+                let field = decode_data_key_map_field(k.as_ref());
+                Box::from(self.map_item_value(key_id, field, Vec::new())?)
+            } else {
+                v
+            };
+            vec.push((decode_data_key_map_item(k.as_ref())?, v));
+            Ok(())
+        })?;
+        Ok(vec)
+    }
+
+    pub fn set_is_member(&self, key: &str, value: &[u8]) -> Result<bool> {
+        match self.get_meta(key)? {
+            Some(meta) => Ok(self
+                .get(encode_data_key_set_item(meta.id, value))?
+                .is_some()),
+            None => Ok(false),
+        }
+    }
+
+    pub fn list_items(&self, key: &str) -> Result<Vec<Box<[u8]>>> {
+        let mut vec = Vec::new();
+        self.for_each_data(key, |_, v| {
+            vec.push(v);
+            Ok(())
+        })?;
+        Ok(vec)
+    }
+
+    pub fn sorted_list_items(&self, key: &str) -> Result<VecScoreVal> {
+        let mut vec = Vec::new();
+        self.for_each_data(key, |k, v| {
+            vec.push((Box::from(decode_data_key_sorted_list_item(k.as_ref())), v));
+            Ok(())
+        })?;
+        Ok(vec)
+    }
+
+    fn for_each_data<F>(&self, key: &str, mut f: F) -> Result<()>
+    where
+        F: FnMut(Box<[u8]>, Box<[u8]>) -> Result<()>,
+    {
+        let meta = match self.get_meta(key)? {
+            Some(m) => m,
+            None => return Ok(()),
+        };
+        let prefix = encode_data_key(meta.id);
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_upper_bound(encode_data_key(meta.id + 1).to_vec());
+        let mode = IteratorMode::From(&prefix, Direction::Forward);
+        let iter = match self.snapshot.as_ref() {
+            Some(snapshot) => snapshot.iterator_opt(mode, opts),
+            None => self.rocksdb.iterator_opt(mode, opts),
+        };
+        for (k, v) in iter {
+            f(k, v)?;
+        }
+        Ok(())
+    }
+
+    /// Get the value of a `map` item from its row, a chunked value is reassembled.
+    fn map_item_value(&self, key_id: u64, field: &[u8], row: Vec<u8>) -> Result<Vec<u8>> {
+        if !row.is_empty() {
+            return Ok(row);
+        }
+        Ok(read_chunked_value(|k| self.get(k), key_id, field)?.unwrap_or(row))
+    }
+
+    fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Vec<u8>>> {
+        Ok(match self.snapshot.as_ref() {
+            Some(snapshot) => snapshot.get(key)?,
+            None => self.rocksdb.get(key)?,
+        })
+    }
+}
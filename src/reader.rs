@@ -0,0 +1,81 @@
+use std::{
+    fs,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use rocksdb::{BlockBasedOptions, Cache, Options as RocksDBOptions, DB};
+
+use crate::database::{Database, Result};
+use crate::namespace::namespace_merge;
+use crate::read_view::ReadView;
+
+/// Read-only handle on a secondary RocksDB instance of a database, see `Database::reader()`.
+///
+/// It has a dedicated block cache, so heavy analytical reads don't evict the blocks cached for
+/// the writers. It can be moved to another thread. The read views borrow the reader
+/// mutably, so it doesn't catch up with the primary database while a view is alive, and every
+/// view sees a single state.
+pub struct Reader {
+    rocksdb: Option<DB>,
+    secondary_path: String,
+    refresh_interval: Duration,
+    refreshed_at: Instant,
+}
+
+impl Database {
+    /// Open a reader with a block cache of `block_cache_size` bytes, it catches up with the
+    /// writes of this database at most once every `refresh_interval`.
+    pub fn reader(&self, block_cache_size: usize, refresh_interval: Duration) -> Result<Reader> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let secondary_path = format!("{}.reader-{}", self.path, nanos);
+        let mut opts = RocksDBOptions::default();
+        // secondary instances must keep all the table files open
+        opts.set_max_open_files(-1);
+        // the WAL of the primary may have the merges of the counters of the namespaces
+        opts.set_merge_operator_associative("simpledb_namespace_stats", namespace_merge);
+        let cache = Cache::new_lru_cache(block_cache_size)?;
+        let mut table = BlockBasedOptions::default();
+        table.set_block_cache(&cache);
+        opts.set_block_based_table_factory(&table);
+        let rocksdb = DB::open_as_secondary(&opts, self.path.as_str(), secondary_path.as_str())?;
+        Ok(Reader {
+            rocksdb: Some(rocksdb),
+            secondary_path,
+            refresh_interval,
+            refreshed_at: Instant::now(),
+        })
+    }
+}
+
+impl Reader {
+    /// Get a read view of the latest state, catches up with the primary database first if the
+    /// refresh interval has passed since the last time.
+    pub fn read_view(&mut self) -> Result<ReadView<'_>> {
+        if self.refreshed_at.elapsed() >= self.refresh_interval {
+            self.refresh()?;
+        }
+        Ok(ReadView::new(self.rocksdb(), false))
+    }
+
+    /// Catch up with the primary database now.
+    pub fn refresh(&mut self) -> Result<()> {
+        self.rocksdb().try_catch_up_with_primary()?;
+        self.refreshed_at = Instant::now();
+        Ok(())
+    }
+
+    fn rocksdb(&self) -> &DB {
+        self.rocksdb.as_ref().unwrap()
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        // close the secondary instance before removing its directory
+        self.rocksdb.take();
+        let _ = fs::remove_dir_all(&self.secondary_path);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::database::{Database, Error, Result};
+
+/// A record stored in a `map` key, every struct field is a map field.
+/// Use `#[derive(SimpledbRecord)]` with the `derive` feature to implement it.
+pub trait Record: Sized {
+    fn save(&self, db: &Database, key: &str) -> Result<()>;
+
+    /// Returns `None` if the key does not exist.
+    fn load(db: &Database, key: &str) -> Result<Option<Self>>;
+}
+
+/// Value of a record field, numbers are stored as decimal text.
+pub trait RecordField: Sized {
+    /// Encode the value, returns `None` to delete the map field.
+    fn to_field(&self) -> Option<Vec<u8>>;
+
+    /// Decode the value, `value` is `None` if the map field does not exist.
+    fn from_field(value: Option<&[u8]>) -> std::result::Result<Self, String>;
+}
+
+impl RecordField for Vec<u8> {
+    fn to_field(&self) -> Option<Vec<u8>> {
+        Some(self.clone())
+    }
+
+    fn from_field(value: Option<&[u8]>) -> std::result::Result<Self, String> {
+        value.map(|v| v.to_vec()).ok_or_else(missing_field)
+    }
+}
+
+impl RecordField for String {
+    fn to_field(&self) -> Option<Vec<u8>> {
+        Some(self.as_bytes().to_vec())
+    }
+
+    fn from_field(value: Option<&[u8]>) -> std::result::Result<Self, String> {
+        let value = value.ok_or_else(missing_field)?;
+        String::from_utf8(value.to_vec()).map_err(|e| e.to_string())
+    }
+}
+
+impl<T: RecordField> RecordField for Option<T> {
+    fn to_field(&self) -> Option<Vec<u8>> {
+        self.as_ref().and_then(|v| v.to_field())
+    }
+
+    fn from_field(value: Option<&[u8]>) -> std::result::Result<Self, String> {
+        match value {
+            None => Ok(None),
+            Some(v) => T::from_field(Some(v)).map(Some),
+        }
+    }
+}
+
+macro_rules! impl_record_field_for_text {
+    ($($t:ty),*) => {
+        $(
+            impl RecordField for $t {
+                fn to_field(&self) -> Option<Vec<u8>> {
+                    Some(self.to_string().into_bytes())
+                }
+
+                fn from_field(value: Option<&[u8]>) -> std::result::Result<Self, String> {
+                    let value = value.ok_or_else(missing_field)?;
+                    std::str::from_utf8(value)
+                        .map_err(|e| e.to_string())?
+                        .parse()
+                        .map_err(|_| format!("invalid {} value", stringify!($t)))
+                }
+            }
+        )*
+    };
+}
+
+impl_record_field_for_text!(bool, i8, i16, i32, i64, u8, u16, u32, u64, f32, f64);
+
+fn missing_field() -> String {
+    "missing field".to_string()
+}
+
+/// Save a field of a record, used by the generated code of `SimpledbRecord`.
+pub fn save_field<T: RecordField>(db: &Database, key: &str, field: &str, value: &T) -> Result<()> {
+    match value.to_field() {
+        Some(v) => db.map_put(key, field, v),
+        None => db.map_delete(key, field).map(|_| ()),
+    }
+}
+
+/// Load a field of a record, used by the generated code of `SimpledbRecord`.
+pub fn load_field<T: RecordField>(db: &Database, key: &str, field: &str) -> Result<T> {
+    let value = db.map_get_pinned(key, field)?;
+    T::from_field(value.as_deref())
+        .map_err(|err| Error::Message(format!("field {} of key {}: {}", field, key, err)))
+}
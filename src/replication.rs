@@ -0,0 +1,153 @@
+use bytes::Buf;
+use rocksdb::{WriteBatch, WriteBatchIterator};
+
+use crate::database::{Database, Error, Result};
+
+/// Key of the last sequence of the write batch applied by `DatabaseSink`.
+pub static KEY_REPLICATION_CHECKPOINT: &[u8] = b"sreplication_checkpoint";
+
+/// A change of a raw row read from the RocksDB WAL, see `Database::replicate_to()`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ReplicationEvent {
+    Put { key: Box<[u8]>, value: Box<[u8]> },
+    Delete { key: Box<[u8]> },
+}
+
+/// A downstream system driven by `Database::replicate_to()`, e.g. a search index or a message
+/// bus.
+///
+/// The delivery is at-least-once: a batch is delivered again if the process stops before its
+/// sequence is saved by `apply_batch()`, so applying a batch must be idempotent.
+pub trait ReplicationSink {
+    /// Last sequence of the last applied batch, `None` if nothing is applied yet.
+    fn checkpoint(&self) -> Result<Option<u64>>;
+
+    /// Apply the events of the write batch whose last sequence is `seq`, then save `seq` as
+    /// the checkpoint. Returning an error stops the replication, the batch is delivered again by
+    /// the next `replicate_to()`.
+    fn apply_batch(&mut self, seq: u64, events: &[ReplicationEvent]) -> Result<()>;
+}
+
+/// A `ReplicationSink` writing the rows into another database, the events and the checkpoint
+/// are written in a single batch. The target database is a read replica, it must not be
+/// written by others.
+pub struct DatabaseSink<'a> {
+    db: &'a Database,
+}
+
+impl<'a> DatabaseSink<'a> {
+    pub fn new(db: &'a Database) -> DatabaseSink<'a> {
+        DatabaseSink { db }
+    }
+}
+
+impl ReplicationSink for DatabaseSink<'_> {
+    fn checkpoint(&self) -> Result<Option<u64>> {
+        Ok(self
+            .db
+            .rocksdb
+            .get_pinned(KEY_REPLICATION_CHECKPOINT)?
+            .filter(|v| v.len() == 8)
+            .map(|v| v.as_ref().get_u64()))
+    }
+
+    fn apply_batch(&mut self, seq: u64, events: &[ReplicationEvent]) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        for event in events {
+            match event {
+                // the checkpoint of the source, when it's a replica too
+                ReplicationEvent::Put { key, .. } | ReplicationEvent::Delete { key }
+                    if key.as_ref() == KEY_REPLICATION_CHECKPOINT => {}
+                ReplicationEvent::Put { key, value } => batch.put(key, value),
+                ReplicationEvent::Delete { key } => batch.delete(key),
+            }
+        }
+        batch.put(KEY_REPLICATION_CHECKPOINT, seq.to_be_bytes());
+        // the metas are written without the counters of the namespaces, so they are rebuilt
+        // when the replica is opened next time
+        self.db.drop_namespace_stats_to_batch(&mut batch);
+        self.db.rocksdb.write(batch)?;
+        self.db.reload_cached_states();
+        Ok(())
+    }
+}
+
+impl Database {
+    /// Read at most `max_batches` write batches after the checkpoint of `sink` from the RocksDB
+    /// WAL, and apply them to `sink` in order. Returns the count of the applied batches.
+    ///
+    /// The WAL files are deleted after they are flushed, set `Options::replication_wal_ttl` to
+    /// keep them until they are replicated, it also makes the writes delete the rows one by
+    /// one instead of by range deletions, which can't be read from the WAL. Fails if the WAL
+    /// following the checkpoint is gone. The first batch of a database is not delivered,
+    /// it's the initialization written when the database is created.
+    pub fn replicate_to(
+        &self,
+        sink: &mut dyn ReplicationSink,
+        max_batches: usize,
+    ) -> Result<usize> {
+        let checkpoint = sink.checkpoint()?;
+        let mut last_seq = checkpoint.unwrap_or(0);
+        if last_seq >= self.rocksdb.latest_sequence_number() {
+            return Ok(0);
+        }
+        // the iterator of the binding skips the batch containing the start sequence, which is
+        // the applied one
+        let mut iter = self.rocksdb.get_updates_since(last_seq)?;
+        let mut count = 0;
+        while count < max_batches {
+            let (seq, batch) = match iter.next() {
+                None => break,
+                Some(item) => item,
+            };
+            if seq <= last_seq {
+                continue;
+            }
+            // every record of a batch takes a sequence, the batches without a checkpoint
+            // follow the skipped initialization
+            if seq != last_seq + 1 && (checkpoint.is_some() || count > 0) {
+                return Err(Error::Message(format!(
+                    "the WAL between sequence {} and {} is gone",
+                    last_seq + 1,
+                    seq - 1
+                )));
+            }
+            let events = decode_write_batch(&batch)?;
+            last_seq = seq + (batch.len() as u64).max(1) - 1;
+            sink.apply_batch(last_seq, &events)?;
+            count += 1;
+        }
+        iter.status()?;
+        Ok(count)
+    }
+}
+
+/// Collects the records of a write batch read by `WriteBatch::iterate()`.
+#[derive(Default)]
+struct EventCollector {
+    events: Vec<ReplicationEvent>,
+}
+
+impl WriteBatchIterator for EventCollector {
+    fn put(&mut self, key: Box<[u8]>, value: Box<[u8]>) {
+        self.events.push(ReplicationEvent::Put { key, value });
+    }
+
+    fn delete(&mut self, key: Box<[u8]>) {
+        self.events.push(ReplicationEvent::Delete { key });
+    }
+}
+
+/// Decode the puts and the deletes of a write batch. `WriteBatch::iterate()` of the binding
+/// stops at the first record of another type, e.g. a range deletion, so the batch is rejected
+/// if fewer records than its count are read.
+fn decode_write_batch(batch: &WriteBatch) -> Result<Vec<ReplicationEvent>> {
+    let mut collector = EventCollector::default();
+    batch.iterate(&mut collector);
+    if collector.events.len() < batch.len() {
+        return Err(Error::Message(
+            "unsupported record in the WAL, e.g. a range deletion".to_string(),
+        ));
+    }
+    Ok(collector.events)
+}
@@ -0,0 +1,134 @@
+use std::time::Duration;
+
+use rocksdb::{Direction, IteratorMode, ReadOptions, WriteBatch};
+
+use crate::codec::*;
+use crate::database::{now_millis, Database, Result};
+use crate::schema::key_matches_pattern;
+
+/// Max items trimmed by a single `sorted_list_add()` or pop, the rest are left to the next
+/// operation or `sorted_list_trim()`.
+pub const SORTED_LIST_TRIM_STEP: usize = 100;
+
+/// Retention policy of a `sorted list`, items out of the policy are trimmed from the left.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SortedListRetention {
+    /// Keep only the items with score >= now - window, the scores must be milliseconds since
+    /// the UNIX epoch encoded by `get_score_bytes::<u64>()`.
+    MaxAge(Duration),
+    /// Keep only the newest (highest score) N items.
+    MaxCount(u64),
+}
+
+impl Database {
+    /// Set the retention policy of the sorted lists matched `pattern`, the pattern is a key name
+    /// or a prefix ending with `*`. The policy is enforced incrementally by `sorted_list_add()`
+    /// and the pops, or fully by `sorted_list_trim()`. The first set pattern wins if a key
+    /// matches many patterns.
+    pub fn sorted_list_set_retention(&self, pattern: &str, retention: SortedListRetention) {
+        let mut retentions = self.retentions.write().unwrap();
+        retentions.retain(|(p, _)| p != pattern);
+        retentions.push((pattern.to_string(), retention));
+    }
+
+    /// Remove the retention policy set with `pattern`, returns `false` if it's not set.
+    pub fn sorted_list_remove_retention(&self, pattern: &str) -> bool {
+        let mut retentions = self.retentions.write().unwrap();
+        let len = retentions.len();
+        retentions.retain(|(p, _)| p != pattern);
+        retentions.len() != len
+    }
+
+    /// Get the retention policy of a sorted list, the policy in its `KeyConfig` wins.
+    pub fn sorted_list_retention(&self, key: impl AsRef<[u8]>) -> Option<SortedListRetention> {
+        let key = key.as_ref();
+        if let Some(retention) = self.key_config(key).and_then(|c| c.retention) {
+            return Some(retention);
+        }
+        self.retentions
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(pattern, _)| key_matches_pattern(key, pattern))
+            .map(|(_, retention)| *retention)
+    }
+
+    /// Trim all the items out of the retention policy of a sorted list.
+    /// Returns the trimmed items count.
+    pub fn sorted_list_trim(&self, key: impl AsRef<[u8]>) -> Result<u64> {
+        let key = key.as_ref();
+        self.check_writable(key)?;
+        match self.get_meta(key)? {
+            Some(mut meta) => self.sorted_list_apply_retention(key, &mut meta, None, true),
+            None => Ok(0),
+        }
+    }
+
+    /// Trim at most `limit` items out of the retention policy, `meta` is updated and saved.
+    pub(crate) fn sorted_list_apply_retention(
+        &self,
+        key: &[u8],
+        meta: &mut KeyMeta,
+        limit: Option<usize>,
+        delete_if_empty: bool,
+    ) -> Result<u64> {
+        let retention = match self.sorted_list_retention(key) {
+            Some(r) => r,
+            None => return Ok(0),
+        };
+        let min_score = match retention {
+            SortedListRetention::MaxAge(window) => Some(get_score_bytes(
+                now_millis().saturating_sub(window.as_millis() as u64),
+            )),
+            SortedListRetention::MaxCount(n) if meta.count > n => None,
+            SortedListRetention::MaxCount(_) => return Ok(0),
+        };
+        let mut remaining = match retention {
+            SortedListRetention::MaxCount(n) => (meta.count - n) as usize,
+            SortedListRetention::MaxAge(_) => usize::MAX,
+        };
+        if let Some(limit) = limit {
+            remaining = remaining.min(limit);
+        }
+
+        let prefix = encode_data_key(meta.id);
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_upper_bound(encode_data_key(meta.id + 1).to_vec());
+        let iter = self
+            .rocksdb
+            .iterator_opt(IteratorMode::From(&prefix, Direction::Forward), opts);
+        let mut batch = WriteBatch::default();
+        let mut last_key = None;
+        for (k, _) in iter.take(remaining) {
+            if let Some(min_score) = &min_score {
+                let score = decode_data_key_sorted_list_item(k.as_ref());
+                if compare_score_bytes(score, min_score) >= 0 {
+                    break;
+                }
+            }
+            batch.delete(k.as_ref());
+            last_key = Some(k);
+        }
+        let last_key = match last_key {
+            Some(k) => k,
+            None => return Ok(0),
+        };
+
+        let trimmed = batch.len() as u64;
+        let (sequence, left_deleted_count, right_deleted_count) = meta.decode_sorted_list_extra();
+        let left_deleted_count = left_deleted_count.saturating_add(trimmed as u32);
+        meta.count -= trimmed;
+        let compact = left_deleted_count >= self.sorted_list_compact_deletes_count(key, meta);
+        if compact {
+            meta.encode_sorted_list_extra(sequence, 0, right_deleted_count);
+        } else {
+            meta.encode_sorted_list_extra(sequence, left_deleted_count, right_deleted_count);
+        }
+        self.save_meta_to_batch(&mut batch, key, meta, delete_if_empty)?;
+        self.rocksdb.write(batch)?;
+        if compact {
+            self.compact_range(Some(prefix.as_ref()), Some(last_key.as_ref()));
+        }
+        Ok(trimmed)
+    }
+}
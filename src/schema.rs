@@ -0,0 +1,103 @@
+use crate::codec::KeyType;
+use crate::database::{Database, Error, Result};
+
+/// Expected encoding of the values written to a key.
+#[derive(Debug, Copy, Clone)]
+pub enum ValueCodec {
+    /// Any bytes.
+    Any,
+    /// UTF-8 text.
+    Utf8,
+    /// Fixed length bytes, e.g. 8 for big-endian `i64`.
+    Fixed(usize),
+    /// Validated by a function, returns `false` if the value is invalid.
+    Custom(fn(&[u8]) -> bool),
+}
+
+impl ValueCodec {
+    pub fn validate(&self, value: &[u8]) -> bool {
+        match self {
+            ValueCodec::Any => true,
+            ValueCodec::Utf8 => std::str::from_utf8(value).is_ok(),
+            ValueCodec::Fixed(len) => value.len() == *len,
+            ValueCodec::Custom(f) => f(value),
+        }
+    }
+}
+
+/// Schema of the keys matched a pattern.
+#[derive(Debug, Copy, Clone)]
+pub struct Schema {
+    pub key_type: KeyType,
+    pub value: ValueCodec,
+}
+
+impl Schema {
+    pub fn new(key_type: KeyType, value: ValueCodec) -> Schema {
+        Schema { key_type, value }
+    }
+}
+
+/// Returns `true` if `key` matches `pattern`, a key name or a prefix ending with `*`.
+pub(crate) fn key_matches_pattern(key: &[u8], pattern: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => key.starts_with(prefix.as_bytes()),
+        None => key == pattern.as_bytes(),
+    }
+}
+
+impl Database {
+    /// Register the schema of the keys matched `pattern`, the pattern is a key name or a prefix
+    /// ending with `*`. Writes to the matched keys return `Error::Schema` if the key type or the
+    /// value does not match. The first registered pattern wins if a key matches many patterns.
+    pub fn register_schema(&self, pattern: &str, schema: Schema) {
+        self.schemas
+            .write()
+            .unwrap()
+            .push((pattern.to_string(), schema));
+    }
+
+    /// Remove the schema registered with `pattern`, returns `false` if it's not registered.
+    pub fn unregister_schema(&self, pattern: &str) -> bool {
+        let mut schemas = self.schemas.write().unwrap();
+        let len = schemas.len();
+        schemas.retain(|(p, _)| p != pattern);
+        schemas.len() != len
+    }
+
+    /// Get the schema of a key.
+    pub fn get_schema(&self, key: impl AsRef<[u8]>) -> Option<Schema> {
+        let key = key.as_ref();
+        self.schemas
+            .read()
+            .unwrap()
+            .iter()
+            .find(|(pattern, _)| key_matches_pattern(key, pattern))
+            .map(|(_, schema)| *schema)
+    }
+
+    /// Validate the key type of a key against the registered schema.
+    pub(crate) fn check_schema_key_type(&self, key: &[u8], key_type: KeyType) -> Result<()> {
+        match self.get_schema(key) {
+            Some(schema) if schema.key_type != key_type => Err(Error::Schema(format!(
+                "key {} expects type {:?}, but got {:?}",
+                String::from_utf8_lossy(key),
+                schema.key_type,
+                key_type
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Validate a value written to a key against the registered schema.
+    pub(crate) fn check_schema_value(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        match self.get_schema(key) {
+            Some(schema) if !schema.value.validate(value) => Err(Error::Schema(format!(
+                "invalid value for key {}, expects {:?}",
+                String::from_utf8_lossy(key),
+                schema.value
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
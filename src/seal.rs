@@ -0,0 +1,73 @@
+use bytes::{BufMut, BytesMut};
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+
+/// Key prefix for the sealed keys, followed by the key name.
+pub static PREFIX_SEALED: &[u8] = b"e";
+
+/// Encode key of the seal of a key.
+pub fn encode_sealed_key(key: impl AsRef<[u8]>) -> BytesMut {
+    let key = key.as_ref();
+    let mut buf = BytesMut::with_capacity(PREFIX_SEALED.len() + key.len());
+    buf.put_slice(PREFIX_SEALED);
+    buf.put_slice(key);
+    buf
+}
+
+impl Database {
+    /// Make an existing key immutable, e.g. an audit record or content-addressed data, all
+    /// the later writes of the key fail with `Error::Sealed`, including the deletes.
+    /// Returns `false` if the key does not exist.
+    pub fn seal_key(&self, key: &str) -> Result<bool> {
+        let key = self.resolve_key(key.as_bytes()).into_owned();
+        if self.get_meta(&key)?.is_none() {
+            return Ok(false);
+        }
+        let mut sealed_keys = self.sealed_keys.write().unwrap();
+        self.rocksdb.put(encode_sealed_key(&key), FILL_EMPTY_DATA)?;
+        self.log_admin("seal_key", &key, 1)?;
+        sealed_keys.insert(key);
+        Ok(true)
+    }
+
+    /// Make a sealed key writable again, returns `false` if it's not sealed.
+    pub fn unseal_key(&self, key: &str) -> Result<bool> {
+        let key = self.resolve_key(key.as_bytes()).into_owned();
+        let mut sealed_keys = self.sealed_keys.write().unwrap();
+        if !sealed_keys.contains(&key) {
+            return Ok(false);
+        }
+        self.rocksdb.delete(encode_sealed_key(&key))?;
+        self.log_admin("unseal_key", &key, 1)?;
+        sealed_keys.remove(&key);
+        Ok(true)
+    }
+
+    pub fn is_sealed(&self, key: impl AsRef<[u8]>) -> bool {
+        let key = self.resolve_key(key.as_ref());
+        self.sealed_keys.read().unwrap().contains(key.as_ref())
+    }
+
+    /// Fail with `Error::Sealed` if the key is sealed, or `Error::Frozen` if it's frozen by
+    /// `freeze_key()`, call it before writing a key.
+    pub(crate) fn check_writable(&self, key: impl AsRef<[u8]>) -> Result<()> {
+        let key = key.as_ref();
+        if self.is_sealed(key) {
+            return Err(Error::Sealed(String::from_utf8_lossy(key).into_owned()));
+        }
+        self.check_frozen(key, true)
+    }
+
+    pub(crate) fn load_sealed_keys(&self) {
+        let mut sealed_keys = self.sealed_keys.write().unwrap();
+        sealed_keys.clear();
+        let iter = self.rocksdb.prefix_iterator(PREFIX_SEALED);
+        for (k, _) in iter {
+            if !has_prefix(PREFIX_SEALED, &k) {
+                break;
+            }
+            sealed_keys.insert(k[PREFIX_SEALED.len()..].to_vec());
+        }
+    }
+}
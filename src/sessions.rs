@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, BytesMut};
+use rand::Rng;
+
+use crate::codec::{decode_expiring_value, encode_expiring_value, encode_hex, KeyType};
+use crate::database::{now_millis, Database, Result};
+
+/// Bytes count of the random part of a session token.
+pub const SESSION_TOKEN_BYTES: usize = 16;
+
+/// Session store, every session is a field of a `map` key, the value contains the expire time,
+/// the ttl and the session data.
+pub struct Sessions<'a> {
+    db: &'a Database,
+    key: String,
+}
+
+impl<'a> Sessions<'a> {
+    /// Create a session store on the `map` named `key`.
+    pub fn new(db: &'a Database, key: impl Into<String>) -> Self {
+        Sessions {
+            db,
+            key: key.into(),
+        }
+    }
+
+    /// Create a new session with `data`, returns the random token in hex.
+    pub fn create_session(&self, data: &[u8], ttl: Duration) -> Result<String> {
+        let random: [u8; SESSION_TOKEN_BYTES] = rand::thread_rng().gen();
+        let token = encode_hex(&random);
+        self.save(&token, data, ttl)?;
+        Ok(token)
+    }
+
+    /// Get the session data, returns `None` if the session does not exist or has expired.
+    pub fn get_session(&self, token: &str) -> Result<Option<Box<[u8]>>> {
+        Ok(self.get(token)?.map(|(_, data)| data))
+    }
+
+    /// Extend the expire time of a session by its ttl, returns `false` if the session does not
+    /// exist or has expired.
+    pub fn touch(&self, token: &str) -> Result<bool> {
+        match self.get(token)? {
+            Some((ttl, data)) => {
+                self.save(token, &data, ttl)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Delete a session, returns `false` if the session does not exist.
+    pub fn revoke(&self, token: &str) -> Result<bool> {
+        self.db.map_delete(&self.key, token)
+    }
+
+    /// Delete all the expired sessions, returns the deleted count.
+    pub fn purge_expired(&self) -> Result<u64> {
+        self.db.purge_expired_values(&self.key)
+    }
+
+    fn save(&self, token: &str, data: &[u8], ttl: Duration) -> Result<()> {
+        let ttl = ttl.as_millis() as u64;
+        let mut buf = BytesMut::with_capacity(8 + data.len());
+        buf.put_u64(ttl);
+        buf.put_slice(data);
+        let value = encode_expiring_value(now_millis().saturating_add(ttl), &buf);
+        self.db.map_put(&self.key, token, value)
+    }
+
+    fn get(&self, token: &str) -> Result<Option<(Duration, Box<[u8]>)>> {
+        let value = match self.db.map_get(&self.key, token)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+        let (expires_at, mut buf) = decode_expiring_value(&value);
+        if expires_at <= now_millis() {
+            if self.db.map_delete(&self.key, token)? {
+                self.db
+                    .emit_expired(self.key.as_bytes(), KeyType::Map, token.as_bytes());
+            }
+            return Ok(None);
+        }
+        let ttl = Duration::from_millis(buf.get_u64());
+        Ok(Some((ttl, Box::from(buf))))
+    }
+}
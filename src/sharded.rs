@@ -0,0 +1,609 @@
+use std::{fs, io, path::Path, time::Duration};
+
+use rocksdb::DBPinnableSlice;
+
+use crate::codec::{OffsetVal, ScoreSeqVal, ScoreVal, TokenScoreVal, Utf8FieldVal, VecScoreVal};
+use crate::config::KeyConfig;
+use crate::database::{
+    Aggregate, Database, Error, NumberFormat, OnConflict, Options, Result, SaveMetaPolicy,
+    ScoreStats,
+};
+use crate::describe::KeyDescription;
+use crate::item::{Item, KeyDigest};
+use crate::priority_queue::{Priority, PriorityQueue};
+use crate::retention::SortedListRetention;
+use crate::schema::Schema;
+use crate::value::{BlobReader, BlobWriter};
+use crate::{KeyMeta, KeyType};
+
+/// Name of the file stores the shards count.
+static SHARDS_FILE: &str = "SHARDS";
+
+/// Database hashes key names across multiple RocksDB instances in the sub directories
+/// `shard-{i}`, the shards count can not be changed after created.
+///
+/// The methods of a key are forwarded to the shard of the key, and the schemas, the save meta
+/// policies and the retentions are registered in every shard. The methods of two keys, e.g.
+/// `map_move_field()`, fail if the keys are in different shards. The methods of the whole
+/// database, e.g. the replication, are called on `shards`.
+pub struct ShardedDatabase {
+    pub path: String,
+    pub shards: Vec<Database>,
+}
+
+macro_rules! forward_to_shard {
+    ($(fn $name:ident(&self, key: $key:ty $(, $arg:ident: $ty:ty)* $(,)?) -> $ret:ty;)*) => {
+        $(
+            pub fn $name(&self, key: $key $(, $arg: $ty)*) -> $ret {
+                self.shard(&key).$name(key $(, $arg)*)
+            }
+        )*
+    };
+}
+
+macro_rules! forward_to_shards {
+    ($(fn $name:ident(&self $(, $arg:ident: $ty:ty)* $(,)?);)*) => {
+        $(
+            pub fn $name(&self $(, $arg: $ty)*) {
+                for db in self.shards.iter() {
+                    db.$name($($arg.clone()),*);
+                }
+            }
+        )*
+    };
+}
+
+impl ShardedDatabase {
+    pub fn open(path: impl AsRef<Path>, shards: usize) -> Result<ShardedDatabase> {
+        Self::open_with_options(path, shards, Options::default)
+    }
+
+    /// Open with the options created by `options` for every shard.
+    pub fn open_with_options<F>(
+        path: impl AsRef<Path>,
+        shards: usize,
+        options: F,
+    ) -> Result<ShardedDatabase>
+    where
+        F: Fn() -> Options,
+    {
+        let path = path.as_ref();
+        if shards < 1 {
+            return Err(Error::Message("shards count must be positive".to_string()));
+        }
+        fs::create_dir_all(path)?;
+        let shards_file = path.join(SHARDS_FILE);
+        if shards_file.exists() {
+            let saved = fs::read_to_string(&shards_file)?;
+            if saved.trim() != shards.to_string() {
+                return Err(Error::Message(format!(
+                    "database {} has {} shards, but opened with {}",
+                    path.display(),
+                    saved.trim(),
+                    shards
+                )));
+            }
+        } else {
+            fs::write(&shards_file, shards.to_string())?;
+        }
+        let shards = (0..shards)
+            .map(|i| Database::open_with_options(path.join(format!("shard-{}", i)), options()))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ShardedDatabase {
+            path: path.to_string_lossy().to_string(),
+            shards,
+        })
+    }
+
+    pub fn destroy(path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if path.exists() {
+            fs::remove_dir_all(path)?;
+        }
+        Ok(())
+    }
+
+    /// Get the shard which stores the key.
+    pub fn shard(&self, key: impl AsRef<[u8]>) -> &Database {
+        let hash = crc32fast::hash(key.as_ref()) as usize;
+        &self.shards[hash % self.shards.len()]
+    }
+
+    /// Get the shard which stores both keys, fails if they are in different shards.
+    pub fn shard_of_both(&self, a: impl AsRef<[u8]>, b: impl AsRef<[u8]>) -> Result<&Database> {
+        let (a, b) = (a.as_ref(), b.as_ref());
+        let db = self.shard(a);
+        if !std::ptr::eq(db, self.shard(b)) {
+            return Err(Error::Message(format!(
+                "keys {} and {} are in different shards",
+                String::from_utf8_lossy(a),
+                String::from_utf8_lossy(b)
+            )));
+        }
+        Ok(db)
+    }
+
+    /// Get all keys of every shard.
+    pub fn keys(&self) -> Result<Vec<(String, KeyMeta)>> {
+        let mut keys = Vec::new();
+        for db in self.shards.iter() {
+            keys.extend(db.keys()?);
+        }
+        Ok(keys)
+    }
+
+    /// Get the counts of the keys, in the order of `keys`.
+    pub fn get_counts(&self, keys: &[&str]) -> Result<Vec<u64>> {
+        keys.iter().map(|key| self.get_count(key)).collect()
+    }
+
+    /// Delete the keys shard by shard, the keys of a shard are deleted atomically.
+    pub fn delete_keys(&self, keys: &[&str]) -> Result<u64> {
+        let mut deleted = 0;
+        for db in self.shards.iter() {
+            let keys: Vec<&str> = keys
+                .iter()
+                .copied()
+                .filter(|key| std::ptr::eq(self.shard(key), db))
+                .collect();
+            if !keys.is_empty() {
+                deleted += db.delete_keys(&keys)?;
+            }
+        }
+        Ok(deleted)
+    }
+
+    pub fn multi_map_get<K: AsRef<[u8]>>(
+        &self,
+        pairs: &[(K, &[u8])],
+    ) -> Result<Vec<Option<Vec<u8>>>> {
+        pairs
+            .iter()
+            .map(|(key, field)| self.map_get(key, field))
+            .collect()
+    }
+
+    /// Pop from the first key having a member not greater than `max_score`, the keys are
+    /// checked one by one, so it's not atomic across the shards.
+    pub fn sorted_list_pop_any<K: AsRef<[u8]>>(
+        &self,
+        keys: &[K],
+        max_score: Option<&[u8]>,
+    ) -> Result<Option<(usize, ScoreVal)>> {
+        for (i, key) in keys.iter().enumerate() {
+            if let Some(item) = self.sorted_list_left_pop(key, max_score)? {
+                return Ok(Some((i, item)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn map_move_field(
+        &self,
+        src_key: &str,
+        dst_key: &str,
+        field: impl AsRef<[u8]>,
+    ) -> Result<bool> {
+        self.shard_of_both(src_key, dst_key)?
+            .map_move_field(src_key, dst_key, field)
+    }
+
+    pub fn map_merge(&self, dst: &str, src: &str, on_conflict: OnConflict) -> Result<u64> {
+        self.shard_of_both(dst, src)?
+            .map_merge(dst, src, on_conflict)
+    }
+
+    pub fn set_diff_count(&self, a: &str, b: &str) -> Result<u64> {
+        self.shard_of_both(a, b)?.set_diff_count(a, b)
+    }
+
+    pub fn set_intersect_count(&self, a: &str, b: &str) -> Result<u64> {
+        self.shard_of_both(a, b)?.set_intersect_count(a, b)
+    }
+
+    pub fn set_intersect_estimate(&self, a: impl AsRef<[u8]>, b: impl AsRef<[u8]>) -> Result<u64> {
+        self.shard_of_both(&a, &b)?.set_intersect_estimate(a, b)
+    }
+
+    pub fn sorted_list_move(
+        &self,
+        src: impl AsRef<[u8]>,
+        dst: impl AsRef<[u8]>,
+        max_score: Option<&[u8]>,
+    ) -> Result<Option<ScoreVal>> {
+        self.shard_of_both(&src, &dst)?
+            .sorted_list_move(src, dst, max_score)
+    }
+
+    pub fn rename_key_rewriting(&self, old: &str, new: &str) -> Result<u64> {
+        self.shard_of_both(old, new)?.rename_key_rewriting(old, new)
+    }
+
+    pub fn alias_key(&self, alias: &str, target: &str) -> Result<()> {
+        self.shard_of_both(alias, target)?.alias_key(alias, target)
+    }
+
+    pub fn blob_writer(&self, key: &str) -> Result<BlobWriter<'_>> {
+        self.shard(key).blob_writer(key)
+    }
+
+    pub fn blob_reader(&self, key: &str) -> Result<Option<BlobReader<'_>>> {
+        self.shard(key).blob_reader(key)
+    }
+
+    pub fn priority_queue<P: Priority>(&self, key: &str) -> PriorityQueue<'_, P> {
+        self.shard(key).priority_queue(key)
+    }
+
+    #[cfg(feature = "rayon")]
+    pub fn par_map_for_each(
+        &self,
+        key: &str,
+        shards: usize,
+        f: impl Fn(&str, &[u8]) -> bool + Sync,
+    ) -> Result<u64> {
+        self.shard(key).par_map_for_each(key, shards, f)
+    }
+
+    forward_to_shards! {
+        fn register_schema(&self, pattern: &str, schema: Schema);
+        fn set_save_meta_policy(&self, pattern: &str, policy: SaveMetaPolicy);
+        fn sorted_list_set_retention(&self, pattern: &str, retention: SortedListRetention);
+    }
+
+    pub fn unregister_schema(&self, pattern: &str) -> bool {
+        let mut found = false;
+        for db in self.shards.iter() {
+            found |= db.unregister_schema(pattern);
+        }
+        found
+    }
+
+    pub fn remove_save_meta_policy(&self, pattern: &str) -> bool {
+        let mut found = false;
+        for db in self.shards.iter() {
+            found |= db.remove_save_meta_policy(pattern);
+        }
+        found
+    }
+
+    pub fn sorted_list_remove_retention(&self, pattern: &str) -> bool {
+        let mut found = false;
+        for db in self.shards.iter() {
+            found |= db.sorted_list_remove_retention(pattern);
+        }
+        found
+    }
+
+    forward_to_shard! {
+        fn get_meta(&self, key: impl AsRef<[u8]>) -> Result<Option<KeyMeta>>;
+        fn get_or_create_meta(&self, key: impl AsRef<[u8]>, key_type: KeyType) -> Result<KeyMeta>;
+        fn save_meta(
+            &self,
+            key: impl AsRef<[u8]>,
+            meta: &KeyMeta,
+            delete_if_empty: bool,
+        ) -> Result<()>;
+        fn save_meta_policy(&self, key: impl AsRef<[u8]>) -> SaveMetaPolicy;
+        fn for_each_data(
+            &self,
+            key: impl AsRef<[u8]>,
+            prefix: Option<&str>,
+            f: impl FnMut(Box<[u8]>, Box<[u8]>) -> bool,
+        ) -> Result<u64>;
+        fn get_count(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+        fn get_count_if_exists(&self, key: impl AsRef<[u8]>) -> Result<Option<u64>>;
+        fn delete_all(&self, key: &str) -> Result<u64>;
+        fn describe_key(&self, key: &str) -> Result<Option<KeyDescription>>;
+        fn dump_key(&self, key: &str) -> Result<Option<Vec<u8>>>;
+        fn restore_key(&self, key: &str, blob: &[u8]) -> Result<u64>;
+        fn copy_key_to(&self, key: &str, dst: &Database) -> Result<u64>;
+        fn for_each_item(&self, key: &str, f: impl FnMut(Item) -> bool) -> Result<u64>;
+        fn key_digest(&self, key: &str) -> Result<Option<KeyDigest>>;
+        fn evict(&self, key: &str) -> Result<bool>;
+
+        fn set_key_config(&self, key: &str, config: KeyConfig) -> Result<()>;
+        fn remove_key_config(&self, key: &str) -> Result<bool>;
+        fn key_config(&self, key: impl AsRef<[u8]>) -> Option<KeyConfig>;
+        fn get_schema(&self, key: impl AsRef<[u8]>) -> Option<Schema>;
+        fn remove_alias(&self, key: &str) -> Result<bool>;
+        fn aliases_of(&self, key: &str) -> Vec<String>;
+        fn seal_key(&self, key: &str) -> Result<bool>;
+        fn unseal_key(&self, key: &str) -> Result<bool>;
+        fn is_sealed(&self, key: impl AsRef<[u8]>) -> bool;
+        fn freeze_key(&self, key: &str, block_reads: bool) -> Result<bool>;
+        fn unfreeze_key(&self, key: &str) -> Result<bool>;
+        fn is_frozen(&self, key: impl AsRef<[u8]>) -> bool;
+
+        fn map_count(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+        fn map_get(&self, key: impl AsRef<[u8]>, field: impl AsRef<[u8]>) -> Result<Option<Vec<u8>>>;
+        fn map_get_pinned(
+            &self,
+            key: impl AsRef<[u8]>,
+            field: impl AsRef<[u8]>,
+        ) -> Result<Option<DBPinnableSlice<'_>>>;
+        fn map_value_len(
+            &self,
+            key: impl AsRef<[u8]>,
+            field: impl AsRef<[u8]>,
+        ) -> Result<Option<usize>>;
+        fn map_put(
+            &self,
+            key: impl AsRef<[u8]>,
+            field: impl AsRef<[u8]>,
+            value: impl AsRef<[u8]>,
+        ) -> Result<()>;
+        fn map_swap(
+            &self,
+            key: impl AsRef<[u8]>,
+            field: impl AsRef<[u8]>,
+            value: impl AsRef<[u8]>,
+        ) -> Result<Option<Vec<u8>>>;
+        fn map_delete(&self, key: impl AsRef<[u8]>, field: impl AsRef<[u8]>) -> Result<bool>;
+        fn map_for_each(
+            &self,
+            key: &str,
+            f: impl FnMut(&[u8], Box<[u8]>) -> bool,
+        ) -> Result<u64>;
+        fn map_for_each_utf8(
+            &self,
+            key: &str,
+            f: impl FnMut(&str, Box<[u8]>) -> bool,
+        ) -> Result<u64>;
+        fn map_items(&self, key: &str) -> Result<Vec<(String, Box<[u8]>)>>;
+        fn map_items_if_exists(&self, key: &str) -> Result<Option<Vec<Utf8FieldVal>>>;
+        fn map_for_each_with_limit(
+            &self,
+            key: &str,
+            limit: usize,
+            f: impl FnMut(&str, Box<[u8]>) -> bool,
+        ) -> Result<u64>;
+        fn map_items_with_limit(
+            &self,
+            key: &str,
+            limit: usize,
+        ) -> Result<Vec<(String, Box<[u8]>)>>;
+        fn map_for_each_with_prefix(
+            &self,
+            key: &str,
+            prefix: &str,
+            f: impl FnMut(&str, Box<[u8]>) -> bool,
+        ) -> Result<u64>;
+        fn map_items_with_prefix(
+            &self,
+            key: &str,
+            prefix: &str,
+        ) -> Result<Vec<(String, Box<[u8]>)>>;
+        fn map_for_each_range(
+            &self,
+            key: &str,
+            start_field: Option<&str>,
+            end_field: Option<&str>,
+            reverse: bool,
+            f: impl FnMut(&str, Box<[u8]>) -> bool,
+        ) -> Result<u64>;
+        fn map_aggregate(
+            &self,
+            key: &str,
+            aggregate: Aggregate,
+            format: NumberFormat,
+        ) -> Result<Option<f64>>;
+        fn idempotency_check_and_set(
+            &self,
+            key: &str,
+            request_id: &str,
+            result: &[u8],
+            ttl: Duration,
+        ) -> Result<Option<Box<[u8]>>>;
+        fn idempotency_purge_expired(&self, key: &str) -> Result<u64>;
+        fn cache_get_or_insert_with(
+            &self,
+            key: &str,
+            field: &str,
+            ttl: Duration,
+            f: impl FnOnce() -> Vec<u8>,
+        ) -> Result<Box<[u8]>>;
+        fn dedupe_window(&self, key: &str, id: &[u8], window: Duration) -> Result<bool>;
+
+        fn set_count(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+        fn set_add(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool>;
+        fn set_is_member(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool>;
+        fn set_delete(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool>;
+        fn set_for_each(&self, key: impl AsRef<[u8]>, f: impl FnMut(Box<[u8]>) -> bool) -> Result<u64>;
+        fn set_items(&self, key: impl AsRef<[u8]>) -> Result<Vec<Box<[u8]>>>;
+        fn set_items_if_exists(&self, key: impl AsRef<[u8]>) -> Result<Option<Vec<Box<[u8]>>>>;
+        fn set_items_with_limit(
+            &self,
+            key: impl AsRef<[u8]>,
+            limit: usize,
+        ) -> Result<Vec<Box<[u8]>>>;
+        fn set_sample(&self, key: impl AsRef<[u8]>, n: usize, seed: u64) -> Result<Vec<Box<[u8]>>>;
+        fn set_register_sketch(&self, key: impl AsRef<[u8]>) -> Result<bool>;
+
+        fn list_count(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+        fn list_left_push(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<u64>;
+        fn list_right_push(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<u64>;
+        fn list_left_pop(&self, key: impl AsRef<[u8]>) -> Result<Option<Box<[u8]>>>;
+        fn list_right_pop(&self, key: impl AsRef<[u8]>) -> Result<Option<Box<[u8]>>>;
+        fn list_left_pop_n(&self, key: impl AsRef<[u8]>, n: usize) -> Result<Vec<Box<[u8]>>>;
+        fn list_right_pop_n(&self, key: impl AsRef<[u8]>, n: usize) -> Result<Vec<Box<[u8]>>>;
+        fn list_rotate(&self, key: impl AsRef<[u8]>, n: i64) -> Result<u64>;
+        fn list_dedupe(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+        fn list_reindex(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+        fn list_for_each(&self, key: impl AsRef<[u8]>, f: impl FnMut(Box<[u8]>) -> bool) -> Result<u64>;
+        fn list_items(&self, key: impl AsRef<[u8]>) -> Result<Vec<Box<[u8]>>>;
+        fn list_items_if_exists(&self, key: impl AsRef<[u8]>) -> Result<Option<Vec<Box<[u8]>>>>;
+        fn list_items_range(
+            &self,
+            key: impl AsRef<[u8]>,
+            start: i64,
+            stop: i64,
+        ) -> Result<Vec<Box<[u8]>>>;
+        fn list_consumer_read(
+            &self,
+            key: impl AsRef<[u8]>,
+            group: &str,
+            count: usize,
+        ) -> Result<Vec<Box<[u8]>>>;
+        fn list_consumer_reset(&self, key: impl AsRef<[u8]>, group: &str) -> Result<bool>;
+
+        fn sorted_list_count(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+        fn sorted_list_add(
+            &self,
+            key: impl AsRef<[u8]>,
+            score: &[u8],
+            value: &[u8],
+        ) -> Result<u64>;
+        fn sorted_list_reseed(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+        fn sorted_list_compact(&self, key: impl AsRef<[u8]>) -> Result<bool>;
+        fn sorted_list_set_compact_deletes_count(
+            &self,
+            key: impl AsRef<[u8]>,
+            count: u32,
+        ) -> Result<bool>;
+        fn sorted_list_left_pop(
+            &self,
+            key: impl AsRef<[u8]>,
+            max_score: Option<&[u8]>,
+        ) -> Result<Option<ScoreVal>>;
+        fn sorted_list_left_pop_with_sequence(
+            &self,
+            key: impl AsRef<[u8]>,
+            max_score: Option<&[u8]>,
+        ) -> Result<Option<ScoreSeqVal>>;
+        fn sorted_list_right_pop(
+            &self,
+            key: impl AsRef<[u8]>,
+            min_score: Option<&[u8]>,
+        ) -> Result<Option<ScoreVal>>;
+        fn sorted_list_right_pop_with_sequence(
+            &self,
+            key: impl AsRef<[u8]>,
+            min_score: Option<&[u8]>,
+        ) -> Result<Option<ScoreSeqVal>>;
+        fn sorted_list_reserve(
+            &self,
+            key: impl AsRef<[u8]>,
+            max_score: Option<&[u8]>,
+            lease_ttl: Duration,
+        ) -> Result<Option<TokenScoreVal>>;
+        fn sorted_list_count_in_range(
+            &self,
+            key: impl AsRef<[u8]>,
+            min_score: &[u8],
+            max_score: &[u8],
+        ) -> Result<u64>;
+        fn sorted_list_for_each(
+            &self,
+            key: impl AsRef<[u8]>,
+            f: impl FnMut((Box<[u8]>, Box<[u8]>)) -> bool,
+        ) -> Result<u64>;
+        fn sorted_list_for_each_with_sequence(
+            &self,
+            key: impl AsRef<[u8]>,
+            f: impl FnMut(ScoreSeqVal) -> bool,
+        ) -> Result<u64>;
+        fn sorted_list_items_after(
+            &self,
+            key: impl AsRef<[u8]>,
+            after: Option<(&[u8], u64)>,
+            limit: usize,
+        ) -> Result<Vec<ScoreSeqVal>>;
+        fn sorted_list_items(&self, key: impl AsRef<[u8]>) -> Result<VecScoreVal>;
+        fn sorted_list_items_if_exists(&self, key: impl AsRef<[u8]>) -> Result<Option<VecScoreVal>>;
+        fn sorted_list_score_stats(&self, key: impl AsRef<[u8]>) -> Result<ScoreStats>;
+        fn sorted_list_retention(&self, key: impl AsRef<[u8]>) -> Option<SortedListRetention>;
+        fn sorted_list_trim(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+
+        fn sorted_set_count(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+        fn sorted_set_add(&self, key: impl AsRef<[u8]>, score: &[u8], value: &[u8]) -> Result<u64>;
+        fn sorted_set_add_ex(
+            &self,
+            key: impl AsRef<[u8]>,
+            score: &[u8],
+            value: &[u8],
+            ttl: Duration,
+        ) -> Result<u64>;
+        fn sorted_set_purge_expired(&self, key: impl AsRef<[u8]>) -> Result<u64>;
+        fn sorted_set_is_member(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool>;
+        fn sorted_set_score_pinned(
+            &self,
+            key: impl AsRef<[u8]>,
+            value: &[u8],
+        ) -> Result<Option<DBPinnableSlice<'_>>>;
+        fn sorted_set_scores(
+            &self,
+            key: impl AsRef<[u8]>,
+            values: &[&[u8]],
+        ) -> Result<Vec<Option<Vec<u8>>>>;
+        fn sorted_set_delete(&self, key: impl AsRef<[u8]>, value: &[u8]) -> Result<bool>;
+        fn sorted_set_update_member(
+            &self,
+            key: impl AsRef<[u8]>,
+            old_value: &[u8],
+            new_value: &[u8],
+        ) -> Result<bool>;
+        fn sorted_set_left(
+            &self,
+            key: impl AsRef<[u8]>,
+            max_score: Option<&[u8]>,
+            limit: usize,
+        ) -> Result<VecScoreVal>;
+        fn sorted_set_right(
+            &self,
+            key: impl AsRef<[u8]>,
+            min_score: Option<&[u8]>,
+            limit: usize,
+        ) -> Result<VecScoreVal>;
+        fn sorted_set_delete_range_by_score(
+            &self,
+            key: impl AsRef<[u8]>,
+            min_score: &[u8],
+            max_score: &[u8],
+        ) -> Result<u64>;
+        fn sorted_set_delete_range_by_rank(
+            &self,
+            key: impl AsRef<[u8]>,
+            start: i64,
+            stop: i64,
+        ) -> Result<u64>;
+        fn sorted_set_for_each(
+            &self,
+            key: impl AsRef<[u8]>,
+            f: impl FnMut((Box<[u8]>, Box<[u8]>)) -> bool,
+        ) -> Result<u64>;
+        fn sorted_set_for_each_rev(
+            &self,
+            key: impl AsRef<[u8]>,
+            f: impl FnMut((Box<[u8]>, Box<[u8]>)) -> bool,
+        ) -> Result<u64>;
+        fn sorted_set_items(&self, key: impl AsRef<[u8]>) -> Result<VecScoreVal>;
+        fn sorted_set_items_if_exists(&self, key: impl AsRef<[u8]>) -> Result<Option<VecScoreVal>>;
+        fn sorted_set_items_with_limit(
+            &self,
+            key: impl AsRef<[u8]>,
+            limit: usize,
+        ) -> Result<VecScoreVal>;
+        fn sorted_set_write_to(&self, key: impl AsRef<[u8]>, writer: impl io::Write) -> Result<u64>;
+        fn sorted_set_score_stats(&self, key: impl AsRef<[u8]>) -> Result<ScoreStats>;
+        fn sorted_set_percentile(
+            &self,
+            key: impl AsRef<[u8]>,
+            p: f64,
+        ) -> Result<Option<Box<[u8]>>>;
+        fn sorted_set_histogram(
+            &self,
+            key: impl AsRef<[u8]>,
+            bucket_boundaries: &[&[u8]],
+        ) -> Result<Vec<u64>>;
+
+        fn value_put(&self, key: &str, data: &[u8]) -> Result<()>;
+        fn value_get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+        fn value_get_pinned(&self, key: &str) -> Result<Option<Vec<DBPinnableSlice<'_>>>>;
+        fn value_len(&self, key: &str) -> Result<Option<u64>>;
+
+        fn log_append(&self, key: &str, value: &[u8]) -> Result<u64>;
+        fn log_read(&self, key: &str, from_offset: u64, max: usize) -> Result<Vec<OffsetVal>>;
+        fn log_truncate_before(&self, key: &str, offset: u64) -> Result<u64>;
+    }
+}
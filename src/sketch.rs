@@ -0,0 +1,154 @@
+use bytes::{BufMut, BytesMut};
+use rocksdb::WriteBatch;
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+
+/// Key prefix for the HyperLogLog sketches of `set` keys, followed by the key ID, the value is
+/// the registers.
+pub static PREFIX_SKETCH: &[u8] = b"h";
+/// Precision of the sketches, a sketch has `2^SKETCH_PRECISION` one-byte registers.
+pub const SKETCH_PRECISION: u32 = 12;
+
+const SKETCH_REGISTERS: usize = 1 << SKETCH_PRECISION;
+
+/// Encode key of the sketch of a `set` key.
+pub fn encode_sketch_key(key_id: u64) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(PREFIX_SKETCH.len() + 8);
+    buf.put_slice(PREFIX_SKETCH);
+    buf.put_u64(key_id);
+    buf
+}
+
+impl Database {
+    /// Build a HyperLogLog sketch of a `set` key from its members, then `set_add()` keeps it up
+    /// to date, see `set_intersect_estimate()`. The sketch does not shrink on `set_delete()`,
+    /// call it again to rebuild the sketch after heavy deletes.
+    /// Returns `false` if the key does not exist.
+    pub fn set_register_sketch(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        let key = key.as_ref();
+        let meta = match self.get_meta(key)? {
+            None => return Ok(false),
+            Some(meta) => meta,
+        };
+        if meta.key_type != KeyType::Set {
+            return Err(Error::Message(format!(
+                "key {} is {:?}, expects Set",
+                String::from_utf8_lossy(key),
+                meta.key_type
+            )));
+        }
+        // the members added meanwhile wait for the sketch, then they are put into it
+        let _lock = self.sketch_lock.lock().unwrap();
+        let mut registers = vec![0u8; SKETCH_REGISTERS];
+        self.set_for_each(key, |member| {
+            sketch_insert(&mut registers, &member);
+            true
+        })?;
+        self.rocksdb.put(encode_sketch_key(meta.id), registers)?;
+        Ok(true)
+    }
+
+    /// Estimate the count of the members in both `a` and `b` from their sketches, it does not
+    /// iterate the members, so it's fast on huge sets. Fails if a set without a sketch is not
+    /// empty, see `set_register_sketch()`.
+    pub fn set_intersect_estimate(&self, a: impl AsRef<[u8]>, b: impl AsRef<[u8]>) -> Result<u64> {
+        let (count_a, sketch_a) = match self.set_sketch(a.as_ref())? {
+            None => return Ok(0),
+            Some(v) => v,
+        };
+        let (count_b, sketch_b) = match self.set_sketch(b.as_ref())? {
+            None => return Ok(0),
+            Some(v) => v,
+        };
+        let union: Vec<u8> = sketch_a
+            .iter()
+            .zip(sketch_b.iter())
+            .map(|(x, y)| *x.max(y))
+            .collect();
+        // inclusion–exclusion with the exact counts of the sets
+        let union_count = sketch_estimate(&union).max(count_a.max(count_b) as f64);
+        let estimate = (count_a + count_b) as f64 - union_count;
+        Ok((estimate.round().max(0.0) as u64).min(count_a.min(count_b)))
+    }
+
+    /// Add a new member into the sketch of a `set` key in the batch if it's registered, the
+    /// caller holds `sketch_lock` until the batch is written.
+    pub(crate) fn sketch_add_to_batch(
+        &self,
+        batch: &mut WriteBatch,
+        key_id: u64,
+        member: &[u8],
+    ) -> Result<()> {
+        let sketch_key = encode_sketch_key(key_id);
+        let mut registers = match self.rocksdb.get(&sketch_key)? {
+            Some(v) if v.len() == SKETCH_REGISTERS => v,
+            _ => return Ok(()),
+        };
+        if sketch_insert(&mut registers, member) {
+            batch.put(sketch_key, registers);
+        }
+        Ok(())
+    }
+
+    /// Delete the sketch of a key ID if it's registered.
+    pub(crate) fn delete_sketch_to_batch(&self, batch: &mut WriteBatch, meta: &KeyMeta) {
+        if meta.key_type == KeyType::Set {
+            batch.delete(encode_sketch_key(meta.id));
+        }
+    }
+
+    /// Get the count and the sketch of a `set` key, `None` if it's empty.
+    fn set_sketch(&self, key: &[u8]) -> Result<Option<(u64, Vec<u8>)>> {
+        let meta = match self.get_meta(key)? {
+            Some(meta) if meta.count > 0 => meta,
+            _ => return Ok(None),
+        };
+        match self.rocksdb.get(encode_sketch_key(meta.id))? {
+            Some(v) if v.len() == SKETCH_REGISTERS => Ok(Some((meta.count, v))),
+            _ => Err(Error::Message(format!(
+                "set {} has no sketch, register it with set_register_sketch()",
+                String::from_utf8_lossy(key)
+            ))),
+        }
+    }
+}
+
+/// Put a member into the registers, returns `true` if a register is changed.
+fn sketch_insert(registers: &mut [u8], member: &[u8]) -> bool {
+    let hash = sketch_hash(member);
+    let index = (hash >> (64 - SKETCH_PRECISION)) as usize;
+    let rank = ((hash << SKETCH_PRECISION) | (1 << (SKETCH_PRECISION - 1))).leading_zeros() + 1;
+    if registers[index] < rank as u8 {
+        registers[index] = rank as u8;
+        true
+    } else {
+        false
+    }
+}
+
+/// Estimate the cardinality of the registers, with the linear counting for small sets.
+fn sketch_estimate(registers: &[u8]) -> f64 {
+    let m = registers.len() as f64;
+    let alpha = 0.7213 / (1.0 + 1.079 / m);
+    let sum: f64 = registers.iter().map(|r| 2f64.powi(-(*r as i32))).sum();
+    let estimate = alpha * m * m / sum;
+    let zeros = registers.iter().filter(|r| **r == 0).count();
+    if estimate <= 2.5 * m && zeros > 0 {
+        m * (m / zeros as f64).ln()
+    } else {
+        estimate
+    }
+}
+
+/// FNV-1a with a splitmix64 finalizer, stable across versions because sketches are persisted.
+fn sketch_hash(member: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in member {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+    hash ^ (hash >> 31)
+}
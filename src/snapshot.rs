@@ -0,0 +1,189 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::{Buf, BufMut, BytesMut};
+use crc32fast::Hasher;
+use rocksdb::checkpoint::Checkpoint;
+
+use crate::database::{Database, Error, Result};
+
+/// Magic bytes at the beginning of a snapshot stream.
+pub static SNAPSHOT_MAGIC: &[u8] = b"SDBSNAP1";
+/// Max size of a single data chunk in a snapshot stream.
+pub const SNAPSHOT_CHUNK_SIZE: usize = 1 << 20;
+/// Max count of the files in a snapshot stream.
+pub const SNAPSHOT_MAX_FILES: u32 = 1 << 16;
+/// Max length of a file name in a snapshot stream.
+pub const SNAPSHOT_MAX_NAME_LEN: usize = 255;
+
+impl Database {
+    /// Write a consistent checkpoint of the whole database into `writer`.
+    ///
+    /// The stream contains a file list, followed by the content of every file split into chunks
+    /// and a CRC32 checksum for each file. Use `Database::receive_snapshot()` on the other side.
+    /// Returns the total bytes of the transferred files.
+    pub fn stream_snapshot(&self, mut writer: impl Write) -> Result<u64> {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let dir = format!("{}.snapshot-{}", self.path, nanos);
+        Checkpoint::new(&self.rocksdb)?.create_checkpoint(&dir)?;
+        let result = write_snapshot_dir(Path::new(&dir), &mut writer);
+        fs::remove_dir_all(&dir)?;
+        result
+    }
+
+    /// Receive a snapshot stream produced by `stream_snapshot()` into `path`, then open it.
+    /// The `path` must not exist or be an empty directory, the received files are removed if
+    /// the stream is invalid.
+    pub fn receive_snapshot(reader: impl Read, path: impl AsRef<Path>) -> Result<Database> {
+        let path = path.as_ref();
+        let existed = path.exists();
+        if existed && fs::read_dir(path)?.next().is_some() {
+            return Err(Error::Message(format!(
+                "snapshot target {} is not empty",
+                path.display()
+            )));
+        }
+        fs::create_dir_all(path)?;
+        if let Err(err) = receive_snapshot_files(reader, path) {
+            let _ = fs::remove_dir_all(path);
+            if existed {
+                let _ = fs::create_dir(path);
+            }
+            return Err(err);
+        }
+        Database::open(path)
+    }
+}
+
+/// Receive the files of a snapshot stream into the directory `path`.
+fn receive_snapshot_files(mut reader: impl Read, path: &Path) -> Result<()> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if magic != SNAPSHOT_MAGIC {
+        return Err(Error::Message("invalid snapshot stream".to_string()));
+    }
+    let files_count = read_u32(&mut reader)?;
+    if files_count > SNAPSHOT_MAX_FILES {
+        return Err(Error::Message(format!(
+            "invalid files count {} in snapshot stream",
+            files_count
+        )));
+    }
+    let mut files = Vec::with_capacity(files_count as usize);
+    for _ in 0..files_count {
+        let name_len = read_u32(&mut reader)? as usize;
+        if name_len > SNAPSHOT_MAX_NAME_LEN {
+            return Err(Error::Message(format!(
+                "invalid file name length {} in snapshot stream",
+                name_len
+            )));
+        }
+        let mut name = vec![0u8; name_len];
+        reader.read_exact(&mut name)?;
+        let name = String::from_utf8(name)?;
+        if name.is_empty() || name.contains('/') || name.contains('\\') || name == ".." {
+            return Err(Error::Message(format!(
+                "invalid file name in snapshot stream: {}",
+                name
+            )));
+        }
+        let size = read_u64(&mut reader)?;
+        files.push((name, size));
+    }
+
+    let mut buf = vec![0u8; SNAPSHOT_CHUNK_SIZE];
+    for (name, size) in files {
+        let mut file = File::create(path.join(&name))?;
+        let mut hasher = Hasher::new();
+        let mut received: u64 = 0;
+        loop {
+            let len = read_u32(&mut reader)? as usize;
+            if len == 0 {
+                break;
+            }
+            if len > SNAPSHOT_CHUNK_SIZE {
+                return Err(Error::Message(format!(
+                    "invalid chunk size {} for file {}",
+                    len, name
+                )));
+            }
+            reader.read_exact(&mut buf[..len])?;
+            hasher.update(&buf[..len]);
+            file.write_all(&buf[..len])?;
+            received += len as u64;
+        }
+        file.sync_all()?;
+        let checksum = read_u32(&mut reader)?;
+        if received != size || checksum != hasher.finalize() {
+            return Err(Error::Message(format!(
+                "checksum mismatch for file {} in snapshot stream",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn write_snapshot_dir(dir: &Path, writer: &mut impl Write) -> Result<u64> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push((
+                entry.file_name().to_string_lossy().to_string(),
+                entry.metadata()?.len(),
+            ));
+        }
+    }
+    files.sort();
+
+    let mut header = BytesMut::new();
+    header.put_slice(SNAPSHOT_MAGIC);
+    header.put_u32(files.len() as u32);
+    for (name, size) in files.iter() {
+        header.put_u32(name.len() as u32);
+        header.put_slice(name.as_bytes());
+        header.put_u64(*size);
+    }
+    writer.write_all(&header)?;
+
+    let mut total: u64 = 0;
+    let mut buf = vec![0u8; SNAPSHOT_CHUNK_SIZE];
+    for (name, _) in files.iter() {
+        let mut file = File::open(dir.join(name))?;
+        let mut hasher = Hasher::new();
+        loop {
+            let len = file.read(&mut buf)?;
+            if len == 0 {
+                break;
+            }
+            hasher.update(&buf[..len]);
+            writer.write_all(&(len as u32).to_be_bytes())?;
+            writer.write_all(&buf[..len])?;
+            total += len as u64;
+        }
+        writer.write_all(&0u32.to_be_bytes())?;
+        writer.write_all(&hasher.finalize().to_be_bytes())?;
+    }
+    writer.flush()?;
+    Ok(total)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut b = [0u8; 4];
+    reader.read_exact(&mut b)?;
+    Ok(b.as_ref().get_u32())
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut b = [0u8; 8];
+    reader.read_exact(&mut b)?;
+    Ok(b.as_ref().get_u64())
+}
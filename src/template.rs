@@ -0,0 +1,181 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rocksdb::WriteBatch;
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+
+/// Initial contents of a key declared in a `Template`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum TemplateKey {
+    Map(BTreeMap<Vec<u8>, Vec<u8>>),
+    List(Vec<Vec<u8>>),
+    Set(BTreeSet<Vec<u8>>),
+    /// `(score, value)` pairs.
+    SortedList(Vec<(Vec<u8>, Vec<u8>)>),
+}
+
+/// Keys created together by `Database::create_from_template()`, e.g. the keys provisioned
+/// for a new user or tenant. The names are appended to the key prefix.
+#[derive(Debug, Clone, Default)]
+pub struct Template {
+    pub keys: Vec<(String, TemplateKey)>,
+}
+
+impl Template {
+    pub fn new() -> Template {
+        Template::default()
+    }
+
+    /// Declare a map with the initial `(field, value)` pairs.
+    pub fn map<F, V>(mut self, name: &str, fields: impl IntoIterator<Item = (F, V)>) -> Template
+    where
+        F: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let fields = fields
+            .into_iter()
+            .map(|(f, v)| (f.as_ref().to_vec(), v.as_ref().to_vec()))
+            .collect();
+        self.keys.push((name.to_string(), TemplateKey::Map(fields)));
+        self
+    }
+
+    /// Declare a list with the initial values, from left to right.
+    pub fn list<V: AsRef<[u8]>>(
+        mut self,
+        name: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> Template {
+        let values = values.into_iter().map(|v| v.as_ref().to_vec()).collect();
+        self.keys
+            .push((name.to_string(), TemplateKey::List(values)));
+        self
+    }
+
+    /// Declare a set with the initial members.
+    pub fn set<V: AsRef<[u8]>>(
+        mut self,
+        name: &str,
+        members: impl IntoIterator<Item = V>,
+    ) -> Template {
+        let members = members.into_iter().map(|v| v.as_ref().to_vec()).collect();
+        self.keys
+            .push((name.to_string(), TemplateKey::Set(members)));
+        self
+    }
+
+    /// Declare a sorted list with the initial `(score, value)` pairs.
+    pub fn sorted_list<S, V>(
+        mut self,
+        name: &str,
+        items: impl IntoIterator<Item = (S, V)>,
+    ) -> Template
+    where
+        S: AsRef<[u8]>,
+        V: AsRef<[u8]>,
+    {
+        let items = items
+            .into_iter()
+            .map(|(s, v)| (s.as_ref().to_vec(), v.as_ref().to_vec()))
+            .collect();
+        self.keys
+            .push((name.to_string(), TemplateKey::SortedList(items)));
+        self
+    }
+}
+
+impl Database {
+    /// Create all the keys of `template` named `{key_prefix}{name}` with their initial contents
+    /// in a single write batch, so they exist all together or not at all. Fails without writing
+    /// anything if any of the keys already exists. Returns the count of the created keys.
+    pub fn create_from_template(&self, key_prefix: &str, template: &Template) -> Result<u64> {
+        let mut keys = Vec::with_capacity(template.keys.len());
+        for (name, contents) in template.keys.iter() {
+            let key = format!("{}{}", key_prefix, name);
+            if keys.iter().any(|(k, _)| *k == key) {
+                return Err(Error::Message(format!(
+                    "key {} is declared twice in the template",
+                    key
+                )));
+            }
+            keys.push((key, contents));
+        }
+        let mut batch = WriteBatch::default();
+        // the keys may be created by another thread between the checks and the write
+        let _guard = self.create_meta_lock.lock().unwrap();
+        for (key, contents) in keys.iter() {
+            let key = self.resolve_key(key.as_bytes()).into_owned();
+            if self.rocksdb.get_pinned(encode_meta_key(&key))?.is_some() {
+                return Err(Error::Message(format!(
+                    "key {} already exists",
+                    String::from_utf8_lossy(&key)
+                )));
+            }
+            let key_type = match contents {
+                TemplateKey::Map(_) => KeyType::Map,
+                TemplateKey::List(_) => KeyType::List,
+                TemplateKey::Set(_) => KeyType::Set,
+                TemplateKey::SortedList(_) => KeyType::SortedList,
+            };
+            self.check_schema_key_type(&key, key_type)?;
+            let mut meta = KeyMeta::new(self.allocate_key_id()?, key_type);
+            match contents {
+                TemplateKey::Map(fields) => {
+                    for (field, value) in fields.iter() {
+                        self.check_template_item(&key, &meta, value)?;
+                        self.map_item_put_to_batch(
+                            &mut batch,
+                            meta.id,
+                            field.as_ref(),
+                            value,
+                            None,
+                        )?;
+                        meta.count += 1;
+                    }
+                }
+                TemplateKey::List(values) => {
+                    let (left, right) = meta.decode_list_extra();
+                    for value in values.iter() {
+                        let full_key =
+                            encode_data_key_list_item(meta.id, right + meta.count as i64);
+                        self.check_template_item(&key, &meta, value)?;
+                        batch.put(full_key, value);
+                        meta.count += 1;
+                    }
+                    meta.encode_list_extra(left, right + meta.count as i64);
+                }
+                TemplateKey::Set(members) => {
+                    for member in members.iter() {
+                        let full_key = encode_data_key_set_item(meta.id, member);
+                        self.check_template_item(&key, &meta, member)?;
+                        batch.put(full_key, FILL_EMPTY_DATA);
+                        meta.count += 1;
+                    }
+                }
+                TemplateKey::SortedList(items) => {
+                    for (score, value) in items.iter() {
+                        let full_key = encode_data_key_sorted_list_item(meta.id, score, meta.count);
+                        self.check_template_item(&key, &meta, value)?;
+                        batch.put(full_key, value);
+                        meta.count += 1;
+                    }
+                    meta.encode_sorted_list_extra(meta.count, 0, 0);
+                }
+            }
+            batch.put(encode_type_index_key(key_type, &key), FILL_EMPTY_DATA);
+            self.save_meta_to_batch(&mut batch, &key, &meta, false)?;
+        }
+        self.rocksdb.write(batch)?;
+        for (key, _) in keys.iter() {
+            self.forget_absent_key(&self.resolve_key(key.as_bytes()));
+        }
+        Ok(keys.len() as u64)
+    }
+
+    /// Check an item of a key of a template as it's added by the write methods.
+    fn check_template_item(&self, key: &[u8], meta: &KeyMeta, value: &[u8]) -> Result<()> {
+        self.check_schema_value(key, value)?;
+        self.check_max_count(key, meta)
+    }
+}
@@ -0,0 +1,40 @@
+use crate::codec::{FieldVal, KeyType};
+use crate::database::{Database, Error, Result};
+
+/// Remote tier behind the local database, set by `Options::tier`, so the database serves as the
+/// local tier of a two-level cache of `map` keys.
+pub trait Tier: Send + Sync {
+    /// Called when `map_get()` misses a field, the fetched value is written into the local map.
+    fn fetch_missing(&self, key: &str, field: &[u8]) -> Result<Option<Vec<u8>>>;
+
+    /// Called by `Database::evict()` with all the fields of a map before it's deleted locally.
+    fn evict(&self, key: &str, items: &[FieldVal]) -> Result<()>;
+}
+
+impl Database {
+    /// Hand a `map` key to `Options::tier` and delete it locally, call it when the application's
+    /// eviction policy fires. Without a tier the key is just deleted.
+    /// Returns `false` if the key does not exist.
+    pub fn evict(&self, key: &str) -> Result<bool> {
+        let meta = match self.get_meta(key)? {
+            None => return Ok(false),
+            Some(meta) => meta,
+        };
+        if meta.key_type != KeyType::Map {
+            return Err(Error::Message(format!(
+                "can not evict key {} of type {:?}, expects Map",
+                key, meta.key_type
+            )));
+        }
+        if let Some(tier) = self.options.tier.as_ref() {
+            let mut items = Vec::with_capacity(meta.count as usize);
+            self.map_for_each(key, |field, value| {
+                items.push((Box::from(field), value));
+                true
+            })?;
+            tier.evict(key, &items)?;
+        }
+        self.delete_all(key)?;
+        Ok(true)
+    }
+}
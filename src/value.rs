@@ -0,0 +1,344 @@
+use std::io::{self, Read, Write};
+
+use bytes::BytesMut;
+use rocksdb::{
+    DBIterator, DBPinnableSlice, Direction, IteratorMode, ReadOptions, Snapshot, WriteBatch,
+};
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+use crate::journal::encode_journal_key;
+
+/// Max chunks of a single write batch in `BlobWriter`.
+const VALUE_BATCH_CHUNKS: usize = 16;
+
+impl Database {
+    /// Put a value, it's split into chunks of `Options::value_chunk_size` bytes stored in
+    /// multiple rows. The chunks are written under a new key ID together with the switch of
+    /// the meta and the deletes of the old chunks in a single batch.
+    pub fn value_put(&self, key: &str, data: &[u8]) -> Result<()> {
+        self.check_schema_value(key.as_bytes(), data)?;
+        let mut writer = self.blob_writer(key)?;
+        writer.batch_chunks = usize::MAX;
+        writer.write_chunks(data)?;
+        writer.finish()
+    }
+
+    /// Get a writer to stream a value into the database, the value is replaced when
+    /// `BlobWriter::finish()` is called. Dropping the writer without finishing discards the data.
+    ///
+    /// The chunks are written in batches before the meta, so the new key ID is journaled
+    /// first, and the chunks of a writer interrupted by a crash are deleted when the database
+    /// is opened next time, see `recover_journal()`.
+    pub fn blob_writer(&self, key: &str) -> Result<BlobWriter<'_>> {
+        self.check_writable(key)?;
+        self.check_schema_key_type(key.as_bytes(), KeyType::Value)?;
+        let old = self.get_value_meta(key)?;
+        let meta = KeyMeta::new(self.allocate_key_id()?, KeyType::Value);
+        // kept by `gc()` until the writer is dropped
+        self.writing_key_ids.lock().unwrap().insert(meta.id);
+        Ok(BlobWriter {
+            db: self,
+            key: key.to_string(),
+            old,
+            meta,
+            chunk_size: self.options.value_chunk_size.max(1) as usize,
+            batch_chunks: VALUE_BATCH_CHUNKS,
+            journaled: false,
+            buf: Vec::new(),
+            batch: WriteBatch::default(),
+            total_len: 0,
+            finished: false,
+        })
+    }
+
+    /// Get a reader to stream a value out of the database chunk by chunk.
+    ///
+    /// The meta and the chunks are read from one snapshot, so the reader keeps streaming the
+    /// value it started with even if the value is replaced meanwhile.
+    pub fn blob_reader(&self, key: &str) -> Result<Option<BlobReader<'_>>> {
+        let snapshot = self.rocksdb.snapshot();
+        let meta = match self.get_value_meta_at(&snapshot, key)? {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+        let prefix = encode_data_key(meta.id);
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_upper_bound(encode_data_key(meta.id + 1).to_vec());
+        let iter = snapshot.iterator_opt(IteratorMode::From(&prefix, Direction::Forward), opts);
+        Ok(Some(BlobReader {
+            iter,
+            _snapshot: snapshot,
+            chunk: Box::default(),
+            pos: 0,
+        }))
+    }
+
+    /// Get a value, the chunks are reassembled.
+    pub fn value_get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        // the old chunks are deleted when the value is replaced, read them with the meta
+        // from one snapshot
+        let snapshot = self.rocksdb.snapshot();
+        let meta = match self.get_value_meta_at(&snapshot, key)? {
+            Some(m) => m,
+            None => return Ok(None),
+        };
+        let (total_len, _) = meta.decode_value_extra();
+        let mut data = Vec::with_capacity(total_len as usize);
+        self.value_for_each_chunk(&snapshot, &meta, |chunk| {
+            data.extend_from_slice(chunk);
+            true
+        });
+        if data.len() as u64 != total_len {
+            return Err(Error::Message(format!(
+                "value of key {} is broken, expects {} bytes but got {}",
+                key,
+                total_len,
+                data.len()
+            )));
+        }
+        Ok(Some(data))
+    }
+
+    /// Same as `value_get()`, but the chunks are pinned in RocksDB instead of copied out and
+    /// reassembled, they are returned in order.
+    pub fn value_get_pinned(&self, key: &str) -> Result<Option<Vec<DBPinnableSlice<'_>>>> {
+        // rows can't be pinned from a snapshot, the chunks are read again if the value is
+        // replaced and its old chunks are deleted meanwhile
+        loop {
+            let meta = match self.get_value_meta(key)? {
+                Some(m) => m,
+                None => return Ok(None),
+            };
+            let (total_len, _) = meta.decode_value_extra();
+            let mut chunks = Vec::with_capacity(meta.count as usize);
+            let mut len = 0;
+            for index in 0..meta.count as u32 {
+                match self
+                    .rocksdb
+                    .get_pinned(encode_data_key_value_chunk(meta.id, index))?
+                {
+                    Some(chunk) => {
+                        len += chunk.len() as u64;
+                        chunks.push(chunk);
+                    }
+                    None => break,
+                }
+            }
+            if len == total_len {
+                return Ok(Some(chunks));
+            }
+            if self.get_value_meta(key)?.map(|m| m.id) == Some(meta.id) {
+                return Err(Error::Message(format!(
+                    "value of key {} is broken, expects {} bytes but got {}",
+                    key, total_len, len
+                )));
+            }
+        }
+    }
+
+    /// Get the length of a value in bytes.
+    pub fn value_len(&self, key: &str) -> Result<Option<u64>> {
+        Ok(self.get_value_meta(key)?.map(|m| m.decode_value_extra().0))
+    }
+
+    pub(crate) fn get_value_meta(&self, key: &str) -> Result<Option<KeyMeta>> {
+        match self.get_meta(key)? {
+            Some(m) if m.key_type != KeyType::Value => Err(Error::Message(format!(
+                "key {} is a {:?}, not a value",
+                key, m.key_type
+            ))),
+            m => Ok(m),
+        }
+    }
+
+    /// Same as `get_value_meta()`, but the meta is read from `snapshot`.
+    fn get_value_meta_at(&self, snapshot: &Snapshot<'_>, key: &str) -> Result<Option<KeyMeta>> {
+        let resolved = self.resolve_key(key.as_bytes());
+        self.check_frozen(&resolved, false)?;
+        match snapshot
+            .get(encode_meta_key(resolved.as_ref()))?
+            .map(|v| KeyMeta::from_bytes(&v))
+        {
+            Some(m) if m.key_type != KeyType::Value => Err(Error::Message(format!(
+                "key {} is a {:?}, not a value",
+                key, m.key_type
+            ))),
+            m => Ok(m),
+        }
+    }
+
+    pub(crate) fn value_for_each_chunk<F>(&self, snapshot: &Snapshot<'_>, meta: &KeyMeta, mut f: F)
+    where
+        F: FnMut(&[u8]) -> bool,
+    {
+        let prefix = encode_data_key(meta.id);
+        let mut opts = ReadOptions::default();
+        opts.set_iterate_upper_bound(encode_data_key(meta.id + 1).to_vec());
+        let iter = snapshot.iterator_opt(IteratorMode::From(&prefix, Direction::Forward), opts);
+        for (_, v) in iter {
+            if !f(&v) {
+                break;
+            }
+        }
+    }
+}
+
+/// Writer of a `value`, see `Database::blob_writer()`.
+pub struct BlobWriter<'a> {
+    db: &'a Database,
+    key: String,
+    old: Option<KeyMeta>,
+    meta: KeyMeta,
+    chunk_size: usize,
+    /// Max chunks of a write batch, the chunks of `value_put()` are written in one batch.
+    batch_chunks: usize,
+    /// The journal entry of the key ID is written, see `journal_key()`.
+    journaled: bool,
+    buf: Vec<u8>,
+    batch: WriteBatch,
+    total_len: u64,
+    finished: bool,
+}
+
+impl<'a> BlobWriter<'a> {
+    /// Write the buffered data, switch the meta to the new value and delete the old chunks.
+    pub fn finish(mut self) -> Result<()> {
+        if !self.buf.is_empty() {
+            let chunk = std::mem::take(&mut self.buf);
+            self.put_chunk(&chunk)?;
+        }
+        self.meta
+            .encode_value_extra(self.total_len, self.chunk_size as u32);
+        let mut batch = std::mem::take(&mut self.batch);
+        match &self.old {
+            Some(old) => {
+                self.db.delete_range_to_batch(
+                    &mut batch,
+                    encode_data_key(old.id),
+                    encode_data_key(old.id + 1),
+                );
+            }
+            None => {
+                batch.put(
+                    encode_type_index_key(KeyType::Value, &self.key),
+                    FILL_EMPTY_DATA,
+                );
+            }
+        }
+        self.db
+            .save_meta_to_batch(&mut batch, &self.key, &self.meta, false)?;
+        if self.journaled {
+            batch.delete(self.journal_key());
+        }
+        self.db.rocksdb.write(batch)?;
+        self.db
+            .forget_absent_key(&self.db.resolve_key(self.key.as_bytes()));
+        self.finished = true;
+        Ok(())
+    }
+
+    /// Split the data into chunks, keep the rest part in the buffer.
+    fn write_chunks(&mut self, mut data: &[u8]) -> Result<()> {
+        if !self.buf.is_empty() {
+            let n = (self.chunk_size - self.buf.len()).min(data.len());
+            self.buf.extend_from_slice(&data[..n]);
+            data = &data[n..];
+            if self.buf.len() < self.chunk_size {
+                return Ok(());
+            }
+            let chunk = std::mem::take(&mut self.buf);
+            self.put_chunk(&chunk)?;
+        }
+        while data.len() >= self.chunk_size {
+            self.put_chunk(&data[..self.chunk_size])?;
+            data = &data[self.chunk_size..];
+        }
+        self.buf.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn put_chunk(&mut self, chunk: &[u8]) -> Result<()> {
+        let index = self.meta.count as u32;
+        self.batch
+            .put(encode_data_key_value_chunk(self.meta.id, index), chunk);
+        self.meta.count += 1;
+        self.total_len += chunk.len() as u64;
+        if self.batch.len() >= self.batch_chunks {
+            if !self.journaled {
+                self.db
+                    .rocksdb
+                    .put(self.journal_key(), self.meta.id.to_be_bytes())?;
+                self.journaled = true;
+            }
+            self.db.rocksdb.write(std::mem::take(&mut self.batch))?;
+        }
+        Ok(())
+    }
+
+    /// Key of the journal entry of the new key ID, the key name followed by the ID, so the
+    /// concurrent writers of a key have their own entries.
+    fn journal_key(&self) -> BytesMut {
+        encode_journal_key([self.key.as_bytes(), &self.meta.id.to_be_bytes()].concat())
+    }
+}
+
+impl<'a> Write for BlobWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_chunks(buf).map_err(io::Error::other)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> Drop for BlobWriter<'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            let mut batch = WriteBatch::default();
+            self.db.delete_range_to_batch(
+                &mut batch,
+                encode_data_key(self.meta.id),
+                encode_data_key(self.meta.id + 1),
+            );
+            if self.journaled {
+                batch.delete(self.journal_key());
+            }
+            let _ = self.db.rocksdb.write(batch);
+        }
+        self.db
+            .writing_key_ids
+            .lock()
+            .unwrap()
+            .remove(&self.meta.id);
+    }
+}
+
+/// Reader of a `value`, see `Database::blob_reader()`.
+pub struct BlobReader<'a> {
+    // dropped before the snapshot it reads
+    iter: DBIterator<'a>,
+    _snapshot: Snapshot<'a>,
+    chunk: Box<[u8]>,
+    pos: usize,
+}
+
+impl<'a> Read for BlobReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos >= self.chunk.len() {
+            match self.iter.next() {
+                Some((_, v)) => {
+                    self.chunk = v;
+                    self.pos = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use bytes::{Buf, BufMut, BytesMut};
+use rocksdb::WriteBatch;
+
+use crate::codec::*;
+use crate::database::{Database, Error, Result};
+
+/// Key prefix for quarantined meta rows, followed by the original meta key.
+pub static PREFIX_QUARANTINE: &[u8] = b"q";
+
+/// A meta row failed the integrity check, or a data row met by `export_range()` or
+/// `dump_key()` which doesn't match the layout of its key type.
+#[derive(Debug, Clone)]
+pub struct BadMeta {
+    /// The raw key of the row, includes the prefix.
+    pub meta_key: Box<[u8]>,
+    pub reason: String,
+}
+
+/// What the scans of keys do with a bad row, see `Options::bad_row_policy`. A meta row is bad
+/// if it fails the checks of `Database::verify_metas()`, a data row is bad if it's too short
+/// for the layout of its key type, e.g. a sorted set member without a full score.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum BadRowPolicy {
+    /// Stop the scan with an error.
+    #[default]
+    Error,
+    /// Skip the row.
+    Skip,
+    /// Move the row under `PREFIX_QUARANTINE` and skip it.
+    Quarantine,
+}
+
+/// Callback of the bad rows skipped by the scans of keys.
+pub type BadRowCallback = Arc<dyn Fn(&BadMeta) + Send + Sync>;
+
+impl Database {
+    /// Register a callback invoked for every bad row skipped or quarantined by the scans of
+    /// keys, see `Options::bad_row_policy`.
+    pub fn on_bad_row<F>(&self, f: F)
+    where
+        F: Fn(&BadMeta) + Send + Sync + 'static,
+    {
+        self.bad_row_callbacks.write().unwrap().push(Arc::new(f));
+    }
+
+    /// Check a meta row met by a scan of keys by `Options::bad_row_policy`, returns `false` if
+    /// the row is bad and skipped. The names which are not valid UTF-8 are bad only if
+    /// `utf8_name` is true.
+    pub(crate) fn check_scanned_meta(
+        &self,
+        key: &[u8],
+        value: &[u8],
+        utf8_name: bool,
+    ) -> Result<bool> {
+        let reason = match check_meta_value(value) {
+            Some(reason) => reason,
+            None if utf8_name => match check_meta_name(key) {
+                Some(reason) => reason,
+                None => return Ok(true),
+            },
+            None => return Ok(true),
+        };
+        self.apply_bad_row_policy(key, value, reason)?;
+        Ok(false)
+    }
+
+    /// Check a data or a reserved row of `meta` met by a scan by `Options::bad_row_policy`,
+    /// returns `false` if the row is bad and skipped. The count of the key is not changed.
+    pub(crate) fn check_scanned_row(
+        &self,
+        meta: &KeyMeta,
+        key: &[u8],
+        value: &[u8],
+    ) -> Result<bool> {
+        match check_data_row(meta, key, value) {
+            Some(reason) => {
+                self.apply_bad_row_policy(key, value, reason)?;
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    fn apply_bad_row_policy(&self, key: &[u8], value: &[u8], reason: String) -> Result<()> {
+        let is_meta = has_prefix(PREFIX_META, key);
+        let bad = BadMeta {
+            meta_key: key.into(),
+            reason,
+        };
+        match self.options.bad_row_policy {
+            BadRowPolicy::Error => {
+                return Err(Error::Message(format!(
+                    "bad {} row {}: {}",
+                    if is_meta { "meta" } else { "data" },
+                    encode_hex(key),
+                    bad.reason
+                )))
+            }
+            BadRowPolicy::Skip => {}
+            BadRowPolicy::Quarantine => {
+                let mut batch = WriteBatch::default();
+                quarantine_to_batch(&mut batch, key, value);
+                let name = if is_meta {
+                    if value.len() > 8 {
+                        if let Some(key_type) = KeyType::from_u8(value[8]) {
+                            batch
+                                .delete(encode_type_index_key(key_type, &key[PREFIX_META.len()..]));
+                        }
+                    }
+                    &key[PREFIX_META.len()..]
+                } else {
+                    &[]
+                };
+                self.rocksdb.write(batch)?;
+                self.log_admin("quarantine", name, 1)?;
+            }
+        }
+        // the callbacks may register more callbacks, so they're not called under the lock
+        let callbacks = self.bad_row_callbacks.read().unwrap().clone();
+        for f in callbacks.iter() {
+            f(&bad);
+        }
+        Ok(())
+    }
+
+    /// Check that every meta decodes, its key type is known and its count is not negative.
+    /// The bad rows are moved under `PREFIX_QUARANTINE` if `quarantine` is true, so they no
+    /// longer break the scans of keys.
+    pub fn verify_metas(&self, quarantine: bool) -> Result<Vec<BadMeta>> {
+        let mut bad = Vec::new();
+        let iter = self
+            .rocksdb
+            .prefix_iterator(PREFIX_META)
+            .take_while(|(k, _)| has_prefix(PREFIX_META, k.as_ref()));
+        let mut batch = WriteBatch::default();
+        for (k, v) in iter {
+            if let Some(reason) = check_meta(k.as_ref(), v.as_ref()) {
+                if quarantine {
+                    quarantine_to_batch(&mut batch, k.as_ref(), v.as_ref());
+                }
+                bad.push(BadMeta {
+                    meta_key: k,
+                    reason,
+                });
+            }
+        }
+        if !batch.is_empty() {
+            self.rocksdb.write(batch)?;
+            self.log_admin("verify_metas", "", bad.len() as u64)?;
+        }
+        Ok(bad)
+    }
+}
+
+/// Move a meta or a data row under `PREFIX_QUARANTINE`.
+fn quarantine_to_batch(batch: &mut WriteBatch, key: &[u8], value: &[u8]) {
+    let mut q = BytesMut::with_capacity(PREFIX_QUARANTINE.len() + key.len());
+    q.put_slice(PREFIX_QUARANTINE);
+    q.put_slice(key);
+    batch.put(q, value);
+    batch.delete(key);
+}
+
+/// Returns the reason if the meta row is bad.
+fn check_meta(key: &[u8], value: &[u8]) -> Option<String> {
+    check_meta_name(key).or_else(|| check_meta_value(value))
+}
+
+fn check_meta_name(key: &[u8]) -> Option<String> {
+    if std::str::from_utf8(&key[PREFIX_META.len()..]).is_err() {
+        return Some("key is not valid UTF-8".to_string());
+    }
+    None
+}
+
+pub(crate) fn check_meta_value(value: &[u8]) -> Option<String> {
+    if value.len() < 17 {
+        return Some(format!(
+            "meta is {} bytes, expects at least 17",
+            value.len()
+        ));
+    }
+    let mut buf = value;
+    buf.advance(8);
+    let key_type = match KeyType::from_u8(buf.get_u8()) {
+        Some(t) => t,
+        None => return Some(format!("unknown key type {}", value[8])),
+    };
+    if buf.get_u64() > i64::MAX as u64 {
+        return Some("count is negative".to_string());
+    }
+    let extra_len = match key_type {
+        KeyType::List | KeyType::SortedList | KeyType::Log => 16,
+        KeyType::SortedSet => 5,
+        KeyType::Value => 12,
+        KeyType::Map | KeyType::Set | KeyType::Custom(_) => 0,
+    };
+    if !buf.is_empty() && buf.len() < extra_len {
+        return Some(format!(
+            "extra data of {:?} is {} bytes, expects {}",
+            key_type,
+            buf.len(),
+            extra_len
+        ));
+    }
+    None
+}
+
+/// Returns the reason if a data or a reserved row of `meta` is too short to be decoded by the
+/// operations of its key type.
+fn check_data_row(meta: &KeyMeta, key: &[u8], value: &[u8]) -> Option<String> {
+    if has_prefix(PREFIX_RESERVED, key) {
+        if value.len() < 12 || value.len() - 12 < (&value[8..]).get_u32() as usize {
+            return Some(format!(
+                "reserved value is truncated, {} bytes",
+                value.len()
+            ));
+        }
+        return None;
+    }
+    let suffix = &key[key.len().min(9)..];
+    let valid = match meta.key_type {
+        KeyType::List => suffix.len() == 9 && matches!(suffix[0], b'>' | b'<'),
+        KeyType::SortedList => suffix.len() >= 8,
+        KeyType::SortedSet => {
+            let score_len = meta.decode_sorted_set_extra().1 as usize;
+            match suffix.first() {
+                Some(1) => suffix.len() > score_len,
+                Some(0) => value.len() == score_len,
+                _ => false,
+            }
+        }
+        KeyType::Value => suffix.len() == 4,
+        KeyType::Log => suffix.len() == 8,
+        KeyType::Map | KeyType::Set | KeyType::Custom(_) => true,
+    };
+    if valid {
+        None
+    } else {
+        Some(format!(
+            "data row of {:?} has a {} bytes suffix and a {} bytes value",
+            meta.key_type,
+            suffix.len(),
+            value.len()
+        ))
+    }
+}
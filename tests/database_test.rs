@@ -1,5 +1,24 @@
+use std::{
+    io::{Read, Write},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
 use common::*;
-use simpledb::codec::{get_score_bytes, get_score_from_bytes, KeyType, VecScoreVal};
+use simpledb::{
+    codec::{
+        encode_data_key, get_score_bytes, get_score_from_bytes, FieldVal, KeyType, VecScoreVal,
+    },
+    sessions::Sessions,
+    Aggregate, BadRowPolicy, CompactOnOpen, CompactionEvent, CompactionKind, CompactionListener,
+    CustomKeyType, Database, DatabaseGroup, DatabaseHandle, DatabaseSink, Error, GcReport,
+    ImportQuota, Item, KeyConfig, KeyDiff, Manifest, MetaExtra, MetricsRecorder, NamespaceStats,
+    NumberFormat, OnConflict, Options, Preset, RawRow, ReadGuard, ReplicationEvent,
+    ReplicationSink, SaveMetaPolicy, Schema, ShardedDatabase, SortedListRetention, Template, Tier,
+    ValueCodec, WriteOp, WritePipeline, WriteStall, GC_TASK_NAME, KEY_MANIFEST,
+    KEY_NAMESPACE_STATS_READY, MARKER_FILE, META_FORMAT_VERSION, PREFIX_CONSUMER,
+    PREFIX_DROPPED_KEY_ID, PREFIX_SKETCH, PREFIX_VALUE_CHUNK, SORTED_SET_SWEEPER_TASK_NAME,
+};
 
 pub mod common;
 
@@ -87,6 +106,7 @@ fn test_for_each_key() {
 }
 
 #[test]
+#[allow(clippy::get_first)]
 fn test_map() {
     let path = get_random_database_path();
     {
@@ -159,6 +179,7 @@ fn test_map() {
 }
 
 #[test]
+#[allow(clippy::get_first)]
 fn test_set() {
     let path = get_random_database_path();
     {
@@ -536,3 +557,4466 @@ fn test_delete_all() {
     assert_eq!(0, db.get_count(key1).unwrap());
     assert_eq!(0, db.get_count(key2).unwrap());
 }
+
+#[test]
+fn test_open_with_preset() {
+    for preset in [
+        Preset::ReadHeavy,
+        Preset::WriteHeavy,
+        Preset::BulkLoad,
+        Preset::Archive,
+    ] {
+        let path = get_random_database_path();
+        let mut options = Options::with_preset(preset).unwrap();
+        options.rate_limit_bytes_per_sec = Some(64 << 20);
+        let db = Database::open_with_options(&path, options).unwrap();
+        db.map_put("hello", "aaa", "123").unwrap();
+        assert_eq!(
+            "123",
+            vec_to_str(db.map_get("hello", "aaa").unwrap().unwrap())
+        );
+    }
+}
+
+#[test]
+fn test_memory_usage() {
+    let db = open_database();
+    for i in 0..100 {
+        db.map_put("hello", format!("key_{}", i), "123").unwrap();
+    }
+    let usage = db.memory_usage().unwrap();
+    assert!(usage.mem_table_total > 0);
+    assert!(usage.mem_table_unflushed > 0);
+}
+
+#[test]
+fn test_stream_snapshot() {
+    let db = open_database();
+    for i in 0..100 {
+        db.map_put("hello", format!("key_{}", i), format!("value_{}", i))
+            .unwrap();
+    }
+    db.list_right_push("world", "123".as_bytes()).unwrap();
+
+    let mut stream = Vec::new();
+    assert!(db.stream_snapshot(&mut stream).unwrap() > 0);
+
+    let path = get_random_database_path();
+    let db2 = Database::receive_snapshot(stream.as_slice(), &path).unwrap();
+    assert_eq!(100, db2.map_count("hello").unwrap());
+    assert_eq!(
+        "value_10",
+        vec_to_str(db2.map_get("hello", "key_10").unwrap().unwrap())
+    );
+    assert_eq!(1, db2.list_count("world").unwrap());
+    assert!(Database::receive_snapshot(stream.as_slice(), &path).is_err());
+
+    let last = stream.len() - 1;
+    stream[last] ^= 0xff;
+    let path = get_random_database_path();
+    assert!(Database::receive_snapshot(stream.as_slice(), &path).is_err());
+    assert!(!std::path::Path::new(&path).exists());
+
+    // the counts and the lengths in the header are capped, the empty target is kept
+    let path = get_random_database_path();
+    std::fs::create_dir_all(&path).unwrap();
+    let mut header = b"SDBSNAP1".to_vec();
+    header.extend_from_slice(&u32::MAX.to_be_bytes());
+    assert!(Database::receive_snapshot(header.as_slice(), &path).is_err());
+    let mut header = b"SDBSNAP1".to_vec();
+    header.extend_from_slice(&1u32.to_be_bytes());
+    header.extend_from_slice(&u32::MAX.to_be_bytes());
+    assert!(Database::receive_snapshot(header.as_slice(), &path).is_err());
+    assert_eq!(std::fs::read_dir(&path).unwrap().count(), 0);
+}
+
+#[test]
+fn test_sorted_set_right_followed_by_key() {
+    let db = open_database();
+    // the rows of the next key ID sort before its sorted set items, so the reverse scan must
+    // not start from them
+    db.sorted_set_add("first", &get_score_bytes(1u32), b"a")
+        .unwrap();
+    db.sorted_set_add("first", &get_score_bytes(2u32), b"b")
+        .unwrap();
+    db.sorted_set_add("second", &get_score_bytes(3u32), b"c")
+        .unwrap();
+    let items: Vec<(u32, Vec<u8>)> = db
+        .sorted_set_right("first", None, 10)
+        .unwrap()
+        .into_iter()
+        .map(|(score, value)| (get_score_from_bytes(&score), value.to_vec()))
+        .collect();
+    assert_eq!(items, vec![(2, b"b".to_vec()), (1, b"a".to_vec())]);
+}
+
+#[test]
+fn test_sorted_set_delete_range_by_score() {
+    let db = open_database();
+    let key = "hello";
+    for i in 1..=10 {
+        db.sorted_set_add(
+            key,
+            get_score_bytes(i * 10).as_slice(),
+            format!("m{}", i).as_bytes(),
+        )
+        .unwrap();
+    }
+    assert_eq!(
+        0,
+        db.sorted_set_delete_range_by_score(
+            key,
+            get_score_bytes(101).as_slice(),
+            get_score_bytes(200).as_slice()
+        )
+        .unwrap()
+    );
+    assert_eq!(
+        4,
+        db.sorted_set_delete_range_by_score(
+            key,
+            get_score_bytes(25).as_slice(),
+            get_score_bytes(60).as_slice()
+        )
+        .unwrap()
+    );
+    assert_eq!(6, db.sorted_set_count(key).unwrap());
+    assert!(!db.sorted_set_is_member(key, "m3".as_bytes()).unwrap());
+    assert!(db.sorted_set_is_member(key, "m2".as_bytes()).unwrap());
+    let scores: Vec<i32> = db
+        .sorted_set_items(key)
+        .unwrap()
+        .iter()
+        .map(|(s, _)| get_score_from_bytes(s))
+        .collect();
+    assert_eq!(vec![10, 20, 70, 80, 90, 100], scores);
+
+    assert_eq!(
+        6,
+        db.sorted_set_delete_range_by_score(
+            key,
+            get_score_bytes(0).as_slice(),
+            get_score_bytes(100).as_slice()
+        )
+        .unwrap()
+    );
+    assert_eq!(0, db.sorted_set_count(key).unwrap());
+    assert!(db.get_meta(key).unwrap().is_none());
+}
+
+#[test]
+fn test_sorted_set_delete_range_by_rank() {
+    let db = open_database();
+    let key = "hello";
+    for i in 1..=10 {
+        db.sorted_set_add(
+            key,
+            get_score_bytes(i * 10).as_slice(),
+            format!("m{}", i).as_bytes(),
+        )
+        .unwrap();
+    }
+    assert_eq!(0, db.sorted_set_delete_range_by_rank(key, 5, 2).unwrap());
+    assert_eq!(2, db.sorted_set_delete_range_by_rank(key, 0, 1).unwrap());
+    assert_eq!(8, db.sorted_set_count(key).unwrap());
+    // keep only the top 3 members
+    assert_eq!(5, db.sorted_set_delete_range_by_rank(key, 0, -4).unwrap());
+    let scores: Vec<i32> = db
+        .sorted_set_items(key)
+        .unwrap()
+        .iter()
+        .map(|(s, _)| get_score_from_bytes(s))
+        .collect();
+    assert_eq!(vec![80, 90, 100], scores);
+    assert!(!db.sorted_set_is_member(key, "m7".as_bytes()).unwrap());
+    assert_eq!(1, db.sorted_set_delete_range_by_rank(key, -1, 100).unwrap());
+    assert_eq!(2, db.sorted_set_count(key).unwrap());
+
+    // the expired members are not ranked
+    db.sorted_set_add_ex(key, &get_score_bytes(1), b"old", Duration::ZERO)
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(2));
+    assert_eq!(1, db.sorted_set_delete_range_by_rank(key, 0, 0).unwrap());
+    assert_eq!(1, db.sorted_set_count(key).unwrap());
+    assert!(db.sorted_set_is_member(key, "m9".as_bytes()).unwrap());
+}
+
+#[test]
+fn test_list_rotate() {
+    let db = open_database();
+    let key = "hello";
+    assert_eq!(0, db.list_rotate(key, 1).unwrap());
+    for v in ["a", "b", "c", "d", "e"] {
+        db.list_right_push(key, v.as_bytes()).unwrap();
+    }
+    let items = |db: &Database| {
+        String::from_utf8(
+            db.list_items(key)
+                .unwrap()
+                .iter()
+                .flat_map(|v| v.to_vec())
+                .collect(),
+        )
+        .unwrap()
+    };
+    assert_eq!(2, db.list_rotate(key, 2).unwrap());
+    assert_eq!("cdeab", items(&db));
+    assert_eq!(1, db.list_rotate(key, -1).unwrap());
+    assert_eq!("bcdea", items(&db));
+    assert_eq!(0, db.list_rotate(key, 5).unwrap());
+    assert_eq!(1, db.list_rotate(key, -6).unwrap());
+    assert_eq!("abcde", items(&db));
+    assert_eq!(5, db.list_count(key).unwrap());
+    assert_eq!(
+        "a".as_bytes(),
+        db.list_left_pop(key).unwrap().unwrap().as_ref()
+    );
+    assert_eq!(
+        "e".as_bytes(),
+        db.list_right_pop(key).unwrap().unwrap().as_ref()
+    );
+
+    db.sorted_set_add("zset", &get_score_bytes(1), b"a")
+        .unwrap();
+    db.sorted_set_add("zset", &get_score_bytes(2), b"b")
+        .unwrap();
+    assert!(db.list_rotate("zset", 1).is_err());
+}
+
+#[test]
+fn test_list_dedupe() {
+    let db = open_database();
+    let key = "hello";
+    assert_eq!(0, db.list_dedupe(key).unwrap());
+    for v in ["a", "b", "a", "c", "b", "d", "a"] {
+        db.list_right_push(key, v.as_bytes()).unwrap();
+    }
+    assert_eq!(3, db.list_dedupe(key).unwrap());
+    assert_eq!(4, db.list_count(key).unwrap());
+    let items: Vec<u8> = db
+        .list_items(key)
+        .unwrap()
+        .iter()
+        .flat_map(|v| v.to_vec())
+        .collect();
+    assert_eq!("abcd", vec_to_str(items));
+    assert_eq!(0, db.list_dedupe(key).unwrap());
+    assert_eq!(5, db.list_right_push(key, "e".as_bytes()).unwrap());
+    assert_eq!(
+        "e".as_bytes(),
+        db.list_right_pop(key).unwrap().unwrap().as_ref()
+    );
+    assert_eq!(
+        "d".as_bytes(),
+        db.list_right_pop(key).unwrap().unwrap().as_ref()
+    );
+
+    db.sorted_set_add("zset", &get_score_bytes(1), b"a")
+        .unwrap();
+    assert!(db.list_dedupe("zset").is_err());
+}
+
+#[test]
+fn test_set_diff_and_intersect_count() {
+    let db = open_database();
+    for v in ["a", "b", "c", "d", "f"] {
+        db.set_add("s1", v.as_bytes()).unwrap();
+    }
+    for v in ["b", "d", "e", "f", "g", "h"] {
+        db.set_add("s2", v.as_bytes()).unwrap();
+    }
+    assert_eq!(2, db.set_diff_count("s1", "s2").unwrap());
+    assert_eq!(3, db.set_diff_count("s2", "s1").unwrap());
+    assert_eq!(3, db.set_intersect_count("s1", "s2").unwrap());
+    assert_eq!(3, db.set_intersect_count("s2", "s1").unwrap());
+    assert_eq!(5, db.set_diff_count("s1", "s3").unwrap());
+    assert_eq!(0, db.set_intersect_count("s1", "s3").unwrap());
+    assert_eq!(0, db.set_diff_count("s3", "s1").unwrap());
+}
+
+#[test]
+fn test_map_for_each_range() {
+    let db = open_database();
+    let key = "hello";
+    for f in ["a", "b", "c", "d", "e"] {
+        db.map_put(key, f, f).unwrap();
+    }
+    db.map_put("other", "a", "1").unwrap();
+    let collect = |start: Option<&str>, end: Option<&str>, reverse: bool| {
+        let mut fields = String::new();
+        db.map_for_each_range(key, start, end, reverse, |f, _| {
+            fields.push_str(f);
+            true
+        })
+        .unwrap();
+        fields
+    };
+    assert_eq!("abcde", collect(None, None, false));
+    assert_eq!("edcba", collect(None, None, true));
+    assert_eq!("bcd", collect(Some("b"), Some("e"), false));
+    assert_eq!("dcb", collect(Some("b"), Some("e"), true));
+    assert_eq!("cba", collect(None, Some("d"), true));
+    assert_eq!("ed", collect(Some("d"), None, true));
+    assert_eq!("", collect(Some("x"), None, false));
+
+    let mut fields = vec![];
+    db.map_for_each_range(key, None, None, true, |f, _| {
+        fields.push(f.to_string());
+        fields.len() < 2
+    })
+    .unwrap();
+    assert_eq!(vec!["e", "d"], fields);
+}
+
+#[test]
+fn test_map_items_with_limit() {
+    let db = open_database();
+    let key = "hello";
+    assert_eq!(0, db.map_items_with_limit(key, 10).unwrap().len());
+    for i in 0..10 {
+        db.map_put(key, format!("key_{}", i), "123").unwrap();
+    }
+    let vec = db.map_items_with_limit(key, 3).unwrap();
+    assert_eq!(3, vec.len());
+    assert_eq!("key_0", vec[0].0);
+    assert_eq!("key_2", vec[2].0);
+    assert_eq!(10, db.map_items_with_limit(key, 100).unwrap().len());
+    assert_eq!(0, db.map_items_with_limit(key, 0).unwrap().len());
+}
+
+#[test]
+fn test_sorted_list_count_in_range() {
+    let db = open_database();
+    let key = "hello";
+    let count = |min: i32, max: i32| {
+        db.sorted_list_count_in_range(
+            key,
+            get_score_bytes(min).as_slice(),
+            get_score_bytes(max).as_slice(),
+        )
+        .unwrap()
+    };
+    assert_eq!(0, count(0, 100));
+    for score in [-10, 5, 10, 10, 20, 30, 30, 30, 40] {
+        db.sorted_list_add(key, get_score_bytes(score).as_slice(), "a".as_bytes())
+            .unwrap();
+    }
+    assert_eq!(9, count(-100, 100));
+    assert_eq!(2, count(10, 10));
+    assert_eq!(6, count(10, 30));
+    assert_eq!(2, count(-10, 9));
+    assert_eq!(0, count(41, 100));
+    assert_eq!(0, count(30, 20));
+}
+
+#[test]
+fn test_score_stats() {
+    let db = open_database();
+    let stats = db.sorted_list_score_stats("hello").unwrap();
+    assert_eq!(0, stats.count);
+    assert!(stats.min.is_none());
+    assert!(stats.histogram.is_empty());
+
+    for i in (1..=25).rev() {
+        db.sorted_list_add("hello", get_score_bytes(i).as_slice(), "a".as_bytes())
+            .unwrap();
+        db.sorted_set_add(
+            "world",
+            get_score_bytes(i * 2).as_slice(),
+            format!("m{}", i).as_bytes(),
+        )
+        .unwrap();
+    }
+
+    let stats = db.sorted_list_score_stats("hello").unwrap();
+    assert_eq!(25, stats.count);
+    assert_eq!(1, get_score_from_bytes::<i32>(&stats.min.unwrap()));
+    assert_eq!(25, get_score_from_bytes::<i32>(&stats.max.unwrap()));
+    let buckets: Vec<(i32, u64)> = stats
+        .histogram
+        .iter()
+        .map(|(s, c)| (get_score_from_bytes(s), *c))
+        .collect();
+    assert_eq!(
+        vec![
+            (3, 3),
+            (6, 3),
+            (9, 3),
+            (12, 3),
+            (15, 3),
+            (18, 3),
+            (21, 3),
+            (24, 3),
+            (25, 1)
+        ],
+        buckets
+    );
+
+    let stats = db.sorted_set_score_stats("world").unwrap();
+    assert_eq!(25, stats.count);
+    assert_eq!(2, get_score_from_bytes::<i32>(&stats.min.unwrap()));
+    assert_eq!(50, get_score_from_bytes::<i32>(&stats.max.unwrap()));
+    assert_eq!(25, stats.histogram.iter().map(|(_, c)| c).sum::<u64>());
+}
+
+#[test]
+fn test_sorted_set_percentile() {
+    let db = open_database();
+    let key = "hello";
+    assert!(db.sorted_set_percentile(key, 50.0).unwrap().is_none());
+    for i in 1..=20 {
+        db.sorted_set_add(
+            key,
+            get_score_bytes(i * 5).as_slice(),
+            format!("m{}", i).as_bytes(),
+        )
+        .unwrap();
+    }
+    let percentile = |p: f64| -> i32 {
+        get_score_from_bytes(&db.sorted_set_percentile(key, p).unwrap().unwrap())
+    };
+    assert_eq!(5, percentile(0.0));
+    assert_eq!(50, percentile(50.0));
+    assert_eq!(95, percentile(95.0));
+    assert_eq!(100, percentile(99.0));
+    assert_eq!(100, percentile(100.0));
+    assert!(db.sorted_set_percentile(key, 101.0).is_err());
+
+    // the expired members are not ranked
+    for i in 0..20 {
+        db.sorted_set_add_ex(
+            key,
+            &get_score_bytes(-i),
+            format!("old{}", i).as_bytes(),
+            Duration::ZERO,
+        )
+        .unwrap();
+    }
+    std::thread::sleep(Duration::from_millis(2));
+    assert_eq!(5, percentile(0.0));
+    assert_eq!(50, percentile(50.0));
+}
+
+#[test]
+fn test_map_aggregate() {
+    let db = open_database();
+    let key = "hello";
+    assert_eq!(
+        None,
+        db.map_aggregate(key, Aggregate::Sum, NumberFormat::Text)
+            .unwrap()
+    );
+    for (f, v) in [("a", 10i64), ("b", -4), ("c", 30)] {
+        db.map_put(key, f, v.to_string()).unwrap();
+        db.map_put("bin", f, v.to_be_bytes()).unwrap();
+        db.map_put("score", f, get_score_bytes(v as f64)).unwrap();
+    }
+    let aggregate =
+        |key: &str, a: Aggregate, f: NumberFormat| db.map_aggregate(key, a, f).unwrap().unwrap();
+    assert_eq!(36.0, aggregate(key, Aggregate::Sum, NumberFormat::Text));
+    assert_eq!(-4.0, aggregate(key, Aggregate::Min, NumberFormat::Text));
+    assert_eq!(30.0, aggregate("bin", Aggregate::Max, NumberFormat::I64));
+    assert_eq!(12.0, aggregate("bin", Aggregate::Avg, NumberFormat::I64));
+    assert_eq!(
+        12.0,
+        aggregate("score", Aggregate::Avg, NumberFormat::ScoreF64)
+    );
+
+    db.map_put(key, "d", "abc").unwrap();
+    assert!(db
+        .map_aggregate(key, Aggregate::Sum, NumberFormat::Text)
+        .is_err());
+}
+
+#[test]
+fn test_list_reindex() {
+    let db = open_database();
+    let key = "hello";
+    assert_eq!(0, db.list_reindex(key).unwrap());
+
+    // start the list near the end of the position space
+    let mut meta = db.get_or_create_meta(key, KeyType::List).unwrap();
+    meta.encode_list_extra(i64::MAX - 3, i64::MAX - 2);
+    db.save_meta(key, &meta, false).unwrap();
+    db.list_right_push(key, b"c").unwrap();
+    db.list_right_push(key, b"d").unwrap();
+    assert!(db.list_right_push(key, b"e").is_err());
+    db.list_left_push(key, b"b").unwrap();
+    db.list_left_push(key, b"a").unwrap();
+    assert_eq!(2, db.list_consumer_read(key, "g", 2).unwrap().len());
+
+    let id = db.get_meta(key).unwrap().unwrap().id;
+    assert_eq!(4, db.list_reindex(key).unwrap());
+    let meta = db.get_meta(key).unwrap().unwrap();
+    assert_eq!((-3, 2), meta.decode_list_extra());
+    // the items are rewritten under a new key ID, and the journal entry is removed
+    assert_ne!(id, meta.id);
+    assert_eq!(
+        4,
+        db.raw_scan(simpledb::codec::PREFIX_DATA, |_, _, _| true)
+            .unwrap()
+    );
+    assert_eq!(0, db.recover_journal().unwrap());
+    assert_eq!(
+        vec![Box::from(*b"c")],
+        db.list_consumer_read(key, "g", 1).unwrap()
+    );
+    assert_eq!(5, db.list_right_push(key, b"e").unwrap());
+    let items: Vec<Vec<u8>> = db
+        .list_items(key)
+        .unwrap()
+        .iter()
+        .map(|v| v.to_vec())
+        .collect();
+    assert_eq!(b"abcde".to_vec(), items.concat());
+    assert_eq!(Some(Box::from(*b"a")), db.list_left_pop(key).unwrap());
+    assert_eq!(Some(Box::from(*b"e")), db.list_right_pop(key).unwrap());
+
+    db.sorted_set_add("zset", &get_score_bytes(1), b"a")
+        .unwrap();
+    assert!(db.list_reindex("zset").is_err());
+
+    // the pushes and the pops of the other threads wait for the reindex
+    let key = "busy";
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..500u32 {
+                db.list_right_push(key, &i.to_be_bytes()).unwrap();
+                if i % 5 == 0 {
+                    db.list_left_pop(key).unwrap();
+                }
+            }
+        });
+        s.spawn(|| {
+            for _ in 0..50 {
+                db.list_reindex(key).unwrap();
+            }
+        });
+    });
+    assert_eq!(400, db.list_count(key).unwrap());
+    assert_eq!(400, db.list_items(key).unwrap().len());
+}
+
+#[test]
+fn test_list_overflow() {
+    let key = "hello";
+    for auto_reindex in [false, true] {
+        let path = get_random_database_path();
+        let options = Options {
+            list_auto_reindex: auto_reindex,
+            ..Default::default()
+        };
+        let db = Database::open_with_options(&path, options).unwrap();
+        let mut meta = db.get_or_create_meta(key, KeyType::List).unwrap();
+        meta.encode_list_extra(i64::MIN, i64::MAX);
+        db.save_meta(key, &meta, false).unwrap();
+        for r in [db.list_left_push(key, b"a"), db.list_right_push(key, b"b")] {
+            if auto_reindex {
+                assert!(r.is_ok());
+            } else {
+                assert!(
+                    matches!(r.as_ref().map_err(Error::root), Err(Error::ListOverflow(k)) if k == key)
+                );
+            }
+        }
+        let count = if auto_reindex { 2 } else { 0 };
+        assert_eq!(count, db.list_count(key).unwrap());
+    }
+}
+
+#[test]
+fn test_sorted_list_reseed() {
+    let db = open_database();
+    let key = "hello";
+    assert_eq!(0, db.sorted_list_reseed(key).unwrap());
+    let mut meta = db.get_or_create_meta(key, KeyType::SortedList).unwrap();
+    meta.encode_sorted_list_extra(u64::MAX - 2, 0, 0);
+    db.save_meta(key, &meta, false).unwrap();
+    for (score, value) in [(2, "a"), (1, "b"), (2, "c"), (1, "d")] {
+        db.sorted_list_add(key, &get_score_bytes(score), value.as_bytes())
+            .unwrap();
+    }
+    let (sequence, _, _) = db
+        .get_meta(key)
+        .unwrap()
+        .unwrap()
+        .decode_sorted_list_extra();
+    assert_eq!(4, sequence);
+    let values: Vec<String> = db
+        .sorted_list_items(key)
+        .unwrap()
+        .into_iter()
+        .map(|(_, v)| vec_to_str(v.to_vec()))
+        .collect();
+    assert_eq!(vec!["b", "d", "a", "c"], values);
+
+    // the new sequence of an item may be the old sequence of the next item of the same score
+    let key = "runs";
+    for (score, value) in [(2, "a"), (2, "b"), (1, "c")] {
+        db.sorted_list_add(key, &get_score_bytes(score), value.as_bytes())
+            .unwrap();
+    }
+    assert_eq!(3, db.sorted_list_reseed(key).unwrap());
+    let values: Vec<String> = db
+        .sorted_list_items(key)
+        .unwrap()
+        .into_iter()
+        .map(|(_, v)| vec_to_str(v.to_vec()))
+        .collect();
+    assert_eq!(vec!["c", "a", "b"], values);
+
+    // a reserved item keeps its old sequence, so the list is not reseeded
+    let (token, _, _) = db
+        .sorted_list_reserve(key, None, Duration::from_secs(60))
+        .unwrap()
+        .unwrap();
+    assert!(db.sorted_list_reseed(key).is_err());
+    assert!(db.sorted_list_release(&token).unwrap());
+    assert_eq!(3, db.sorted_list_reseed(key).unwrap());
+
+    // the items are moved in several batches
+    let key = "large";
+    for i in 0..6000u32 {
+        db.sorted_list_add(key, &get_score_bytes(3 - i % 3), &i.to_be_bytes())
+            .unwrap();
+    }
+    assert_eq!(6000, db.sorted_list_reseed(key).unwrap());
+    let items = db.sorted_list_items(key).unwrap();
+    assert_eq!(6000, items.len());
+    assert_eq!(6000, db.sorted_list_count(key).unwrap());
+    assert_eq!(2u32.to_be_bytes(), items[0].1.as_ref());
+    assert_eq!(5997u32.to_be_bytes(), items[5999].1.as_ref());
+}
+
+#[test]
+fn test_sorted_list_move() {
+    let db = open_database();
+    let (src, dst) = ("pending", "in-progress");
+    assert!(db.sorted_list_move(src, src, None).is_err());
+    assert!(db.sorted_list_move(src, dst, None).unwrap().is_none());
+    for (score, value) in [(3, "c"), (1, "a"), (2, "b")] {
+        db.sorted_list_add(src, &get_score_bytes(score), value.as_bytes())
+            .unwrap();
+    }
+    db.sorted_list_add(dst, &get_score_bytes(5), b"e").unwrap();
+
+    let (score, value) = db.sorted_list_move(src, dst, None).unwrap().unwrap();
+    assert_eq!(1, get_score_from_bytes(&score));
+    assert_eq!("a", vec_to_str(value.to_vec()));
+    let max_score = get_score_bytes(2);
+    assert!(db
+        .sorted_list_move(src, dst, Some(&max_score))
+        .unwrap()
+        .is_some());
+    assert!(db
+        .sorted_list_move(src, dst, Some(&max_score))
+        .unwrap()
+        .is_none());
+
+    assert_eq!(1, db.sorted_list_count(src).unwrap());
+    assert_eq!(3, db.sorted_list_count(dst).unwrap());
+    let values: Vec<String> = db
+        .sorted_list_items(dst)
+        .unwrap()
+        .into_iter()
+        .map(|(_, v)| vec_to_str(v.to_vec()))
+        .collect();
+    assert_eq!(vec!["a", "b", "e"], values);
+}
+
+#[test]
+fn test_sorted_list_reserve() {
+    let db = open_database();
+    let key = "jobs";
+    let ttl = Duration::from_secs(60);
+    assert!(db.sorted_list_reserve(key, None, ttl).unwrap().is_none());
+    for (score, value) in [(1, "a"), (2, "b"), (3, "c")] {
+        db.sorted_list_add(key, &get_score_bytes(score), value.as_bytes())
+            .unwrap();
+    }
+
+    let (token_a, score, value) = db.sorted_list_reserve(key, None, ttl).unwrap().unwrap();
+    assert_eq!(1, get_score_from_bytes(&score));
+    assert_eq!("a", vec_to_str(value.to_vec()));
+    let (token_b, _, value) = db.sorted_list_reserve(key, None, ttl).unwrap().unwrap();
+    assert_eq!("b", vec_to_str(value.to_vec()));
+    assert_eq!(1, db.sorted_list_count(key).unwrap());
+
+    // a released item is visible again, a committed one is gone
+    assert!(db.sorted_list_release(&token_a).unwrap());
+    assert!(db.sorted_list_commit(&token_b).unwrap());
+    assert!(!db.sorted_list_commit(&token_b).unwrap());
+    assert_eq!(2, db.sorted_list_count(key).unwrap());
+
+    // an expired reservation is put back by the next reserve
+    let (_, _, value) = db
+        .sorted_list_reserve(key, None, Duration::ZERO)
+        .unwrap()
+        .unwrap();
+    assert_eq!("a", vec_to_str(value.to_vec()));
+    let max_score = get_score_bytes(2);
+    let (token, _, value) = db
+        .sorted_list_reserve(key, Some(&max_score), ttl)
+        .unwrap()
+        .unwrap();
+    assert_eq!("a", vec_to_str(value.to_vec()));
+    assert!(db
+        .sorted_list_reserve(key, Some(&max_score), ttl)
+        .unwrap()
+        .is_none());
+    assert!(db.sorted_list_commit(&token).unwrap());
+    let (token, _, _) = db.sorted_list_reserve(key, None, ttl).unwrap().unwrap();
+    assert!(db.sorted_list_commit(&token).unwrap());
+    assert!(db.get_meta(key).unwrap().is_none());
+
+    // deleting the key deletes its reserved items
+    db.sorted_list_add(key, &get_score_bytes(1), b"a").unwrap();
+    let (token, _, _) = db.sorted_list_reserve(key, None, ttl).unwrap().unwrap();
+    db.delete_all(key).unwrap();
+    assert!(!db.sorted_list_commit(&token).unwrap());
+
+    // an item is reserved by only one of the concurrent calls, a token is released only once
+    for i in 0..100u32 {
+        db.sorted_list_add(key, &get_score_bytes(i), &i.to_be_bytes())
+            .unwrap();
+    }
+    let tokens = std::sync::Mutex::new(Vec::new());
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                while let Some((token, _, _)) = db.sorted_list_reserve(key, None, ttl).unwrap() {
+                    tokens.lock().unwrap().push(token);
+                }
+            });
+        }
+    });
+    let tokens = tokens.into_inner().unwrap();
+    assert_eq!(100, tokens.len());
+    assert_eq!(0, db.sorted_list_count(key).unwrap());
+    let released = std::sync::atomic::AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..2 {
+            s.spawn(|| {
+                for token in tokens.iter() {
+                    if db.sorted_list_release(token).unwrap() {
+                        released.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    }
+                }
+            });
+        }
+    });
+    assert_eq!(100, released.load(std::sync::atomic::Ordering::SeqCst));
+    assert_eq!(100, db.sorted_list_count(key).unwrap());
+    assert_eq!(100, db.sorted_list_items(key).unwrap().len());
+
+    db.set_add("members", b"a").unwrap();
+    assert!(db.sorted_list_reserve("members", None, ttl).is_err());
+}
+
+#[test]
+fn test_idempotency_check_and_set() {
+    let db = open_database();
+    let key = "requests";
+    let ttl = Duration::from_secs(60);
+    assert_eq!(
+        None,
+        db.idempotency_check_and_set(key, "r1", b"ok", ttl).unwrap()
+    );
+    assert_eq!(
+        Some(Box::from(*b"ok")),
+        db.idempotency_check_and_set(key, "r1", b"other", ttl)
+            .unwrap()
+    );
+    assert_eq!(
+        None,
+        db.idempotency_check_and_set(key, "r2", b"expired", Duration::ZERO)
+            .unwrap()
+    );
+    assert_eq!(
+        None,
+        db.idempotency_check_and_set(key, "r3", b"expired", Duration::ZERO)
+            .unwrap()
+    );
+    assert_eq!(2, db.idempotency_purge_expired(key).unwrap());
+    assert_eq!(1, db.map_count(key).unwrap());
+
+    // a request ID is set by only one of the concurrent calls
+    let set = std::sync::atomic::AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                if db
+                    .idempotency_check_and_set(key, "r4", b"ok", ttl)
+                    .unwrap()
+                    .is_none()
+                {
+                    set.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                }
+            });
+        }
+    });
+    assert_eq!(1, set.load(std::sync::atomic::Ordering::SeqCst));
+}
+
+#[test]
+fn test_dedupe_window() {
+    let db = open_database();
+    let key = "consumed";
+    let window = Duration::from_secs(60);
+    assert!(!db.dedupe_window(key, b"m1", window).unwrap());
+    assert!(db.dedupe_window(key, b"m1", window).unwrap());
+    assert!(!db.dedupe_window(key, b"m2", window).unwrap());
+
+    // a zero window forgets the ids seen before
+    std::thread::sleep(Duration::from_millis(2));
+    assert!(!db.dedupe_window(key, b"m1", Duration::ZERO).unwrap());
+    assert_eq!(1, db.sorted_set_count(key).unwrap());
+
+    // an id is new to only one of the concurrent calls
+    let new = std::sync::atomic::AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                if !db.dedupe_window(key, b"m3", window).unwrap() {
+                    new.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                }
+            });
+        }
+    });
+    assert_eq!(1, new.load(std::sync::atomic::Ordering::SeqCst));
+}
+
+#[test]
+fn test_sessions() {
+    let db = open_database();
+    let sessions = Sessions::new(&db, "sessions");
+    let token = sessions
+        .create_session(b"user=1", Duration::from_secs(60))
+        .unwrap();
+    assert_eq!(32, token.len());
+    assert_eq!(
+        "user=1",
+        vec_to_str(sessions.get_session(&token).unwrap().unwrap().to_vec())
+    );
+    assert!(sessions.touch(&token).unwrap());
+    assert!(sessions.revoke(&token).unwrap());
+    assert!(sessions.get_session(&token).unwrap().is_none());
+    assert!(!sessions.touch(&token).unwrap());
+
+    let token = sessions.create_session(b"user=2", Duration::ZERO).unwrap();
+    sessions.create_session(b"user=3", Duration::ZERO).unwrap();
+    assert!(sessions.get_session(&token).unwrap().is_none());
+    assert_eq!(1, sessions.purge_expired().unwrap());
+    assert_eq!(0, db.map_count("sessions").unwrap());
+}
+
+#[test]
+fn test_cache_get_or_insert_with() {
+    let db = open_database();
+    let key = "cache";
+    let mut loads = 0;
+    for _ in 0..3 {
+        let value = db
+            .cache_get_or_insert_with(key, "a", Duration::from_secs(60), || {
+                loads += 1;
+                b"123".to_vec()
+            })
+            .unwrap();
+        assert_eq!("123", vec_to_str(value.to_vec()));
+    }
+    assert_eq!(1, loads);
+    for _ in 0..2 {
+        db.cache_get_or_insert_with(key, "b", Duration::ZERO, || {
+            loads += 1;
+            b"456".to_vec()
+        })
+        .unwrap();
+    }
+    assert_eq!(3, loads);
+
+    // the concurrent misses run the loader once
+    let loads = std::sync::atomic::AtomicUsize::new(0);
+    let barrier = std::sync::Barrier::new(8);
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            s.spawn(|| {
+                barrier.wait();
+                let value = db
+                    .cache_get_or_insert_with(key, "c", Duration::from_secs(60), || {
+                        loads.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        std::thread::sleep(Duration::from_millis(50));
+                        b"789".to_vec()
+                    })
+                    .unwrap();
+                assert_eq!("789", vec_to_str(value.to_vec()));
+            });
+        }
+    });
+    assert_eq!(1, loads.load(std::sync::atomic::Ordering::SeqCst));
+
+    // a panicked loader doesn't break the later loads of the field
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        db.cache_get_or_insert_with(key, "d", Duration::from_secs(60), || panic!("load"))
+    }));
+    assert!(result.is_err());
+    let value = db
+        .cache_get_or_insert_with(key, "d", Duration::from_secs(60), || b"0".to_vec())
+        .unwrap();
+    assert_eq!("0", vec_to_str(value.to_vec()));
+}
+
+#[test]
+fn test_write_pipeline() {
+    let db = open_database();
+    db.register_schema("counter", Schema::new(KeyType::Map, ValueCodec::Fixed(8)));
+    let pipeline = WritePipeline::start(db, Duration::from_millis(1));
+    let threads: Vec<_> = (0..4)
+        .map(|t| {
+            let writer = pipeline.writer();
+            std::thread::spawn(move || {
+                for i in 0..50 {
+                    writer
+                        .map_put(format!("key-{}", t), i.to_string(), "value")
+                        .unwrap();
+                    writer.list_right_push("list", b"x").unwrap();
+                }
+                writer.map_delete("key-0", "0").unwrap();
+                writer
+                    .write(vec![
+                        WriteOp::SetAdd(Box::from(&b"set"[..]), Box::from(&[t as u8][..])),
+                        WriteOp::MapPut(
+                            Box::from(&b"map"[..]),
+                            Box::from(&[t as u8][..]),
+                            Box::from(&b"v"[..]),
+                        ),
+                    ])
+                    .unwrap();
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    let writer = pipeline.writer();
+    // the operations are checked like the methods of the database
+    assert!(writer.map_put("counter", "a", "1").is_err());
+    assert!(writer
+        .write(vec![
+            WriteOp::SetAdd(Box::from(&b"twice"[..]), Box::from(&b"a"[..])),
+            WriteOp::SetDelete(Box::from(&b"twice"[..]), Box::from(&b"a"[..])),
+        ])
+        .is_err());
+    let db = pipeline.stop().unwrap();
+    assert!(writer.map_put("key", "a", "value").is_err());
+    assert_eq!(49, db.map_count("key-0").unwrap());
+    assert_eq!(50, db.map_count("key-3").unwrap());
+    assert_eq!(200, db.list_count("list").unwrap());
+    assert_eq!(4, db.set_count("set").unwrap());
+    assert_eq!(4, db.map_count("map").unwrap());
+    assert!(db.get_meta("twice").unwrap().is_none());
+}
+
+#[test]
+fn test_sharded_database() {
+    let path = get_random_database_path();
+    {
+        let db = ShardedDatabase::open(&path, 4).unwrap();
+        for i in 0..20 {
+            let key = format!("key-{}", i);
+            db.map_put(&key, "a", i.to_string()).unwrap();
+            db.list_right_push(&key, b"x").unwrap();
+        }
+        assert_eq!(20, db.keys().unwrap().len());
+        assert!(db.shards.iter().all(|s| !s.keys().unwrap().is_empty()));
+        assert_eq!(2, db.get_count("key-7").unwrap());
+    }
+    assert!(ShardedDatabase::open(&path, 2).is_err());
+    let db = ShardedDatabase::open(&path, 4).unwrap();
+    assert_eq!("7", vec_to_str(db.map_get("key-7", "a").unwrap().unwrap()));
+    assert_eq!(
+        vec![2, 2, 0],
+        db.get_counts(&["key-1", "key-2", "none"]).unwrap()
+    );
+    assert_eq!(
+        vec![Some(b"1".to_vec()), None],
+        db.multi_map_get(&[("key-1", &b"a"[..]), ("key-2", b"b")])
+            .unwrap()
+    );
+    assert_eq!(2, db.delete_keys(&["key-1", "key-2", "none"]).unwrap());
+    assert_eq!(18, db.keys().unwrap().len());
+
+    // the schemas are registered in every shard
+    db.register_schema("user:*", Schema::new(KeyType::Map, ValueCodec::Utf8));
+    for i in 0..8 {
+        assert!(db.map_put(format!("user:{}", i), "name", [0xff]).is_err());
+    }
+    assert!(db.unregister_schema("user:*"));
+
+    let mut count = 0;
+    db.sorted_set_add("scores", &get_score_bytes(1u32), b"a")
+        .unwrap();
+    db.sorted_set_for_each("scores", |_| {
+        count += 1;
+        true
+    })
+    .unwrap();
+    assert_eq!(1, count);
+    db.value_put("value", b"data").unwrap();
+    assert_eq!(Some(b"data".to_vec()), db.value_get("value").unwrap());
+
+    // the methods of two keys need the keys in the same shard
+    let keys: Vec<String> = (0..20).map(|i| format!("key-{}", i)).collect();
+    let same = keys[3..]
+        .iter()
+        .find(|k| std::ptr::eq(db.shard(k), db.shard(&keys[3])) && *k != &keys[3])
+        .unwrap();
+    let other = keys[3..]
+        .iter()
+        .find(|k| !std::ptr::eq(db.shard(k), db.shard(&keys[3])))
+        .unwrap();
+    assert!(db.map_move_field(&keys[3], same, "a").unwrap());
+    assert!(db.map_move_field(same, other, "a").is_err());
+    drop(db);
+    ShardedDatabase::destroy(&path).unwrap();
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_par_map_for_each() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    let db = open_database();
+    let key = "hello";
+    for i in 0..1000u64 {
+        db.map_put(key, format!("field-{}", i), i.to_be_bytes())
+            .unwrap();
+    }
+    let sum = AtomicU64::new(0);
+    let count = db
+        .par_map_for_each(key, 8, |_, v| {
+            sum.fetch_add(u64::from_be_bytes(v.try_into().unwrap()), Ordering::Relaxed);
+            true
+        })
+        .unwrap();
+    assert_eq!(1000, count);
+    assert_eq!(999 * 1000 / 2, sum.into_inner());
+    assert_eq!(1, db.par_for_each_key(8, |_, _| true).unwrap());
+}
+
+#[test]
+fn test_map_get_pinned() {
+    let db = open_database();
+    let key = "hello";
+    assert!(db.map_get_pinned(key, "a").unwrap().is_none());
+    db.map_put(key, "a", "123").unwrap();
+    assert!(db.map_get_pinned(key, "b").unwrap().is_none());
+    let value = db.map_get_pinned(key, "a").unwrap().unwrap();
+    assert_eq!(b"123", value.as_ref());
+}
+
+#[test]
+fn test_pinned_reads() {
+    let path = get_random_database_path();
+    let options = Options {
+        value_chunk_size: 4,
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    assert!(db.value_get_pinned("value").unwrap().is_none());
+    db.value_put("value", b"hello world").unwrap();
+    let chunks = db.value_get_pinned("value").unwrap().unwrap();
+    assert_eq!(3, chunks.len());
+    assert_eq!(
+        b"hello world".to_vec(),
+        chunks.iter().flat_map(|c| c.to_vec()).collect::<Vec<u8>>()
+    );
+    db.value_put("value", b"").unwrap();
+    assert!(db.value_get_pinned("value").unwrap().unwrap().is_empty());
+
+    let key = "scores";
+    assert!(db.sorted_set_score_pinned(key, b"a").unwrap().is_none());
+    db.sorted_set_add(key, &get_score_bytes(7u32), b"a")
+        .unwrap();
+    db.sorted_set_add_ex(key, &get_score_bytes(8u32), b"b", Duration::from_millis(1))
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(5));
+    let score = db.sorted_set_score_pinned(key, b"a").unwrap().unwrap();
+    assert_eq!(get_score_bytes(7u32), score.as_ref());
+    assert!(db.sorted_set_score_pinned(key, b"b").unwrap().is_none());
+    assert!(db.sorted_set_score_pinned(key, b"c").unwrap().is_none());
+}
+
+#[test]
+fn test_map_for_each_borrowed_field() {
+    let db = open_database();
+    let key = "hello";
+    db.map_put(key, "a", "1").unwrap();
+    db.map_put(key, "b", "2").unwrap();
+    let mut fields = Vec::new();
+    db.map_for_each(key, |f, _| {
+        fields.extend_from_slice(f);
+        true
+    })
+    .unwrap();
+    assert_eq!(b"ab".to_vec(), fields);
+    let mut fields = String::new();
+    db.map_for_each_utf8(key, |f, _| {
+        fields.push_str(f);
+        true
+    })
+    .unwrap();
+    assert_eq!("ab", fields);
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_derive_record() {
+    use simpledb::{Record, SimpledbRecord};
+
+    #[derive(Debug, PartialEq, SimpledbRecord)]
+    struct User {
+        name: String,
+        age: u32,
+        score: f64,
+        email: Option<String>,
+    }
+
+    let db = open_database();
+    let key = "user:1";
+    assert_eq!(None, User::load(&db, key).unwrap());
+    let mut user = User {
+        name: "tom".to_string(),
+        age: 18,
+        score: 9.5,
+        email: None,
+    };
+    user.save(&db, key).unwrap();
+    assert_eq!(3, db.map_count(key).unwrap());
+    assert_eq!(Some(&user), User::load(&db, key).unwrap().as_ref());
+
+    User::update_age(&db, key, &19).unwrap();
+    User::update_email(&db, key, &Some("tom@example.com".to_string())).unwrap();
+    user.age = 19;
+    user.email = Some("tom@example.com".to_string());
+    assert_eq!(Some(user), User::load(&db, key).unwrap());
+
+    db.map_put(key, "age", "abc").unwrap();
+    assert!(User::load(&db, key).is_err());
+}
+
+#[test]
+fn test_schema() {
+    let db = open_database();
+    db.register_schema("user:*", Schema::new(KeyType::Map, ValueCodec::Utf8));
+    db.register_schema("counter", Schema::new(KeyType::Map, ValueCodec::Fixed(8)));
+    assert!(db.get_schema("hello").is_none());
+
+    db.map_put("user:1", "name", "tom").unwrap();
+    assert!(matches!(
+        db.map_put("user:1", "avatar", [0xff, 0xfe])
+            .as_ref()
+            .map_err(Error::root),
+        Err(Error::Schema(_))
+    ));
+    assert!(matches!(
+        db.list_right_push("user:2", b"tom")
+            .as_ref()
+            .map_err(Error::root),
+        Err(Error::Schema(_))
+    ));
+    db.map_put("counter", "a", 1i64.to_be_bytes()).unwrap();
+    assert!(db.map_put("counter", "a", "1").is_err());
+
+    assert!(db.unregister_schema("user:*"));
+    assert!(!db.unregister_schema("user:*"));
+    db.list_right_push("user:2", b"tom").unwrap();
+}
+
+#[test]
+fn test_blob() {
+    let db = open_database();
+    let hash = db.blob_put(b"hello world").unwrap();
+    assert_eq!(hash, db.blob_put(b"hello world").unwrap());
+    assert_eq!(
+        b"hello world".to_vec(),
+        db.blob_get(&hash).unwrap().unwrap()
+    );
+    assert!(db.blob_ref("a", &[0u8; 32]).is_err());
+
+    assert_eq!(1, db.blob_ref("a", &hash).unwrap());
+    assert_eq!(1, db.blob_ref("a", &hash).unwrap());
+    assert_eq!(2, db.blob_ref("b", &hash).unwrap());
+    let other = db.blob_put(b"other").unwrap();
+    assert_eq!(1, db.blob_gc().unwrap());
+    assert!(db.blob_get(&other).unwrap().is_none());
+
+    assert_eq!(1, db.blob_unref("a", &hash).unwrap());
+    assert_eq!(1, db.blob_unref("a", &hash).unwrap());
+    assert_eq!(0, db.blob_gc().unwrap());
+    assert_eq!(0, db.blob_unref("b", &hash).unwrap());
+    assert_eq!(1, db.blob_gc().unwrap());
+    assert!(db.blob_get(&hash).unwrap().is_none());
+
+    // the concurrent references are all counted, and a referenced blob survives the gc
+    let hash = db.blob_put(b"shared").unwrap();
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            let db = &db;
+            s.spawn(move || {
+                for i in 0..50 {
+                    db.blob_ref(&format!("k{}-{}", t, i), &hash).unwrap();
+                    db.blob_gc().unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(200, db.blob_ref_count(&hash).unwrap());
+    assert!(db.blob_get(&hash).unwrap().is_some());
+}
+
+#[test]
+fn test_value_chunking() {
+    let path = get_random_database_path();
+    let options = Options {
+        value_chunk_size: 1000,
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    let key = "hello";
+    assert!(db.value_get(key).unwrap().is_none());
+    let data: Vec<u8> = (0..10500).map(|i| (i % 251) as u8).collect();
+    db.value_put(key, &data).unwrap();
+    assert_eq!(11, db.get_count(key).unwrap());
+    assert_eq!(Some(10500), db.value_len(key).unwrap());
+    assert_eq!(data, db.value_get(key).unwrap().unwrap());
+
+    db.value_put(key, b"small").unwrap();
+    assert_eq!(1, db.get_count(key).unwrap());
+    assert_eq!(b"small".to_vec(), db.value_get(key).unwrap().unwrap());
+    db.value_put(key, b"").unwrap();
+    assert_eq!(Some(vec![]), db.value_get(key).unwrap());
+
+    db.map_put("map", "a", "1").unwrap();
+    assert!(db.value_get("map").is_err());
+}
+
+#[test]
+fn test_map_large_values() {
+    let path = get_random_database_path();
+    let options = Options {
+        value_chunk_size: 100,
+        large_value_threshold: Some(256),
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    let key = "hello";
+    let data: Vec<u8> = (0..1050).map(|i| (i % 251) as u8).collect();
+    db.map_put(key, "a", &data).unwrap();
+    db.map_put(key, "b", "small").unwrap();
+    assert_eq!(2, db.map_count(key).unwrap());
+    // the manifest and 11 chunks
+    assert_eq!(12, db.raw_scan(PREFIX_VALUE_CHUNK, |_, _, _| true).unwrap());
+    assert_eq!(data, db.map_get(key, "a").unwrap().unwrap());
+    assert_eq!(Some(1050), db.map_value_len(key, "a").unwrap());
+    assert!(db.map_get_pinned(key, "a").is_err());
+    let items = db.map_items(key).unwrap();
+    assert_eq!(data, items[0].1.as_ref());
+    assert_eq!(b"small", items[1].1.as_ref());
+
+    let blob = db.dump_key(key).unwrap().unwrap();
+    db.map_put(key, "a", "1").unwrap();
+    assert_eq!(0, db.raw_scan(PREFIX_VALUE_CHUNK, |_, _, _| true).unwrap());
+    assert_eq!(b"1".to_vec(), db.map_get(key, "a").unwrap().unwrap());
+    db.delete_all(key).unwrap();
+    db.restore_key(key, &blob).unwrap();
+    assert_eq!(data, db.map_get(key, "a").unwrap().unwrap());
+
+    db.map_delete(key, "a").unwrap();
+    assert_eq!(0, db.raw_scan(PREFIX_VALUE_CHUNK, |_, _, _| true).unwrap());
+    db.map_put(key, "a", &data).unwrap();
+    db.delete_all(key).unwrap();
+    assert_eq!(0, db.raw_scan(PREFIX_VALUE_CHUNK, |_, _, _| true).unwrap());
+
+    // the chunked values are still reassembled without the threshold
+    db.map_put(key, "a", &data).unwrap();
+    drop(db);
+    let db = open_database_with_path(&path);
+    assert_eq!(data, db.map_get(key, "a").unwrap().unwrap());
+    assert!(db
+        .manifest()
+        .unwrap()
+        .unwrap()
+        .features
+        .contains(&"chunked_values".to_string()));
+}
+
+#[test]
+fn test_blob_streaming() {
+    let path = get_random_database_path();
+    let options = Options {
+        value_chunk_size: 100,
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    let key = "stream";
+    assert!(db.blob_reader(key).unwrap().is_none());
+    let data: Vec<u8> = (0..5050).map(|i| (i % 251) as u8).collect();
+    let mut writer = db.blob_writer(key).unwrap();
+    for part in data.chunks(33) {
+        writer.write_all(part).unwrap();
+    }
+    assert!(db.value_get(key).unwrap().is_none());
+    writer.finish().unwrap();
+    assert_eq!(51, db.get_count(key).unwrap());
+    assert_eq!(data, db.value_get(key).unwrap().unwrap());
+
+    let mut out = Vec::new();
+    db.blob_reader(key)
+        .unwrap()
+        .unwrap()
+        .read_to_end(&mut out)
+        .unwrap();
+    assert_eq!(data, out);
+
+    // a reader keeps streaming the value it started with after the value is replaced
+    let mut reader = db.blob_reader(key).unwrap().unwrap();
+    let mut head = [0u8; 10];
+    reader.read_exact(&mut head).unwrap();
+    db.value_put(key, b"replaced").unwrap();
+    let mut out = head.to_vec();
+    reader.read_to_end(&mut out).unwrap();
+    assert_eq!(data, out);
+    assert_eq!(b"replaced".to_vec(), db.value_get(key).unwrap().unwrap());
+    db.value_put(key, &data).unwrap();
+
+    let mut writer = db.blob_writer(key).unwrap();
+    writer.write_all(b"discarded").unwrap();
+    drop(writer);
+    assert_eq!(data, db.value_get(key).unwrap().unwrap());
+    assert_eq!(0, db.recover_journal().unwrap());
+
+    // the chunks of a writer lost by a crash are deleted by the journal
+    let mut writer = db.blob_writer(key).unwrap();
+    writer.write_all(&data).unwrap();
+    std::mem::forget(writer);
+    assert_eq!(1, db.recover_journal().unwrap());
+    assert_eq!(data, db.value_get(key).unwrap().unwrap());
+    assert_eq!(0, db.gc().unwrap().orphaned_keys);
+}
+
+#[test]
+fn test_sorted_list_retention() {
+    let db = open_database();
+    db.sorted_list_set_retention("count", SortedListRetention::MaxCount(3));
+    for i in 0..5u64 {
+        db.sorted_list_add("count", &get_score_bytes(i), b"v")
+            .unwrap();
+    }
+    assert_eq!(3, db.sorted_list_count("count").unwrap());
+    let (score, _) = db.sorted_list_left_pop("count", None).unwrap().unwrap();
+    assert_eq!(2u64, get_score_from_bytes(&score));
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64;
+    for ms in [now - 60_000, now - 50_000, now - 1_000, now] {
+        db.sorted_list_add("age", &get_score_bytes(ms), b"v")
+            .unwrap();
+    }
+    assert_eq!(4, db.sorted_list_count("age").unwrap());
+    db.sorted_list_set_retention("age*", SortedListRetention::MaxAge(Duration::from_secs(10)));
+    assert_eq!(
+        Some(SortedListRetention::MaxAge(Duration::from_secs(10))),
+        db.sorted_list_retention("age")
+    );
+    assert_eq!(2, db.sorted_list_trim("age").unwrap());
+    assert_eq!(2, db.sorted_list_count("age").unwrap());
+    assert_eq!(0, db.sorted_list_trim("age").unwrap());
+    assert!(db.sorted_list_remove_retention("age*"));
+    assert!(db.sorted_list_retention("age").is_none());
+}
+
+#[test]
+fn test_log() {
+    let db = open_database();
+    let key = "events";
+    assert!(db.log_read(key, 0, 10).unwrap().is_empty());
+    for i in 0..5u64 {
+        assert_eq!(i, db.log_append(key, format!("e{}", i).as_bytes()).unwrap());
+    }
+    let entries = db.log_read(key, 3, 10).unwrap();
+    assert_eq!(vec![3, 4], entries.iter().map(|e| e.0).collect::<Vec<_>>());
+    assert_eq!(b"e3".as_ref(), entries[0].1.as_ref());
+    assert_eq!(2, db.log_read(key, 0, 2).unwrap().len());
+
+    assert_eq!(3, db.log_truncate_before(key, 3).unwrap());
+    assert_eq!(0, db.log_truncate_before(key, 2).unwrap());
+    assert_eq!(2, db.get_count(key).unwrap());
+    assert_eq!(3, db.log_read(key, 0, 10).unwrap()[0].0);
+    assert_eq!(2, db.log_truncate_before(key, 100).unwrap());
+    assert_eq!(5, db.log_append(key, b"e5").unwrap());
+
+    db.map_put("map", "a", "1").unwrap();
+    assert!(db.log_append("map", b"x").is_err());
+
+    // the concurrent appends get distinct offsets
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                for _ in 0..50 {
+                    db.log_append("concurrent", b"e").unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(200, db.get_count("concurrent").unwrap());
+    assert_eq!(200, db.log_read("concurrent", 0, 1000).unwrap().len());
+}
+
+#[test]
+fn test_read_view() {
+    let db = open_database();
+    db.map_put("map", "a", "1").unwrap();
+    db.list_right_push("list", b"x").unwrap();
+    let view = db.read_view();
+    db.map_put("map", "a", "2").unwrap();
+    db.map_put("map", "b", "3").unwrap();
+    db.list_right_push("list", b"y").unwrap();
+    db.set_add("set", b"m").unwrap();
+
+    assert_eq!(Some(b"1".to_vec()), view.map_get("map", "a").unwrap());
+    assert!(view.map_get("map", "b").unwrap().is_none());
+    assert_eq!(1, view.get_count("map").unwrap());
+    assert_eq!(1, view.map_items("map").unwrap().len());
+    assert_eq!(
+        vec![Box::from(b"x".as_ref())],
+        view.list_items("list").unwrap()
+    );
+    assert!(!view.set_is_member("set", b"m").unwrap());
+    drop(view);
+
+    let view = db.read_view();
+    assert_eq!(2, view.get_count("map").unwrap());
+    assert!(view.set_is_member("set", b"m").unwrap());
+}
+
+#[test]
+fn test_database_handle() {
+    let handle = DatabaseHandle::new(open_database());
+    for i in 0..300 {
+        handle
+            .map_put("map", format!("f{:03}", i), i.to_string())
+            .unwrap();
+        handle
+            .list_right_push("list", i.to_string().as_bytes())
+            .unwrap();
+    }
+    let iter = handle.map_iter("map").unwrap();
+    let h = handle.clone();
+    let fields = std::thread::spawn(move || {
+        assert_eq!(300, h.get_count("list").unwrap());
+        iter.map(|(f, _)| f).collect::<Vec<_>>()
+    })
+    .join()
+    .unwrap();
+    assert_eq!(300, fields.len());
+    assert_eq!(b"f000".as_ref(), fields[0].as_ref());
+    assert_eq!(b"f299".as_ref(), fields[299].as_ref());
+    assert_eq!(
+        Some(Box::from(b"299".as_ref())),
+        handle.list_iter("list").unwrap().last()
+    );
+    assert_eq!(0, handle.list_iter("missing").unwrap().count());
+}
+
+#[cfg(feature = "error-context")]
+#[test]
+fn test_error_context() {
+    let db = open_database();
+    db.register_schema("user:*", Schema::new(KeyType::Map, ValueCodec::Utf8));
+    let err = db.map_put("user:1", "avatar", [0xff]).unwrap_err();
+    assert!(matches!(&err, Error::Context { op: "map_put", key, .. } if key == "user:1"));
+    assert!(matches!(err.root(), Error::Schema(_)));
+    assert!(err.to_string().starts_with("map_put user:1: "));
+}
+
+#[test]
+fn test_retry_transient_errors() {
+    let path = get_random_database_path();
+    let options = Options {
+        retry_max_retries: 2,
+        retry_backoff: Duration::from_millis(1),
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    let mut attempts = 0;
+    let r: simpledb::Result<()> = db.retry(|| {
+        attempts += 1;
+        Err(std::io::Error::from(std::io::ErrorKind::TimedOut).into())
+    });
+    assert!(r.unwrap_err().is_transient());
+    assert_eq!(3, attempts);
+
+    let mut attempts = 0;
+    let r = db.retry(|| {
+        attempts += 1;
+        if attempts < 2 {
+            Err(std::io::Error::from(std::io::ErrorKind::Interrupted).into())
+        } else {
+            Ok(attempts)
+        }
+    });
+    assert_eq!(2, r.unwrap());
+
+    let mut attempts = 0;
+    let r: simpledb::Result<()> = db.retry(|| {
+        attempts += 1;
+        Err(Error::Message("fatal".to_string()))
+    });
+    assert!(!r.unwrap_err().is_transient());
+    assert_eq!(1, attempts);
+}
+
+#[derive(Default)]
+struct TestRecorder {
+    ops: std::sync::Mutex<Vec<(&'static str, Option<KeyType>, bool)>>,
+}
+
+impl MetricsRecorder for TestRecorder {
+    fn record_op(
+        &self,
+        name: &'static str,
+        key_type: Option<KeyType>,
+        _duration: Duration,
+        result: Result<(), &Error>,
+    ) {
+        self.ops
+            .lock()
+            .unwrap()
+            .push((name, key_type, result.is_ok()));
+    }
+}
+
+#[test]
+fn test_metrics_recorder() {
+    let recorder = std::sync::Arc::new(TestRecorder::default());
+    let path = get_random_database_path();
+    let options = Options {
+        metrics_recorder: Some(recorder.clone()),
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    db.register_schema("typed", Schema::new(KeyType::Map, ValueCodec::Fixed(1)));
+    db.map_put("typed", "a", "1").unwrap();
+    assert!(db.map_put("typed", "a", "12").is_err());
+    db.list_right_push("list", b"a").unwrap();
+    db.delete_all("list").unwrap();
+    assert_eq!(
+        vec![
+            ("map_put", Some(KeyType::Map), true),
+            ("map_put", Some(KeyType::Map), false),
+            ("list_right_push", Some(KeyType::List), true),
+            ("delete_all", None, true),
+        ],
+        *recorder.ops.lock().unwrap()
+    );
+}
+
+#[test]
+fn test_health_check() {
+    let db = open_database();
+    let status = db.health_check().unwrap();
+    assert!(status.is_healthy());
+    assert!(status.last_flush_time.is_none());
+    db.map_put("hello", "a", "1").unwrap();
+    db.rocksdb.flush().unwrap();
+    let status = db.health_check().unwrap();
+    assert!(status.last_flush_time.is_some());
+    assert!(!status.write_stopped);
+    #[cfg(unix)]
+    assert!(status.free_disk_space.unwrap() > 0);
+}
+
+#[test]
+fn test_background_tasks() {
+    let db = open_database();
+    let ticks = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let ticks2 = ticks.clone();
+    db.background_tasks()
+        .spawn("tick", Duration::from_millis(1), move |_| {
+            if ticks2.fetch_add(1, std::sync::atomic::Ordering::SeqCst) == 0 {
+                panic!("first tick");
+            }
+        });
+    db.background_tasks()
+        .spawn("wait", Duration::from_secs(3600), |stop| {
+            stop.wait(Duration::from_secs(3600));
+        });
+    while ticks.load(std::sync::atomic::Ordering::SeqCst) < 3 {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(1, db.background_tasks().panic_count());
+    assert!(db.background_tasks().stop("tick"));
+    assert!(!db.background_tasks().stop("missing"));
+    assert!(db.background_tasks().shutdown(Duration::from_secs(5)));
+    assert!(db.background_tasks().names().is_empty());
+}
+
+#[test]
+fn test_verify_on_open() {
+    let path = get_random_database_path();
+    {
+        let db = Database::open(&path).unwrap();
+        db.map_put("good", "a", "1").unwrap();
+        db.rocksdb.put(b"mshort", [1u8, 2, 3]).unwrap();
+        let mut meta = [0u8; 17];
+        meta[8] = 99;
+        db.rocksdb.put(b"munknown", meta).unwrap();
+    }
+    let options = Options {
+        verify_on_open: true,
+        quarantine_on_verify: true,
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    let report = db.verify_report();
+    assert_eq!(2, report.len());
+    assert_eq!(b"mshort".as_ref(), report[0].meta_key.as_ref());
+    assert_eq!(b"munknown".as_ref(), report[1].meta_key.as_ref());
+    let keys = db.keys().unwrap();
+    assert_eq!(1, keys.len());
+    assert_eq!("good", keys[0].0);
+    assert!(db.rocksdb.get(b"qmshort").unwrap().is_some());
+    assert!(db.verify_metas(false).unwrap().is_empty());
+}
+
+#[test]
+fn test_persisted_next_key_id() {
+    let path = get_random_database_path();
+    {
+        let db = Database::open(&path).unwrap();
+        db.map_put("a", "f", "1").unwrap();
+        db.map_put("b", "f", "1").unwrap();
+        db.map_delete("b", "f").unwrap();
+        assert_eq!(
+            Some(3u64.to_be_bytes().to_vec()),
+            db.rocksdb.get(b"snext_key_id").unwrap()
+        );
+    }
+    {
+        // the deleted key ID is not reused
+        let db = Database::open(&path).unwrap();
+        assert_eq!(3, db.get_or_create_meta("c", KeyType::Map).unwrap().id);
+        db.rocksdb.delete(b"snext_key_id").unwrap();
+    }
+    let db = Database::open(&path).unwrap();
+    assert_eq!(4, db.get_or_create_meta("d", KeyType::Map).unwrap().id);
+}
+
+#[test]
+fn test_keys_of_type() {
+    let path = get_random_database_path();
+    {
+        let db = Database::open(&path).unwrap();
+        db.map_put("m1", "f", "1").unwrap();
+        db.map_put("m2", "f", "1").unwrap();
+        db.list_right_push("l1", b"a").unwrap();
+        db.value_put("v1", b"abc").unwrap();
+        db.value_put("v1", b"def").unwrap();
+        assert_eq!(vec!["m1", "m2"], db.keys_of_type(KeyType::Map).unwrap());
+        assert_eq!(vec!["v1"], db.keys_of_type(KeyType::Value).unwrap());
+        db.map_delete("m1", "f").unwrap();
+        db.delete_all("l1").unwrap();
+        assert_eq!(vec!["m2"], db.keys_of_type(KeyType::Map).unwrap());
+        assert!(db.keys_of_type(KeyType::List).unwrap().is_empty());
+        // simulate a database created by an old version
+        db.rocksdb.delete(b"stype_index_ready").unwrap();
+        db.rocksdb.delete(b"t\x01m2").unwrap();
+    }
+    let db = Database::open(&path).unwrap();
+    assert_eq!(vec!["m2"], db.keys_of_type(KeyType::Map).unwrap());
+    assert_eq!(2, db.rebuild_type_index().unwrap());
+}
+
+#[test]
+fn test_expired_callbacks() {
+    let db = open_database();
+    let expired = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let expired2 = expired.clone();
+    db.on_expired(move |entry| {
+        expired2.lock().unwrap().push((
+            entry.key.to_string(),
+            entry.key_type,
+            entry.field.to_vec(),
+        ));
+    });
+    let sessions = Sessions::new(&db, "sessions");
+    let token = sessions.create_session(b"a", Duration::ZERO).unwrap();
+    assert!(sessions.get_session(&token).unwrap().is_none());
+    db.idempotency_check_and_set("requests", "r1", b"ok", Duration::ZERO)
+        .unwrap();
+    assert_eq!(1, db.idempotency_purge_expired("requests").unwrap());
+    assert_eq!(
+        vec![
+            ("sessions".to_string(), KeyType::Map, token.into_bytes()),
+            ("requests".to_string(), KeyType::Map, b"r1".to_vec()),
+        ],
+        *expired.lock().unwrap()
+    );
+}
+
+#[test]
+fn test_get_counts() {
+    let db = open_database();
+    db.list_right_push("a", b"1").unwrap();
+    db.list_right_push("a", b"2").unwrap();
+    db.set_add("c", b"1").unwrap();
+    assert_eq!(vec![2, 0, 1], db.get_counts(&["a", "b", "c"]).unwrap());
+    assert!(db.get_counts(&[]).unwrap().is_empty());
+}
+
+#[test]
+fn test_delete_keys() {
+    let db = open_database();
+    db.map_put("a", "f", "1").unwrap();
+    db.sorted_set_add("b", &get_score_bytes(1i64), b"x")
+        .unwrap();
+    db.sorted_list_add("c", &get_score_bytes(1i64), b"x")
+        .unwrap();
+    db.sorted_list_reserve("c", None, Duration::from_secs(60))
+        .unwrap()
+        .unwrap();
+    db.set_add("s", b"x").unwrap();
+    db.set_register_sketch("s").unwrap();
+    db.list_right_push("keep", b"x").unwrap();
+    assert_eq!(4, db.delete_keys(&["a", "b", "c", "s", "missing"]).unwrap());
+    assert_eq!(db.raw_scan(PREFIX_SKETCH, |_, _, _| true).unwrap(), 0);
+    assert!(db.get_meta("a").unwrap().is_none());
+    assert!(db.map_get_pinned("a", "f").unwrap().is_none());
+    assert!(db.sorted_set_items("b").unwrap().is_empty());
+    let keys = db.keys().unwrap();
+    assert_eq!(1, keys.len());
+    assert_eq!("keep", keys[0].0);
+    assert!(db.keys_of_type(KeyType::Map).unwrap().is_empty());
+    let mut rows = 0;
+    for (k, _) in db.rocksdb.iterator(simpledb::rocksdb::IteratorMode::Start) {
+        if k[0] == b'd' || k[0] == b'r' {
+            rows += 1;
+        }
+    }
+    assert_eq!(1, rows);
+
+    // a key named twice, or by its aliases, is deleted once
+    db.set_add("s2", b"x").unwrap();
+    db.alias_key("s2-alias", "s2").unwrap();
+    db.alias_key("s2-other", "s2").unwrap();
+    assert_eq!(
+        1,
+        db.delete_keys(&["s2", "s2", "s2-alias", "s2-other"])
+            .unwrap()
+    );
+    assert!(db.get_meta("s2").unwrap().is_none());
+}
+
+#[test]
+fn test_compact_all() {
+    let db = open_database();
+    for i in 0..10 {
+        db.list_right_push(format!("list{}", i), b"x").unwrap();
+    }
+    let mut calls = Vec::new();
+    assert!(db
+        .compact_all(|p| {
+            calls.push((p.done, p.total));
+            true
+        })
+        .unwrap());
+    assert_eq!(vec![(1, 3), (2, 3), (3, 3)], calls);
+    assert_eq!(10, db.keys().unwrap().len());
+
+    let mut calls = 0;
+    assert!(!db
+        .compact_all(|_| {
+            calls += 1;
+            false
+        })
+        .unwrap());
+    assert_eq!(1, calls);
+}
+
+#[test]
+fn test_raw_scan() {
+    let db = open_database();
+    db.map_put("map", "f", "1").unwrap();
+    db.list_right_push("list", b"a").unwrap();
+    db.sorted_set_add("zset", &get_score_bytes(7i64), b"m")
+        .unwrap();
+    let mut rows = Vec::new();
+    let count = db
+        .raw_scan(b"", |_, row, _| {
+            rows.push(row.clone());
+            true
+        })
+        .unwrap();
+    assert_eq!(count as usize, rows.len());
+    assert!(rows
+        .iter()
+        .any(|r| matches!(r, RawRow::MapItem { field, .. } if field.as_ref() == b"f")));
+    assert!(rows.iter().any(|r| matches!(r, RawRow::ListItem { .. })));
+    assert!(rows
+        .iter()
+        .any(|r| matches!(r, RawRow::SortedSetItem { member, .. } if member.as_ref() == b"m")));
+    assert!(rows
+        .iter()
+        .any(|r| matches!(r, RawRow::Meta { key, meta: Some(_) } if key.as_ref() == b"map")));
+    assert!(rows.iter().any(|r| matches!(r, RawRow::System { .. })));
+    assert!(rows
+        .iter()
+        .all(|r| !matches!(r, RawRow::Unknown | RawRow::Data { .. })));
+    assert_eq!(3, db.raw_scan(b"m", |_, _, _| true).unwrap());
+}
+
+#[test]
+fn test_sorted_set_items_with_limit() {
+    let db = open_database();
+    let key = "hello";
+    assert_eq!(0, db.sorted_set_items_with_limit(key, 10).unwrap().len());
+    for i in 0..10 {
+        db.sorted_set_add(
+            key,
+            get_score_bytes(i).as_slice(),
+            format!("m{}", i).as_bytes(),
+        )
+        .unwrap();
+    }
+    let vec = db.sorted_set_items_with_limit(key, 3).unwrap();
+    assert_eq!(3, vec.len());
+    assert_eq!(b"m0", vec[0].1.as_ref());
+    assert_eq!(b"m2", vec[2].1.as_ref());
+    assert_eq!(10, db.sorted_set_items_with_limit(key, 100).unwrap().len());
+    assert_eq!(0, db.sorted_set_items_with_limit(key, 0).unwrap().len());
+
+    let mut buf = Vec::new();
+    assert_eq!(10, db.sorted_set_write_to(key, &mut buf).unwrap());
+    let mut reader = buf.as_slice();
+    let read_bytes = |reader: &mut &[u8]| {
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len).unwrap();
+        let mut b = vec![0u8; u32::from_be_bytes(len) as usize];
+        reader.read_exact(&mut b).unwrap();
+        b
+    };
+    for i in 0..10 {
+        assert_eq!(get_score_bytes(i), read_bytes(&mut reader));
+        assert_eq!(format!("m{}", i).into_bytes(), read_bytes(&mut reader));
+    }
+    assert!(reader.is_empty());
+    assert_eq!(0, db.sorted_set_write_to("none", &mut Vec::new()).unwrap());
+}
+
+#[test]
+fn test_set_sample() {
+    let db = open_database();
+    let key = "hello";
+    assert_eq!(0, db.set_sample(key, 5, 1).unwrap().len());
+    for i in 0..100 {
+        db.set_add(key, format!("m{:03}", i).as_bytes()).unwrap();
+    }
+    let vec = db.set_items_with_limit(key, 3).unwrap();
+    assert_eq!(3, vec.len());
+    assert_eq!(b"m000", vec[0].as_ref());
+    assert_eq!(100, db.set_items_with_limit(key, 1000).unwrap().len());
+
+    let sample = db.set_sample(key, 10, 42).unwrap();
+    assert_eq!(10, sample.len());
+    assert_eq!(sample, db.set_sample(key, 10, 42).unwrap());
+    for v in sample.iter() {
+        assert!(db.set_is_member(key, v).unwrap());
+    }
+    assert_eq!(100, db.set_sample(key, 200, 42).unwrap().len());
+    assert_eq!(0, db.set_sample(key, 0, 42).unwrap().len());
+}
+
+#[test]
+fn test_list_items_range() {
+    let db = open_database();
+    let key = "hello";
+    assert_eq!(0, db.list_items_range(key, 0, -1).unwrap().len());
+    for i in 0..5 {
+        db.list_right_push(key, format!("r{}", i).as_bytes())
+            .unwrap();
+        db.list_left_push(key, format!("l{}", i).as_bytes())
+            .unwrap();
+    }
+    let range = |start: i64, stop: i64| {
+        db.list_items_range(key, start, stop)
+            .unwrap()
+            .iter()
+            .map(|v| String::from_utf8(v.to_vec()).unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(vec!["l4", "l3"], range(0, 1));
+    assert_eq!(vec!["l0", "r0", "r1"], range(4, 6));
+    assert_eq!(vec!["r3", "r4"], range(-2, -1));
+    assert_eq!(10, range(0, -1).len());
+    assert_eq!(10, range(-100, 100).len());
+    assert!(range(5, 4).is_empty());
+    assert!(range(10, 20).is_empty());
+    db.list_left_pop(key).unwrap();
+    assert_eq!(vec!["l3"], range(0, 0));
+
+    db.sorted_set_add("zset", &get_score_bytes(1), b"a")
+        .unwrap();
+    assert!(db.list_items_range("zset", 0, -1).is_err());
+}
+
+#[test]
+fn test_alias_key() {
+    let db = open_database();
+    db.map_put("new_name", "a", "1").unwrap();
+    db.alias_key("old_name", "new_name").unwrap();
+    db.alias_key("older_name", "old_name").unwrap();
+    assert!(db.alias_key("new_name", "new_name").is_err());
+    assert!(db.alias_key("new_name", "other").is_err());
+    assert_eq!(vec!["old_name", "older_name"], db.aliases_of("new_name"));
+    assert_eq!(vec!["old_name", "older_name"], db.aliases_of("old_name"));
+
+    assert_eq!(Some(b"1".to_vec()), db.map_get("older_name", "a").unwrap());
+    db.map_put("old_name", "b", "2").unwrap();
+    assert_eq!(2, db.map_count("new_name").unwrap());
+    assert_eq!(
+        db.get_meta("old_name").unwrap().unwrap().id,
+        db.get_meta("new_name").unwrap().unwrap().id
+    );
+    assert_eq!(
+        vec![2, 2],
+        db.get_counts(&["old_name", "new_name"]).unwrap()
+    );
+    assert_eq!(1, db.keys().unwrap().len());
+    assert_eq!(2, db.read_view().get_count("older_name").unwrap());
+
+    assert!(db.remove_alias("older_name").unwrap());
+    assert!(!db.remove_alias("older_name").unwrap());
+    assert_eq!(0, db.get_count("older_name").unwrap());
+
+    assert_eq!(2, db.delete_all("old_name").unwrap());
+    assert!(db.get_meta("new_name").unwrap().is_none());
+    assert!(db.aliases_of("new_name").is_empty());
+}
+
+#[test]
+fn test_compact_on_open() {
+    let path = get_random_database_path();
+    {
+        let db = Database::open(&path).unwrap();
+        for i in 0..100 {
+            db.map_put("a", format!("f{}", i), "1").unwrap();
+        }
+        db.rocksdb.flush().unwrap();
+        for i in 0..100 {
+            db.map_delete("a", format!("f{}", i)).unwrap();
+        }
+        db.rocksdb.flush().unwrap();
+        assert!(db.tombstone_ratio().unwrap() > 0.3);
+        assert!(!db.compact_on_open(CompactOnOpen::Disabled).unwrap());
+        assert!(!db
+            .compact_on_open(CompactOnOpen::TombstoneRatio(0.9))
+            .unwrap());
+    }
+    let options = Options {
+        compact_on_open: CompactOnOpen::TombstoneRatio(0.3),
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    assert_eq!(0.0, db.tombstone_ratio().unwrap());
+    assert!(db.compact_on_open(CompactOnOpen::Full).unwrap());
+    assert_eq!(0, db.map_count("a").unwrap());
+}
+
+#[test]
+fn test_map_move_and_merge() {
+    let db = open_database();
+    db.map_put("a", "x", "1").unwrap();
+    db.map_put("a", "y", "2").unwrap();
+    db.map_put("b", "y", "20").unwrap();
+    db.map_put("b", "z", "30").unwrap();
+
+    assert!(!db.map_move_field("a", "b", "none").unwrap());
+    assert!(!db.map_move_field("none", "b", "x").unwrap());
+    assert!(db.map_move_field("a", "b", "x").unwrap());
+    assert_eq!(1, db.map_count("a").unwrap());
+    assert_eq!(3, db.map_count("b").unwrap());
+    assert_eq!(Some(b"1".to_vec()), db.map_get("b", "x").unwrap());
+    assert!(db.map_move_field("a", "c", "y").unwrap());
+    assert!(db.get_meta("a").unwrap().is_none());
+    assert_eq!(1, db.map_count("c").unwrap());
+
+    db.map_put("c", "z", "300").unwrap();
+    assert_eq!(0, db.map_merge("b", "c", OnConflict::KeepDst).unwrap());
+    assert!(db.get_meta("c").unwrap().is_none());
+    assert_eq!(3, db.map_count("b").unwrap());
+    assert_eq!(Some(b"30".to_vec()), db.map_get("b", "z").unwrap());
+    assert_eq!(Some(b"20".to_vec()), db.map_get("b", "y").unwrap());
+
+    db.map_put("d", "z", "3000").unwrap();
+    db.map_put("d", "w", "4000").unwrap();
+    assert_eq!(2, db.map_merge("b", "d", OnConflict::Overwrite).unwrap());
+    assert_eq!(4, db.map_count("b").unwrap());
+    assert_eq!(Some(b"3000".to_vec()), db.map_get("b", "z").unwrap());
+    assert_eq!(0, db.map_merge("b", "none", OnConflict::Overwrite).unwrap());
+
+    // the source must be a map
+    db.set_add("set", b"x").unwrap();
+    assert!(db.map_move_field("set", "b", "x").is_err());
+    assert!(db.map_merge("b", "set", OnConflict::Overwrite).is_err());
+    assert_eq!(4, db.map_count("b").unwrap());
+    assert_eq!(1, db.get_count("set").unwrap());
+
+    // so is the destination
+    assert!(db.map_move_field("b", "set", "x").is_err());
+    assert!(db.map_merge("set", "b", OnConflict::Overwrite).is_err());
+    assert_eq!(4, db.map_count("b").unwrap());
+    assert_eq!(1, db.get_count("set").unwrap());
+}
+
+type RemoteRow = (String, Vec<u8>, Vec<u8>);
+
+#[derive(Default)]
+struct TestTier {
+    remote: std::sync::Mutex<Vec<RemoteRow>>,
+}
+
+impl Tier for TestTier {
+    fn fetch_missing(&self, key: &str, field: &[u8]) -> simpledb::Result<Option<Vec<u8>>> {
+        Ok(self
+            .remote
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(k, f, _)| k == key && f == field)
+            .map(|(_, _, v)| v.clone()))
+    }
+
+    fn evict(&self, key: &str, items: &[FieldVal]) -> simpledb::Result<()> {
+        let mut remote = self.remote.lock().unwrap();
+        for (f, v) in items {
+            remote.push((key.to_string(), f.to_vec(), v.to_vec()));
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_tier() {
+    let tier = std::sync::Arc::new(TestTier::default());
+    let path = get_random_database_path();
+    let options = Options {
+        tier: Some(tier.clone()),
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    db.map_put("a", "x", "1").unwrap();
+    db.map_put("a", "y", "2").unwrap();
+    assert!(db.evict("a").unwrap());
+    assert!(!db.evict("a").unwrap());
+    assert!(db.get_meta("a").unwrap().is_none());
+    assert_eq!(2, tier.remote.lock().unwrap().len());
+
+    assert_eq!(Some(b"2".to_vec()), db.map_get("a", "y").unwrap());
+    assert_eq!(1, db.map_count("a").unwrap());
+    assert_eq!(None, db.map_get("a", "z").unwrap());
+    tier.remote.lock().unwrap().clear();
+    assert_eq!(Some(b"2".to_vec()), db.map_get("a", "y").unwrap());
+
+    db.list_right_push("list", b"x").unwrap();
+    assert!(db.evict("list").is_err());
+}
+
+#[test]
+fn test_copy_key_to() {
+    let src = open_database();
+    let dst_path = get_random_database_path();
+    {
+        let dst = Database::open(&dst_path).unwrap();
+        for i in 0..10 {
+            src.sorted_set_add(
+                "zset",
+                get_score_bytes(i).as_slice(),
+                format!("m{}", i).as_bytes(),
+            )
+            .unwrap();
+        }
+        src.map_put("map", "a", "1").unwrap();
+        assert_eq!(20, src.copy_key_to("zset", &dst).unwrap());
+        assert_eq!(0, src.copy_key_to("none", &dst).unwrap());
+        assert!(src.copy_key_to("zset", &dst).is_err());
+        assert_eq!(
+            src.sorted_set_items("zset").unwrap(),
+            dst.sorted_set_items("zset").unwrap()
+        );
+        assert_eq!(vec!["zset"], dst.keys_of_type(KeyType::SortedSet).unwrap());
+
+        // simulate an interrupted transfer
+        dst.rocksdb.put(b"jmap", 1000u64.to_be_bytes()).unwrap();
+        dst.rocksdb.put(b"d\0\0\0\0\0\0\x03\xe8a", b"1").unwrap();
+    }
+    let dst = Database::open(&dst_path).unwrap();
+    assert!(dst.rocksdb.get(b"jmap").unwrap().is_none());
+    assert!(dst
+        .rocksdb
+        .get(b"d\0\0\0\0\0\0\x03\xe8a")
+        .unwrap()
+        .is_none());
+    assert_eq!(10, dst.sorted_set_items("zset").unwrap().len());
+    assert_eq!(1, src.copy_key_to("map", &dst).unwrap());
+    assert_eq!(Some(b"1".to_vec()), dst.map_get("map", "a").unwrap());
+}
+
+/// A counter keyed by the first byte of the items.
+struct Counter;
+
+impl CustomKeyType for Counter {
+    fn name(&self) -> &str {
+        "Counter"
+    }
+
+    fn encode_item(&self, item: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        (item[..1].to_vec(), item[1..].to_vec())
+    }
+
+    fn decode_item(&self, suffix: &[u8], value: &[u8]) -> Vec<u8> {
+        [suffix, value].concat()
+    }
+}
+
+#[test]
+fn test_custom_key_type() {
+    let db = open_database();
+    assert!(db
+        .register_key_type(1, std::sync::Arc::new(Counter))
+        .is_err());
+    assert!(db.custom_put("c", 200, b"a1").is_err());
+    db.register_key_type(200, std::sync::Arc::new(Counter))
+        .unwrap();
+    assert!(db
+        .register_key_type(200, std::sync::Arc::new(Counter))
+        .is_err());
+
+    assert!(db.custom_put("c", 200, b"a1").unwrap());
+    assert!(db.custom_put("c", 200, b"b2").unwrap());
+    assert!(!db.custom_put("c", 200, b"a3").unwrap());
+    assert_eq!(
+        vec![b"a3".to_vec(), b"b2".to_vec()],
+        db.custom_items("c").unwrap()
+    );
+    assert_eq!(2, db.get_count("c").unwrap());
+    let meta = db.get_meta("c").unwrap().unwrap();
+    assert_eq!(KeyType::Custom(200), meta.key_type);
+    assert_eq!("Counter", db.key_type_name(meta.key_type));
+    assert_eq!("Map", db.key_type_name(KeyType::Map));
+    assert_eq!(vec!["c"], db.keys_of_type(KeyType::Custom(200)).unwrap());
+
+    db.map_put("m", "a", "1").unwrap();
+    assert!(db.custom_put("m", 200, b"a1").is_err());
+    assert!(db.custom_items("m").is_err());
+
+    assert!(db.custom_delete("c", 200, b"b").unwrap());
+    assert!(!db.custom_delete("c", 200, b"b").unwrap());
+    assert_eq!(1, db.delete_all("c").unwrap());
+    assert!(db.get_meta("c").unwrap().is_none());
+}
+
+#[test]
+fn test_keys_after() {
+    let db = open_database();
+    assert!(db.keys_after(None, 10).unwrap().is_empty());
+    for i in 0..10 {
+        db.map_put(format!("key{}", i), "f", "1").unwrap();
+    }
+    db.map_put("key1a", "f", "1").unwrap();
+    let names = |page: Vec<(String, _)>| page.into_iter().map(|(k, _)| k).collect::<Vec<_>>();
+    assert_eq!(
+        vec!["key0", "key1", "key1a"],
+        names(db.keys_after(None, 3).unwrap())
+    );
+    assert_eq!(
+        vec!["key1a", "key2"],
+        names(db.keys_after(Some("key1"), 2).unwrap())
+    );
+    assert_eq!(
+        vec!["key2", "key3"],
+        names(db.keys_after(Some("key1a"), 2).unwrap())
+    );
+    assert_eq!(vec!["key9"], names(db.keys_after(Some("key8"), 5).unwrap()));
+    assert!(db.keys_after(Some("key9"), 5).unwrap().is_empty());
+    assert!(db.keys_after(None, 0).unwrap().is_empty());
+
+    let mut all = Vec::new();
+    let mut last: Option<String> = None;
+    loop {
+        let page = db.keys_after(last.as_deref(), 4).unwrap();
+        if page.is_empty() {
+            break;
+        }
+        last = page.last().map(|(k, _)| k.clone());
+        all.extend(names(page));
+    }
+    assert_eq!(11, all.len());
+}
+
+#[test]
+fn test_reader() {
+    let db = open_database();
+    db.map_put("a", "x", "1").unwrap();
+    let mut reader = db.reader(1 << 20, Duration::from_secs(3600)).unwrap();
+    assert_eq!(
+        Some(b"1".to_vec()),
+        reader.read_view().unwrap().map_get("a", "x").unwrap()
+    );
+
+    db.map_put("a", "y", "2").unwrap();
+    assert_eq!(1, reader.read_view().unwrap().get_count("a").unwrap());
+    reader.refresh().unwrap();
+    assert_eq!(2, reader.read_view().unwrap().get_count("a").unwrap());
+
+    let mut reader = db.reader(1 << 20, Duration::ZERO).unwrap();
+    db.map_put("a", "z", "3").unwrap();
+    let handle = std::thread::spawn(move || reader.read_view().unwrap().map_items("a").unwrap());
+    assert_eq!(3, handle.join().unwrap().len());
+}
+
+#[test]
+fn test_sorted_set_add_ex() {
+    let db = open_database();
+    let key = "active";
+    let expired = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let expired2 = expired.clone();
+    db.on_expired(move |e| expired2.lock().unwrap().push(e.field.to_vec()));
+    db.sorted_set_add(key, get_score_bytes(1).as_slice(), b"a")
+        .unwrap();
+    db.sorted_set_add_ex(key, get_score_bytes(2).as_slice(), b"b", Duration::ZERO)
+        .unwrap();
+    db.sorted_set_add_ex(
+        key,
+        get_score_bytes(3).as_slice(),
+        b"c",
+        Duration::from_secs(3600),
+    )
+    .unwrap();
+
+    let members = |items: VecScoreVal| {
+        items
+            .into_iter()
+            .map(|(_, m)| m.to_vec())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        vec![b"a".to_vec(), b"c".to_vec()],
+        members(db.sorted_set_items(key).unwrap())
+    );
+    assert_eq!(
+        vec![b"a".to_vec(), b"c".to_vec()],
+        members(db.sorted_set_left(key, None, 10).unwrap())
+    );
+    assert_eq!(
+        vec![b"c".to_vec(), b"a".to_vec()],
+        members(db.sorted_set_right(key, None, 10).unwrap())
+    );
+    assert!(db.sorted_set_is_member(key, b"a").unwrap());
+    assert!(!db.sorted_set_is_member(key, b"b").unwrap());
+    assert!(db.sorted_set_is_member(key, b"c").unwrap());
+    assert_eq!(3, db.get_count(key).unwrap());
+
+    assert_eq!(1, db.sorted_set_purge_expired(key).unwrap());
+    assert_eq!(0, db.sorted_set_purge_expired(key).unwrap());
+    assert_eq!(2, db.get_count(key).unwrap());
+    assert_eq!(vec![b"b".to_vec()], *expired.lock().unwrap());
+    assert_eq!(0, db.sorted_set_purge_expired("none").unwrap());
+
+    // the sweeper purges all the sorted sets
+    for key in ["s1", "s2"] {
+        db.sorted_set_add_ex(key, get_score_bytes(1).as_slice(), b"x", Duration::ZERO)
+            .unwrap();
+    }
+    db.sorted_set_add_ex("s2", get_score_bytes(2).as_slice(), b"y", Duration::ZERO)
+        .unwrap();
+    let handle = DatabaseHandle::new(db);
+    handle.start_sorted_set_sweeper(Duration::from_millis(1));
+    assert!(handle
+        .background_tasks()
+        .names()
+        .contains(&SORTED_SET_SWEEPER_TASK_NAME.to_string()));
+    while handle.get_count("s1").unwrap() + handle.get_count("s2").unwrap() > 0 {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(2, handle.get_count(key).unwrap());
+}
+
+#[test]
+fn test_dump_and_restore_key() {
+    let src = open_database();
+    let dst = open_database();
+    assert!(src.dump_key("none").unwrap().is_none());
+    for i in 0..5 {
+        src.list_right_push("list", format!("v{}", i).as_bytes())
+            .unwrap();
+    }
+    let blob = src.dump_key("list").unwrap().unwrap();
+    assert_eq!(5, dst.restore_key("list", &blob).unwrap());
+    assert_eq!(
+        src.list_items("list").unwrap(),
+        dst.list_items("list").unwrap()
+    );
+    assert_eq!(vec!["list"], dst.keys_of_type(KeyType::List).unwrap());
+    assert!(dst.restore_key("list", &blob).is_err());
+    dst.list_right_push("list", b"v5").unwrap();
+    assert_eq!(6, dst.list_count("list").unwrap());
+
+    assert_eq!(5, src.restore_key("copy", &blob).unwrap());
+    assert_eq!(
+        src.list_items("list").unwrap(),
+        src.list_items("copy").unwrap()
+    );
+
+    let mut bad = blob.clone();
+    let n = bad.len();
+    bad[n - 5] ^= 1;
+    assert!(dst.restore_key("bad", &bad).is_err());
+    assert!(dst.restore_key("bad", b"nope").is_err());
+    assert!(dst.get_meta("bad").unwrap().is_none());
+}
+
+#[test]
+fn test_binary_key_names() {
+    let db = open_database();
+    let binary: &[u8] = b"bin\xff\x00key";
+    db.map_put("text", "f", "1").unwrap();
+    db.map_put(binary, "f", "2").unwrap();
+    assert_eq!(Some(b"2".to_vec()), db.map_get(binary, "f").unwrap());
+    assert!(db.keys().is_err());
+    let keys = db.keys_bytes();
+    assert_eq!(2, keys.len());
+    assert_eq!(binary, keys[0].0.as_ref());
+    assert_eq!(b"text", keys[1].0.as_ref());
+    let mut count = 0;
+    assert_eq!(
+        1,
+        db.for_each_key_bytes(|_, _| {
+            count += 1;
+            false
+        })
+    );
+    assert_eq!(1, count);
+    assert_eq!(
+        vec![Some(b"2".to_vec()), Some(b"1".to_vec())],
+        db.multi_map_get(&[(binary, b"f" as &[u8]), (b"text", b"f")])
+            .unwrap()
+    );
+
+    let list: &[u8] = b"list\xff";
+    db.list_right_push(list, b"a").unwrap();
+    assert_eq!(vec![Box::from(&b"a"[..])], db.list_items(list).unwrap());
+    let set: &[u8] = b"set\xff";
+    assert!(db.set_add(set, b"a").unwrap());
+    assert!(db.set_is_member(set, b"a").unwrap());
+    let sorted_list: &[u8] = b"sorted_list\xff";
+    let sorted_list2: &[u8] = b"sorted_list\xfe";
+    db.sorted_list_add(sorted_list, b"1", b"a").unwrap();
+    assert_eq!(
+        Some((0, (Box::from(&b"1"[..]), Box::from(&b"a"[..])))),
+        db.sorted_list_pop_any(&[sorted_list, sorted_list2], None)
+            .unwrap()
+    );
+    let sorted_set: &[u8] = b"sorted_set\xff";
+    db.sorted_set_add(sorted_set, b"1", b"a").unwrap();
+    assert_eq!(1, db.sorted_set_count(sorted_set).unwrap());
+    assert_eq!(5, db.keys_bytes().len());
+
+    let path = get_random_database_path();
+    let options = Options {
+        strict_utf8_keys: true,
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    assert!(db.map_put(binary, "f", "2").is_err());
+    db.map_put("text", "f", "1").unwrap();
+    assert_eq!(1, db.keys().unwrap().len());
+}
+
+#[derive(Default)]
+struct TestCompactionListener {
+    events: std::sync::Mutex<Vec<(bool, CompactionEvent)>>,
+}
+
+impl CompactionListener for TestCompactionListener {
+    fn on_compaction_start(&self, event: &CompactionEvent) {
+        self.events.lock().unwrap().push((false, event.clone()));
+    }
+
+    fn on_compaction_finish(&self, event: &CompactionEvent, _duration: Duration) {
+        self.events.lock().unwrap().push((true, event.clone()));
+    }
+}
+
+#[test]
+fn test_compaction_listener() {
+    let listener = std::sync::Arc::new(TestCompactionListener::default());
+    let path = get_random_database_path();
+    let options = Options {
+        compaction_listener: Some(listener.clone()),
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    for i in 0..100 {
+        db.map_put("map", format!("f{}", i), "value").unwrap();
+    }
+    db.flush().unwrap();
+    {
+        let events = listener.events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(!events[0].0 && events[1].0);
+        assert_eq!(events[1].1.kind, CompactionKind::Flush);
+        assert!(events[1].1.input_bytes > 0);
+        assert!(events[1].1.output_bytes > 0);
+    }
+
+    assert!(db.compact_on_open(CompactOnOpen::Full).unwrap());
+    let events = listener.events.lock().unwrap();
+    assert_eq!(events.len(), 4);
+    let (finished, event) = &events[3];
+    assert!(finished);
+    assert_eq!(event.kind, CompactionKind::Compaction);
+    assert!(event.input_bytes > 0);
+    assert!(event.output_bytes > 0);
+    assert!(event.output_level > 0);
+}
+
+#[test]
+fn test_export_import_range() {
+    let db = open_database();
+    db.map_put("app:a", "f", "1").unwrap();
+    db.list_right_push("app:b", b"x").unwrap();
+    db.list_right_push("app:b", b"y").unwrap();
+    db.set_add("app:c", b"m").unwrap();
+    db.map_put("other", "f", "2").unwrap();
+
+    let mut buf = Vec::new();
+    assert_eq!(db.export_range("app:", Some("app:c"), &mut buf).unwrap(), 2);
+
+    let dst = open_database();
+    assert_eq!(dst.import_range(buf.as_slice()).unwrap(), 2);
+    assert_eq!(
+        dst.map_get("app:a", "f").unwrap().as_deref(),
+        Some(&b"1"[..])
+    );
+    assert_eq!(
+        dst.list_items("app:b").unwrap(),
+        vec![Box::from(&b"x"[..]), Box::from(&b"y"[..])]
+    );
+    assert!(dst.get_meta("app:c").unwrap().is_none());
+    assert!(dst.get_meta("other").unwrap().is_none());
+    assert!(dst.import_range(buf.as_slice()).is_err());
+
+    // a key name which is not UTF-8 round-trips
+    db.list_right_push(&b"app:\xff"[..], b"z").unwrap();
+    let mut buf = Vec::new();
+    assert_eq!(db.export_range("app:c", None, &mut buf).unwrap(), 3);
+    assert_eq!(dst.import_range(buf.as_slice()).unwrap(), 3);
+    assert!(dst.set_is_member("app:c", b"m").unwrap());
+    assert_eq!(
+        dst.list_items(&b"app:\xff"[..]).unwrap(),
+        vec![Box::from(&b"z"[..])]
+    );
+    assert!(dst.import_range(&b"garbage!"[..]).is_err());
+    // a broken length fails without allocating it
+    let stream = [&b"SDBEXPT1"[..], &[1], &u32::MAX.to_be_bytes()].concat();
+    assert!(dst.import_range(stream.as_slice()).is_err());
+    let stream = [&b"SDBEXPT1"[..], &[1], &1000u32.to_be_bytes(), b"app:d"].concat();
+    assert!(dst.import_range(stream.as_slice()).is_err());
+}
+
+#[test]
+fn test_sorted_list_compact_deletes_count() {
+    let db = open_database();
+    assert!(!db.sorted_list_compact("queue").unwrap());
+    assert!(!db
+        .sorted_list_set_compact_deletes_count("queue", 2)
+        .unwrap());
+    for i in 0..10u64 {
+        db.sorted_list_add("queue", &i.to_be_bytes(), b"job")
+            .unwrap();
+    }
+    assert!(db
+        .sorted_list_set_compact_deletes_count("queue", 2)
+        .unwrap());
+    let meta = db.get_meta("queue").unwrap().unwrap();
+    assert_eq!(meta.decode_sorted_list_compact_deletes_count(), 2);
+    assert_eq!(meta.decode_sorted_list_extra(), (10, 0, 0));
+
+    db.sorted_list_left_pop("queue", None).unwrap();
+    db.sorted_list_left_pop("queue", None).unwrap();
+    let meta = db.get_meta("queue").unwrap().unwrap();
+    assert_eq!(meta.decode_sorted_list_extra(), (10, 2, 0));
+    // the third pop reaches the per-key count and resets the counter
+    db.sorted_list_left_pop("queue", None).unwrap();
+    let meta = db.get_meta("queue").unwrap().unwrap();
+    assert_eq!(meta.decode_sorted_list_extra(), (10, 0, 0));
+    assert_eq!(meta.decode_sorted_list_compact_deletes_count(), 2);
+
+    db.sorted_list_right_pop("queue", None).unwrap();
+    assert!(db.sorted_list_compact("queue").unwrap());
+    let meta = db.get_meta("queue").unwrap().unwrap();
+    assert_eq!(meta.decode_sorted_list_extra(), (10, 0, 0));
+    assert_eq!(db.sorted_list_count("queue").unwrap(), 6);
+    assert!(db.verify_metas(false).unwrap().is_empty());
+
+    // the extra of the other key types is not touched
+    db.sorted_set_add("set", &get_score_bytes(1u32), b"a")
+        .unwrap();
+    assert!(db.sorted_list_compact("set").is_err());
+    assert!(db.sorted_list_set_compact_deletes_count("set", 2).is_err());
+    assert!(db.verify_metas(false).unwrap().is_empty());
+}
+
+#[test]
+fn test_map_value_len() {
+    let db = open_database();
+    assert_eq!(db.map_value_len("blobs", "a").unwrap(), None);
+    db.map_put("blobs", "a", vec![7u8; 4096]).unwrap();
+    db.map_put("blobs", "b", "").unwrap();
+    assert_eq!(db.map_value_len("blobs", "a").unwrap(), Some(4096));
+    assert_eq!(db.map_value_len("blobs", "b").unwrap(), Some(0));
+    assert_eq!(db.map_value_len("blobs", "c").unwrap(), None);
+}
+
+#[test]
+fn test_set_intersect_estimate() {
+    let db = open_database();
+    for i in 0..10000 {
+        db.set_add("a", format!("m{}", i).as_bytes()).unwrap();
+    }
+    for i in 5000..20000 {
+        db.set_add("b", format!("m{}", i).as_bytes()).unwrap();
+    }
+    assert!(db.set_intersect_estimate("a", "b").is_err());
+    assert!(db.set_register_sketch("a").unwrap());
+    assert!(!db.set_register_sketch("missing").unwrap());
+    assert_eq!(db.set_intersect_estimate("a", "missing").unwrap(), 0);
+    assert!(db.set_intersect_estimate("a", "b").is_err());
+
+    // the members added after the registration are counted too
+    db.set_add("c", b"m0").unwrap();
+    assert!(db.set_register_sketch("c").unwrap());
+    for i in 5000..20000 {
+        db.set_add("c", format!("m{}", i).as_bytes()).unwrap();
+    }
+    let estimate = db.set_intersect_estimate("a", "c").unwrap();
+    let exact = db.set_intersect_count("a", "c").unwrap();
+    assert_eq!(exact, 5001);
+    assert!((estimate as f64 - exact as f64).abs() < exact as f64 * 0.2);
+
+    db.delete_all("c").unwrap();
+    assert_eq!(db.raw_scan(PREFIX_SKETCH, |_, _, _| true).unwrap(), 1);
+
+    // the concurrent adds update the sketch and the count in the same batch
+    db.set_add("d", b"m0").unwrap();
+    assert!(db.set_register_sketch("d").unwrap());
+    std::thread::scope(|s| {
+        for t in 0..4 {
+            let db = &db;
+            s.spawn(move || {
+                for i in 0..1000 {
+                    db.set_add("d", format!("m{}", t * 1000 + i).as_bytes())
+                        .unwrap();
+                }
+            });
+        }
+    });
+    assert_eq!(db.get_count("d").unwrap(), 4000);
+    let sketch_key = [
+        PREFIX_SKETCH,
+        &db.get_meta("d").unwrap().unwrap().id.to_be_bytes(),
+    ]
+    .concat();
+    let sketch = db.rocksdb.get(&sketch_key).unwrap();
+    assert!(db.set_register_sketch("d").unwrap());
+    assert_eq!(sketch, db.rocksdb.get(&sketch_key).unwrap());
+}
+
+#[test]
+fn test_list_consumer_read() {
+    let db = open_database();
+    assert!(db.list_consumer_read("log", "a", 10).unwrap().is_empty());
+    for i in 0..5 {
+        db.list_right_push("log", format!("v{}", i).as_bytes())
+            .unwrap();
+    }
+    let read = |group: &str, count: usize| -> Vec<String> {
+        db.list_consumer_read("log", group, count)
+            .unwrap()
+            .iter()
+            .map(|v| String::from_utf8(v.to_vec()).unwrap())
+            .collect()
+    };
+    assert_eq!(read("a", 2), vec!["v0", "v1"]);
+    assert_eq!(read("a", 2), vec!["v2", "v3"]);
+    assert_eq!(read("b", 3), vec!["v0", "v1", "v2"]);
+    db.list_right_push("log", b"v5").unwrap();
+    assert_eq!(read("a", 10), vec!["v4", "v5"]);
+    assert!(read("a", 10).is_empty());
+    assert_eq!(db.list_count("log").unwrap(), 6);
+
+    // the popped items are skipped
+    db.list_left_pop("log").unwrap();
+    db.list_left_pop("log").unwrap();
+    db.list_left_pop("log").unwrap();
+    db.list_left_pop("log").unwrap();
+    assert_eq!(read("b", 10), vec!["v4", "v5"]);
+
+    assert!(db.list_consumer_reset("log", "b").unwrap());
+    assert_eq!(read("b", 1), vec!["v4"]);
+    db.list_left_pop("log").unwrap();
+    db.list_left_pop("log").unwrap();
+    assert!(db.get_meta("log").unwrap().is_none());
+    assert_eq!(db.raw_scan(PREFIX_CONSUMER, |_, _, _| true).unwrap(), 0);
+}
+
+#[test]
+fn test_rename_key_rewriting() {
+    let db = open_database();
+    for i in 0..2500 {
+        db.list_right_push("old", format!("v{}", i).as_bytes())
+            .unwrap();
+    }
+    db.list_consumer_read("old", "g", 10).unwrap();
+    db.alias_key("alias", "old").unwrap();
+    db.map_put("taken", "f", "v").unwrap();
+    let old_id = db.get_meta("old").unwrap().unwrap().id;
+
+    assert!(db.rename_key_rewriting("old", "taken").is_err());
+    assert!(db.rename_key_rewriting("missing", "new").is_err());
+    // the items and the offset of the consumer group
+    assert_eq!(db.rename_key_rewriting("old", "new").unwrap(), 2501);
+
+    assert!(db.get_meta("old").unwrap().is_none());
+    assert!(db.get_meta("alias").unwrap().is_none());
+    let meta = db.get_meta("new").unwrap().unwrap();
+    assert_ne!(meta.id, old_id);
+    assert_eq!(meta.count, 2500);
+    assert_eq!(db.list_items_range("new", 0, 1).unwrap().len(), 2);
+    assert_eq!(db.keys_of_type(KeyType::List).unwrap(), vec!["new"]);
+    assert_eq!(
+        db.list_consumer_read("new", "g", 1).unwrap(),
+        vec![Box::from(&b"v10"[..])]
+    );
+    let mut old_rows = 0;
+    db.raw_scan(b"", |k, row, _| {
+        // the key of an admin log entry is its sequence, it's not a key ID
+        if k.len() >= 9
+            && k[1..9] == old_id.to_be_bytes()
+            && !matches!(row, RawRow::AdminLog { .. })
+        {
+            old_rows += 1;
+        }
+        true
+    })
+    .unwrap();
+    assert_eq!(old_rows, 0);
+
+    // the writes during the rename fail instead of being lost
+    for i in 0..2500 {
+        db.list_right_push("busy", format!("v{}", i).as_bytes())
+            .unwrap();
+    }
+    let done = AtomicBool::new(false);
+    let pushed = std::thread::scope(|s| {
+        let writer = s.spawn(|| {
+            let mut pushed = 0;
+            while !done.load(Ordering::SeqCst) {
+                match db.list_right_push("busy", b"late") {
+                    Ok(_) => pushed += 1,
+                    Err(err) => assert!(matches!(err.root(), Error::Frozen(_))),
+                }
+            }
+            pushed
+        });
+        db.rename_key_rewriting("busy", "busy2").unwrap();
+        done.store(true, Ordering::SeqCst);
+        writer.join().unwrap()
+    });
+    assert!(!db.is_frozen("busy"));
+    let count = |key| db.get_meta(key).unwrap().map(|m| m.count).unwrap_or(0);
+    assert_eq!(count("busy") + count("busy2"), 2500 + pushed);
+}
+
+#[test]
+fn test_for_each_item() {
+    let db = open_database();
+    db.map_put("map", "f", "v").unwrap();
+    db.set_add("set", b"m").unwrap();
+    db.list_right_push("list", b"a").unwrap();
+    db.list_right_push("list", b"b").unwrap();
+    db.sorted_set_add("zset", &get_score_bytes(1), b"m")
+        .unwrap();
+    db.log_append("log", b"e").unwrap();
+    db.value_put("value", b"data").unwrap();
+
+    let items = |key: &str| {
+        let mut vec = Vec::new();
+        db.for_each_item(key, |item| {
+            vec.push(item);
+            true
+        })
+        .unwrap();
+        vec
+    };
+    assert_eq!(
+        items("map"),
+        vec![Item::MapEntry {
+            field: Box::from(&b"f"[..]),
+            value: Box::from(&b"v"[..])
+        }]
+    );
+    assert_eq!(items("set"), vec![Item::SetMember(Box::from(&b"m"[..]))]);
+    assert_eq!(
+        items("list"),
+        vec![
+            Item::ListValue {
+                index: 0,
+                value: Box::from(&b"a"[..])
+            },
+            Item::ListValue {
+                index: 1,
+                value: Box::from(&b"b"[..])
+            }
+        ]
+    );
+    assert_eq!(
+        items("zset"),
+        vec![Item::SortedScoreValue {
+            score: Box::from(&get_score_bytes(1)[..]),
+            value: Box::from(&b"m"[..])
+        }]
+    );
+    assert!(matches!(&items("log")[..], [Item::LogEntry { value, .. }] if &value[..] == b"e"));
+    assert!(
+        matches!(&items("value")[..], [Item::ValueChunk { index: 0, data }] if &data[..] == b"data")
+    );
+    assert!(items("missing").is_empty());
+}
+
+#[test]
+fn test_meta_negative_cache() {
+    let path = get_random_database_path();
+    let options = Options {
+        meta_negative_cache_size: 2,
+        ..Default::default()
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    assert!(!db.set_is_member("set", b"m").unwrap());
+    assert!(db.get_meta("set").unwrap().is_none());
+    db.set_add("set", b"m").unwrap();
+    assert!(db.set_is_member("set", b"m").unwrap());
+
+    assert!(db.get_meta("a").unwrap().is_none());
+    assert!(db.get_meta("b").unwrap().is_none());
+    assert!(db.get_meta("c").unwrap().is_none());
+    db.map_put("b", "f", "v").unwrap();
+    db.map_put("c", "f", "v").unwrap();
+    assert_eq!(db.map_count("b").unwrap(), 1);
+    assert_eq!(db.map_count("c").unwrap(), 1);
+
+    // a deleted key is cached as absent again
+    db.delete_all("b").unwrap();
+    assert!(db.get_meta("b").unwrap().is_none());
+    db.list_right_push("b", b"x").unwrap();
+    assert_eq!(db.list_count("b").unwrap(), 1);
+
+    // the readers racing with the creation never cache a created key as absent
+    let db = std::sync::Arc::new(db);
+    for i in 0..20 {
+        let key = format!("race{}", i);
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let db = db.clone();
+                let key = key.clone();
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        db.get_meta(&key).unwrap();
+                    }
+                })
+            })
+            .collect();
+        db.map_put(&key, "f", "v").unwrap();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert!(db.get_meta(&key).unwrap().is_some());
+    }
+}
+
+#[test]
+fn test_database_group() {
+    let mut group = DatabaseGroup::new();
+    let users = open_database();
+    users.map_put("alice", "age", "30").unwrap();
+    users.map_put("bob", "age", "40").unwrap();
+    let orders = open_database();
+    orders.list_right_push("alice", b"o1").unwrap();
+    group.attach("users", users).unwrap();
+    group.attach("orders", orders).unwrap();
+    assert!(group.attach("users", open_database()).is_err());
+    assert!(group.attach("a/b", open_database()).is_err());
+
+    let names = |keys: Vec<(String, simpledb::KeyMeta)>| -> Vec<String> {
+        keys.into_iter().map(|(k, _)| k).collect()
+    };
+    assert_eq!(
+        names(group.keys().unwrap()),
+        vec!["orders/alice", "users/alice", "users/bob"]
+    );
+    assert_eq!(names(group.keys_with_prefix("us").unwrap()).len(), 2);
+    assert_eq!(
+        names(group.keys_with_prefix("users/b").unwrap()),
+        vec!["users/bob"]
+    );
+    assert_eq!(
+        group.map_get("users/alice", "age").unwrap().as_deref(),
+        Some(&b"30"[..])
+    );
+    assert_eq!(group.list_items("orders/alice").unwrap().len(), 1);
+    assert!(group.get_meta("alice").is_err());
+    assert!(group.get_meta("missing/alice").is_err());
+
+    let mut buf = Vec::new();
+    assert_eq!(group.export_prefix("users/a", &mut buf).unwrap(), 1);
+    let mut backup = DatabaseGroup::new();
+    backup.attach("users", open_database()).unwrap();
+    assert_eq!(backup.import(buf.as_slice()).unwrap(), 1);
+    assert_eq!(names(backup.keys().unwrap()), vec!["users/alice"]);
+    // a broken name length fails without allocating it
+    assert!(backup.import(&u32::MAX.to_be_bytes()[..]).is_err());
+    assert!(group.detach("orders").is_some());
+    assert_eq!(group.keys().unwrap().len(), 2);
+}
+
+#[test]
+fn test_backpressure_on_write_stall() {
+    use std::sync::{Arc, Condvar, Mutex};
+
+    let path = get_random_database_path();
+    let mut options = Options {
+        backpressure_on_write_stall: true,
+        ..Default::default()
+    };
+    // 2 level 0 files stop the writes until their compaction finishes, the compaction is held
+    // by the filter until the gate is opened, so the stall lasts as long as the test needs
+    let gate = Arc::new((Mutex::new(false), Condvar::new()));
+    let gate2 = gate.clone();
+    options
+        .rocksdb_options
+        .set_level_zero_file_num_compaction_trigger(2);
+    options
+        .rocksdb_options
+        .set_level_zero_slowdown_writes_trigger(2);
+    options
+        .rocksdb_options
+        .set_level_zero_stop_writes_trigger(2);
+    options
+        .rocksdb_options
+        .set_compaction_filter("gate", move |_, _, _| {
+            let (open, opened) = &*gate2;
+            let _open = opened
+                .wait_while(open.lock().unwrap(), |open| !*open)
+                .unwrap();
+            simpledb::rocksdb::compaction_filter::Decision::Keep
+        });
+    let open_gate = || {
+        *gate.0.lock().unwrap() = true;
+        gate.1.notify_all();
+    };
+    let db = Database::open_with_options(&path, options).unwrap();
+    assert_eq!(db.write_stall().unwrap(), WriteStall::Normal);
+    assert!(db
+        .wait_for_write_capacity(Duration::from_millis(10))
+        .unwrap());
+    db.map_put("map", "a", "1").unwrap();
+    db.flush().unwrap();
+    db.map_put("map", "b", "2").unwrap();
+    db.flush().unwrap();
+
+    let stall = db.write_stall().unwrap();
+    let err = db.map_put("map", "c", "3").unwrap_err();
+    let capacity = db.wait_for_write_capacity(Duration::from_millis(30));
+    let read = db.map_get("map", "a");
+    open_gate();
+    assert_eq!(stall, WriteStall::Stopped);
+    assert!(matches!(
+        err.root(),
+        Error::Backpressure(WriteStall::Stopped)
+    ));
+    assert!(err.is_transient());
+    assert!(!capacity.unwrap());
+    // the reads are not throttled
+    assert_eq!(read.unwrap().as_deref(), Some(&b"1"[..]));
+
+    assert!(db.wait_for_write_capacity(Duration::from_secs(10)).unwrap());
+    db.map_put("map", "c", "3").unwrap();
+}
+
+#[test]
+fn test_describe_key() {
+    let db = open_database();
+    assert!(db.describe_key("none").unwrap().is_none());
+
+    db.list_right_push("list", b"a").unwrap();
+    db.list_right_push("list", b"b").unwrap();
+    db.list_left_pop("list").unwrap();
+    let description = db.describe_key("list").unwrap().unwrap();
+    assert_eq!(description.key_type, KeyType::List);
+    assert_eq!(description.count, 1);
+    assert_eq!(description.rows, 1);
+    assert!(description.size > 0);
+    assert!(matches!(description.extra, MetaExtra::List { right, .. } if right > 1));
+
+    db.sorted_set_add("sorted_set", &get_score_bytes(1u32), b"a")
+        .unwrap();
+    let description = db.describe_key("sorted_set").unwrap().unwrap();
+    assert_eq!(description.count, 1);
+    // a member has a row with the score and a row without it
+    assert_eq!(description.rows, 2);
+    assert_eq!(
+        description.extra,
+        MetaExtra::SortedSet {
+            deleted_count: 0,
+            score_len: get_score_bytes(1u32).len() as u8
+        }
+    );
+
+    db.map_put("map", "a", "1").unwrap();
+    let description = db.describe_key("map").unwrap().unwrap();
+    assert_eq!(description.id, db.get_meta("map").unwrap().unwrap().id);
+    assert_eq!(description.extra, MetaExtra::None);
+    assert_eq!((description.count, description.rows), (1, 1));
+}
+
+#[test]
+fn test_sorted_set_update_member() {
+    let db = open_database();
+    assert!(!db.sorted_set_update_member("zset", b"a", b"b").unwrap());
+    db.sorted_set_add("zset", &get_score_bytes(2u32), b"a")
+        .unwrap();
+    db.sorted_set_add("zset", &get_score_bytes(1u32), b"c")
+        .unwrap();
+    assert!(!db.sorted_set_update_member("zset", b"x", b"y").unwrap());
+
+    assert!(db.sorted_set_update_member("zset", b"a", b"b").unwrap());
+    assert!(!db.sorted_set_is_member("zset", b"a").unwrap());
+    assert!(db.sorted_set_is_member("zset", b"b").unwrap());
+    assert_eq!(db.get_count("zset").unwrap(), 2);
+    let items = db.sorted_set_items("zset").unwrap();
+    assert_eq!(items.len(), 2);
+    assert_eq!(get_score_from_bytes::<u32>(&items[1].0), 2);
+    assert_eq!(items[1].1.as_ref(), b"b");
+
+    assert!(db.sorted_set_update_member("zset", b"b", b"c").is_err());
+    assert!(db.sorted_set_is_member("zset", b"b").unwrap());
+
+    db.map_put("map", "f", "v").unwrap();
+    assert!(db.sorted_set_update_member("map", b"f", b"g").is_err());
+}
+
+#[test]
+fn test_sorted_set_scores() {
+    let db = open_database();
+    assert_eq!(
+        db.sorted_set_scores("zset", &[b"a", b"b"]).unwrap(),
+        vec![None, None]
+    );
+    db.sorted_set_add("zset", &get_score_bytes(1u32), b"a")
+        .unwrap();
+    db.sorted_set_add("zset", &get_score_bytes(3u32), b"c")
+        .unwrap();
+    db.sorted_set_add_ex(
+        "zset",
+        &get_score_bytes(4u32),
+        b"d",
+        Duration::from_millis(0),
+    )
+    .unwrap();
+    std::thread::sleep(Duration::from_millis(2));
+
+    let scores = db
+        .sorted_set_scores("zset", &[b"c", b"b", b"a", b"d"])
+        .unwrap();
+    assert_eq!(
+        scores,
+        vec![
+            Some(get_score_bytes(3u32)),
+            None,
+            Some(get_score_bytes(1u32)),
+            None
+        ]
+    );
+}
+
+#[test]
+fn test_diff_against() {
+    let db = open_database();
+    let other = open_database();
+    // the key IDs differ but the contents are the same
+    other.map_put("unused", "x", "1").unwrap();
+    other.delete_all("unused").unwrap();
+    for db in [&db, &other] {
+        db.map_put("same", "a", "1").unwrap();
+        db.list_right_push("list", b"a").unwrap();
+    }
+    assert!(db.diff_against(&other).unwrap().is_empty());
+
+    db.map_put("removed", "a", "1").unwrap();
+    other.set_add("added", b"a").unwrap();
+    db.map_put("changed", "a", "1").unwrap();
+    other.map_put("changed", "a", "2").unwrap();
+    db.set_add("count", b"a").unwrap();
+    other.set_add("count", b"a").unwrap();
+    other.set_add("count", b"b").unwrap();
+
+    let diffs = db.diff_against(&other).unwrap();
+    let keys: Vec<_> = diffs.iter().map(|d| d.key()).collect();
+    assert_eq!(
+        keys,
+        vec![
+            &b"added"[..],
+            &b"changed"[..],
+            &b"count"[..],
+            &b"removed"[..]
+        ]
+    );
+    assert!(matches!(diffs[0], KeyDiff::Added { .. }));
+    assert!(matches!(diffs[1], KeyDiff::Changed { .. }));
+    match &diffs[2] {
+        KeyDiff::Changed {
+            meta, other_meta, ..
+        } => assert_eq!((meta.count, other_meta.count), (1, 2)),
+        diff => panic!("unexpected {:?}", diff),
+    }
+    assert!(matches!(diffs[3], KeyDiff::Removed { .. }));
+    assert_eq!(other.for_each_diff(&db, |_| false).unwrap(), 1);
+}
+
+#[test]
+fn test_key_digest() {
+    let db = open_database();
+    let other = Database::open_with_options(
+        get_random_database_path(),
+        Options {
+            value_chunk_size: 3,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(db.key_digest("list").unwrap().is_none());
+
+    // the positions of the list differ
+    db.list_right_push("list", b"a").unwrap();
+    db.list_right_push("list", b"b").unwrap();
+    other.list_left_push("list", b"b").unwrap();
+    other.list_left_push("list", b"a").unwrap();
+    db.value_put("value", b"0123456789").unwrap();
+    other.value_put("value", b"0123456789").unwrap();
+    db.map_put("map", "a", "1").unwrap();
+    other.map_put("map", "a", "1").unwrap();
+    for key in ["list", "value", "map"] {
+        assert_eq!(
+            db.key_digest(key).unwrap(),
+            other.key_digest(key).unwrap(),
+            "{}",
+            key
+        );
+    }
+
+    other.list_right_push("list", b"c").unwrap();
+    assert_ne!(
+        db.key_digest("list").unwrap(),
+        other.key_digest("list").unwrap()
+    );
+    // the same bytes split differently between the field and the value
+    other.map_put("map2", "a1", "").unwrap();
+    assert_ne!(
+        db.key_digest("map").unwrap(),
+        other.key_digest("map2").unwrap()
+    );
+    // the same item of another key type
+    db.set_add("set", b"a").unwrap();
+    db.list_right_push("list2", b"a").unwrap();
+    assert_ne!(
+        db.key_digest("set").unwrap(),
+        db.key_digest("list2").unwrap()
+    );
+}
+
+#[test]
+fn test_get_or_create_meta_concurrently() {
+    let db = open_database();
+    for round in 0..200 {
+        let key = format!("key{}", round);
+        let barrier = std::sync::Barrier::new(8);
+        let ids: Vec<u64> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..8)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        db.get_or_create_meta(&key, KeyType::Map).unwrap().id
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        let id = db.get_meta(&key).unwrap().unwrap().id;
+        assert!(ids.iter().all(|i| *i == id), "{:?}", ids);
+    }
+}
+
+#[test]
+fn test_save_meta_policy() {
+    let db = open_database();
+    assert_eq!(
+        db.save_meta_policy("queue:a"),
+        SaveMetaPolicy::DeleteWhenEmpty
+    );
+    db.set_save_meta_policy("queue:*", SaveMetaPolicy::KeepWhenEmpty);
+    assert_eq!(
+        db.save_meta_policy("queue:a"),
+        SaveMetaPolicy::KeepWhenEmpty
+    );
+
+    db.list_right_push("queue:a", b"1").unwrap();
+    db.list_right_push("cache", b"1").unwrap();
+    let id = db.get_meta("queue:a").unwrap().unwrap().id;
+    db.list_left_pop("queue:a").unwrap();
+    db.list_left_pop("cache").unwrap();
+    // the queue keeps its key ID, the cache is cleaned up
+    assert_eq!(db.get_meta("queue:a").unwrap().unwrap().id, id);
+    assert_eq!(db.get_count("queue:a").unwrap(), 0);
+    assert!(db.get_meta("cache").unwrap().is_none());
+
+    assert!(db.remove_save_meta_policy("queue:*"));
+    assert!(!db.remove_save_meta_policy("queue:*"));
+    db.list_right_push("queue:a", b"1").unwrap();
+    db.list_left_pop("queue:a").unwrap();
+    assert!(db.get_meta("queue:a").unwrap().is_none());
+
+    let db = Database::open_with_options(
+        get_random_database_path(),
+        Options {
+            delete_meta_when_empty: false,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    db.set_save_meta_policy("cache", SaveMetaPolicy::DeleteWhenEmpty);
+    db.set_add("cache", b"a").unwrap();
+    db.set_add("set", b"a").unwrap();
+    db.set_delete("cache", b"a").unwrap();
+    db.set_delete("set", b"a").unwrap();
+    assert!(db.get_meta("cache").unwrap().is_none());
+    assert!(db.get_meta("set").unwrap().is_some());
+}
+
+#[test]
+fn test_key_config() {
+    let path = get_random_database_path();
+    let db = Database::open(&path).unwrap();
+    assert_eq!(db.key_config("queue"), None);
+    let config = KeyConfig {
+        max_count: Some(2),
+        save_meta_policy: Some(SaveMetaPolicy::KeepWhenEmpty),
+        ..Default::default()
+    };
+    db.set_key_config("queue", config).unwrap();
+    db.set_key_config(
+        "events",
+        KeyConfig {
+            retention: Some(SortedListRetention::MaxCount(2)),
+            compact_deletes_count: Some(1),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert!(db
+        .set_key_config(
+            "bad",
+            KeyConfig {
+                compact_deletes_count: Some(0),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    drop(db);
+
+    let db = Database::open(&path).unwrap();
+    assert_eq!(db.key_config("queue"), Some(config));
+    db.list_right_push("queue", b"1").unwrap();
+    db.list_right_push("queue", b"2").unwrap();
+    assert!(db.list_right_push("queue", b"3").is_err());
+    assert_eq!(db.get_count("queue").unwrap(), 2);
+    let id = db.get_meta("queue").unwrap().unwrap().id;
+    db.list_left_pop("queue").unwrap();
+    db.list_left_pop("queue").unwrap();
+    assert_eq!(db.get_meta("queue").unwrap().unwrap().id, id);
+
+    for i in 0..4u64 {
+        db.sorted_list_add("events", &get_score_bytes(i), b"e")
+            .unwrap();
+    }
+    assert_eq!(db.get_count("events").unwrap(), 2);
+    // the pattern is overridden by the configuration of the key
+    db.sorted_list_set_retention("events", SortedListRetention::MaxCount(3));
+    assert_eq!(
+        db.sorted_list_retention("events"),
+        Some(SortedListRetention::MaxCount(2))
+    );
+
+    // the configuration is kept when the key is deleted
+    db.delete_all("events").unwrap();
+    assert!(db.key_config("events").is_some());
+    assert!(db.remove_key_config("events").unwrap());
+    assert!(!db.remove_key_config("events").unwrap());
+    assert_eq!(
+        db.sorted_list_retention("events"),
+        Some(SortedListRetention::MaxCount(3))
+    );
+
+    // an unknown tag of max_count is invalid
+    let mut bytes = config.get_bytes();
+    assert_eq!(KeyConfig::from_bytes(&bytes), Some(config));
+    bytes[10] = 3;
+    assert_eq!(KeyConfig::from_bytes(&bytes), None);
+}
+
+#[test]
+fn test_sorted_list_sequence() {
+    let db = open_database();
+    let one = get_score_bytes(1u32);
+    let two = get_score_bytes(2u32);
+    for value in [b"a", b"b", b"c"] {
+        db.sorted_list_add("list", &one, value).unwrap();
+    }
+    db.sorted_list_add("list", &two, b"d").unwrap();
+
+    let mut items = Vec::new();
+    db.sorted_list_for_each_with_sequence("list", |item| {
+        items.push(item);
+        true
+    })
+    .unwrap();
+    let sequences: Vec<u64> = items.iter().map(|(_, seq, _)| *seq).collect();
+    assert!(sequences[..3].windows(2).all(|w| w[0] < w[1]));
+
+    // resume after "a" even though "b" and "c" have the same score
+    let (score, sequence, value) = &items[0];
+    assert_eq!(value.as_ref(), b"a");
+    let page = db
+        .sorted_list_items_after("list", Some((score, *sequence)), 2)
+        .unwrap();
+    assert_eq!(page.len(), 2);
+    assert_eq!(page[0].2.as_ref(), b"b");
+    assert_eq!(page[1].2.as_ref(), b"c");
+    // the cursor is stable when the item before it is deleted
+    db.sorted_list_left_pop("list", None).unwrap();
+    let (score, sequence, _) = &page[1];
+    let page = db
+        .sorted_list_items_after("list", Some((score, *sequence)), 10)
+        .unwrap();
+    assert_eq!(page.len(), 1);
+    assert_eq!(page[0].2.as_ref(), b"d");
+    assert_eq!(
+        db.sorted_list_items_after("list", None, 1).unwrap()[0]
+            .2
+            .as_ref(),
+        b"b"
+    );
+
+    let (_, sequence, value) = db
+        .sorted_list_left_pop_with_sequence("list", None)
+        .unwrap()
+        .unwrap();
+    assert_eq!((sequence, value.as_ref()), (items[1].1, &b"b"[..]));
+    let (score, sequence, value) = db
+        .sorted_list_right_pop_with_sequence("list", None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(score.as_ref(), two.as_slice());
+    assert_eq!((sequence, value.as_ref()), (items[3].1, &b"d"[..]));
+}
+
+#[test]
+fn test_map_swap() {
+    let db = open_database();
+    assert_eq!(db.map_swap("state", "job", "queued").unwrap(), None);
+    assert_eq!(db.get_count("state").unwrap(), 1);
+    assert_eq!(
+        db.map_swap("state", "job", "running").unwrap().as_deref(),
+        Some(&b"queued"[..])
+    );
+    assert_eq!(db.get_count("state").unwrap(), 1);
+    assert_eq!(
+        db.map_get("state", "job").unwrap().as_deref(),
+        Some(&b"running"[..])
+    );
+
+    // every swap gets a distinct old value
+    db.map_put("counter", "n", 0u64.to_be_bytes()).unwrap();
+    let mut olds: Vec<u64> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (1..=8u64)
+            .map(|i| {
+                let db = &db;
+                scope.spawn(move || {
+                    let old = db.map_swap("counter", "n", i.to_be_bytes()).unwrap();
+                    u64::from_be_bytes(old.unwrap().try_into().unwrap())
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    olds.sort_unstable();
+    olds.dedup();
+    assert_eq!(olds.len(), 8);
+}
+
+#[test]
+fn test_open_archive() {
+    let path = get_random_database_path();
+    let db = Database::open_with_options(&path, Options::archive()).unwrap();
+    for i in 0..100 {
+        db.map_put("hello", format!("key_{}", i), "123").unwrap();
+    }
+    db.flush().unwrap();
+    // read from the mmapped files, the blocks are not cached
+    assert_eq!(db.map_items("hello").unwrap().len(), 100);
+    assert_eq!(db.memory_usage().unwrap().block_cache_total, 0);
+}
+
+#[test]
+fn test_admin_log() {
+    let path = get_random_database_path();
+    let db = Database::open(&path).unwrap();
+    // the index of key types is built when a database is created
+    assert_eq!(db.admin_log(10).unwrap().len(), 1);
+    db.map_put("a", "f", "1").unwrap();
+    db.map_put("b", "f", "1").unwrap();
+    let blob = db.dump_key("a").unwrap().unwrap();
+    db.delete_all("a").unwrap();
+    db.restore_key("c", &blob).unwrap();
+    db.delete_keys(&["b", "c"]).unwrap();
+    drop(db);
+
+    let db = Database::open(&path).unwrap();
+    db.delete_all("missing").unwrap();
+    db.map_put("d", "f", "1").unwrap();
+    let mut buf = Vec::new();
+    db.export_range("", None, &mut buf).unwrap();
+    db.delete_all("d").unwrap();
+    db.import_range(buf.as_slice()).unwrap();
+
+    let log = db.admin_log(10).unwrap();
+    let ops: Vec<(&str, &str, u64)> = log
+        .iter()
+        .map(|e| (e.op.as_str(), e.key.as_str(), e.count))
+        .collect();
+    assert_eq!(
+        ops,
+        vec![
+            ("import_range", "", 1),
+            ("delete_all", "d", 1),
+            ("delete_keys", "", 2),
+            ("restore_key", "c", 1),
+            ("delete_all", "a", 1),
+            ("rebuild_type_index", "", 0),
+        ]
+    );
+    // the sequences continue after reopening
+    assert!(log
+        .windows(2)
+        .all(|w| w[0].seq > w[1].seq && w[0].time >= w[1].time));
+    assert_eq!(db.admin_log(2).unwrap(), log[..2].to_vec());
+}
+
+#[test]
+fn test_destroy_checked() {
+    let path = get_random_database_path();
+    assert!(Database::destroy_checked(&path, false).unwrap().is_empty());
+
+    std::fs::create_dir_all(&path).unwrap();
+    std::fs::write(std::path::Path::new(&path).join("notes.txt"), "keep").unwrap();
+    assert!(Database::destroy_checked(&path, false).is_err());
+    std::fs::remove_dir_all(&path).unwrap();
+
+    let db = Database::open(&path).unwrap();
+    db.map_put("a", "f", "1").unwrap();
+    // the database is locked while it's open
+    assert!(Database::destroy_checked(&path, false).is_err());
+    drop(db);
+
+    let files = Database::destroy_checked(&path, true).unwrap();
+    assert!(files.iter().any(|f| f.ends_with(MARKER_FILE)));
+    assert!(files.iter().any(|f| f.ends_with("CURRENT")));
+    assert!(std::path::Path::new(&path).join(MARKER_FILE).exists());
+
+    assert_eq!(Database::destroy_checked(&path, false).unwrap(), files);
+    assert!(!std::path::Path::new(&path).exists());
+}
+
+#[test]
+fn test_replicate_to() {
+    struct FlakySink {
+        batches: Vec<(u64, Vec<ReplicationEvent>)>,
+        fail: bool,
+    }
+
+    impl ReplicationSink for FlakySink {
+        fn checkpoint(&self) -> simpledb::Result<Option<u64>> {
+            Ok(self.batches.last().map(|(seq, _)| *seq))
+        }
+
+        fn apply_batch(&mut self, seq: u64, events: &[ReplicationEvent]) -> simpledb::Result<()> {
+            if self.fail {
+                self.fail = false;
+                return Err(Error::Message("unavailable".to_string()));
+            }
+            self.batches.push((seq, events.to_vec()));
+            Ok(())
+        }
+    }
+
+    let options = || {
+        let mut options = Options::default();
+        options.rocksdb_options.create_if_missing(true);
+        options.replication_wal_ttl = Some(Duration::from_secs(3600));
+        options
+    };
+    let src = Database::open_with_options(get_random_database_path(), options()).unwrap();
+    let dst_path = get_random_database_path();
+    let dst = Database::open(&dst_path).unwrap();
+
+    src.map_put("m", "a", "1").unwrap();
+    src.list_right_push("l", b"x").unwrap();
+    src.set_add("s", b"v").unwrap();
+    src.delete_all("s").unwrap();
+    src.sorted_set_add("z", &get_score_bytes(1u32), b"v")
+        .unwrap();
+
+    let mut sink = DatabaseSink::new(&dst);
+    assert_eq!(src.replicate_to(&mut sink, 2).unwrap(), 2);
+    assert!(src.replicate_to(&mut sink, usize::MAX).unwrap() > 0);
+    assert_eq!(src.replicate_to(&mut sink, usize::MAX).unwrap(), 0);
+    assert!(src.diff_against(&dst).unwrap().is_empty());
+    assert_eq!(dst.map_get("m", "a").unwrap(), Some(b"1".to_vec()));
+    assert!(dst.get_meta("s").unwrap().is_none());
+
+    src.map_put("m", "b", "2").unwrap();
+    let list_id = src.get_meta("l").unwrap().unwrap().id;
+    src.delete_all("l").unwrap();
+    drop(dst);
+    let dst = Database::open(&dst_path).unwrap();
+    let mut sink = DatabaseSink::new(&dst);
+    assert!(src.replicate_to(&mut sink, usize::MAX).unwrap() > 0);
+    assert!(src.diff_against(&dst).unwrap().is_empty());
+    assert_eq!(dst.map_count("m").unwrap(), 2);
+
+    // a failed batch is delivered again
+    let mut sink = FlakySink {
+        batches: Vec::new(),
+        fail: true,
+    };
+    assert!(src.replicate_to(&mut sink, usize::MAX).is_err());
+    assert!(src.replicate_to(&mut sink, usize::MAX).unwrap() > 0);
+    // the rows of a deleted key are deleted one by one
+    let list_row = encode_data_key(list_id);
+    assert!(sink
+        .batches
+        .iter()
+        .flat_map(|(_, events)| events)
+        .any(|e| matches!(e, ReplicationEvent::Delete { key } if key.starts_with(&list_row))));
+
+    // a checkpoint in the middle of a batch leaves a gap
+    let (seq, _) = sink
+        .batches
+        .iter()
+        .find(|(_, events)| events.len() > 1)
+        .unwrap();
+    let mut sink = FlakySink {
+        batches: vec![(seq - 1, Vec::new())],
+        fail: false,
+    };
+    assert!(src.replicate_to(&mut sink, usize::MAX).is_err());
+    assert_eq!(sink.batches.len(), 1);
+}
+
+#[test]
+fn test_import_range_resumable() {
+    let src = open_database();
+    for i in 0..150 {
+        src.map_put(format!("a/{:03}", i), "f", "v").unwrap();
+    }
+    for i in 0..50 {
+        src.map_put(format!("b/{:03}", i), "f", "v").unwrap();
+    }
+    let mut stream = Vec::new();
+    src.export_range("", None, &mut stream).unwrap();
+    let checkpoint = format!("{}.checkpoint", get_random_database_path());
+
+    let db = open_database();
+    let quota = ImportQuota {
+        max_keys_per_namespace: Some(100),
+        max_bytes: None,
+    };
+    assert!(db
+        .import_range_resumable(stream.as_slice(), &checkpoint, &quota, |_| {})
+        .is_err());
+    assert_eq!(db.for_each_key(|_, _| true).unwrap(), 100);
+    assert!(std::path::Path::new(&checkpoint).exists());
+
+    let mut checkpoints = Vec::new();
+    let progress = db
+        .import_range_resumable(
+            stream.as_slice(),
+            &checkpoint,
+            &ImportQuota::default(),
+            |p| checkpoints.push(p.keys),
+        )
+        .unwrap();
+    assert_eq!(checkpoints, vec![200, 200]);
+    assert_eq!(progress.keys, 200);
+    assert_eq!(progress.namespaces.get("a"), Some(&150));
+    assert_eq!(progress.last_key.as_deref(), Some(&b"b/049"[..]));
+    assert!(!std::path::Path::new(&checkpoint).exists());
+    assert!(src.diff_against(&db).unwrap().is_empty());
+
+    // the keys imported after the lost checkpoint are not imported again
+    let db = open_database();
+    db.import_range_resumable(
+        &stream[..stream.len() / 2],
+        &checkpoint,
+        &ImportQuota::default(),
+        |_| {},
+    )
+    .unwrap_err();
+    std::fs::remove_file(&checkpoint).unwrap();
+    let progress = db
+        .import_range_resumable(
+            stream.as_slice(),
+            &checkpoint,
+            &ImportQuota::default(),
+            |_| {},
+        )
+        .unwrap();
+    assert_eq!(progress.keys, 200);
+    assert!(src.diff_against(&db).unwrap().is_empty());
+}
+
+#[test]
+fn test_sorted_set_for_each_rev() {
+    let db = open_database();
+    assert_eq!(db.sorted_set_for_each_rev("zset", |_| true).unwrap(), 0);
+    for i in 1..=5u32 {
+        db.sorted_set_add("zset", &get_score_bytes(i), format!("v{}", i).as_bytes())
+            .unwrap();
+    }
+    db.sorted_set_add_ex(
+        "zset",
+        &get_score_bytes(6u32),
+        b"v6",
+        Duration::from_millis(0),
+    )
+    .unwrap();
+    db.sorted_set_add("other", &get_score_bytes(9u32), b"x")
+        .unwrap();
+    std::thread::sleep(Duration::from_millis(2));
+
+    let mut top = Vec::new();
+    let count = db
+        .sorted_set_for_each_rev("zset", |(score, value)| {
+            top.push((get_score_from_bytes::<u32>(&score), value.to_vec()));
+            top.len() < 3
+        })
+        .unwrap();
+    assert_eq!(count, 3);
+    assert_eq!(
+        top,
+        vec![
+            (5, b"v5".to_vec()),
+            (4, b"v4".to_vec()),
+            (3, b"v3".to_vec())
+        ]
+    );
+    assert_eq!(db.sorted_set_for_each_rev("zset", |_| true).unwrap(), 5);
+}
+
+#[test]
+fn test_export_import_meta() {
+    let db = open_database();
+    db.map_put("m\"1", "a", "1").unwrap();
+    db.map_put("m\"1", "b", "2").unwrap();
+    db.list_right_push("list", b"x").unwrap();
+    db.sorted_set_add("z", &get_score_bytes(1u32), b"v")
+        .unwrap();
+    db.map_put([0xffu8, 0x00], "f", "v").unwrap();
+
+    let mut doc = Vec::new();
+    assert_eq!(db.export_meta(&mut doc).unwrap(), 4);
+    let doc = String::from_utf8(doc).unwrap();
+    assert!(doc.contains("name = \"m\\\"1\"\ntype = \"map\""));
+    assert!(doc.contains("name_hex = \"ff00\""));
+    assert!(doc.contains("type = \"sorted_set\""));
+    assert!(doc.contains("# List { left: "));
+
+    let other = open_database();
+    assert_eq!(other.import_meta(doc.as_bytes()).unwrap(), 4);
+    for key in ["m\"1", "list", "z"] {
+        let (meta, other_meta) = (db.get_meta(key).unwrap(), other.get_meta(key).unwrap());
+        assert_eq!(format!("{:?}", meta), format!("{:?}", other_meta));
+    }
+    assert_eq!(other.get_meta([0xffu8, 0x00]).unwrap().unwrap().count, 1);
+    assert_eq!(
+        other.keys_of_type(KeyType::SortedSet).unwrap(),
+        vec!["z".to_string()]
+    );
+    // the imported key IDs are not allocated again
+    other.map_put("new", "f", "v").unwrap();
+    assert!(other.get_meta("new").unwrap().unwrap().id > db.get_meta("z").unwrap().unwrap().id);
+
+    // fix a count
+    let fixed = doc.replacen("count = 2", "count = 5", 1);
+    db.import_meta(fixed.as_bytes()).unwrap();
+    assert_eq!(db.get_count("m\"1").unwrap(), 5);
+    assert!(db
+        .import_meta("[[key]]\nname = \"a\"\ntype = \"tree\"\n".as_bytes())
+        .is_err());
+    assert!(db.import_meta("id = 1\n".as_bytes()).is_err());
+
+    // a short extra, and the ID of another key are rejected
+    let list_id = db.get_meta("list").unwrap().unwrap().id;
+    let doc = "[[key]]\nname = \"l2\"\ntype = \"list\"\nid = 1000\ncount = 1\nextra = \"00\"\n";
+    assert!(db.import_meta(doc.as_bytes()).is_err());
+    let doc = format!(
+        "[[key]]\nname = \"m2\"\ntype = \"map\"\nid = {}\ncount = 1\n",
+        list_id
+    );
+    assert!(db.import_meta(doc.as_bytes()).is_err());
+    let doc = "[[key]]\nname = \"m2\"\ntype = \"map\"\nid = 1000\ncount = 1\n\
+               [[key]]\nname = \"m3\"\ntype = \"map\"\nid = 1000\ncount = 1\n";
+    assert!(db.import_meta(doc.as_bytes()).is_err());
+    assert!(db.get_meta("l2").unwrap().is_none());
+    assert!(db.get_meta("m2").unwrap().is_none());
+    // the ID of a key can be moved to another key replaced in the same document
+    let doc = format!(
+        "[[key]]\nname = \"list\"\ntype = \"map\"\nid = 1000\ncount = 0\n\
+         [[key]]\nname = \"m2\"\ntype = \"list\"\nid = {}\ncount = 1\n",
+        list_id
+    );
+    assert_eq!(db.import_meta(doc.as_bytes()).unwrap(), 2);
+}
+
+#[test]
+fn test_scan_options() {
+    let mut options = Options::default();
+    options.rocksdb_options.create_if_missing(true);
+    options.scan_readahead_size = Some(2 * 1024 * 1024);
+    options.iter_page_size = 3;
+    let handle = DatabaseHandle::new(
+        Database::open_with_options(get_random_database_path(), options).unwrap(),
+    );
+    for i in 0..10 {
+        handle
+            .map_put("map", format!("f{}", i), i.to_string())
+            .unwrap();
+    }
+    let fields: Vec<_> = handle.map_iter("map").unwrap().map(|(f, _)| f).collect();
+    assert_eq!(fields.len(), 10);
+    assert_eq!(fields[9].as_ref(), b"f9");
+    assert_eq!(handle.for_each_data("map", None, |_, _| true).unwrap(), 10);
+    let mut buf = Vec::new();
+    assert_eq!(handle.export_range("", None, &mut buf).unwrap(), 1);
+}
+
+#[test]
+fn test_multi_map_get() {
+    let db = open_database();
+    assert!(db.multi_map_get::<&str>(&[]).unwrap().is_empty());
+    for i in 0..12 {
+        db.map_put(format!("user:{}", i), "name", format!("u{}", i))
+            .unwrap();
+    }
+    db.set_add("set", b"name").unwrap();
+    let keys: Vec<String> = (0..12).map(|i| format!("user:{}", i)).collect();
+    let mut pairs: Vec<(&str, &[u8])> = keys
+        .iter()
+        .map(|k| (k.as_str(), b"name".as_ref()))
+        .collect();
+    pairs.push(("user:0", b"age"));
+    pairs.push(("missing", b"name"));
+    pairs.push(("set", b"name"));
+    pairs.push(("user:11", b"name"));
+    let values = db.multi_map_get(&pairs).unwrap();
+    assert_eq!(values.len(), 16);
+    assert_eq!(values[0], Some(b"u0".to_vec()));
+    assert_eq!(values[11], Some(b"u11".to_vec()));
+    assert_eq!(values[12..15], [None, None, None]);
+    assert_eq!(values[15], Some(b"u11".to_vec()));
+}
+
+#[test]
+fn test_seal_key() {
+    let path = get_random_database_path();
+    {
+        let db = Database::open(&path).unwrap();
+        assert!(!db.seal_key("missing").unwrap());
+        db.map_put("audit", "a", "1").unwrap();
+        db.value_put("blob", b"data").unwrap();
+        assert!(db.seal_key("audit").unwrap());
+        assert!(db.seal_key("blob").unwrap());
+        assert!(db.is_sealed("audit"));
+
+        let err = db.map_put("audit", "b", "2").unwrap_err();
+        assert!(matches!(err.root(), Error::Sealed(key) if key == "audit"));
+        assert!(matches!(
+            db.delete_all("audit").unwrap_err().root(),
+            Error::Sealed(_)
+        ));
+        assert!(matches!(
+            db.delete_keys(&["map", "audit"]).unwrap_err().root(),
+            Error::Sealed(_)
+        ));
+        assert!(matches!(
+            db.value_put("blob", b"other").unwrap_err(),
+            Error::Sealed(_)
+        ));
+        // the reads still work
+        assert_eq!(
+            db.map_get("audit", "a").unwrap().as_deref(),
+            Some(&b"1"[..])
+        );
+        assert_eq!(db.value_get("blob").unwrap().as_deref(), Some(&b"data"[..]));
+    }
+    let db = Database::open(&path).unwrap();
+    assert!(db.is_sealed("audit"));
+    assert!(db.map_put("audit", "b", "2").is_err());
+    assert!(db.unseal_key("audit").unwrap());
+    assert!(!db.unseal_key("audit").unwrap());
+    db.map_put("audit", "b", "2").unwrap();
+    assert_eq!(db.delete_all("audit").unwrap(), 2);
+
+    // the maintenance writes are blocked too
+    db.sorted_list_add("events", &get_score_bytes(1u32), b"e")
+        .unwrap();
+    let mut exported = Vec::new();
+    db.export_meta(&mut exported).unwrap();
+    assert!(db.seal_key("events").unwrap());
+    assert!(matches!(
+        db.set_key_config("events", KeyConfig::default())
+            .unwrap_err(),
+        Error::Sealed(_)
+    ));
+    assert!(matches!(
+        db.sorted_list_compact("events").unwrap_err().root(),
+        Error::Sealed(_)
+    ));
+    assert!(matches!(
+        db.sorted_list_set_compact_deletes_count("events", 1)
+            .unwrap_err()
+            .root(),
+        Error::Sealed(_)
+    ));
+    assert!(matches!(
+        db.import_meta(&exported[..]).unwrap_err(),
+        Error::Sealed(_)
+    ));
+
+    // and so are the writes of the key through a pipeline
+    let pipeline = WritePipeline::start(db, Duration::from_millis(1));
+    let writer = pipeline.writer();
+    assert!(matches!(
+        writer.list_right_push("events", b"x").unwrap_err(),
+        Error::Sealed(_)
+    ));
+    writer.map_put("other", "a", "1").unwrap();
+}
+
+#[test]
+fn test_read_guard() {
+    fn count_users(guard: ReadGuard) -> u64 {
+        guard.get_count("users").unwrap()
+    }
+
+    let db = open_database();
+    db.map_put("users", "alice", "1").unwrap();
+    db.list_right_push("list", b"a").unwrap();
+    let guard = db.read_guard();
+    assert_eq!(count_users(guard), 1);
+    assert_eq!(
+        guard.map_get("users", "alice").unwrap(),
+        Some(b"1".to_vec())
+    );
+    assert_eq!(guard.list_items("list").unwrap().len(), 1);
+    // the reads see the latest state
+    db.map_put("users", "bob", "2").unwrap();
+    assert_eq!(count_users(guard), 2);
+    assert_eq!(guard.map_items("users").unwrap().len(), 2);
+    assert!(guard.get_meta("missing").unwrap().is_none());
+    // reading a missing key creates no meta
+    assert_eq!(guard.map_get("missing", "a").unwrap(), None);
+    assert!(db.get_meta("missing").unwrap().is_none());
+    assert!(guard.map_get("list", "a").is_err());
+
+    // the remote tier is not consulted
+    let tier = std::sync::Arc::new(TestTier::default());
+    let options = Options {
+        tier: Some(tier.clone()),
+        ..Default::default()
+    };
+    let db = Database::open_with_options(get_random_database_path(), options).unwrap();
+    db.map_put("a", "x", "1").unwrap();
+    assert!(db.evict("a").unwrap());
+    assert_eq!(db.read_guard().map_get("a", "x").unwrap(), None);
+    assert!(db.get_meta("a").unwrap().is_none());
+}
+
+#[test]
+fn test_list_pop_n() {
+    let db = open_database();
+    assert!(db.list_left_pop_n("missing", 3).unwrap().is_empty());
+    for v in [b"a", b"b", b"c", b"d", b"e"] {
+        db.list_right_push("list", v).unwrap();
+    }
+    let values = db.list_left_pop_n("list", 2).unwrap();
+    assert_eq!(values, vec![Box::from(&b"a"[..]), Box::from(&b"b"[..])]);
+    let values = db.list_right_pop_n("list", 2).unwrap();
+    assert_eq!(values, vec![Box::from(&b"e"[..]), Box::from(&b"d"[..])]);
+    assert_eq!(db.list_count("list").unwrap(), 1);
+    assert!(db.list_left_pop_n("list", 0).unwrap().is_empty());
+    let values = db.list_right_pop_n("list", 10).unwrap();
+    assert_eq!(values, vec![Box::from(&b"c"[..])]);
+    assert_eq!(db.list_count("list").unwrap(), 0);
+    assert!(db.list_left_pop_n("list", 10).unwrap().is_empty());
+    // the positions are still consistent with the single pops and pushes
+    db.list_left_push("list", b"x").unwrap();
+    db.list_right_push("list", b"y").unwrap();
+    assert_eq!(
+        db.list_left_pop("list").unwrap().as_deref(),
+        Some(&b"x"[..])
+    );
+    assert_eq!(
+        db.list_right_pop_n("list", 5).unwrap(),
+        vec![Box::from(&b"y"[..])]
+    );
+
+    db.sorted_set_add("zset", &get_score_bytes(1), b"a")
+        .unwrap();
+    assert!(db.list_left_pop_n("zset", 1).is_err());
+    assert_eq!(1, db.sorted_set_count("zset").unwrap());
+}
+
+#[test]
+fn test_priority_queue() {
+    let db = open_database();
+    let queue = db.priority_queue::<i64>("jobs");
+    assert!(queue.is_empty().unwrap());
+    assert!(queue.peek_highest().unwrap().is_none());
+    assert!(queue.pop_lowest().unwrap().is_none());
+    queue.push(5, b"five").unwrap();
+    queue.push(-3, b"minus three").unwrap();
+    queue.push(10, b"ten").unwrap();
+    assert_eq!(queue.push(5, b"five again").unwrap(), 4);
+    // another key after the queue does not affect the peeks
+    db.sorted_list_add("other", &get_score_bytes(100i64), b"x")
+        .unwrap();
+
+    assert_eq!(
+        queue.peek_highest().unwrap(),
+        Some((10, Box::from(&b"ten"[..])))
+    );
+    assert_eq!(
+        queue.peek_lowest().unwrap(),
+        Some((-3, Box::from(&b"minus three"[..])))
+    );
+    assert_eq!(queue.len().unwrap(), 4);
+    assert_eq!(
+        queue.pop_highest().unwrap(),
+        Some((10, Box::from(&b"ten"[..])))
+    );
+    assert_eq!(
+        queue.pop_lowest().unwrap(),
+        Some((-3, Box::from(&b"minus three"[..])))
+    );
+    assert_eq!(
+        queue.pop_lowest().unwrap(),
+        Some((5, Box::from(&b"five"[..])))
+    );
+    assert_eq!(
+        queue.pop_lowest().unwrap(),
+        Some((5, Box::from(&b"five again"[..])))
+    );
+    assert!(queue.pop_highest().unwrap().is_none());
+
+    // the ties are popped in the order they are pushed from the lowest end, and in the
+    // reverse order from the highest end
+    for item in ["a", "b", "c"] {
+        queue.push(1, item.as_bytes()).unwrap();
+    }
+    assert_eq!(
+        queue.peek_highest().unwrap(),
+        Some((1, Box::from(&b"c"[..])))
+    );
+    assert_eq!(
+        queue.pop_highest().unwrap(),
+        Some((1, Box::from(&b"c"[..])))
+    );
+    assert_eq!(
+        queue.peek_lowest().unwrap(),
+        Some((1, Box::from(&b"a"[..])))
+    );
+    assert_eq!(queue.pop_lowest().unwrap(), Some((1, Box::from(&b"a"[..]))));
+    assert_eq!(
+        queue.pop_highest().unwrap(),
+        Some((1, Box::from(&b"b"[..])))
+    );
+
+    // a priority of another encoding fails instead of panicking
+    db.sorted_list_add("jobs", b"x", b"raw").unwrap();
+    assert!(queue.peek_lowest().is_err());
+    assert!(queue.peek_highest().is_err());
+    assert!(queue.pop_lowest().is_err());
+    assert!(queue.is_empty().unwrap());
+}
+
+#[test]
+fn test_namespaces() {
+    let db = open_database();
+    assert!(db.namespaces().unwrap().is_empty());
+    for i in 0..50 {
+        db.map_put(format!("tenant1/user:{}", i), "name", vec![0; 1000])
+            .unwrap();
+    }
+    db.list_right_push("tenant2/jobs", b"a").unwrap();
+    db.list_right_push("tenant2/jobs", b"b").unwrap();
+    db.set_add("tenant10/set", b"a").unwrap();
+    db.set_add("global", b"a").unwrap();
+    db.set_add("tenant1.x", b"a").unwrap();
+    assert_eq!(
+        db.namespaces().unwrap(),
+        vec!["", "tenant1", "tenant10", "tenant2"]
+    );
+
+    let stats = db.namespace_stats("tenant1").unwrap();
+    assert_eq!(stats.keys, 50);
+    assert_eq!(stats.items, 50);
+    let stats = db.namespace_stats("tenant2").unwrap();
+    assert_eq!((stats.keys, stats.items), (1, 2));
+    let stats = db.namespace_stats("").unwrap();
+    assert_eq!((stats.keys, stats.items), (2, 2));
+    assert_eq!(
+        db.namespace_stats("missing").unwrap(),
+        NamespaceStats::default()
+    );
+
+    // the counters follow the deletes and the items
+    db.list_left_pop("tenant2/jobs").unwrap();
+    db.delete_all("tenant1/user:0").unwrap();
+    db.delete_keys(&["tenant1/user:1", "global"]).unwrap();
+    for i in 0..2000 {
+        db.set_add("tenant3/set", format!("{}", i).as_bytes())
+            .unwrap();
+    }
+    let stats = db.namespace_stats("tenant1").unwrap();
+    assert_eq!((stats.keys, stats.items), (48, 48));
+    let stats = db.namespace_stats("tenant2").unwrap();
+    assert_eq!((stats.keys, stats.items), (1, 1));
+    let stats = db.namespace_stats("").unwrap();
+    assert_eq!((stats.keys, stats.items), (1, 1));
+    let stats = db.namespace_stats("tenant3").unwrap();
+    assert_eq!((stats.keys, stats.items), (1, 2000));
+
+    // the counters survive reopening, and are rebuilt if the marker is missing
+    let path = db.path.clone();
+    drop(db);
+    let db = Database::open(&path).unwrap();
+    let stats = db.namespace_stats("tenant3").unwrap();
+    assert_eq!((stats.keys, stats.items), (1, 2000));
+    db.rocksdb.delete(KEY_NAMESPACE_STATS_READY).unwrap();
+    db.rocksdb.put(b"nbroken", b"").unwrap();
+    drop(db);
+    let db = Database::open(&path).unwrap();
+    assert_eq!(
+        db.namespace_stats("broken").unwrap(),
+        NamespaceStats::default()
+    );
+    let stats = db.namespace_stats("tenant1").unwrap();
+    assert_eq!((stats.keys, stats.items), (48, 48));
+    let stats = db.namespace_stats("tenant3").unwrap();
+    assert_eq!((stats.keys, stats.items), (1, 2000));
+}
+
+#[test]
+fn test_create_from_template() {
+    let db = open_database();
+    let template = Template::new()
+        .map("profile", [("name", "alice"), ("plan", "free")])
+        .list("inbox", ["welcome", "tips"])
+        .set("roles", ["user", "user", "beta"])
+        .set("devices", Vec::<&[u8]>::new())
+        .sorted_list(
+            "jobs",
+            [(get_score_bytes(2i64), "b"), (get_score_bytes(1i64), "a")],
+        );
+    assert_eq!(db.create_from_template("user:1/", &template).unwrap(), 5);
+    assert_eq!(
+        db.map_get("user:1/profile", "plan").unwrap(),
+        Some(b"free".to_vec())
+    );
+    assert_eq!(
+        db.list_items("user:1/inbox").unwrap(),
+        vec![Box::from(&b"welcome"[..]), Box::from(&b"tips"[..])]
+    );
+    assert_eq!(db.set_count("user:1/roles").unwrap(), 2);
+    assert!(db.get_meta("user:1/devices").unwrap().is_some());
+    assert_eq!(
+        db.sorted_list_left_pop("user:1/jobs", None)
+            .unwrap()
+            .unwrap()
+            .1,
+        Box::from(&b"a"[..])
+    );
+    // the created keys work with the write methods
+    db.list_right_push("user:1/inbox", b"news").unwrap();
+    db.list_left_push("user:1/inbox", b"first").unwrap();
+    assert_eq!(db.list_items("user:1/inbox").unwrap().len(), 4);
+    db.sorted_list_add("user:1/jobs", &get_score_bytes(0i64), b"c")
+        .unwrap();
+    assert_eq!(db.sorted_list_count("user:1/jobs").unwrap(), 2);
+    assert_eq!(db.keys_of_type(KeyType::Set).unwrap().len(), 2);
+
+    // nothing is created if one of the keys exists
+    db.set_add("user:2/roles", b"admin").unwrap();
+    assert!(db.create_from_template("user:2/", &template).is_err());
+    assert!(db.get_meta("user:2/profile").unwrap().is_none());
+    assert_eq!(db.set_count("user:2/roles").unwrap(), 1);
+    let duplicated = Template::new().set("a", ["x"]).list("a", ["y"]);
+    assert!(db.create_from_template("user:3/", &duplicated).is_err());
+}
+
+#[test]
+fn test_items_if_exists() {
+    let db = open_database();
+    assert_eq!(db.get_count_if_exists("map").unwrap(), None);
+    assert_eq!(db.map_items_if_exists("map").unwrap(), None);
+    assert_eq!(db.list_items_if_exists("list").unwrap(), None);
+    assert_eq!(db.set_items_if_exists("set").unwrap(), None);
+    assert_eq!(db.sorted_list_items_if_exists("sorted_list").unwrap(), None);
+    assert_eq!(db.sorted_set_items_if_exists("sorted_set").unwrap(), None);
+
+    db.set_save_meta_policy("list", SaveMetaPolicy::KeepWhenEmpty);
+    db.map_put("map", "a", "1").unwrap();
+    db.map_delete("map", "a").unwrap();
+    db.list_right_push("list", b"a").unwrap();
+    db.list_right_pop("list").unwrap();
+    db.set_add("set", b"a").unwrap();
+    db.sorted_set_add("sorted_set", &get_score_bytes(1i64), b"a")
+        .unwrap();
+    assert_eq!(db.get_count_if_exists("list").unwrap(), Some(0));
+    assert_eq!(db.list_items_if_exists("list").unwrap(), Some(vec![]));
+    assert_eq!(db.get_count_if_exists("set").unwrap(), Some(1));
+    assert_eq!(
+        db.set_items_if_exists("set").unwrap(),
+        Some(vec![Box::from(&b"a"[..])])
+    );
+    assert_eq!(
+        db.sorted_set_items_if_exists("sorted_set")
+            .unwrap()
+            .unwrap()
+            .len(),
+        1
+    );
+    // an empty map is deleted by default
+    assert_eq!(db.map_items_if_exists("map").unwrap(), None);
+}
+
+#[test]
+fn test_sorted_set_histogram() {
+    let db = open_database();
+    let b10 = get_score_bytes(10i64);
+    let b20 = get_score_bytes(20i64);
+    let b30 = get_score_bytes(30i64);
+    let boundaries = [b10.as_slice(), b20.as_slice(), b30.as_slice()];
+    assert_eq!(
+        db.sorted_set_histogram("scores", &boundaries).unwrap(),
+        vec![0, 0, 0, 0]
+    );
+    for (i, score) in [-5i64, 0, 10, 15, 19, 20, 35, 100].iter().enumerate() {
+        db.sorted_set_add(
+            "scores",
+            &get_score_bytes(*score),
+            format!("m{}", i).as_bytes(),
+        )
+        .unwrap();
+    }
+    assert_eq!(
+        db.sorted_set_histogram("scores", &boundaries).unwrap(),
+        vec![2, 3, 1, 2]
+    );
+    assert_eq!(db.sorted_set_histogram("scores", &[]).unwrap(), vec![8]);
+    assert!(db
+        .sorted_set_histogram("scores", &[b20.as_slice(), b10.as_slice()])
+        .is_err());
+}
+
+#[test]
+fn test_lazy_delete() {
+    let path = get_random_database_path();
+    let options = || Options {
+        lazy_delete: true,
+        ..Default::default()
+    };
+    let count_data_rows = |db: &Database| {
+        db.raw_scan(simpledb::codec::PREFIX_DATA, |_, _, _| true)
+            .unwrap()
+    };
+    {
+        let db = Database::open_with_options(&path, options()).unwrap();
+        for i in 0..100 {
+            db.map_put("map", format!("f{}", i), b"v").unwrap();
+        }
+        assert_eq!(db.delete_all("map").unwrap(), 100);
+        assert_eq!(db.map_count("map").unwrap(), 0);
+        assert_eq!(count_data_rows(&db), 0);
+        assert_eq!(
+            db.raw_scan(PREFIX_DROPPED_KEY_ID, |_, _, _| true).unwrap(),
+            0
+        );
+
+        // the process stops after the meta is deleted, before the compaction
+        for i in 0..100 {
+            db.map_put("map", format!("f{}", i), b"v").unwrap();
+        }
+        let key_id = db.get_meta("map").unwrap().unwrap().id;
+        let mut marker = PREFIX_DROPPED_KEY_ID.to_vec();
+        marker.extend_from_slice(&key_id.to_be_bytes());
+        db.rocksdb.put(&marker, b"").unwrap();
+        db.rocksdb
+            .delete(simpledb::codec::encode_meta_key("map"))
+            .unwrap();
+    }
+    let db = Database::open_with_options(&path, options()).unwrap();
+    assert_eq!(count_data_rows(&db), 100);
+    db.compact_all(|_| true).unwrap();
+    assert_eq!(count_data_rows(&db), 0);
+
+    // so does delete_keys()
+    let markers = db.raw_scan(PREFIX_DROPPED_KEY_ID, |_, _, _| true).unwrap();
+    for i in 0..100 {
+        db.map_put("a", format!("f{}", i), b"v").unwrap();
+        db.set_add("b", format!("m{}", i).as_bytes()).unwrap();
+    }
+    assert_eq!(db.delete_keys(&["a", "b"]).unwrap(), 2);
+    assert_eq!(count_data_rows(&db), 0);
+    assert_eq!(
+        db.raw_scan(PREFIX_DROPPED_KEY_ID, |_, _, _| true).unwrap(),
+        markers
+    );
+
+    // the compactions drop the rows ordered by the score of the expired members, the rows
+    // without score and the count are fixed by the purge
+    let score = get_score_bytes(1u32);
+    db.sorted_set_add_ex("zset", &score, b"a", Duration::from_millis(1))
+        .unwrap();
+    db.sorted_set_add_ex("zset", &score, b"b", Duration::from_millis(1))
+        .unwrap();
+    db.sorted_set_add_ex("zset", &score, b"c", Duration::from_secs(3600))
+        .unwrap();
+    drop(db);
+    let db = Database::open_with_options(&path, options()).unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    db.compact_all(|_| true).unwrap();
+    let key_id = db.get_meta("zset").unwrap().unwrap().id;
+    let with_score = simpledb::codec::encode_data_key_sorted_set_prefix(key_id);
+    assert_eq!(db.raw_scan(&with_score, |_, _, _| true).unwrap(), 1);
+    assert_eq!(count_data_rows(&db), 4);
+    assert!(!db.sorted_set_is_member("zset", b"a").unwrap());
+    assert_eq!(db.sorted_set_count("zset").unwrap(), 3);
+    assert_eq!(db.sorted_set_purge_expired("zset").unwrap(), 2);
+    assert_eq!(db.sorted_set_count("zset").unwrap(), 1);
+    assert_eq!(count_data_rows(&db), 2);
+    assert!(db.sorted_set_is_member("zset", b"c").unwrap());
+}
+
+#[test]
+fn test_gc() {
+    let path = get_random_database_path();
+    let db = Database::open_with_options(
+        &path,
+        Options {
+            value_chunk_size: 1,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(db.gc().unwrap(), GcReport::default());
+    for i in 0..10 {
+        db.map_put("live", format!("f{}", i), b"v").unwrap();
+        db.set_add("crashed", format!("m{}", i).as_bytes()).unwrap();
+    }
+    // the chunks of an open blob writer have no meta yet
+    let mut writer = db.blob_writer("blob").unwrap();
+    writer.write_all(&[7u8; 100]).unwrap();
+    // a crash of delete_all() after the meta is deleted
+    db.rocksdb
+        .delete(simpledb::codec::encode_meta_key("crashed"))
+        .unwrap();
+    assert_eq!(
+        db.gc().unwrap(),
+        GcReport {
+            orphaned_keys: 1,
+            reclaimed_rows: 10,
+        }
+    );
+    assert_eq!(db.gc().unwrap(), GcReport::default());
+    assert_eq!(db.map_count("live").unwrap(), 10);
+    writer.finish().unwrap();
+    assert_eq!(db.value_get("blob").unwrap().unwrap(), vec![7u8; 100]);
+
+    let handle = DatabaseHandle::new(db);
+    handle.map_put("crashed", "f", b"v").unwrap();
+    handle
+        .rocksdb
+        .delete(simpledb::codec::encode_meta_key("crashed"))
+        .unwrap();
+    handle.start_gc(Duration::from_millis(1));
+    assert!(handle
+        .background_tasks()
+        .names()
+        .contains(&GC_TASK_NAME.to_string()));
+    while handle
+        .raw_scan(simpledb::codec::PREFIX_DATA, |_, _, _| true)
+        .unwrap()
+        > 110
+    {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn test_bad_row_policy() {
+    let open = |policy| {
+        let db = Database::open_with_options(
+            get_random_database_path(),
+            Options {
+                bad_row_policy: policy,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        db.map_put("a", "f", "1").unwrap();
+        db.map_put("c", "f", "1").unwrap();
+        db.rocksdb.put(b"mb", [1u8, 2, 3]).unwrap();
+        db.list_right_push("l", b"x").unwrap();
+        // a list item without its position
+        let id = db.get_meta("l").unwrap().unwrap().id;
+        let bad_row = [encode_data_key(id).as_ref(), b">"].concat();
+        db.rocksdb.put(&bad_row, b"y").unwrap();
+        (db, bad_row)
+    };
+
+    let (db, _) = open(BadRowPolicy::Error);
+    assert!(db.for_each_key(|_, _| true).is_err());
+    assert!(db.export_range("", None, Vec::new()).is_err());
+    assert!(db.dump_key("l").is_err());
+    assert!(db.diff_against(&open_database()).is_err());
+
+    let (db, bad_row) = open(BadRowPolicy::Skip);
+    let skipped = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+    let skipped2 = skipped.clone();
+    db.on_bad_row(move |bad| skipped2.lock().unwrap().push(bad.meta_key.to_vec()));
+    let mut keys = Vec::new();
+    db.for_each_key(|key, _| {
+        keys.push(key.to_string());
+        true
+    })
+    .unwrap();
+    assert_eq!(keys, vec!["a", "c", "l"]);
+    assert_eq!(db.export_range("", None, Vec::new()).unwrap(), 3);
+    assert_eq!(
+        *skipped.lock().unwrap(),
+        vec![b"mb".to_vec(), b"mb".to_vec(), bad_row.clone()]
+    );
+    assert!(db.rocksdb.get(&bad_row).unwrap().is_some());
+    assert_eq!(db.diff_against(&open_database()).unwrap().len(), 3);
+
+    let (db, bad_row) = open(BadRowPolicy::Quarantine);
+    assert_eq!(db.for_each_key_with_limit(10, |_, _| true).unwrap(), 3);
+    assert_eq!(db.rocksdb.get(b"qmb").unwrap(), Some(vec![1u8, 2, 3]));
+    assert_eq!(db.rocksdb.get(b"mb").unwrap(), None);
+    let mut export = Vec::new();
+    assert_eq!(db.export_range("", None, &mut export).unwrap(), 3);
+    assert_eq!(db.rocksdb.get(&bad_row).unwrap(), None);
+    assert_eq!(
+        db.rocksdb.get([b"q", bad_row.as_slice()].concat()).unwrap(),
+        Some(b"y".to_vec())
+    );
+    let other = open_database();
+    assert_eq!(other.import_range(export.as_slice()).unwrap(), 3);
+    assert_eq!(other.list_items("l").unwrap().len(), 1);
+}
+
+#[test]
+fn test_freeze_key() {
+    let db = DatabaseHandle::new(open_database());
+    db.list_right_push("list", b"a").unwrap();
+    assert!(db.freeze_key("list", false).unwrap());
+    assert!(!db.freeze_key("list", true).unwrap());
+    assert!(db.is_frozen("list"));
+    // the thread freezing the key runs the maintenance
+    db.list_right_push("list", b"b").unwrap();
+    let other = db.clone();
+    std::thread::spawn(move || {
+        let err = other.list_right_push("list", b"c").unwrap_err();
+        assert!(matches!(err.root(), Error::Frozen(key) if key == "list"));
+        assert!(other.delete_all("list").is_err());
+        assert_eq!(other.list_count("list").unwrap(), 2);
+        other.list_right_push("other", b"c").unwrap();
+    })
+    .join()
+    .unwrap();
+
+    assert!(db.unfreeze_key("list").unwrap());
+    assert!(!db.unfreeze_key("list").unwrap());
+    assert!(db.freeze_key("list", true).unwrap());
+    let other = db.clone();
+    std::thread::spawn(move || {
+        assert!(other.list_count("list").is_err());
+        assert!(other.unfreeze_key("list").unwrap());
+        other.list_right_push("list", b"c").unwrap();
+    })
+    .join()
+    .unwrap();
+    assert!(!db.is_frozen("list"));
+    assert_eq!(db.list_count("list").unwrap(), 3);
+}
+
+#[test]
+fn test_sorted_list_pop_any() {
+    let db = open_database();
+    assert_eq!(db.sorted_list_pop_any(&["a", "b"], None).unwrap(), None);
+    let score = |n: i64| get_score_bytes(n);
+    db.sorted_list_add("a", &score(3), b"a3").unwrap();
+    db.sorted_list_add("b", &score(1), b"b1").unwrap();
+    db.sorted_list_add("c", &score(2), b"c2").unwrap();
+    let keys = ["a", "b", "c", "missing"];
+    let (i, (s, v)) = db.sorted_list_pop_any(&keys, None).unwrap().unwrap();
+    assert_eq!(
+        (i, s.as_ref(), v.as_ref()),
+        (1, score(1).as_slice(), b"b1".as_ref())
+    );
+    assert_eq!(db.sorted_list_count("b").unwrap(), 0);
+    assert_eq!(
+        db.sorted_list_pop_any(&keys, Some(&score(1))).unwrap(),
+        None
+    );
+    assert_eq!(db.sorted_list_pop_any(&keys, None).unwrap().unwrap().0, 2);
+    assert_eq!(db.sorted_list_pop_any(&keys, None).unwrap().unwrap().0, 0);
+    assert_eq!(db.sorted_list_pop_any(&keys, None).unwrap(), None);
+
+    // the ties are served in turns
+    for i in 0..4 {
+        db.sorted_list_add("a", &score(0), format!("a{}", i).as_bytes())
+            .unwrap();
+        db.sorted_list_add("b", &score(0), format!("b{}", i).as_bytes())
+            .unwrap();
+    }
+    let mut popped = [0, 0];
+    for _ in 0..4 {
+        let (i, _) = db.sorted_list_pop_any(&["a", "b"], None).unwrap().unwrap();
+        popped[i] += 1;
+    }
+    assert_eq!(popped, [2, 2]);
+    assert_eq!(db.sorted_list_count("a").unwrap(), 2);
+
+    // the keys must be sorted lists
+    db.map_put("m", "f", "v").unwrap();
+    assert!(db.sorted_list_pop_any(&["a", "m"], None).is_err());
+    assert_eq!(db.sorted_list_count("a").unwrap(), 2);
+
+    // an item is popped once by the concurrent pops
+    for i in 0..100 {
+        db.sorted_list_add("q", &score(i), format!("q{}", i).as_bytes())
+            .unwrap();
+    }
+    let popped: Vec<_> = std::thread::scope(|s| {
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut values = Vec::new();
+                    while let Some((_, (_, v))) = db.sorted_list_pop_any(&["q"], None).unwrap() {
+                        values.push(v);
+                    }
+                    values
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    assert_eq!(popped.len(), 100);
+    assert_eq!(
+        popped
+            .iter()
+            .collect::<std::collections::HashSet<_>>()
+            .len(),
+        100
+    );
+    assert_eq!(db.sorted_list_count("q").unwrap(), 0);
+}
+
+#[test]
+fn test_manifest() {
+    let path = get_random_database_path();
+    {
+        let db = Database::open(&path).unwrap();
+        let manifest = db.manifest().unwrap().unwrap();
+        assert_eq!(manifest.meta_format_version, META_FORMAT_VERSION);
+        assert!(manifest.features.is_empty());
+    }
+    {
+        let options = Options {
+            lazy_delete: true,
+            ..Default::default()
+        };
+        let db = Database::open_with_options(&path, options).unwrap();
+        assert_eq!(
+            db.manifest().unwrap().unwrap().features,
+            vec!["lazy_delete"]
+        );
+        // a database written by a newer version
+        let manifest = Manifest {
+            crate_version: "99.0.0".to_string(),
+            meta_format_version: META_FORMAT_VERSION + 1,
+            features: Vec::new(),
+        };
+        db.rocksdb.put(KEY_MANIFEST, manifest.to_bytes()).unwrap();
+    }
+    let err = Database::open(&path).err().unwrap();
+    assert!(matches!(err, Error::IncompatibleFormat(ref msg) if msg.contains("99.0.0")));
+    {
+        let db = simpledb::rocksdb::DB::open_default(&path).unwrap();
+        let manifest = Manifest {
+            crate_version: "99.0.0".to_string(),
+            meta_format_version: META_FORMAT_VERSION,
+            features: vec!["lazy_delete".to_string(), "future".to_string()],
+        };
+        db.put(KEY_MANIFEST, manifest.to_bytes()).unwrap();
+    }
+    let err = Database::open(&path).err().unwrap();
+    assert!(matches!(err, Error::IncompatibleFormat(ref msg) if msg.contains("future")));
+}